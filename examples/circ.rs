@@ -1,20 +1,31 @@
 #![allow(unused_imports)]
-use bellman::gadgets::test::TestConstraintSystem;
-use bellman::Circuit;
-use bls12_381::Scalar;
 use circ::front::zokrates::{Inputs, Mode, Zokrates};
 use circ::front::FrontEnd;
-use circ::ir::opt::{opt, Opt};
+use circ::ir::cost::{cost_report, AbyBoolCost, CostModel, IlpCost, R1csCost};
+use circ::ir::opt::ite::CostTarget;
+use circ::ir::opt::mem::plan::{PlannedArrayElim, Strategy};
+use circ::ir::opt::pass::{parse_pipeline_config, PassManager};
+use circ::ir::opt::specialize;
+use circ::ir::opt::validate::ValidationConfig;
+use circ::ir::term::Curve;
 use circ::target::aby::output::write_aby_exec;
 use circ::target::aby::trans::to_aby;
-use circ::target::r1cs::opt::reduce_linearities;
-use circ::target::r1cs::trans::to_r1cs;
+use circ::target::r1cs::iden3;
+use circ::target::r1cs::json;
+use circ::target::r1cs::lint;
+use circ::target::r1cs::opt::{clean_up, gauss_reduce, reduce_linearities};
+use circ::target::r1cs::R1cs;
+use circ::target::r1cs::trans::to_r1cs_with_program;
 use circ::target::ilp::{
     trans::to_ilp
 };
 use env_logger;
 use good_lp::default_solver;
-use std::path::PathBuf;
+use rug::Integer;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -32,9 +43,147 @@ struct Options {
     #[structopt(short, long, name = "PARTIES")]
     parties: Option<u8>,
 
+    /// Curve whose scalar field to compile for: bn128 or bls12_381. Defaults to the curve named
+    /// by the input's `#pragma curve`, or else bls12_381.
+    #[structopt(long, name = "CURVE")]
+    curve: Option<Curve>,
+
     /// Whether to maximize the output
     #[structopt(short, long)]
     maximize: bool,
+
+    /// Fix an input to a value, specializing the circuit. May be repeated. NAME is the input's
+    /// variable: ZoKrates parameter `x` of `main` is `main_f0_lex0_x_v0`, and its element `i` (or
+    /// field `f`) is `main_f0_lex0_x_v0.i` (or `main_f0_lex0_x_v0.f`).
+    #[structopt(long, name = "NAME=VALUE", parse(try_from_str = parse_fix))]
+    fix: Vec<(String, Integer)>,
+
+    /// Optimization pipeline, e.g. `flatten,cfold,(inline,cfold)*`. Overrides the default for
+    /// the target. Stages followed by `?` are undone if they raise the target's cost. Passes
+    /// like `mem-auto-r1cs`, `ite-r1cs`, and `dead-inputs` only run if listed here.
+    #[structopt(long, name = "PIPELINE")]
+    pipeline: Option<String>,
+
+    /// File of `TARGET: PIPELINE` lines, giving pipelines for the r1cs, aby, and ilp targets
+    #[structopt(long, name = "PIPELINE_FILE", parse(from_os_str))]
+    pipeline_config: Option<PathBuf>,
+
+    /// Check each optimization pass with an SMT solver (or random testing, for fields)
+    #[structopt(long)]
+    validate_passes: bool,
+
+    /// Print the cost of the optimized computation for the target, by operator
+    #[structopt(long)]
+    cost_report: bool,
+
+    /// Eliminate an array variable by the given strategy (oblivious, lookup, linear, or
+    /// permutation), if it applies, in the `mem-auto-*` passes. May be repeated. Permutation
+    /// only applies to R1CS, and is never chosen without this option.
+    #[structopt(long, name = "ARRAY=STRATEGY", parse(try_from_str = parse_array_strategy))]
+    array_strategy: Vec<(String, Strategy)>,
+
+    /// Write a program computing the R1CS witness from the inputs to this file
+    #[structopt(long, name = "PROGRAM_FILE", parse(from_os_str))]
+    witness_program: Option<PathBuf>,
+
+    /// Write the R1CS to PREFIX.r1cs and PREFIX.sym, and its witness (if inputs are given) to
+    /// PREFIX.wtns, in the iden3/circom formats
+    #[structopt(long, name = "PREFIX", parse(from_os_str))]
+    iden3: Option<PathBuf>,
+
+    /// Write the R1CS (and its witness, if inputs are given) to this file, as JSON
+    #[structopt(long, name = "JSON_FILE", parse(from_os_str))]
+    r1cs_json: Option<PathBuf>,
+
+    /// If the witness (from --inputs) violates R1CS constraints, write a report of every violated
+    /// constraint to this file, instead of printing it
+    #[structopt(long, name = "REPORT_FILE", parse(from_os_str))]
+    check_report: Option<PathBuf>,
+
+    /// Reduce R1CS linearities by Gaussian elimination, only taking pivots which add at most
+    /// MAX_FILL entries
+    #[structopt(long, name = "MAX_FILL")]
+    max_fill: Option<usize>,
+
+    /// Report R1CS signals which the inputs might not determine
+    #[structopt(long)]
+    lint: bool,
+
+    /// Check each signal reported by --lint with an SMT solver (only practical for small systems,
+    /// over small fields)
+    #[structopt(long)]
+    lint_smt: bool,
+}
+
+fn write_iden3<S: std::hash::Hash + Eq + std::fmt::Display>(r1cs: &R1cs<S>, prefix: &Path) {
+    let create = |ext: &str| {
+        let mut path = prefix.as_os_str().to_owned();
+        path.push(ext);
+        let file = File::create(&path)
+            .unwrap_or_else(|e| panic!("Could not create {}: {}", Path::new(&path).display(), e));
+        std::io::BufWriter::new(file)
+    };
+    iden3::write_r1cs(r1cs, &mut create(".r1cs")).expect("Could not write .r1cs");
+    iden3::write_sym(r1cs, &mut create(".sym")).expect("Could not write .sym");
+    let mut wtns = Vec::new();
+    match iden3::write_wtns(r1cs, &mut wtns) {
+        Ok(()) => create(".wtns")
+            .write_all(&wtns)
+            .expect("Could not write .wtns"),
+        Err(iden3::Iden3Error::MissingValues) => {}
+        Err(e) => panic!("Could not write .wtns: {}", e),
+    }
+}
+
+/// Exit if the witness violates any constraint of `r1cs`, after `stage`.
+fn check_witness<S: std::hash::Hash + Eq + Clone + std::fmt::Display>(
+    r1cs: &R1cs<S>,
+    stage: &str,
+    report: Option<&Path>,
+) {
+    if let Err(e) = r1cs.check_all() {
+        match report {
+            Some(path) => {
+                std::fs::write(path, e.to_string()).expect("Could not write check report");
+                eprintln!(
+                    "The witness violates {} constraints after {}; see {}",
+                    e.violations.len(),
+                    stage,
+                    path.display()
+                );
+            }
+            None => eprint!("After {}: {}", stage, e),
+        }
+        std::process::exit(1);
+    }
+}
+
+fn parse_fix(s: &str) -> Result<(String, Integer), String> {
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().unwrap().trim().to_owned();
+    let value = parts
+        .next()
+        .ok_or_else(|| format!("Expected NAME=VALUE, got {}", s))?;
+    let value = Integer::from_str_radix(value.trim(), 10).map_err(|e| e.to_string())?;
+    Ok((name, value))
+}
+
+fn parse_array_strategy(s: &str) -> Result<(String, Strategy), String> {
+    let mut parts = s.splitn(2, '=');
+    let name = parts.next().unwrap().trim().to_owned();
+    let strategy = parts
+        .next()
+        .ok_or_else(|| format!("Expected ARRAY=STRATEGY, got {}", s))?;
+    let strategy = strategy.trim().parse().map_err(|e| format!("{}", e))?;
+    Ok((name, strategy))
+}
+
+fn cost_model(target: &str) -> Box<dyn CostModel> {
+    match target {
+        "r1cs" => Box::new(R1csCost),
+        "aby" => Box::new(AbyBoolCost),
+        _ => Box::new(IlpCost),
+    }
 }
 
 fn main() {
@@ -57,43 +206,113 @@ fn main() {
         file: options.zokrates_path,
         inputs: options.inputs,
         mode: mode.clone(),
+        curve: options.curve,
     };
     let cs = Zokrates::gen(inputs);
-    let cs = match mode {
-        Mode::Opt => opt(
-            cs,
-            vec![Opt::ConstantFold],
-        ),
-        Mode::Mpc(_) => opt(
-            cs,
-            vec![Opt::Sha, Opt::ConstantFold, Opt::Mem, Opt::ConstantFold],
-        ),
-        Mode::Proof => opt(
-            cs,
-            vec![
-                Opt::Flatten,
-                Opt::Sha,
-                Opt::ConstantFold,
-                Opt::Flatten,
-                Opt::FlattenAssertions,
-                Opt::Inline,
-                Opt::Mem,
-                Opt::Flatten,
-                Opt::FlattenAssertions,
-                Opt::ConstantFold,
-                Opt::Inline,
-            ],
+    let cs = if options.fix.is_empty() {
+        cs
+    } else {
+        let assertions = matches!(mode, Mode::Proof);
+        specialize::int_values(&cs, options.fix.clone())
+            .and_then(|fixed| specialize::specialize(cs, &fixed, assertions))
+            .unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            })
+    };
+    let (target, default_pipeline) = match mode {
+        Mode::Opt => ("ilp", "cfold"),
+        Mode::Mpc(_) => ("aby", "sha,cfold,mem,cfold"),
+        Mode::Proof => (
+            "r1cs",
+            "flatten,sha,cfold,flatten,flatten-assertions,inline,mem,flatten,flatten-assertions,\
+             cfold,inline",
         ),
     };
+    let configured_pipeline = options.pipeline_config.as_ref().and_then(|path| {
+        let text = std::fs::read_to_string(path).expect("Could not read pipeline config");
+        let config = parse_pipeline_config(&text).unwrap_or_else(|e| panic!("{}", e));
+        config.get(target).cloned()
+    });
+    let pipeline_src = options
+        .pipeline
+        .clone()
+        .or(configured_pipeline)
+        .unwrap_or_else(|| default_pipeline.to_owned());
+    let mut pass_manager = PassManager::new();
+    if options.validate_passes {
+        pass_manager.validate(ValidationConfig::default());
+    }
+    pass_manager.cost_model(cost_model(target));
+    if !options.array_strategy.is_empty() {
+        for t in vec![CostTarget::R1cs, CostTarget::Aby] {
+            let mut mem = PlannedArrayElim::new(t);
+            for (name, strategy) in &options.array_strategy {
+                mem.set_override(name.clone(), *strategy);
+            }
+            pass_manager.register(mem);
+        }
+    }
+    let pipeline = pass_manager
+        .parse(&pipeline_src)
+        .unwrap_or_else(|e| panic!("{}", e));
+    println!("Optimizing with: {}", pipeline);
+    let cs = pass_manager.run(cs, &pipeline);
+    for stats in pass_manager.stats() {
+        println!("  {}", stats);
+    }
     println!("Done with IR optimization");
+    if options.cost_report {
+        print!("{}", cost_report(&cs, &*cost_model(target)));
+    }
 
     match mode {
         Mode::Proof => {
             println!("Converting to r1cs");
-            let r1cs = to_r1cs(cs, circ::front::zokrates::ZOKRATES_MODULUS.clone());
+            let modulus = (**cs.metadata.field.as_ref().expect("No field chosen")).clone();
+            let declared = cs.metadata.inputs.clone();
+            let (r1cs, mut program) = to_r1cs_with_program(cs, modulus);
+            let report = options.check_report.as_deref();
+            check_witness(&r1cs, "lowering", report);
             println!("Pre-opt R1cs size: {}", r1cs.constraints().len());
-            let r1cs = reduce_linearities(r1cs);
-            println!("Final R1cs size: {}", r1cs.constraints().len());
+            let start = Instant::now();
+            let r1cs = match options.max_fill {
+                Some(max_fill) => gauss_reduce(r1cs, max_fill),
+                None => reduce_linearities(r1cs),
+            };
+            let (r1cs, map) = clean_up(r1cs);
+            check_witness(&r1cs, "optimization", report);
+            println!(
+                "Final R1cs size: {} ({} signals, reduced in {:?})",
+                r1cs.constraints().len(),
+                r1cs.num_signals(),
+                start.elapsed()
+            );
+            program.renumber(&map);
+            if options.lint || options.lint_smt {
+                // Only the computation's inputs are given; the lowering of any other variable
+                // must be determined by the constraints.
+                let inputs = program.inputs_where(|n| declared.contains_key(n));
+                let mut suspects = lint::underconstrained(&r1cs, &inputs);
+                if options.lint_smt {
+                    let timeout = Duration::from_secs(10);
+                    suspects = lint::confirm(&r1cs, &inputs, suspects, timeout);
+                }
+                println!("Possibly under-constrained signals: {}", suspects.len());
+                for s in &suspects {
+                    let confirmed = if s.confirmed { " (confirmed)" } else { "" };
+                    println!("  {}{}", s.signal, confirmed);
+                }
+            }
+            if let Some(path) = &options.witness_program {
+                std::fs::write(path, program.to_string()).expect("Could not write witness program");
+            }
+            if let Some(prefix) = &options.iden3 {
+                write_iden3(&r1cs, prefix);
+            }
+            if let Some(path) = &options.r1cs_json {
+                std::fs::write(path, json::to_json(&r1cs)).expect("Could not write R1CS JSON");
+            }
         }
         Mode::Mpc(_) => {
             println!("Converting to aby");
@@ -113,13 +332,4 @@ fn main() {
             }
         }
     }
-
-    //r1cs.check_all();
-    //let mut cs = TestConstraintSystem::<Scalar>::new();
-    //r1cs.synthesize(&mut cs).unwrap();
-    //println!("Num constraints: {}", cs.num_constraints());
-    //println!("{}", cs.pretty_print());
-    //if let Some(c) = cs.which_is_unsatisfied() {
-    //    panic!("Unsat: {}", c);
-    //}
 }
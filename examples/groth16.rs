@@ -0,0 +1,164 @@
+use ahash::AHashMap;
+use bellman::groth16::{Parameters, Proof, VerifyingKey};
+use bls12_381::Bls12;
+use circ::ir::term::{FieldElem, Value};
+use circ::target::r1cs::groth16::{prove, public_values, read_public, setup, verify, write_public};
+use circ::target::r1cs::json::from_json;
+use circ::target::r1cs::witness::WitnessProgram;
+use circ::target::r1cs::R1cs;
+use rand::rngs::OsRng;
+use rug::ops::RemRounding;
+use rug::Integer;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use structopt::StructOpt;
+
+/// Groth16 proofs, over BLS12-381, of R1CS written by `circ --r1cs-json`
+///
+/// For BN254 (`--curve bn128`), write iden3 files with `circ --iden3` and prove with snarkjs.
+#[derive(Debug, StructOpt)]
+#[structopt(name = "groth16")]
+enum Command {
+    /// Generate a proving key (which includes the verifying key) and a verifying key
+    Setup {
+        /// R1CS, as JSON
+        #[structopt(parse(from_os_str))]
+        r1cs: PathBuf,
+        /// Output proving key
+        #[structopt(long, parse(from_os_str))]
+        pk: PathBuf,
+        /// Output verifying key
+        #[structopt(long, parse(from_os_str))]
+        vk: PathBuf,
+    },
+    /// Prove the R1CS, with the witness in its JSON or computed from inputs
+    Prove {
+        /// R1CS, as JSON
+        #[structopt(parse(from_os_str))]
+        r1cs: PathBuf,
+        /// Proving key
+        #[structopt(long, parse(from_os_str))]
+        pk: PathBuf,
+        /// Witness program, from `circ --witness-program`
+        #[structopt(long, parse(from_os_str), requires = "inputs")]
+        program: Option<PathBuf>,
+        /// Inputs to the witness program: `NAME VALUE` lines
+        #[structopt(long, parse(from_os_str), requires = "program")]
+        inputs: Option<PathBuf>,
+        /// Output proof
+        #[structopt(long, parse(from_os_str))]
+        proof: PathBuf,
+        /// Output public inputs, one per line
+        #[structopt(long, parse(from_os_str))]
+        public: PathBuf,
+    },
+    /// Verify a proof
+    Verify {
+        /// Verifying key
+        #[structopt(long, parse(from_os_str))]
+        vk: PathBuf,
+        /// Proof
+        #[structopt(long, parse(from_os_str))]
+        proof: PathBuf,
+        /// Public inputs, one per line, in order of the public signals' indices
+        #[structopt(long, parse(from_os_str))]
+        public: PathBuf,
+    },
+}
+
+fn open(path: &Path) -> BufReader<File> {
+    BufReader::new(
+        File::open(path).unwrap_or_else(|e| panic!("Could not open {}: {}", path.display(), e)),
+    )
+}
+
+fn create(path: &Path) -> BufWriter<File> {
+    BufWriter::new(
+        File::create(path).unwrap_or_else(|e| panic!("Could not create {}: {}", path.display(), e)),
+    )
+}
+
+fn read_to_string(path: &Path) -> String {
+    std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e))
+}
+
+fn read_r1cs(path: &Path) -> R1cs<String> {
+    from_json(&read_to_string(path)).unwrap_or_else(|e| panic!("{}", e))
+}
+
+/// Parse `NAME VALUE` lines as field elements.
+fn read_inputs(path: &Path, modulus: &Integer) -> AHashMap<String, Value> {
+    let modulus = Arc::new(modulus.clone());
+    read_to_string(path)
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| {
+            let mut parts = l.split_whitespace();
+            let name = parts.next().unwrap().to_owned();
+            let value = parts
+                .next()
+                .and_then(|v| Integer::from_str_radix(v, 10).ok())
+                .unwrap_or_else(|| panic!("Expected NAME VALUE, got {}", l));
+            let value = value.rem_floor(&*modulus);
+            (name, Value::Field(FieldElem::new(value, modulus.clone())))
+        })
+        .collect()
+}
+
+fn main() {
+    env_logger::Builder::from_default_env()
+        .format_level(false)
+        .format_timestamp(None)
+        .init();
+    match Command::from_args() {
+        Command::Setup { r1cs, pk, vk } => {
+            let r1cs = read_r1cs(&r1cs);
+            let params =
+                setup::<Bls12, _, _>(&r1cs, &mut OsRng).unwrap_or_else(|e| panic!("{}", e));
+            params
+                .write(create(&pk))
+                .expect("Could not write proving key");
+            params
+                .vk
+                .write(create(&vk))
+                .expect("Could not write verifying key");
+        }
+        Command::Prove {
+            r1cs,
+            pk,
+            program,
+            inputs,
+            proof,
+            public,
+        } => {
+            let mut r1cs = read_r1cs(&r1cs);
+            if let (Some(program), Some(inputs)) = (program, inputs) {
+                let program: WitnessProgram = read_to_string(&program)
+                    .parse()
+                    .unwrap_or_else(|e| panic!("{}", e));
+                let inputs = read_inputs(&inputs, program.modulus());
+                r1cs.set_values(program.eval(&inputs).unwrap_or_else(|e| panic!("{}", e)));
+            }
+            let params =
+                Parameters::<Bls12>::read(open(&pk), false).expect("Could not read proving key");
+            let pf = prove(&r1cs, &params, &mut OsRng).unwrap_or_else(|e| panic!("{}", e));
+            pf.write(create(&proof)).expect("Could not write proof");
+            let values = public_values(&r1cs).unwrap_or_else(|e| panic!("{}", e));
+            write_public(&values, &mut create(&public)).expect("Could not write public inputs");
+        }
+        Command::Verify { vk, proof, public } => {
+            let vk = VerifyingKey::<Bls12>::read(open(&vk)).expect("Could not read verifying key");
+            let proof = Proof::<Bls12>::read(open(&proof)).expect("Could not read proof");
+            let public = read_public(open(&public)).unwrap_or_else(|e| panic!("{}", e));
+            if verify(&vk, &proof, &public).unwrap_or_else(|e| panic!("{}", e)) {
+                println!("Proof verified");
+            } else {
+                println!("Proof did not verify");
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -11,7 +11,7 @@ type AllocId = usize;
 struct Alloc {
     id: AllocId,
     addr_width: usize,
-    val_width: usize,
+    val_sort: Sort,
     cur_ver: usize,
     size: usize,
     cur_var: Term,
@@ -31,26 +31,26 @@ impl Alloc {
     fn sort(&self) -> Sort {
         Sort::Array(
             Box::new(Sort::BitVector(self.addr_width)),
-            Box::new(Sort::BitVector(self.val_width)),
+            Box::new(self.val_sort.clone()),
             self.size,
         )
     }
 
-    fn new(id: AllocId, addr_width: usize, val_width: usize, size: usize) -> Self {
+    fn new(id: AllocId, addr_width: usize, val_sort: Sort, size: usize) -> Self {
         Self {
             id,
             addr_width,
-            val_width,
-            size,
             cur_ver: 0,
             cur_var: leaf_term(Op::Var(
                 format!("mem_{}_v{}", id, 0),
                 Sort::Array(
                     Box::new(Sort::BitVector(addr_width)),
-                    Box::new(Sort::BitVector(val_width)),
+                    Box::new(val_sort.clone()),
                     size,
                 ),
             )),
+            val_sort,
+            size,
         }
     }
 
@@ -89,13 +89,14 @@ impl MemManager {
     }
 
     /// Allocate a new stack array, equal to `array`.
+    ///
+    /// The array must have bit-vector indices. Its elements may have any sort, including tuples
+    /// and arrays.
     pub fn allocate(&mut self, array: Term) -> AllocId {
         let s = check(&array);
-        if let Sort::Array(box Sort::BitVector(addr_width), box Sort::BitVector(val_width), size) =
-            s
-        {
+        if let Sort::Array(box Sort::BitVector(addr_width), box val_sort, size) = s {
             let id = self.take_next_id();
-            let alloc = Alloc::new(id, addr_width, val_width, size);
+            let alloc = Alloc::new(id, addr_width, val_sort, size);
             let v = alloc.var().clone();
             if let Op::Var(n, _) = &v.op {
                 self.cs.borrow_mut().eval_and_save(&n, &array);
@@ -147,7 +148,7 @@ impl MemManager {
     pub fn store(&mut self, id: AllocId, offset: Term, val: Term) {
         let alloc = self.allocs.get_mut(&id).expect("Missing allocation");
         assert_eq!(alloc.addr_width, check(&offset).as_bv());
-        assert_eq!(alloc.val_width, check(&val).as_bv());
+        assert_eq!(alloc.val_sort, check(&val));
         let new = term![Op::Store; alloc.var().clone(), offset, val];
         alloc.next_var();
         let v = alloc.var().clone();
@@ -211,4 +212,19 @@ mod test {
         );
         assert!(!check_sat(&sys))
     }
+
+    #[test]
+    fn nested_sorts() {
+        let cs = Rc::new(RefCell::new(Computation::new(false)));
+        let mut mem = MemManager::new(cs.clone());
+        let pair = term![Op::Tuple; leaf_term(Op::Const(Value::Bool(false))), bv_lit(0, 8)];
+        let pairs = term![Op::ConstArray(Sort::BitVector(4), 6); pair.clone()];
+        let id0 = mem.allocate(pairs.clone());
+        let id1 = mem.allocate(term![Op::ConstArray(Sort::BitVector(4), 2); pairs]);
+        mem.store(id0, bv_var("i", 4), pair.clone());
+        assert_eq!(check(&mem.load(id0, bv_lit(3, 4))), check(&pair));
+        let row = mem.load(id1, bv_lit(1, 4));
+        let row_sort = Sort::Array(Box::new(Sort::BitVector(4)), Box::new(check(&pair)), 6);
+        assert_eq!(check(&row), row_sort);
+    }
 }
@@ -13,12 +13,27 @@ use std::collections::HashMap;
 use std::fmt::{self, Display, Formatter};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::Arc;
 use zokrates_pest_ast as ast;
 
 use term::*;
 
-/// The modulus for the ZoKrates language.
-pub use term::ZOKRATES_MODULUS;
+#[allow(deprecated)]
+pub use default_modulus::ZOKRATES_MODULUS;
+
+#[allow(deprecated)]
+mod default_modulus {
+    use crate::ir::term::Curve;
+    use lazy_static::lazy_static;
+    use rug::Integer;
+
+    lazy_static! {
+        /// The modulus for ZoKrates, when no curve is chosen: BLS12-381's scalar field.
+        #[deprecated(note = "the modulus depends on the curve; use `Curve::modulus`, or the \
+                             `field` in the computation's metadata")]
+        pub static ref ZOKRATES_MODULUS: Integer = Curve::Bls12_381.modulus();
+    }
+}
 
 const PROVER_VIS: Option<PartyId> = Some(proof::PROVER_ID);
 const PUBLIC_VIS: Option<PartyId> = None;
@@ -40,6 +55,9 @@ pub struct Inputs {
     pub inputs: Option<PathBuf>,
     /// The mode to generate for (MPC or proof). Effects visibility.
     pub mode: Mode,
+    /// The curve whose scalar field to compile for. If missing, uses the one named by the
+    /// `#pragma curve` of `file`, or else the default curve.
+    pub curve: Option<Curve>,
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -72,7 +90,9 @@ impl FrontEnd for Zokrates {
     fn gen(i: Inputs) -> Computation {
         let loader = parser::ZLoad::new();
         let asts = loader.load(&i.file);
-        let mut g = ZGen::new(i.inputs, asts, i.mode);
+        let curve = choose_curve(&asts, &i.file, i.curve);
+        debug!("Compiling for {}", curve);
+        let mut g = ZGen::new(i.inputs, asts, i.mode, Arc::new(curve.modulus()));
         g.visit_files();
         g.file_stack.push(i.file);
         g.entry_fn("main");
@@ -81,6 +101,27 @@ impl FrontEnd for Zokrates {
     }
 }
 
+/// The curve to compile `file` for: `requested`, if given, or else the one named by the file's
+/// pragma, or else the default.
+fn choose_curve(
+    asts: &HashMap<PathBuf, ast::File>,
+    file: &Path,
+    requested: Option<Curve>,
+) -> Curve {
+    let pragma = asts.get(file).and_then(|f| f.pragma.as_ref()).map(|p| {
+        Curve::from_str(&p.curve.name).unwrap_or_else(|e| panic!("In {}: {}", file.display(), e))
+    });
+    match (requested, pragma) {
+        (Some(r), Some(p)) if r != p => panic!(
+            "{} is for curve {}, but compiling for {}",
+            file.display(),
+            p,
+            r
+        ),
+        (r, p) => r.or(p).unwrap_or_default(),
+    }
+}
+
 struct ZGen<'ast> {
     circ: Circify<ZoKrates>,
     stdlib: parser::ZStdLib,
@@ -89,6 +130,7 @@ struct ZGen<'ast> {
     functions: HashMap<(PathBuf, String), ast::Function<'ast>>,
     import_map: HashMap<(PathBuf, String), (PathBuf, String)>,
     mode: Mode,
+    modulus: Arc<Integer>,
 }
 
 enum ZLoc {
@@ -108,22 +150,30 @@ impl ZLoc {
 }
 
 impl<'ast> ZGen<'ast> {
-    fn new(inputs: Option<PathBuf>, asts: HashMap<PathBuf, ast::File<'ast>>, mode: Mode) -> Self {
+    fn new(
+        inputs: Option<PathBuf>,
+        asts: HashMap<PathBuf, ast::File<'ast>>,
+        mode: Mode,
+        modulus: Arc<Integer>,
+    ) -> Self {
         let this = Self {
-            circ: Circify::new(ZoKrates::new(inputs.map(|i| parser::parse_inputs(i)))),
+            circ: Circify::new(ZoKrates::new(
+                inputs.map(|i| parser::parse_inputs(i)),
+                modulus.clone(),
+            )),
             asts,
             stdlib: parser::ZStdLib::new(),
             file_stack: vec![],
             functions: HashMap::new(),
             import_map: HashMap::new(),
             mode,
+            modulus,
         };
-        this.circ
-            .cir_ctx()
-            .cs
-            .borrow_mut()
-            .metadata
-            .add_prover_and_verifier();
+        {
+            let mut cs = this.circ.cir_ctx().cs.borrow_mut();
+            cs.metadata.add_prover_and_verifier();
+            cs.metadata.field = Some(this.modulus.clone());
+        }
         this
     }
 
@@ -183,9 +233,10 @@ impl<'ast> ZGen<'ast> {
                 self.unwrap(decl_res, &i.index.span);
                 for j in s..e {
                     self.circ.enter_scope();
-                    let ass_res = self
-                        .circ
-                        .assign(Loc::local(v_name.clone()), Val::Term(T::Field(pf_lit(j))));
+                    let ass_res = self.circ.assign(
+                        Loc::local(v_name.clone()),
+                        Val::Term(T::Field(pf_lit(j, &self.modulus))),
+                    );
                     self.unwrap(ass_res, &i.index.span);
                     for s in &i.statements {
                         self.stmt(s);
@@ -287,7 +338,8 @@ impl<'ast> ZGen<'ast> {
                 bv_lit(u32::from_str_radix(&u.value[2..], 16).unwrap(), 32),
             ),
             ast::ConstantExpression::DecimalNumber(u) => {
-                T::Field(pf_lit(Integer::from_str_radix(&u.value, 10).unwrap()))
+                let i = Integer::from_str_radix(&u.value, 10).unwrap();
+                T::Field(pf_lit(i, &self.modulus))
             }
             ast::ConstantExpression::BooleanLiteral(u) => {
                 Self::const_bool(bool::from_str(&u.value).unwrap())
@@ -654,3 +706,43 @@ impl<'ast> ZGen<'ast> {
         self.asts = t;
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BN128_FILE: &str = "examples/ZoKrates/pf/bn128_square.zok";
+
+    fn field(file: &str, curve: Option<Curve>) -> Option<Arc<Integer>> {
+        let inputs = Inputs {
+            file: PathBuf::from(file),
+            inputs: None,
+            mode: Mode::Proof,
+            curve,
+        };
+        Zokrates::gen(inputs).metadata.field
+    }
+
+    #[test]
+    fn curve_pragma() {
+        let bn128 = Some(Arc::new(Curve::Bn128.modulus()));
+        assert_eq!(field(BN128_FILE, None), bn128);
+        assert_eq!(field(BN128_FILE, Some(Curve::Bn128)), bn128);
+        assert_eq!(
+            field("examples/ZoKrates/opt/mult_opt.zok", None),
+            Some(Arc::new(Curve::default().modulus()))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "is for curve bn128, but compiling for bls12_381")]
+    fn curve_conflict() {
+        field(BN128_FILE, Some(Curve::Bls12_381));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn default_modulus() {
+        assert_eq!(*ZOKRATES_MODULUS, Curve::Bls12_381.modulus());
+    }
+}
@@ -3,24 +3,11 @@ use std::collections::{BTreeMap, HashMap};
 use std::fmt::{self, Display, Formatter};
 use std::sync::Arc;
 
-use lazy_static::lazy_static;
 use rug::Integer;
 
 use crate::circify::{CirCtx, Embeddable};
 use crate::ir::term::*;
 
-lazy_static! {
-    // TODO: handle this better
-    /// The modulus for ZoKrates.
-    pub static ref ZOKRATES_MODULUS: Integer = Integer::from_str_radix(
-        "52435875175126190479447740508185965837690552500527637822603658699938581184513",
-        10
-    )
-    .unwrap();
-    /// The modulus for ZoKrates, as an ARC
-    pub static ref ZOKRATES_MODULUS_ARC: Arc<Integer> = Arc::new(ZOKRATES_MODULUS.clone());
-}
-
 #[derive(Clone, PartialEq, Eq)]
 pub enum Ty {
     Uint(usize),
@@ -49,16 +36,16 @@ impl fmt::Debug for Ty {
 }
 
 impl Ty {
-    fn default(&self) -> T {
+    fn default(&self, modulus: &Arc<Integer>) -> T {
         match self {
             Self::Bool => T::Bool(leaf_term(Op::Const(Value::Bool(false)))),
             Self::Uint(w) => T::Uint(*w, bv_lit(0, *w)),
-            Self::Field => T::Field(pf_lit(0)),
-            Self::Array(n, b) => T::Array((**b).clone(), vec![b.default(); *n]),
+            Self::Field => T::Field(pf_lit(0, modulus)),
+            Self::Array(n, b) => T::Array((**b).clone(), vec![b.default(modulus); *n]),
             Self::Struct(n, fs) => T::Struct(
                 n.clone(),
                 fs.iter()
-                    .map(|(f_name, f_ty)| (f_name.to_owned(), f_ty.default()))
+                    .map(|(f_name, f_ty)| (f_name.to_owned(), f_ty.default(modulus)))
                     .collect(),
             ),
         }
@@ -421,13 +408,13 @@ pub fn cond(c: T, a: T, b: T) -> Result<T, String> {
     ite(bool(c)?, a, b)
 }
 
-pub fn pf_lit<I>(i: I) -> Term
+pub fn pf_lit<I>(i: I, modulus: &Arc<Integer>) -> Term
 where
     Integer: From<I>,
 {
     leaf_term(Op::Const(Value::Field(FieldElem::new(
         Integer::from(i),
-        ZOKRATES_MODULUS_ARC.clone(),
+        modulus.clone(),
     ))))
 }
 
@@ -468,12 +455,13 @@ pub fn field_store(struct_: T, field: &str, val: T) -> Result<T, String> {
 pub fn array_select(array: T, idx: T) -> Result<T, String> {
     match (array, idx) {
         (T::Array(_, list), T::Field(idx)) => {
+            let modulus = check(&idx).as_pf();
             let mut it = list.into_iter().enumerate();
             let first = it
                 .next()
                 .ok_or_else(|| format!("Cannot index empty array"))?;
             it.fold(Ok(first.1), |acc, (i, elem)| {
-                ite(term![Op::Eq; pf_lit(i), idx.clone()], elem, acc?)
+                ite(term![Op::Eq; pf_lit(i, &modulus), idx.clone()], elem, acc?)
             })
         }
         (a, b) => Err(format!("Cannot index {} by {}", b, a)),
@@ -482,13 +470,22 @@ pub fn array_select(array: T, idx: T) -> Result<T, String> {
 
 pub fn array_store(array: T, idx: T, val: T) -> Result<T, String> {
     match (array, idx) {
-        (T::Array(ty, list), T::Field(idx)) => Ok(T::Array(
-            ty,
-            list.into_iter()
-                .enumerate()
-                .map(|(i, elem)| ite(term![Op::Eq; pf_lit(i), idx.clone()], val.clone(), elem))
-                .collect::<Result<Vec<_>, _>>()?,
-        )),
+        (T::Array(ty, list), T::Field(idx)) => {
+            let modulus = check(&idx).as_pf();
+            Ok(T::Array(
+                ty,
+                list.into_iter()
+                    .enumerate()
+                    .map(|(i, elem)| {
+                        ite(
+                            term![Op::Eq; pf_lit(i, &modulus), idx.clone()],
+                            val.clone(),
+                            elem,
+                        )
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ))
+        }
         (a, b) => Err(format!("Cannot index {} by {}", b, a)),
     }
 }
@@ -566,11 +563,8 @@ fn idx_name(struct_name: &str, idx: usize) -> String {
 }
 
 impl ZoKrates {
-    pub fn new(values: Option<HashMap<String, Integer>>) -> Self {
-        Self {
-            values,
-            modulus: ZOKRATES_MODULUS_ARC.clone(),
-        }
+    pub fn new(values: Option<HashMap<String, Integer>>, modulus: Arc<Integer>) -> Self {
+        Self { values, modulus }
     }
 }
 
@@ -732,6 +726,6 @@ impl Embeddable for ZoKrates {
     }
 
     fn initialize_return(&self, ty: &Self::Ty, _ssa_name: &String) -> Self::T {
-        ty.default()
+        ty.default(&self.modulus)
     }
 }
@@ -0,0 +1,431 @@
+//! Cost models for backends
+//!
+//! A [CostModel] estimates what a term will cost once lowered to some backend, without lowering
+//! it. Passes use them to choose between equivalent terms, and [cost_report] breaks the cost of a
+//! [Computation] down by output and by operator.
+//!
+//! The models are:
+//!
+//!    * [R1csCost]: rank-1 constraints
+//!    * [AbyBoolCost]: AND gates in ABY's Boolean sharing
+//!    * [AbyArithCost]: multiplications in ABY's arithmetic sharing
+//!    * [IlpCost]: variables plus constraints in the ILP encoding
+//!    * [SmtCost]: SMT term size
+
+use crate::ir::term::*;
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+
+/// An estimate of what terms cost on some backend.
+pub trait CostModel {
+    /// The name of the model, for reports.
+    fn name(&self) -> &str;
+
+    /// The cost of choosing between two values of sort `s`.
+    fn mux_cost(&self, s: &Sort) -> usize;
+
+    /// The cost of the top-most operator in `t` (not counting its children).
+    fn op_cost(&self, t: &Term) -> usize;
+
+    /// The cost of `t`, counting shared subterms once.
+    fn cost(&self, t: &Term) -> usize {
+        self.cost_all(std::slice::from_ref(t))
+    }
+
+    /// The cost of all of `ts`, counting subterms shared between them once.
+    fn cost_all(&self, ts: &[Term]) -> usize {
+        let mut seen = TermSet::new();
+        let mut cost = 0;
+        for t in ts {
+            for s in PostOrderIter::new(t.clone()) {
+                if seen.insert(s.clone()) {
+                    cost += self.op_cost(&s);
+                }
+            }
+        }
+        cost
+    }
+}
+
+fn log2_ceil(w: usize) -> usize {
+    (usize::BITS - w.saturating_sub(1).leading_zeros()) as usize
+}
+
+/// The sum of `scalar` over the scalars in `s`.
+fn sum_scalars(s: &Sort, scalar: &impl Fn(&Sort) -> usize) -> usize {
+    match s {
+        Sort::Tuple(ss) => ss.iter().map(|s| sum_scalars(s, scalar)).sum(),
+        Sort::Array(_, v, size) => size * sum_scalars(v, scalar),
+        s => scalar(s),
+    }
+}
+
+fn width(t: &Term) -> usize {
+    match check(t) {
+        Sort::BitVector(w) => w,
+        _ => 1,
+    }
+}
+
+fn child_width(t: &Term) -> usize {
+    width(&t.cs[0])
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Rank-1 constraint systems. Cost is (roughly) the number of constraints.
+pub struct R1csCost;
+
+impl CostModel for R1csCost {
+    fn name(&self) -> &str {
+        "r1cs"
+    }
+
+    fn mux_cost(&self, s: &Sort) -> usize {
+        sum_scalars(s, &|_| 1)
+    }
+
+    fn op_cost(&self, t: &Term) -> usize {
+        let n = t.cs.len();
+        match &t.op {
+            Op::Var(..) | Op::Const(_) => 0,
+            Op::Ite => self.mux_cost(&check(t)),
+            Op::Not
+            | Op::BvBit(_)
+            | Op::BoolToBv
+            | Op::BvExtract(..)
+            | Op::BvConcat
+            | Op::BvUext(_)
+            | Op::BvSext(_)
+            | Op::BvUnOp(BvUnOp::Not)
+            | Op::Tuple
+            | Op::Field(_)
+            | Op::PfUnOp(PfUnOp::Neg)
+            | Op::PfNaryOp(PfNaryOp::Add) => 0,
+            Op::BoolNaryOp(_) => n.saturating_sub(1).max(1),
+            Op::Implies => 1,
+            Op::BoolMaj => 2,
+            Op::Eq => match check(&t.cs[0]) {
+                Sort::Bool => 1,
+                _ => 2,
+            },
+            Op::BvNaryOp(BvNaryOp::Add) | Op::BvNaryOp(BvNaryOp::Mul) => width(t) + n,
+            Op::BvNaryOp(_) => width(t) * n.saturating_sub(1),
+            Op::BvBinOp(BvBinOp::Sub) => width(t) + 1,
+            Op::BvBinOp(BvBinOp::Udiv) | Op::BvBinOp(BvBinOp::Urem) => 4 * width(t),
+            Op::BvBinOp(_) => width(t) * log2_ceil(width(t)).max(1),
+            Op::BvBinPred(_) => child_width(t) + 1,
+            Op::BvUnOp(BvUnOp::Neg) => 2,
+            Op::PfToBv(w) => *w,
+            Op::PfNaryOp(PfNaryOp::Mul) => n.saturating_sub(1),
+            Op::PfUnOp(PfUnOp::Recip) => 2,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// ABY's Boolean sharing. Cost is (roughly) the number of AND gates; XOR and NOT gates are free.
+pub struct AbyBoolCost;
+
+impl CostModel for AbyBoolCost {
+    fn name(&self) -> &str {
+        "aby-bool"
+    }
+
+    fn mux_cost(&self, s: &Sort) -> usize {
+        sum_scalars(s, &|s| match s {
+            Sort::BitVector(w) => *w,
+            _ => 1,
+        })
+    }
+
+    fn op_cost(&self, t: &Term) -> usize {
+        let n = t.cs.len();
+        match &t.op {
+            Op::Var(..) | Op::Const(_) => 0,
+            Op::Ite => self.mux_cost(&check(t)),
+            Op::BoolNaryOp(BoolNaryOp::Xor) | Op::BvNaryOp(BvNaryOp::Xor) => 0,
+            Op::BoolNaryOp(_) => n.saturating_sub(1),
+            Op::Implies | Op::BoolMaj => 1,
+            Op::Eq => match check(&t.cs[0]) {
+                Sort::Bool => 0,
+                _ => child_width(t).saturating_sub(1),
+            },
+            Op::BvNaryOp(BvNaryOp::Mul) => width(t) * width(t) * n.saturating_sub(1),
+            Op::BvNaryOp(_) => width(t) * n.saturating_sub(1),
+            Op::BvBinOp(BvBinOp::Sub) => width(t),
+            Op::BvBinOp(BvBinOp::Udiv) | Op::BvBinOp(BvBinOp::Urem) => 2 * width(t) * width(t),
+            Op::BvBinOp(_) => width(t) * log2_ceil(width(t)).max(1),
+            Op::BvBinPred(_) => child_width(t),
+            Op::BvUnOp(BvUnOp::Neg) => width(t),
+            Op::Not
+            | Op::BvBit(_)
+            | Op::BoolToBv
+            | Op::BvExtract(..)
+            | Op::BvConcat
+            | Op::BvUext(_)
+            | Op::BvSext(_)
+            | Op::BvUnOp(BvUnOp::Not)
+            | Op::Tuple
+            | Op::Field(_) => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// ABY's arithmetic sharing. Cost is the number of multiplications; additions are free.
+///
+/// Bits are 0/1 integers, so `a & b` is `a * b` and `a ^ b` is `a + b - 2 * a * b`. Operations
+/// which arithmetic sharing cannot compute (comparisons, bitwise operations on bit-vectors,
+/// shifts, division) cost nothing here: they need Boolean sharing, and [AbyBoolCost] counts them.
+pub struct AbyArithCost;
+
+impl CostModel for AbyArithCost {
+    fn name(&self) -> &str {
+        "aby-arith"
+    }
+
+    fn mux_cost(&self, s: &Sort) -> usize {
+        sum_scalars(s, &|_| 1)
+    }
+
+    fn op_cost(&self, t: &Term) -> usize {
+        let n = t.cs.len();
+        match &t.op {
+            Op::Ite => self.mux_cost(&check(t)),
+            Op::BoolNaryOp(_) | Op::BvNaryOp(BvNaryOp::Mul) | Op::PfNaryOp(PfNaryOp::Mul) => {
+                n.saturating_sub(1)
+            }
+            Op::Implies => 1,
+            Op::BoolMaj => 2,
+            Op::Eq if check(&t.cs[0]) == Sort::Bool => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// Integer linear programs. Cost is (roughly) the number of variables plus the number of
+/// constraints in the encoding of [crate::target::ilp].
+pub struct IlpCost;
+
+impl IlpCost {
+    /// A fresh bit `r` with `r <= x_i` and `sum x_i - r <= n - 1`.
+    fn and(n: usize) -> usize {
+        n + 2
+    }
+
+    /// A bit decomposition of a sum of `n` bits.
+    fn xor(n: usize) -> usize {
+        log2_ceil(n + 1) + 1
+    }
+
+    /// A fresh bit with two big-M constraints.
+    fn lt() -> usize {
+        3
+    }
+}
+
+impl CostModel for IlpCost {
+    fn name(&self) -> &str {
+        "ilp"
+    }
+
+    fn mux_cost(&self, s: &Sort) -> usize {
+        sum_scalars(s, &|s| match s {
+            // Three ANDs, one of them an OR
+            Sort::Bool => 3 * Self::and(2),
+            // A fresh variable with four big-M constraints
+            _ => 5,
+        })
+    }
+
+    fn op_cost(&self, t: &Term) -> usize {
+        let n = t.cs.len();
+        match &t.op {
+            Op::Var(..) => 1,
+            Op::Ite => self.mux_cost(&check(t)),
+            Op::BoolNaryOp(BoolNaryOp::Xor) => Self::xor(n),
+            Op::BoolNaryOp(_) => Self::and(n),
+            Op::Implies => Self::and(2),
+            Op::Eq => match check(&t.cs[0]) {
+                Sort::Bool => Self::xor(2),
+                _ => 2 * Self::lt() + Self::and(2),
+            },
+            Op::BvBinPred(_) => Self::lt(),
+            Op::BvNaryOp(BvNaryOp::Add) | Op::BvBinOp(BvBinOp::Sub) | Op::BvUnOp(BvUnOp::Neg) => {
+                3
+            }
+            // Per product: decompose one factor, mux the other by each bit, and add
+            Op::BvNaryOp(BvNaryOp::Mul) => n * (width(t) + 1 + 5 * width(t) + 3),
+            // Decompose each argument, then combine each bit
+            Op::BvNaryOp(o) => {
+                let w = width(t);
+                let bit = match o {
+                    BvNaryOp::Xor => Self::xor(n),
+                    _ => Self::and(n),
+                };
+                n * (w + 1) + w * bit
+            }
+            Op::BvExtract(..) | Op::BvSext(_) => child_width(t) + 1,
+            Op::Not
+            | Op::Const(_)
+            | Op::BoolToBv
+            | Op::BvConcat
+            | Op::BvUext(_)
+            | Op::BvUnOp(BvUnOp::Not) => 0,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+/// SMT. Cost is the number of distinct terms.
+pub struct SmtCost;
+
+impl CostModel for SmtCost {
+    fn name(&self) -> &str {
+        "smt"
+    }
+
+    fn mux_cost(&self, _s: &Sort) -> usize {
+        1
+    }
+
+    fn op_cost(&self, _t: &Term) -> usize {
+        1
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+/// The cost of one kind of operator.
+pub struct OpCost {
+    /// Distinct terms with this operator
+    pub count: usize,
+    /// Their total cost
+    pub cost: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// A breakdown of the cost of a computation. See [cost_report].
+pub struct CostReport {
+    /// The name of the cost model
+    pub model: String,
+    /// The cost of the whole computation, counting shared terms once
+    pub total: usize,
+    /// The cost of each output on its own, in order
+    pub outputs: Vec<usize>,
+    /// Costs by operator name. Variables are "var" and constants are "const".
+    pub ops: BTreeMap<String, OpCost>,
+}
+
+impl Display for CostReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "{} cost: {}", self.model, self.total)?;
+        let mut ops: Vec<_> = self.ops.iter().filter(|(_, c)| c.cost > 0).collect();
+        ops.sort_by(|a, b| b.1.cost.cmp(&a.1.cost).then(a.0.cmp(b.0)));
+        for (op, c) in ops {
+            writeln!(f, "  {}: {} ({} terms)", op, c.cost, c.count)?;
+        }
+        Ok(())
+    }
+}
+
+fn op_name(op: &Op) -> String {
+    match op {
+        Op::Var(..) => "var".to_owned(),
+        Op::Const(_) => "const".to_owned(),
+        // Drop parameters, like the indices of `extract`
+        op => format!("{}", op)
+            .split(' ')
+            .next()
+            .unwrap_or_default()
+            .to_owned(),
+    }
+}
+
+/// Break down the cost of `cs` under `model`, by output and by operator.
+pub fn cost_report(cs: &Computation, model: &dyn CostModel) -> CostReport {
+    let mut ops = BTreeMap::<String, OpCost>::new();
+    let mut seen = TermSet::new();
+    let mut total = 0;
+    for o in &cs.outputs {
+        for t in PostOrderIter::new(o.clone()) {
+            if seen.insert(t.clone()) {
+                let cost = model.op_cost(&t);
+                let entry = ops.entry(op_name(&t.op)).or_default();
+                entry.count += 1;
+                entry.cost += cost;
+                total += cost;
+            }
+        }
+    }
+    CostReport {
+        model: model.name().to_owned(),
+        total,
+        outputs: cs.outputs.iter().map(|o| model.cost(o)).collect(),
+        ops,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bv_var(s: &str, w: usize) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn bool_var(s: &str) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::Bool))
+    }
+
+    #[test]
+    fn models_disagree() {
+        let (x, y) = (bv_var("x", 8), bv_var("y", 8));
+        let prod = term![BV_MUL; x.clone(), y.clone()];
+        assert_eq!(R1csCost.cost(&prod), 10);
+        assert_eq!(AbyBoolCost.cost(&prod), 64);
+        assert_eq!(AbyArithCost.cost(&prod), 1);
+        assert_eq!(SmtCost.cost(&prod), 3);
+        let xor = term![BV_XOR; x, y];
+        assert_eq!(AbyBoolCost.cost(&xor), 0);
+        assert_eq!(R1csCost.cost(&xor), 8);
+        assert!(IlpCost.cost(&xor) > IlpCost.cost(&term![BV_ADD; bv_var("x", 8), bv_var("y", 8)]));
+    }
+
+    #[test]
+    fn shared_terms_counted_once() {
+        let (a, b, c) = (bool_var("a"), bool_var("b"), bool_var("c"));
+        let ab = term![AND; a, b];
+        let t = term![OR; ab.clone(), term![AND; ab.clone(), c]];
+        // Two ANDs and an OR, though `ab` appears twice
+        assert_eq!(AbyBoolCost.cost(&t), 3);
+        assert_eq!(SmtCost.cost(&t), 6);
+    }
+
+    #[test]
+    fn report() {
+        let (a, b, c) = (bool_var("a"), bool_var("b"), bool_var("c"));
+        let ab = term![AND; a, b];
+        let abc = term![AND; ab.clone(), c];
+        let x = bv_var("x", 4);
+        let cs = Computation {
+            outputs: vec![ab, abc, term![BV_ULT; x, bv_lit(3, 4)]],
+            metadata: ComputationMetadata::default(),
+            values: None,
+        };
+        let r = cost_report(&cs, &AbyBoolCost);
+        assert_eq!(r.model, "aby-bool");
+        assert_eq!(r.total, 6);
+        assert_eq!(r.outputs, vec![1, 2, 4]);
+        assert_eq!(r.ops["and"], OpCost { count: 2, cost: 2 });
+        assert_eq!(r.ops["var"], OpCost { count: 4, cost: 0 });
+        assert_eq!(r.ops["const"], OpCost { count: 1, cost: 0 });
+        assert_eq!(r.ops["bvult"].cost, 4);
+        let r = cost_report(&cs, &R1csCost);
+        assert_eq!(r.total, 7);
+    }
+}
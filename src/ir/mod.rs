@@ -2,5 +2,7 @@
 
 #[macro_use]
 pub mod term;
+pub mod cost;
 pub mod opt;
 pub mod proof;
+pub mod slice;
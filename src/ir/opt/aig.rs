@@ -0,0 +1,654 @@
+//! And-inverter graph minimization, for MPC
+//!
+//! In Yao's garbled circuits and in GMW, AND gates cost communication, while XOR and NOT gates are
+//! free. This pass moves the Boolean parts of a computation into an XOR-AND-inverter graph (an
+//! AIG, with XORs as well), reduces the number of ANDs in it, and moves them back.
+//!
+//!    * The translation into the graph already uses one-AND forms: `(ite c t f)` is
+//!      `f ^ (c & (t ^ f))` and `(maj a b c)` is `a ^ ((a ^ b) & (a ^ c))`. ORs become ANDs by De
+//!      Morgan's laws.
+//!    * Structural hashing merges identical gates, and simple rules fold constants, repeated
+//!      inputs, and complemented inputs.
+//!    * Cut-based resynthesis: for each gate, we enumerate cuts of at most three inputs. If the
+//!      function of a cut can be computed with fewer ANDs than the gate's fanout-free cone uses
+//!      (e.g., `(a & b) ^ (a & c)` is `a & (b ^ c)`), we replace the cone. Resynthesis repeats
+//!      until the number of ANDs stops falling.
+//!
+//! Boolean terms built from other operators (variables, bits, comparisons, ...) are inputs to the
+//! graph.
+
+use super::ite::CostTarget;
+use crate::ir::term::*;
+
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use std::fmt::{self, Display, Formatter};
+
+/// The most rounds of resynthesis.
+const MAX_ROUNDS: usize = 8;
+
+/// The most cuts kept per gate.
+const MAX_CUTS: usize = 12;
+
+/// The truth tables of the three cut inputs.
+const VARS: [u8; 3] = [0xAA, 0xCC, 0xF0];
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Debug)]
+/// A possibly-complemented reference to a node.
+struct Lit(u32);
+
+impl Lit {
+    fn new(node: usize, neg: bool) -> Self {
+        Lit(((node as u32) << 1) | neg as u32)
+    }
+    fn node(self) -> usize {
+        (self.0 >> 1) as usize
+    }
+    fn is_neg(self) -> bool {
+        self.0 & 1 == 1
+    }
+    fn regular(self) -> Self {
+        Lit(self.0 & !1)
+    }
+    fn neg_if(self, neg: bool) -> Self {
+        Lit(self.0 ^ neg as u32)
+    }
+}
+
+impl std::ops::Not for Lit {
+    type Output = Lit;
+    fn not(self) -> Lit {
+        Lit(self.0 ^ 1)
+    }
+}
+
+const FALSE: Lit = Lit(0);
+const TRUE: Lit = Lit(1);
+
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+enum Node {
+    False,
+    Input(Term),
+    And(Lit, Lit),
+    Xor(Lit, Lit),
+}
+
+/// An XOR-AND-inverter graph. Nodes are in topological order.
+struct Xag {
+    nodes: Vec<Node>,
+    /// Structural hashing
+    ids: AHashMap<Node, usize>,
+}
+
+impl Xag {
+    fn new() -> Self {
+        let mut ids = AHashMap::new();
+        ids.insert(Node::False, 0);
+        Self {
+            nodes: vec![Node::False],
+            ids,
+        }
+    }
+
+    fn node(&mut self, n: Node) -> Lit {
+        let next = self.nodes.len();
+        let id = *self.ids.entry(n.clone()).or_insert(next);
+        if id == next {
+            self.nodes.push(n);
+        }
+        Lit::new(id, false)
+    }
+
+    fn input(&mut self, t: Term) -> Lit {
+        self.node(Node::Input(t))
+    }
+
+    fn and(&mut self, a: Lit, b: Lit) -> Lit {
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        if a == FALSE || a == !b {
+            FALSE
+        } else if a == TRUE || a == b {
+            b
+        } else {
+            self.node(Node::And(a, b))
+        }
+    }
+
+    fn xor(&mut self, a: Lit, b: Lit) -> Lit {
+        // XOR nodes have regular inputs
+        let neg = a.is_neg() ^ b.is_neg();
+        let (a, b) = (a.regular(), b.regular());
+        let (a, b) = if a <= b { (a, b) } else { (b, a) };
+        let x = if a == b {
+            FALSE
+        } else if a == FALSE {
+            b
+        } else {
+            self.node(Node::Xor(a, b))
+        };
+        x.neg_if(neg)
+    }
+
+    fn or(&mut self, a: Lit, b: Lit) -> Lit {
+        !self.and(!a, !b)
+    }
+
+    fn ite(&mut self, c: Lit, t: Lit, f: Lit) -> Lit {
+        let d = self.xor(t, f);
+        let cd = self.and(c, d);
+        self.xor(f, cd)
+    }
+
+    fn maj(&mut self, a: Lit, b: Lit, c: Lit) -> Lit {
+        let ab = self.xor(a, b);
+        let ac = self.xor(a, c);
+        let m = self.and(ab, ac);
+        self.xor(a, m)
+    }
+
+    fn children(&self, n: usize) -> Vec<Lit> {
+        match &self.nodes[n] {
+            Node::And(a, b) | Node::Xor(a, b) => vec![*a, *b],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Which nodes are reachable from `roots`.
+    fn reachable(&self, roots: &[Lit]) -> Vec<bool> {
+        let mut seen = vec![false; self.nodes.len()];
+        let mut stack: Vec<usize> = roots.iter().map(|l| l.node()).collect();
+        while let Some(n) = stack.pop() {
+            if !seen[n] {
+                seen[n] = true;
+                stack.extend(self.children(n).into_iter().map(Lit::node));
+            }
+        }
+        seen
+    }
+
+    /// The number of ANDs reachable from `roots`.
+    fn and_count(&self, roots: &[Lit]) -> usize {
+        self.reachable(roots)
+            .into_iter()
+            .enumerate()
+            .filter(|(n, r)| *r && matches!(self.nodes[*n], Node::And(..)))
+            .count()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// An affine function of the three cut inputs: the XOR of the inputs in `mask`, complemented if
+/// `neg`.
+struct Affine {
+    mask: u8,
+    neg: bool,
+}
+
+impl Affine {
+    fn table(&self) -> u8 {
+        let t = (0..3)
+            .filter(|i| self.mask & (1 << i) != 0)
+            .fold(0, |t, i| t ^ VARS[i]);
+        if self.neg {
+            !t
+        } else {
+            t
+        }
+    }
+
+    fn build(&self, g: &mut Xag, inputs: &[Lit; 3]) -> Lit {
+        (0..3)
+            .filter(|i| self.mask & (1 << i) != 0)
+            .fold(FALSE, |acc, i| g.xor(acc, inputs[i]))
+            .neg_if(self.neg)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+/// A cheapest implementation of a three-input function.
+enum Impl {
+    Affine(Affine),
+    /// `l0 ^ (l1 & l2)`
+    OneAnd(Affine, Affine, Affine),
+}
+
+impl Impl {
+    fn ands(&self) -> usize {
+        match self {
+            Impl::Affine(_) => 0,
+            Impl::OneAnd(..) => 1,
+        }
+    }
+
+    fn build(&self, g: &mut Xag, inputs: &[Lit; 3]) -> Lit {
+        match self {
+            Impl::Affine(l) => l.build(g, inputs),
+            Impl::OneAnd(l0, l1, l2) => {
+                let l0 = l0.build(g, inputs);
+                let l1 = l1.build(g, inputs);
+                let l2 = l2.build(g, inputs);
+                let a = g.and(l1, l2);
+                g.xor(l0, a)
+            }
+        }
+    }
+}
+
+/// Implementations of all three-input functions with multiplicative complexity at most one.
+fn implementations() -> Vec<Option<Impl>> {
+    let affines: Vec<Affine> = (0..8)
+        .flat_map(|mask| vec![Affine { mask, neg: false }, Affine { mask, neg: true }])
+        .collect();
+    let mut impls = vec![None; 256];
+    for l in &affines {
+        impls[l.table() as usize] = Some(Impl::Affine(*l));
+    }
+    for l0 in &affines {
+        for l1 in &affines {
+            for l2 in &affines {
+                let t = l0.table() ^ (l1.table() & l2.table());
+                if impls[t as usize].is_none() {
+                    impls[t as usize] = Some(Impl::OneAnd(*l0, *l1, *l2));
+                }
+            }
+        }
+    }
+    impls
+}
+
+/// Enumerate cuts of at most three nodes for the reachable nodes of `g`.
+fn cuts(g: &Xag, reachable: &[bool]) -> Vec<Vec<Vec<usize>>> {
+    let mut cuts: Vec<Vec<Vec<usize>>> = vec![Vec::new(); g.nodes.len()];
+    for n in 0..g.nodes.len() {
+        if !reachable[n] {
+            continue;
+        }
+        cuts[n] = match &g.nodes[n] {
+            Node::False => vec![Vec::new()],
+            Node::Input(_) => vec![vec![n]],
+            Node::And(a, b) | Node::Xor(a, b) => {
+                let mut cs = vec![vec![n]];
+                'outer: for ca in &cuts[a.node()] {
+                    for cb in &cuts[b.node()] {
+                        let mut c = ca.clone();
+                        c.extend(cb.iter().cloned());
+                        c.sort_unstable();
+                        c.dedup();
+                        if c.len() <= 3 && !cs.contains(&c) {
+                            cs.push(c);
+                            if cs.len() == MAX_CUTS {
+                                break 'outer;
+                            }
+                        }
+                    }
+                }
+                cs
+            }
+        };
+    }
+    cuts
+}
+
+/// The truth table of `n` over the nodes `leaves`, which must form a cut for `n`.
+fn table(g: &Xag, n: usize, leaves: &[usize], memo: &mut AHashMap<usize, u8>) -> u8 {
+    if let Some(i) = leaves.iter().position(|l| *l == n) {
+        return VARS[i];
+    }
+    if let Some(t) = memo.get(&n) {
+        return *t;
+    }
+    let lit_table = |l: Lit, memo: &mut AHashMap<usize, u8>| {
+        let t = table(g, l.node(), leaves, memo);
+        if l.is_neg() {
+            !t
+        } else {
+            t
+        }
+    };
+    let t = match &g.nodes[n] {
+        Node::False => 0,
+        Node::And(a, b) => lit_table(*a, memo) & lit_table(*b, memo),
+        Node::Xor(a, b) => lit_table(*a, memo) ^ lit_table(*b, memo),
+        Node::Input(_) => unreachable!("cut does not cover input {}", n),
+    };
+    memo.insert(n, t);
+    t
+}
+
+/// The number of ANDs which would die if `n` were computed directly from `leaves`: those in the
+/// cone between them which are used only in that cone.
+fn fanout_free_ands(g: &Xag, n: usize, leaves: &[usize], refs: &mut [usize]) -> usize {
+    let mut count = 0;
+    let mut derefed = Vec::new();
+    let mut stack = vec![n];
+    while let Some(m) = stack.pop() {
+        if matches!(g.nodes[m], Node::And(..)) {
+            count += 1;
+        }
+        for c in g.children(m) {
+            let c = c.node();
+            if !leaves.contains(&c) && matches!(g.nodes[c], Node::And(..) | Node::Xor(..)) {
+                refs[c] -= 1;
+                derefed.push(c);
+                if refs[c] == 0 {
+                    stack.push(c);
+                }
+            }
+        }
+    }
+    for c in derefed {
+        refs[c] += 1;
+    }
+    count
+}
+
+/// Rebuild `g` from `roots`, resynthesizing cuts. Returns the new graph and roots.
+fn resynthesize(g: &Xag, roots: &[Lit], impls: &[Option<Impl>]) -> (Xag, Vec<Lit>) {
+    let reachable = g.reachable(roots);
+    let mut refs = vec![0; g.nodes.len()];
+    for n in (0..g.nodes.len()).filter(|n| reachable[*n]) {
+        for c in g.children(n) {
+            refs[c.node()] += 1;
+        }
+    }
+    for r in roots {
+        refs[r.node()] += 1;
+    }
+    let cuts = cuts(g, &reachable);
+    let mut new = Xag::new();
+    let mut map: Vec<Lit> = vec![FALSE; g.nodes.len()];
+    let map_lit = |map: &Vec<Lit>, l: Lit| map[l.node()].neg_if(l.is_neg());
+    for n in (0..g.nodes.len()).filter(|n| reachable[*n]) {
+        map[n] = match &g.nodes[n] {
+            Node::False => FALSE,
+            Node::Input(t) => new.input(t.clone()),
+            Node::And(a, b) | Node::Xor(a, b) => {
+                let mut best: Option<(usize, &Vec<usize>, Impl)> = None;
+                for cut in cuts[n].iter().skip(1) {
+                    let mut memo = AHashMap::new();
+                    let t = table(g, n, cut, &mut memo);
+                    if let Some(i) = impls[t as usize] {
+                        let saved = fanout_free_ands(g, n, cut, &mut refs);
+                        if saved > i.ands() && best.map_or(true, |b| saved - i.ands() > b.0) {
+                            best = Some((saved - i.ands(), cut, i));
+                        }
+                    }
+                }
+                match best {
+                    Some((_, cut, i)) => {
+                        let mut inputs = [FALSE; 3];
+                        for (j, l) in cut.iter().enumerate() {
+                            inputs[j] = map[*l];
+                        }
+                        i.build(&mut new, &inputs)
+                    }
+                    None => {
+                        let (a, b) = (map_lit(&map, *a), map_lit(&map, *b));
+                        if matches!(g.nodes[n], Node::And(..)) {
+                            new.and(a, b)
+                        } else {
+                            new.xor(a, b)
+                        }
+                    }
+                }
+            }
+        };
+    }
+    let roots = roots.iter().map(|r| map_lit(&map, *r)).collect();
+    (new, roots)
+}
+
+fn is_bool_op(t: &Term) -> bool {
+    match &t.op {
+        Op::Const(Value::Bool(_)) | Op::Not | Op::BoolNaryOp(_) | Op::Implies | Op::BoolMaj => true,
+        Op::Ite | Op::Eq => check(&t.cs[1]) == Sort::Bool,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// The AND gates in the Boolean parts of a computation, before and after [minimize_ands].
+pub struct AndCountReport {
+    /// ANDs before, according to the ABY cost model
+    pub before: usize,
+    /// ANDs after
+    pub after: usize,
+}
+
+impl Display for AndCountReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{} ANDs before, {} after", self.before, self.after)
+    }
+}
+
+/// Minimize the number of ANDs in the Boolean parts of `outputs`. See module documentation.
+pub fn minimize_ands(outputs: &mut [Term]) -> AndCountReport {
+    let mut g = Xag::new();
+    let mut lits = TermMap::<Lit>::new();
+    let mut roots = TermMap::<usize>::new();
+    let mut root_lits = Vec::new();
+    let mut report = AndCountReport::default();
+    let mut seen = TermSet::new();
+    let mut order = Vec::new();
+    for o in outputs.iter() {
+        for t in PostOrderIter::new(o.clone()) {
+            if !seen.insert(t.clone()) {
+                continue;
+            }
+            order.push(t.clone());
+            if is_bool_op(&t) {
+                report.before += CostTarget::Aby.op_cost(&t);
+                let cs: Vec<Lit> =
+                    t.cs.iter()
+                        .map(|c| match lits.get(c) {
+                            Some(l) => *l,
+                            None => g.input(c.clone()),
+                        })
+                        .collect();
+                let l = match &t.op {
+                    Op::Const(Value::Bool(b)) => FALSE.neg_if(*b),
+                    Op::Not => !cs[0],
+                    Op::BoolNaryOp(o) => {
+                        let mut cs = cs.into_iter();
+                        let first = cs.next().unwrap();
+                        cs.fold(first, |acc, c| match o {
+                            BoolNaryOp::And => g.and(acc, c),
+                            BoolNaryOp::Or => g.or(acc, c),
+                            BoolNaryOp::Xor => g.xor(acc, c),
+                        })
+                    }
+                    Op::Implies => g.or(!cs[0], cs[1]),
+                    Op::BoolMaj => g.maj(cs[0], cs[1], cs[2]),
+                    Op::Ite => g.ite(cs[0], cs[1], cs[2]),
+                    Op::Eq => !g.xor(cs[0], cs[1]),
+                    _ => unreachable!(),
+                };
+                lits.insert(t, l);
+            } else {
+                for c in &t.cs {
+                    if let Some(l) = lits.get(c) {
+                        if !roots.contains_key(c) {
+                            roots.insert(c.clone(), root_lits.len());
+                            root_lits.push(*l);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(l) = lits.get(o) {
+            if !roots.contains_key(o) {
+                roots.insert(o.clone(), root_lits.len());
+                root_lits.push(*l);
+            }
+        }
+    }
+
+    let mut ands = g.and_count(&root_lits);
+    debug!("Initial AIG: {} ANDs", ands);
+    let impls = implementations();
+    for _ in 0..MAX_ROUNDS {
+        let (new_g, new_roots) = resynthesize(&g, &root_lits, &impls);
+        let new_ands = new_g.and_count(&new_roots);
+        if new_ands >= ands {
+            break;
+        }
+        debug!("Resynthesized AIG: {} ANDs", new_ands);
+        g = new_g;
+        root_lits = new_roots;
+        ands = new_ands;
+    }
+    report.after = ands;
+
+    // Move back to terms
+    let mut cache = TermMap::<Term>::new();
+    let mut node_terms: Vec<Option<Term>> = vec![None; g.nodes.len()];
+    for t in order {
+        let new_t = if let Some(i) = roots.get(&t) {
+            let root = root_lits[*i];
+            // Convert the nodes under `root` which have not been converted yet, in order
+            let mut needed = AHashSet::new();
+            let mut stack = vec![root.node()];
+            while let Some(n) = stack.pop() {
+                if node_terms[n].is_none() && needed.insert(n) {
+                    stack.extend(g.children(n).into_iter().map(Lit::node));
+                }
+            }
+            let mut needed: Vec<usize> = needed.into_iter().collect();
+            needed.sort_unstable();
+            for n in needed {
+                let lit_term = |l: &Lit| {
+                    let t = node_terms[l.node()].clone().unwrap();
+                    if l.is_neg() {
+                        term![NOT; t]
+                    } else {
+                        t
+                    }
+                };
+                node_terms[n] = Some(match &g.nodes[n] {
+                    Node::False => leaf_term(Op::Const(Value::Bool(false))),
+                    Node::Input(i) => cache.get(i).unwrap().clone(),
+                    Node::And(a, b) => term![AND; lit_term(a), lit_term(b)],
+                    Node::Xor(a, b) => term![XOR; lit_term(a), lit_term(b)],
+                });
+            }
+            let t = node_terms[root.node()].clone().unwrap();
+            if root == TRUE {
+                leaf_term(Op::Const(Value::Bool(true)))
+            } else if root.is_neg() {
+                term![NOT; t]
+            } else {
+                t
+            }
+        } else if lits.contains_key(&t) {
+            // Only used inside the graph
+            continue;
+        } else {
+            term(
+                t.op.clone(),
+                t.cs.iter().map(|c| cache.get(c).unwrap().clone()).collect(),
+            )
+        };
+        cache.insert(t, new_t);
+    }
+    for o in outputs.iter_mut() {
+        *o = cache.get(o).unwrap().clone();
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::dist::test::*;
+    use quickcheck_macros::quickcheck;
+
+    fn bool_var(s: &str) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::Bool))
+    }
+
+    fn minimize(t: &Term) -> (Term, AndCountReport) {
+        let mut outputs = vec![t.clone()];
+        let report = minimize_ands(&mut outputs);
+        (outputs.pop().unwrap(), report)
+    }
+
+    fn ands(t: &Term) -> usize {
+        PostOrderIter::new(t.clone())
+            .map(|c| CostTarget::Aby.op_cost(&c))
+            .sum()
+    }
+
+    /// Check that `t` and `u`, over Boolean variables `vars`, agree everywhere.
+    fn equivalent(t: &Term, u: &Term, vars: &[&str]) {
+        for a in 0..(1 << vars.len()) {
+            let env: AHashMap<String, Value> = vars
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (v.to_string(), Value::Bool((a >> i) & 1 == 1)))
+                .collect();
+            assert_eq!(eval(t, &env), eval(u, &env));
+        }
+    }
+
+    #[test]
+    fn majority() {
+        let (a, b, c) = (bool_var("a"), bool_var("b"), bool_var("c"));
+        let t = term![OR;
+            term![AND; a.clone(), b.clone()],
+            term![AND; a.clone(), c.clone()],
+            term![AND; b, c]];
+        let (tt, report) = minimize(&t);
+        assert_eq!(
+            report,
+            AndCountReport {
+                before: 5,
+                after: 1
+            }
+        );
+        assert_eq!(ands(&tt), 1);
+        equivalent(&t, &tt, &["a", "b", "c"]);
+    }
+
+    #[test]
+    fn distribute() {
+        let (a, b, c) = (bool_var("a"), bool_var("b"), bool_var("c"));
+        let t = term![XOR; term![AND; a.clone(), b], term![AND; a, c]];
+        let (tt, report) = minimize(&t);
+        assert_eq!(report.after, 1);
+        assert_eq!(ands(&tt), 1);
+        equivalent(&t, &tt, &["a", "b", "c"]);
+    }
+
+    #[test]
+    fn structural_hashing() {
+        let (a, b) = (bool_var("a"), bool_var("b"));
+        let t = term![XOR; term![AND; a.clone(), b.clone()], term![NOT; term![AND; b, a]]];
+        let (tt, report) = minimize(&t);
+        assert_eq!(report.after, 0);
+        assert_eq!(tt, leaf_term(Op::Const(Value::Bool(true))));
+    }
+
+    #[test]
+    fn inputs_kept() {
+        let x = leaf_term(Op::Var("x".to_owned(), Sort::BitVector(4)));
+        let lt = term![BV_ULT; x.clone(), bv_lit(3, 4)];
+        let a = bool_var("a");
+        let t = term![Op::Ite; term![OR; lt.clone(), a.clone()], x.clone(), bv_lit(0, 4)];
+        let (tt, _) = minimize(&t);
+        assert_eq!(tt.op, Op::Ite);
+        assert_eq!(tt.cs[1], x);
+        assert!(PostOrderIter::new(tt).any(|c| c == lt));
+    }
+
+    #[quickcheck]
+    fn semantic_random_bool(ArbitraryBoolEnv(t, vs): ArbitraryBoolEnv) -> bool {
+        let (tt, report) = minimize(&t);
+        report.after <= report.before && eval(&t, &vs) == eval(&tt, &vs)
+    }
+
+    #[quickcheck]
+    fn semantic_random(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let (tt, _) = minimize(&t);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+}
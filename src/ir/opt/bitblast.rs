@@ -0,0 +1,626 @@
+//! Bit-blasting
+//!
+//! Rewrites bit-vector terms into networks of Boolean operators over the bits of bit-vector
+//! variables (`BvBit`s), for backends which need pure Boolean circuits.
+//!
+//!    * Bitwise operators, extensions, extracts, and concatenations just rearrange bits.
+//!    * Addition, subtraction, and negation use an [Adder] of the chosen architecture.
+//!    * Multiplication is shift-and-add.
+//!    * Comparisons read the carry out of a subtraction.
+//!    * Division and remainder use restoring long division.
+//!    * Shifts use a barrel shifter.
+//!
+//! Operators which cannot be blasted (e.g., `PfToBv`) are kept, and their bits are taken with
+//! `BvBit`. Bit-vectors which are needed as words (e.g., outputs) are re-assembled with
+//! `BvConcat` and `BoolToBv`.
+//!
+//! The Boolean builders fold constants as they go, so blasting, e.g., a multiplication by a
+//! constant only produces partial products for its set bits.
+
+use crate::ir::term::*;
+
+use log::debug;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The architecture used for adders.
+pub enum Adder {
+    /// A chain of full adders: `w` majority gates, but depth `w`.
+    RippleCarry,
+    /// The Kogge-Stone parallel-prefix adder: depth `log w`, but about `w log w` gates.
+    KoggeStone,
+}
+
+fn bool_lit(b: bool) -> Term {
+    leaf_term(Op::Const(Value::Bool(b)))
+}
+
+fn as_bool_lit(t: &Term) -> Option<bool> {
+    match &t.op {
+        Op::Const(Value::Bool(b)) => Some(*b),
+        _ => None,
+    }
+}
+
+fn not(a: &Term) -> Term {
+    match as_bool_lit(a) {
+        Some(b) => bool_lit(!b),
+        None if a.op == NOT => a.cs[0].clone(),
+        None => term![NOT; a.clone()],
+    }
+}
+
+fn and(a: &Term, b: &Term) -> Term {
+    match (as_bool_lit(a), as_bool_lit(b)) {
+        (Some(false), _) | (_, Some(false)) => bool_lit(false),
+        (Some(true), _) => b.clone(),
+        (_, Some(true)) => a.clone(),
+        _ if a == b => a.clone(),
+        _ => term![AND; a.clone(), b.clone()],
+    }
+}
+
+fn or(a: &Term, b: &Term) -> Term {
+    match (as_bool_lit(a), as_bool_lit(b)) {
+        (Some(true), _) | (_, Some(true)) => bool_lit(true),
+        (Some(false), _) => b.clone(),
+        (_, Some(false)) => a.clone(),
+        _ if a == b => a.clone(),
+        _ => term![OR; a.clone(), b.clone()],
+    }
+}
+
+fn xor(a: &Term, b: &Term) -> Term {
+    match (as_bool_lit(a), as_bool_lit(b)) {
+        (Some(x), _) => {
+            if x {
+                not(b)
+            } else {
+                b.clone()
+            }
+        }
+        (_, Some(y)) => {
+            if y {
+                not(a)
+            } else {
+                a.clone()
+            }
+        }
+        _ if a == b => bool_lit(false),
+        _ => term![XOR; a.clone(), b.clone()],
+    }
+}
+
+fn ite(c: &Term, t: &Term, f: &Term) -> Term {
+    match (as_bool_lit(c), as_bool_lit(t), as_bool_lit(f)) {
+        (Some(c), _, _) => {
+            if c {
+                t.clone()
+            } else {
+                f.clone()
+            }
+        }
+        _ if t == f => t.clone(),
+        (_, Some(true), _) => or(c, f),
+        (_, Some(false), _) => and(&not(c), f),
+        (_, _, Some(true)) => or(&not(c), t),
+        (_, _, Some(false)) => and(c, t),
+        _ => term![ITE; c.clone(), t.clone(), f.clone()],
+    }
+}
+
+fn maj(a: &Term, b: &Term, c: &Term) -> Term {
+    match (as_bool_lit(a), as_bool_lit(b), as_bool_lit(c)) {
+        (Some(x), _, _) => {
+            if x {
+                or(b, c)
+            } else {
+                and(b, c)
+            }
+        }
+        (_, Some(y), _) => {
+            if y {
+                or(a, c)
+            } else {
+                and(a, c)
+            }
+        }
+        (_, _, Some(z)) => {
+            if z {
+                or(a, b)
+            } else {
+                and(a, b)
+            }
+        }
+        _ => term![Op::BoolMaj; a.clone(), b.clone(), c.clone()],
+    }
+}
+
+/// The conjunction of `xs`, as one n-ary AND.
+fn and_all(xs: impl IntoIterator<Item = Term>) -> Term {
+    let mut cs = Vec::new();
+    for x in xs {
+        match as_bool_lit(&x) {
+            Some(false) => return bool_lit(false),
+            Some(true) => {}
+            None => cs.push(x),
+        }
+    }
+    match cs.len() {
+        0 => bool_lit(true),
+        1 => cs.pop().unwrap(),
+        _ => term(AND, cs),
+    }
+}
+
+/// Pick `t` or `f`, bitwise, on `c`.
+fn ite_bits(c: &Term, t: &[Term], f: &[Term]) -> Vec<Term> {
+    t.iter().zip(f).map(|(t, f)| ite(c, t, f)).collect()
+}
+
+fn not_bits(a: &[Term]) -> Vec<Term> {
+    a.iter().map(not).collect()
+}
+
+fn zeros(w: usize) -> Vec<Term> {
+    vec![bool_lit(false); w]
+}
+
+/// Assemble the word (LSB first) `bits`.
+fn word(bits: &[Term]) -> Term {
+    // Avoid re-assembling an unchanged bit-vector
+    if let Some(first) = bits.first() {
+        if let Op::BvBit(0) = &first.op {
+            let x = &first.cs[0];
+            if check(x) == Sort::BitVector(bits.len())
+                && bits
+                    .iter()
+                    .enumerate()
+                    .all(|(i, b)| b.op == Op::BvBit(i) && &b.cs[0] == x)
+            {
+                return x.clone();
+            }
+        }
+    }
+    term(
+        BV_CONCAT,
+        bits.iter()
+            .rev()
+            .map(|b| term![BOOL_TO_BV; b.clone()])
+            .collect(),
+    )
+}
+
+/// The bits of `t`, which must be a bit-vector, LSB first.
+fn bits_of(t: &Term) -> Vec<Term> {
+    (0..check(t).as_bv())
+        .map(|i| term![Op::BvBit(i); t.clone()])
+        .collect()
+}
+
+struct BitBlaster {
+    adder: Adder,
+    /// The bits of (original) bit-vector terms, LSB first
+    bits: TermMap<Vec<Term>>,
+    /// The rewrites of (original) other terms
+    cache: TermMap<Term>,
+}
+
+impl BitBlaster {
+    fn new(adder: Adder) -> Self {
+        Self {
+            adder,
+            bits: TermMap::new(),
+            cache: TermMap::new(),
+        }
+    }
+
+    fn get_bits(&self, t: &Term) -> &Vec<Term> {
+        self.bits.get(t).expect("missing bits")
+    }
+
+    /// The rewritten version of `t`, as a single term.
+    fn get(&self, t: &Term) -> Term {
+        match self.bits.get(t) {
+            Some(bits) => word(bits),
+            None => self.cache.get(t).expect("missing rewrite").clone(),
+        }
+    }
+
+    /// Returns the sum of `a`, `b` and `carry_in`, and the carry out.
+    fn add(&self, a: &[Term], b: &[Term], carry_in: Term) -> (Vec<Term>, Term) {
+        match self.adder {
+            Adder::RippleCarry => {
+                let mut c = carry_in;
+                let mut sum = Vec::new();
+                for (a, b) in a.iter().zip(b) {
+                    sum.push(xor(&xor(a, b), &c));
+                    c = maj(a, b, &c);
+                }
+                (sum, c)
+            }
+            Adder::KoggeStone => {
+                // Generate and propagate signals, with the carry in as an extra, lowest position.
+                let prop: Vec<Term> = a.iter().zip(b).map(|(a, b)| xor(a, b)).collect();
+                let mut g: Vec<Term> = std::iter::once(carry_in)
+                    .chain(a.iter().zip(b).map(|(a, b)| and(a, b)))
+                    .collect();
+                let mut p: Vec<Term> = std::iter::once(bool_lit(false))
+                    .chain(prop.iter().cloned())
+                    .collect();
+                let mut d = 1;
+                while d < g.len() {
+                    // Descending, so that position `i - d` still holds this level's inputs
+                    for i in (d..g.len()).rev() {
+                        g[i] = or(&g[i], &and(&p[i], &g[i - d]));
+                        p[i] = and(&p[i], &p[i - d]);
+                    }
+                    d *= 2;
+                }
+                // g[i] is now the carry into bit i
+                let sum = prop.iter().zip(&g).map(|(p, c)| xor(p, c)).collect();
+                (sum, g.pop().unwrap())
+            }
+        }
+    }
+
+    fn sub(&self, a: &[Term], b: &[Term]) -> Vec<Term> {
+        self.add(a, &not_bits(b), bool_lit(true)).0
+    }
+
+    /// Shift-and-add multiplication, modulo `2^w`.
+    fn mul(&self, a: &[Term], b: &[Term]) -> Vec<Term> {
+        let w = a.len();
+        let mut acc = zeros(w);
+        for (i, b_i) in b.iter().enumerate() {
+            let partial: Vec<Term> = (0..w)
+                .map(|j| {
+                    if j < i {
+                        bool_lit(false)
+                    } else {
+                        and(&a[j - i], b_i)
+                    }
+                })
+                .collect();
+            if partial.iter().any(|p| as_bool_lit(p) != Some(false)) {
+                acc = self.add(&acc, &partial, bool_lit(false)).0;
+            }
+        }
+        acc
+    }
+
+    /// Whether `a < b`, as unsigned numbers.
+    fn ult(&self, a: &[Term], b: &[Term]) -> Term {
+        // a - b borrows iff a < b.
+        not(&self.add(a, &not_bits(b), bool_lit(true)).1)
+    }
+
+    /// Restoring division. Returns the quotient and remainder.
+    ///
+    /// Division by zero gives a quotient of all ones, and a remainder equal to `a`.
+    fn div_rem(&self, a: &[Term], b: &[Term]) -> (Vec<Term>, Vec<Term>) {
+        let w = a.len();
+        let mut b_ext = b.to_vec();
+        b_ext.push(bool_lit(false));
+        let not_b = not_bits(&b_ext);
+        // One extra bit, since the shifted remainder may be as large as 2b - 1.
+        let mut r = zeros(w + 1);
+        let mut q = zeros(w);
+        for i in (0..w).rev() {
+            r.pop();
+            r.insert(0, a[i].clone());
+            let (diff, no_borrow) = self.add(&r, &not_b, bool_lit(true));
+            r = ite_bits(&no_borrow, &diff, &r);
+            q[i] = no_borrow;
+        }
+        r.pop();
+        (q, r)
+    }
+
+    /// Barrel shifter. Shifts `a` left (or right, if `right`) by `b`, filling with `fill`.
+    fn shift(&self, a: &[Term], b: &[Term], right: bool, fill: &Term) -> Vec<Term> {
+        let w = a.len();
+        let mut res = a.to_vec();
+        let mut overflow = bool_lit(false);
+        for (k, b_k) in b.iter().enumerate() {
+            if k >= (usize::BITS - 1) as usize || (1 << k) >= w {
+                overflow = or(&overflow, b_k);
+                continue;
+            }
+            let s = 1 << k;
+            let shifted: Vec<Term> = (0..w)
+                .map(|j| {
+                    if right {
+                        res.get(j + s).unwrap_or(fill).clone()
+                    } else if j >= s {
+                        res[j - s].clone()
+                    } else {
+                        fill.clone()
+                    }
+                })
+                .collect();
+            res = ite_bits(b_k, &shifted, &res);
+        }
+        ite_bits(&overflow, &vec![fill.clone(); w], &res)
+    }
+
+    fn compare(&self, pred: &BvBinPred, a: &[Term], b: &[Term]) -> Term {
+        let flip_sign = |x: &[Term]| {
+            let mut x = x.to_vec();
+            let msb = x.pop().unwrap();
+            x.push(not(&msb));
+            x
+        };
+        let (a, b) = match pred {
+            BvBinPred::Slt | BvBinPred::Sgt | BvBinPred::Sle | BvBinPred::Sge => {
+                (flip_sign(a), flip_sign(b))
+            }
+            _ => (a.to_vec(), b.to_vec()),
+        };
+        match pred {
+            BvBinPred::Ult | BvBinPred::Slt => self.ult(&a, &b),
+            BvBinPred::Ugt | BvBinPred::Sgt => self.ult(&b, &a),
+            BvBinPred::Ule | BvBinPred::Sle => not(&self.ult(&b, &a)),
+            BvBinPred::Uge | BvBinPred::Sge => not(&self.ult(&a, &b)),
+        }
+    }
+
+    /// Blast the bit-vector term `t`, whose children have been visited.
+    fn blast_bv(&self, t: &Term) -> Vec<Term> {
+        let arg = |i: usize| self.get_bits(&t.cs[i]);
+        let args = || t.cs.iter().map(|c| self.get_bits(c).clone());
+        let fold_bits = |f: &dyn Fn(&Term, &Term) -> Term| {
+            let mut args = args();
+            let first = args.next().unwrap();
+            args.fold(first, |acc, x| {
+                acc.iter().zip(&x).map(|(a, b)| f(a, b)).collect()
+            })
+        };
+        match &t.op {
+            Op::Const(Value::BitVector(bv)) => (0..bv.width())
+                .map(|i| bool_lit(bv.uint().get_bit(i as u32)))
+                .collect(),
+            Op::Var(..) => bits_of(t),
+            Op::Ite => ite_bits(&self.get(&t.cs[0]), arg(1), arg(2)),
+            Op::BvNaryOp(BvNaryOp::And) => fold_bits(&and),
+            Op::BvNaryOp(BvNaryOp::Or) => fold_bits(&or),
+            Op::BvNaryOp(BvNaryOp::Xor) => fold_bits(&xor),
+            Op::BvNaryOp(BvNaryOp::Add) => {
+                let mut args = args();
+                let first = args.next().unwrap();
+                args.fold(first, |acc, x| self.add(&acc, &x, bool_lit(false)).0)
+            }
+            Op::BvNaryOp(BvNaryOp::Mul) => {
+                let mut args = args();
+                let first = args.next().unwrap();
+                args.fold(first, |acc, x| self.mul(&acc, &x))
+            }
+            Op::BvUnOp(BvUnOp::Not) => not_bits(arg(0)),
+            Op::BvUnOp(BvUnOp::Neg) => self.sub(&zeros(arg(0).len()), arg(0)),
+            Op::BvBinOp(o) => {
+                let (a, b) = (arg(0), arg(1));
+                match o {
+                    BvBinOp::Sub => self.sub(a, b),
+                    BvBinOp::Udiv => self.div_rem(a, b).0,
+                    BvBinOp::Urem => self.div_rem(a, b).1,
+                    BvBinOp::Shl => self.shift(a, b, false, &bool_lit(false)),
+                    BvBinOp::Lshr => self.shift(a, b, true, &bool_lit(false)),
+                    BvBinOp::Ashr => self.shift(a, b, true, a.last().unwrap()),
+                }
+            }
+            Op::BvExtract(h, l) => arg(0)[*l..=*h].to_vec(),
+            // The first child holds the high bits
+            Op::BvConcat => {
+                t.cs.iter()
+                    .rev()
+                    .flat_map(|c| self.get_bits(c).clone())
+                    .collect()
+            }
+            Op::BvUext(n) => {
+                let mut bits = arg(0).clone();
+                bits.extend(zeros(*n));
+                bits
+            }
+            Op::BvSext(n) => {
+                let mut bits = arg(0).clone();
+                let msb = bits.last().unwrap().clone();
+                bits.extend(std::iter::repeat(msb).take(*n));
+                bits
+            }
+            Op::BoolToBv => vec![self.get(&t.cs[0])],
+            _ => bits_of(&self.rebuild(t)),
+        }
+    }
+
+    /// Blast the non-bit-vector term `t`, whose children have been visited.
+    fn blast_other(&self, t: &Term) -> Term {
+        match &t.op {
+            Op::Eq if self.bits.contains_key(&t.cs[0]) => and_all(
+                self.get_bits(&t.cs[0])
+                    .iter()
+                    .zip(self.get_bits(&t.cs[1]))
+                    .map(|(a, b)| not(&xor(a, b))),
+            ),
+            Op::BvBinPred(p) => self.compare(p, self.get_bits(&t.cs[0]), self.get_bits(&t.cs[1])),
+            Op::BvBit(i) => self.get_bits(&t.cs[0])[*i].clone(),
+            _ => self.rebuild(t),
+        }
+    }
+
+    /// `t`, over the rewritten children.
+    fn rebuild(&self, t: &Term) -> Term {
+        term(t.op.clone(), t.cs.iter().map(|c| self.get(c)).collect())
+    }
+
+    fn blast(&mut self, t: &Term) -> Term {
+        for c in PostOrderIter::new(t.clone()) {
+            if self.bits.contains_key(&c) || self.cache.contains_key(&c) {
+                continue;
+            }
+            if let Sort::BitVector(_) = check(&c) {
+                let bits = self.blast_bv(&c);
+                self.bits.insert(c, bits);
+            } else {
+                let new = self.blast_other(&c);
+                self.cache.insert(c, new);
+            }
+        }
+        self.get(t)
+    }
+}
+
+/// Bit-blast all bit-vector operations in `outputs`, using adders of architecture `adder`. See
+/// module documentation.
+pub fn bitblast(outputs: &mut [Term], adder: Adder) {
+    let mut blaster = BitBlaster::new(adder);
+    for o in outputs.iter_mut() {
+        *o = blaster.blast(o);
+    }
+    debug!("Bit-blasted {} bit-vector terms", blaster.bits.len());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::dist::test::*;
+    use ahash::AHashMap;
+    use quickcheck_macros::quickcheck;
+    use rug::Integer;
+
+    const W: usize = 3;
+
+    fn bv_var(s: &str) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::BitVector(W)))
+    }
+
+    fn blast(t: &Term, adder: Adder) -> Term {
+        let mut outputs = vec![t.clone()];
+        bitblast(&mut outputs, adder);
+        outputs.pop().unwrap()
+    }
+
+    /// Whether `t` is Boolean, except for the bits of variables.
+    fn is_boolean(t: &Term) -> bool {
+        PostOrderIter::new(t.clone()).all(|c| match check(&c) {
+            Sort::Bool => true,
+            Sort::BitVector(_) => matches!(c.op, Op::Var(..)),
+            _ => false,
+        })
+    }
+
+    /// Check `op` on all pairs of `W`-bit inputs, with both adders.
+    fn exhaustive(op: Op, skip: impl Fn(usize, usize) -> bool) {
+        let t = term(op, vec![bv_var("a"), bv_var("b")]);
+        let t = if check(&t) == Sort::Bool {
+            t
+        } else {
+            term![Op::Eq; t, bv_var("c")]
+        };
+        for adder in vec![Adder::RippleCarry, Adder::KoggeStone] {
+            let tt = blast(&t, adder);
+            assert!(is_boolean(&tt), "Not Boolean: {}", tt);
+            for a in 0..(1 << W) {
+                for b in 0..(1 << W) {
+                    if skip(a, b) {
+                        continue;
+                    }
+                    for c in 0..(1 << W) {
+                        let env: AHashMap<String, Value> = vec![("a", a), ("b", b), ("c", c)]
+                            .into_iter()
+                            .map(|(n, v)| {
+                                (
+                                    n.to_owned(),
+                                    Value::BitVector(BitVector::new(Integer::from(v), W)),
+                                )
+                            })
+                            .collect();
+                        assert_eq!(
+                            eval(&t, &env),
+                            eval(&tt, &env),
+                            "{} on {} {} {}",
+                            t,
+                            a,
+                            b,
+                            c
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn arith() {
+        exhaustive(BV_ADD, |_, _| false);
+        exhaustive(BV_SUB, |_, _| false);
+        exhaustive(BV_MUL, |_, _| false);
+        exhaustive(BV_UDIV, |_, _| false);
+        exhaustive(BV_UREM, |_, _| false);
+    }
+
+    #[test]
+    fn shifts() {
+        exhaustive(BV_SHL, |_, _| false);
+        exhaustive(BV_LSHR, |_, _| false);
+        // `eval` only supports arithmetic shifts by less than the width
+        exhaustive(BV_ASHR, |_, b| b >= W);
+    }
+
+    #[test]
+    fn comparisons() {
+        for p in vec![
+            BvBinPred::Ult,
+            BvBinPred::Ugt,
+            BvBinPred::Ule,
+            BvBinPred::Uge,
+            BvBinPred::Slt,
+            BvBinPred::Sgt,
+            BvBinPred::Sle,
+            BvBinPred::Sge,
+        ] {
+            exhaustive(Op::BvBinPred(p), |_, _| false);
+        }
+        exhaustive(Op::Eq, |_, _| false);
+    }
+
+    #[test]
+    fn kogge_stone_is_shallow() {
+        fn depth(t: &Term) -> usize {
+            let mut depths = TermMap::<usize>::new();
+            for c in PostOrderIter::new(t.clone()) {
+                let d = c.cs.iter().map(|c| depths[c] + 1).max().unwrap_or(0);
+                depths.insert(c, d);
+            }
+            depths[t]
+        }
+        let a = leaf_term(Op::Var("a".to_owned(), Sort::BitVector(64)));
+        let b = leaf_term(Op::Var("b".to_owned(), Sort::BitVector(64)));
+        let t = term![BV_ULT; a, b];
+        let ripple = depth(&blast(&t, Adder::RippleCarry));
+        let ks = depth(&blast(&t, Adder::KoggeStone));
+        assert!(ks * 2 < ripple, "{} vs {}", ks, ripple);
+    }
+
+    #[test]
+    fn constant_multiplier() {
+        let a = bv_var("a");
+        let t = term![BV_MUL; a.clone(), bv_lit(2, W)];
+        let tt = blast(&t, Adder::RippleCarry);
+        let expected = term![BV_CONCAT;
+            term![BOOL_TO_BV; term![Op::BvBit(1); a.clone()]],
+            term![BOOL_TO_BV; term![Op::BvBit(0); a]],
+            term![BOOL_TO_BV; bool_lit(false)]];
+        assert_eq!(tt, expected);
+    }
+
+    #[quickcheck]
+    fn semantic_random_ripple(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = blast(&t, Adder::RippleCarry);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+
+    #[quickcheck]
+    fn semantic_random_kogge_stone(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = blast(&t, Adder::KoggeStone);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+}
@@ -0,0 +1,434 @@
+//! Truth-table-based re-synthesis of small bitwise functions
+//!
+//! Hash functions are full of small Boolean functions, applied bitwise to a few words: SHA-2's CH
+//! and MAJ, Keccak's chi, MD5's F, G, H and I. Written in C-style `&`, `|`, `^` and `~`, they are
+//! often more expensive than necessary.
+//!
+//! This pass finds maximal fanout-free cones of bit-vector AND, OR, XOR and NOT with at most
+//! [MAX_INPUTS] distinct inputs, computes the truth table of each, and re-synthesizes the cheapest expression
+//! it can find (with ITE, majority, XOR, AND, OR and NOT). The rewrite happens only if it is
+//! cheaper than the original cone, according to the cost model of the target. A cone never
+//! extends through a term with more than one use: that term is computed anyway, so re-synthesizing
+//! through it would duplicate its logic rather than replace it.
+//!
+//! For R1CS, the new expression is built bit-by-bit, using `BoolMaj` and `Ite`. For ABY, it stays
+//! at the word level: `(ite c t f)` becomes `f ^ (c & (t ^ f))` and `(maj a b c)` becomes
+//! `a ^ ((a ^ b) & (a ^ c))`, each costing one AND per bit.
+//!
+//! The default pipelines still use the SHA-2 peepholes ([super::sha]); this pass runs only when
+//! asked for, as `bitwise-r1cs` or `bitwise-aby`.
+
+use super::ite::CostTarget;
+use crate::ir::term::extras::substitute_cache;
+use crate::ir::term::*;
+
+use ahash::AHashMap;
+use log::debug;
+use rug::Integer;
+
+/// The most distinct inputs a bitwise function may have to be re-synthesized.
+pub const MAX_INPUTS: usize = 4;
+
+/// A truth table over [MAX_INPUTS] inputs. Bit `a` is the output for the assignment `a`, in which
+/// input `j` is bit `j` of `a`.
+type Table = u16;
+
+const ALL: Table = 0xFFFF;
+const INPUT_TABLES: [Table; MAX_INPUTS] = [0xAAAA, 0xCCCC, 0xF0F0, 0xFF00];
+
+fn is_bitwise(t: &Term) -> bool {
+    matches!(t.op, BV_AND | BV_OR | BV_XOR | BV_NOT)
+}
+
+/// The Boolean variable standing for input `j` in a template.
+fn input(j: usize) -> Term {
+    leaf_term(Op::Var(format!("x{}", j), Sort::Bool))
+}
+
+fn bool_const(b: bool) -> Term {
+    leaf_term(Op::Const(Value::Bool(b)))
+}
+
+/// A cone of bitwise operators.
+struct Cone {
+    /// The distinct inputs to the cone
+    inputs: Vec<Term>,
+    /// A Boolean version of the cone, over the variables from [input]
+    template: Term,
+}
+
+/// Find the cone of bitwise operators rooted at `t`, unless it has more than [MAX_INPUTS] inputs.
+/// The cone stops at terms in `roots`, which are its inputs.
+fn cone(t: &Term, roots: &TermSet) -> Option<Cone> {
+    let mut inputs = Vec::new();
+    let mut templates = TermMap::new();
+    let mut stack = vec![(t.clone(), false)];
+    while let Some((c, children_done)) = stack.pop() {
+        if templates.contains_key(&c) {
+            continue;
+        }
+        if !is_bitwise(&c) || (&c != t && roots.contains(&c)) {
+            if inputs.len() == MAX_INPUTS {
+                return None;
+            }
+            templates.insert(c.clone(), input(inputs.len()));
+            inputs.push(c);
+        } else if children_done {
+            let op = match c.op {
+                BV_AND => AND,
+                BV_OR => OR,
+                BV_XOR => XOR,
+                _ => NOT,
+            };
+            let cs =
+                c.cs.iter()
+                    .map(|c| templates.get(c).unwrap().clone())
+                    .collect();
+            templates.insert(c, term(op, cs));
+        } else {
+            stack.push((c.clone(), true));
+            stack.extend(c.cs.iter().rev().map(|c| (c.clone(), false)));
+        }
+    }
+    Some(Cone {
+        inputs,
+        template: templates.remove(t).unwrap(),
+    })
+}
+
+/// The truth table of `template`.
+fn truth_table(template: &Term) -> Table {
+    (0..16).fold(0, |table, a| {
+        let env: AHashMap<String, Value> = (0..MAX_INPUTS)
+            .map(|j| (format!("x{}", j), Value::Bool((a >> j) & 1 == 1)))
+            .collect();
+        if eval(template, &env).as_bool() {
+            table | (1 << a)
+        } else {
+            table
+        }
+    })
+}
+
+/// The table of `f`, with input `j` fixed to `v`.
+fn cofactor(f: Table, j: usize, v: bool) -> Table {
+    (0..16).fold(0, |table, a| {
+        let a_fixed = if v { a | (1 << j) } else { a & !(1 << j) };
+        table | (((f >> a_fixed) & 1) << a)
+    })
+}
+
+/// Synthesizes cheap templates for truth tables.
+struct Synthesizer {
+    target: CostTarget,
+    best: AHashMap<Table, Term>,
+}
+
+impl Synthesizer {
+    fn new(target: CostTarget) -> Self {
+        Self {
+            target,
+            best: AHashMap::new(),
+        }
+    }
+
+    /// The cost of one bit of `template`.
+    fn cost(&self, template: &Term) -> usize {
+        PostOrderIter::new(template.clone())
+            .map(|t| self.target.op_cost(&t))
+            .sum()
+    }
+
+    /// Majorities of three (possibly negated) inputs which compute `f`.
+    fn majorities(&self, f: Table) -> Vec<Term> {
+        let mut out = Vec::new();
+        let lit = |j: usize, neg: bool| {
+            if neg {
+                (!INPUT_TABLES[j], term![NOT; input(j)])
+            } else {
+                (INPUT_TABLES[j], input(j))
+            }
+        };
+        for i in 0..MAX_INPUTS {
+            for j in i + 1..MAX_INPUTS {
+                for k in j + 1..MAX_INPUTS {
+                    for negs in 0..8 {
+                        let (a, a_t) = lit(i, negs & 1 != 0);
+                        let (b, b_t) = lit(j, negs & 2 != 0);
+                        let (c, c_t) = lit(k, negs & 4 != 0);
+                        if (a & b) | (b & c) | (c & a) == f {
+                            out.push(term![Op::BoolMaj; a_t, b_t, c_t]);
+                        }
+                    }
+                }
+            }
+        }
+        out
+    }
+
+    /// The cheapest template we can find for `f`.
+    fn synthesize(&mut self, f: Table) -> Term {
+        if let Some(t) = self.best.get(&f) {
+            return t.clone();
+        }
+        let t = if f == 0 || f == ALL {
+            bool_const(f == ALL)
+        } else if let Some(j) = INPUT_TABLES.iter().position(|t| *t == f) {
+            input(j)
+        } else if let Some(j) = INPUT_TABLES.iter().position(|t| !*t == f) {
+            term![NOT; input(j)]
+        } else {
+            let mut candidates = self.majorities(f);
+            for j in 0..MAX_INPUTS {
+                let f0 = cofactor(f, j, false);
+                let f1 = cofactor(f, j, true);
+                if f0 == f1 {
+                    continue;
+                }
+                let x = input(j);
+                candidates.push(if f1 == !f0 {
+                    term![XOR; x, self.synthesize(f0)]
+                } else if f0 == 0 {
+                    term![AND; x, self.synthesize(f1)]
+                } else if f1 == 0 {
+                    term![AND; term![NOT; x], self.synthesize(f0)]
+                } else if f1 == ALL {
+                    term![OR; x, self.synthesize(f0)]
+                } else if f0 == ALL {
+                    term![OR; term![NOT; x], self.synthesize(f1)]
+                } else {
+                    term![ITE; x, self.synthesize(f1), self.synthesize(f0)]
+                });
+            }
+            candidates
+                .into_iter()
+                .min_by_key(|t| self.cost(t))
+                .expect("no candidates for a non-constant function")
+        };
+        self.best.insert(f, t.clone());
+        t
+    }
+}
+
+/// Build `template` over `inputs` (bit-vectors of width `w`), bit-by-bit.
+fn lower_bits(template: &Term, inputs: &[Term], w: usize) -> Term {
+    term(
+        BV_CONCAT,
+        (0..w)
+            .rev()
+            .map(|i| {
+                let mut subs = TermMap::new();
+                for (j, x) in inputs.iter().enumerate() {
+                    subs.insert(input(j), term![Op::BvBit(i); x.clone()]);
+                }
+                term![BOOL_TO_BV; substitute_cache(template, &mut subs)]
+            })
+            .collect(),
+    )
+}
+
+/// Build `template` over `inputs` (bit-vectors of width `w`), with word-level operators.
+fn lower_words(template: &Term, inputs: &[Term], w: usize) -> Term {
+    let mut cache = TermMap::<Term>::new();
+    for t in PostOrderIter::new(template.clone()) {
+        let c = |i: usize| cache.get(&t.cs[i]).unwrap().clone();
+        let cs: Vec<Term> = t.cs.iter().map(|c| cache.get(c).unwrap().clone()).collect();
+        let new_t = match &t.op {
+            Op::Var(name, _) => inputs[name[1..].parse::<usize>().unwrap()].clone(),
+            Op::Const(Value::Bool(false)) => bv_lit(0, w),
+            Op::Const(Value::Bool(true)) => bv_lit((Integer::from(1) << w as u32) - 1, w),
+            &AND => term(BV_AND, cs),
+            &OR => term(BV_OR, cs),
+            &XOR => term(BV_XOR, cs),
+            &NOT => term(BV_NOT, cs),
+            // f ^ (c & (t ^ f))
+            &ITE => term![BV_XOR; c(2), term![BV_AND; c(0), term![BV_XOR; c(1), c(2)]]],
+            // a ^ ((a ^ b) & (a ^ c))
+            Op::BoolMaj => term![BV_XOR; c(0), term![BV_AND;
+                term![BV_XOR; c(0), c(1)],
+                term![BV_XOR; c(0), c(2)]]],
+            o => unreachable!("Unexpected operator {} in template", o),
+        };
+        cache.insert(t, new_t);
+    }
+    cache.remove(template).unwrap()
+}
+
+/// Re-synthesize small bitwise functions in `outputs` for `target`. See module documentation.
+pub fn resynthesize_bitwise(outputs: &mut [Term], target: CostTarget) {
+    // The roots of bitwise cones: bitwise terms used by non-bitwise terms, by nothing, or more
+    // than once.
+    let mut order = Vec::new();
+    let mut seen = TermSet::new();
+    let mut roots = TermSet::new();
+    let mut uses = TermMap::<usize>::new();
+    for o in outputs.iter() {
+        for t in PostOrderIter::new(o.clone()) {
+            if seen.insert(t.clone()) {
+                for c in t.cs.iter().filter(|c| is_bitwise(c)) {
+                    let n = uses.entry(c.clone()).or_insert(0);
+                    *n += 1;
+                    if !is_bitwise(&t) || *n > 1 {
+                        roots.insert(c.clone());
+                    }
+                }
+                order.push(t);
+            }
+        }
+        if is_bitwise(o) {
+            roots.insert(o.clone());
+        }
+    }
+
+    // Parents come before children in reverse post-order. When a root's cone is too large, its
+    // bitwise children become roots themselves.
+    let mut cones = TermMap::new();
+    for t in order.iter().rev() {
+        if roots.contains(t) {
+            match cone(t, &roots) {
+                Some(c) => {
+                    cones.insert(t.clone(), c);
+                }
+                None => roots.extend(t.cs.iter().filter(|c| is_bitwise(c)).cloned()),
+            }
+        }
+    }
+
+    let mut synth = Synthesizer::new(target);
+    let mut cache = TermMap::<Term>::new();
+    let mut rewrites = 0;
+    for t in order {
+        let rebuilt = term(
+            t.op.clone(),
+            t.cs.iter().map(|c| cache.get(c).unwrap().clone()).collect(),
+        );
+        let new_t = match cones.get(&t) {
+            Some(cone) => {
+                let new = synth.synthesize(truth_table(&cone.template));
+                if synth.cost(&new) < synth.cost(&cone.template) {
+                    rewrites += 1;
+                    let inputs: Vec<Term> = cone
+                        .inputs
+                        .iter()
+                        .map(|i| cache.get(i).unwrap().clone())
+                        .collect();
+                    let w = check(&t).as_bv();
+                    match target {
+                        CostTarget::R1cs => lower_bits(&new, &inputs, w),
+                        CostTarget::Aby => lower_words(&new, &inputs, w),
+                    }
+                } else {
+                    rebuilt
+                }
+            }
+            None => rebuilt,
+        };
+        cache.insert(t, new_t);
+    }
+    debug!("Re-synthesized {} bitwise functions", rewrites);
+    for o in outputs.iter_mut() {
+        *o = cache.get(o).unwrap().clone();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::dist::test::*;
+    use quickcheck_macros::quickcheck;
+
+    fn bv_var(s: &str, w: usize) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn resynth(t: Term, target: CostTarget) -> Term {
+        let mut outputs = vec![t];
+        resynthesize_bitwise(&mut outputs, target);
+        outputs.pop().unwrap()
+    }
+
+    #[test]
+    fn tables() {
+        let t =
+            term![OR; term![AND; input(0), input(1)], term![AND; term![NOT; input(0)], input(2)]];
+        assert_eq!(truth_table(&t), 0xE4E4);
+        assert_eq!(cofactor(0xE4E4, 0, true), 0xCCCC);
+        assert_eq!(cofactor(0xE4E4, 0, false), 0xF0F0);
+    }
+
+    #[test]
+    fn ch_r1cs() {
+        let (a, b, c) = (bv_var("a", 2), bv_var("b", 2), bv_var("c", 2));
+        let t = term![BV_OR; term![BV_AND; a.clone(), b.clone()], term![BV_AND; term![BV_NOT; a.clone()], c.clone()]];
+        let bit = |i: usize| {
+            term![BOOL_TO_BV; term![ITE; term![Op::BvBit(i); a.clone()],
+                                         term![Op::BvBit(i); b.clone()],
+                                         term![Op::BvBit(i); c.clone()]]]
+        };
+        assert_eq!(
+            term![BV_CONCAT; bit(1), bit(0)],
+            resynth(t, CostTarget::R1cs)
+        );
+    }
+
+    #[test]
+    fn maj_aby() {
+        let (a, b, c) = (bv_var("a", 8), bv_var("b", 8), bv_var("c", 8));
+        let t = term![BV_OR;
+            term![BV_AND; a.clone(), b.clone()],
+            term![BV_AND; a.clone(), c.clone()],
+            term![BV_AND; b.clone(), c.clone()]];
+        let tt = resynth(t, CostTarget::Aby);
+        let ands = PostOrderIter::new(tt)
+            .filter(|t| t.op == BV_AND || t.op == BV_OR)
+            .count();
+        assert_eq!(ands, 1);
+    }
+
+    #[test]
+    fn cheap_functions_untouched() {
+        let (a, b, c) = (bv_var("a", 8), bv_var("b", 8), bv_var("c", 8));
+        // Keccak's chi
+        let chi = term![BV_XOR; a.clone(), term![BV_AND; term![BV_NOT; b.clone()], c.clone()]];
+        assert_eq!(chi, resynth(chi.clone(), CostTarget::R1cs));
+        assert_eq!(chi, resynth(chi.clone(), CostTarget::Aby));
+        let x = term![BV_XOR; a, b];
+        assert_eq!(x, resynth(x.clone(), CostTarget::Aby));
+    }
+
+    #[test]
+    fn large_cone_split() {
+        let vs: Vec<Term> = (0..6).map(|i| bv_var(&format!("v{}", i), 4)).collect();
+        let ch = term![BV_OR; term![BV_AND; vs[0].clone(), vs[1].clone()], term![BV_AND; term![BV_NOT; vs[0].clone()], vs[2].clone()]];
+        let t = term![BV_XOR; ch, vs[3].clone(), vs[4].clone(), vs[5].clone()];
+        let tt = resynth(t.clone(), CostTarget::R1cs);
+        assert_ne!(t, tt);
+        assert_eq!(tt.op, BV_XOR);
+        assert_eq!(tt.cs[0].op, BV_CONCAT);
+    }
+
+    #[test]
+    fn shared_terms_are_inputs() {
+        let (a, b, c) = (bv_var("a", 4), bv_var("b", 4), bv_var("c", 4));
+        let d = bv_var("d", 4);
+        let ab = term![BV_AND; a.clone(), b.clone()];
+        let ch = term![BV_OR; ab.clone(), term![BV_AND; term![BV_NOT; a.clone()], c.clone()]];
+        let mut outputs = vec![ch, term![BV_XOR; ab.clone(), d]];
+        resynthesize_bitwise(&mut outputs, CostTarget::R1cs);
+        // CH would be an ITE on its own, but `ab` is needed by the second output anyway.
+        assert!(PostOrderIter::new(outputs[0].clone()).any(|t| t == ab));
+        assert!(PostOrderIter::new(outputs[1].clone()).any(|t| t == ab));
+    }
+
+    #[quickcheck]
+    fn semantic_random_r1cs(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = resynth(t.clone(), CostTarget::R1cs);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+
+    #[quickcheck]
+    fn semantic_random_aby(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = resynth(t.clone(), CostTarget::Aby);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+}
@@ -3,6 +3,7 @@
 use crate::ir::term::*;
 use lazy_static::lazy_static;
 use rug::Integer;
+use std::collections::BTreeMap;
 use std::ops::DerefMut;
 use std::sync::RwLock;
 
@@ -175,6 +176,22 @@ pub fn fold_cache(node: &Term, cache: &mut TermMap<Term>) -> Term {
                     PfUnOp::Neg => -pf.clone(),
                 })))
             }),
+            Op::ConstArray(key_sort, size) => match &get(0).op {
+                Op::Const(v) => Some(leaf_term(Op::Const(Value::Array(
+                    Sort::Array(Box::new(key_sort.clone()), Box::new(v.sort()), *size),
+                    Box::new(v.clone()),
+                    BTreeMap::new(),
+                    *size,
+                )))),
+                _ => None,
+            },
+            Op::Store => match (&get(0).op, &get(1).op, &get(2).op) {
+                (Op::Const(a), Op::Const(k), Op::Const(v)) => {
+                    Some(leaf_term(Op::Const(a.store(k.clone(), v.clone()))))
+                }
+                _ => None,
+            },
+            Op::Select => fold_select(&get(0), &get(1)),
             _ => None,
         };
         let c_get = |x: &Term| -> Term { cache.get(&x).expect("postorder cache").clone() };
@@ -185,6 +202,30 @@ pub fn fold_cache(node: &Term, cache: &mut TermMap<Term>) -> Term {
     cache.get(&node).expect("postorder cache").clone()
 }
 
+/// Fold `(select a k)`, looking through stores to keys which are distinct from `k`.
+fn fold_select(a: &Term, k: &Term) -> Option<Term> {
+    let k_const = matches!(k.op, Op::Const(_));
+    let mut arr = a.clone();
+    loop {
+        let next = match &arr.op {
+            Op::Store if &arr.cs[1] == k => return Some(arr.cs[2].clone()),
+            Op::Store if k_const && matches!(arr.cs[1].op, Op::Const(_)) => arr.cs[0].clone(),
+            Op::ConstArray(..) => return Some(arr.cs[0].clone()),
+            Op::Const(v) => match &k.op {
+                Op::Const(kv) => return Some(leaf_term(Op::Const(v.select(kv)))),
+                _ => break,
+            },
+            _ => break,
+        };
+        arr = next;
+    }
+    if &arr == a {
+        None
+    } else {
+        Some(term![Op::Select; arr, k.clone()])
+    }
+}
+
 fn neg_bool(t: Term) -> Term {
     match &t.op {
         &NOT => t.cs[0].clone(),
@@ -426,6 +467,7 @@ impl NaryFlat<FieldElem> for PfNaryOp {
 mod test {
     use super::*;
     use crate::ir::term::dist::test::*;
+    use ahash::AHashMap;
     use quickcheck_macros::quickcheck;
 
     fn v_bv(n: &str, w: usize) -> Term {
@@ -436,6 +478,38 @@ mod test {
         leaf_term(Op::Const(Value::Bool(b)))
     }
 
+    #[test]
+    fn select_store() {
+        let z = term![Op::ConstArray(Sort::BitVector(4), 16); bv_lit(0, 8)];
+        let x = v_bv("x", 8);
+        let a = term![Op::Store; z, bv_lit(3, 4), x.clone()];
+        assert_eq!(fold(&term![Op::Select; a.clone(), bv_lit(3, 4)]), x);
+        assert_eq!(fold(&term![Op::Select; a.clone(), bv_lit(2, 4)]), bv_lit(0, 8));
+        let k = v_bv("k", 4);
+        let b = term![Op::Store; a, k.clone(), x.clone()];
+        assert_eq!(fold(&term![Op::Select; b.clone(), k]), x);
+        // `k` might be 3
+        let s = term![Op::Select; b, bv_lit(3, 4)];
+        assert_eq!(fold(&s), s);
+    }
+
+    #[test]
+    fn const_table() {
+        let z = term![Op::ConstArray(Sort::BitVector(2), 4); bv_lit(0, 8)];
+        let table = (0..4).fold(z, |a, i| {
+            term![Op::Store; a, bv_lit(i, 2), bv_lit(i * i + 1, 8)]
+        });
+        let folded = fold(&table);
+        assert!(matches!(folded.op, Op::Const(Value::Array(..))));
+        assert_eq!(eval(&folded, &AHashMap::new()), eval(&table, &AHashMap::new()));
+        assert_eq!(fold(&term![Op::Select; table.clone(), bv_lit(3, 2)]), bv_lit(10, 8));
+        let k = v_bv("k", 2);
+        assert_eq!(
+            fold(&term![Op::Select; table, k.clone()]),
+            term![Op::Select; folded, k]
+        );
+    }
+
     #[quickcheck]
     fn semantics_random(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) {
         let tt = fold(&t);
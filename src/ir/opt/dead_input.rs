@@ -0,0 +1,119 @@
+//! Dead input elimination
+//!
+//! Removes inputs (and their values) that no longer appear in any output, so that backends see
+//! the true interface of the computation.
+
+use crate::ir::term::*;
+use ahash::AHashSet;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+/// What [remove_dead_inputs] dropped.
+pub struct DeadInputReport {
+    /// Inputs removed from the metadata.
+    pub inputs: Vec<String>,
+    /// Variables whose values were removed.
+    pub values: Vec<String>,
+}
+
+impl Display for DeadInputReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "dropped {} inputs {:?} and {} values {:?}",
+            self.inputs.len(),
+            self.inputs,
+            self.values.len(),
+            self.values
+        )
+    }
+}
+
+/// Remove the inputs and values of `cs` that are not used by any output.
+///
+/// If `keep_public`, then public inputs are kept, even if unused.
+pub fn remove_dead_inputs(cs: &mut Computation, keep_public: bool) -> DeadInputReport {
+    let live: AHashSet<String> = PostOrderIter::from_roots(cs.outputs.iter().cloned())
+        .filter_map(|t| match &t.op {
+            Op::Var(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect();
+    let keep = |name: &String, party: Option<PartyId>| {
+        live.contains(name) || (keep_public && party.is_none())
+    };
+    let mut report = DeadInputReport::default();
+    let md = &mut cs.metadata;
+    report.inputs = md
+        .inputs
+        .iter()
+        .filter(|(name, party)| !keep(*name, **party))
+        .map(|(name, _)| name.clone())
+        .collect();
+    report.inputs.sort();
+    for name in &report.inputs {
+        md.remove_input(name);
+    }
+    if let Some(values) = cs.values.as_mut() {
+        let md = &cs.metadata;
+        report.values = values
+            .keys()
+            .filter(|name| !live.contains(*name) && !md.inputs.contains_key(*name))
+            .cloned()
+            .collect();
+        report.values.sort();
+        for name in &report.values {
+            values.remove(name);
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn comp(values: bool) -> Computation {
+        let mut cs = Computation::new(values);
+        let a = cs.new_var("a", Sort::Bool, || Value::Bool(true), None);
+        cs.new_var("b", Sort::Bool, || Value::Bool(false), None);
+        let c = cs.new_var("c", Sort::Bool, || Value::Bool(true), Some(0));
+        cs.new_var("d", Sort::Bool, || Value::Bool(true), Some(0));
+        cs.eval_and_save("e", &leaf_term(Op::Const(Value::Bool(true))));
+        cs.assert(term![AND; a, c]);
+        cs
+    }
+
+    #[test]
+    fn drop_all() {
+        let mut cs = comp(true);
+        let report = remove_dead_inputs(&mut cs, false);
+        assert_eq!(report.inputs, vec!["b".to_owned(), "d".to_owned()]);
+        assert_eq!(
+            report.values,
+            vec!["b".to_owned(), "d".to_owned(), "e".to_owned()]
+        );
+        let mut inputs: Vec<_> = cs.metadata.inputs.keys().cloned().collect();
+        inputs.sort();
+        assert_eq!(inputs, vec!["a".to_owned(), "c".to_owned()]);
+        assert_eq!(cs.values.as_ref().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn keep_public() {
+        let mut cs = comp(true);
+        let report = remove_dead_inputs(&mut cs, true);
+        assert_eq!(report.inputs, vec!["d".to_owned()]);
+        assert_eq!(report.values, vec!["d".to_owned(), "e".to_owned()]);
+        assert!(cs.metadata.inputs.contains_key("b"));
+        assert!(cs.values.as_ref().unwrap().contains_key("b"));
+    }
+
+    #[test]
+    fn no_values() {
+        let mut cs = comp(false);
+        let report = remove_dead_inputs(&mut cs, false);
+        assert_eq!(report.inputs.len(), 2);
+        assert!(report.values.is_empty());
+    }
+}
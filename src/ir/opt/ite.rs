@@ -0,0 +1,412 @@
+//! If-then-else lifting and multiplexer sharing
+//!
+//! Merging branches produces many terms of the form `(ite c (f a x) (f a y))`. Lowered naively,
+//! these cost two copies of `f` and a multiplexer. This pass rewrites them to `(f a (ite c x y))`
+//! when the cost model of the target says that is cheaper.
+//!
+//! It also:
+//!
+//!    * merges nested ites on the same condition: `(ite c (ite c a b) d) => (ite c a d)`
+//!    * normalizes negated conditions: `(ite (not c) a b) => (ite c b a)`
+//!    * orients comparisons with constants as `(= CONST k)`, the orientation used by linear
+//!      memory scans ([super::mem::lin]), so that a comparison written either way is the same term
+//!      as the one in a scan, and is lowered once.
+//!    * turns the comparisons of a bit-vector key `k` with constants, like those in the ite chains
+//!      of linear scans, into a shared *indicator vector*, when the cost model of the target says
+//!      that is cheaper. The indicator for `i` is the AND of the bits of `k` (or their negations)
+//!      from the most significant down, so indicators of constants with a common prefix share the
+//!      ANDs for it, across all the chains on `k`.
+
+use crate::ir::cost::{AbyBoolCost, CostModel, R1csCost};
+use crate::ir::term::extras::substitute_cache;
+use crate::ir::term::*;
+use log::debug;
+use rug::Integer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The backend whose costs guide ite lifting.
+pub enum CostTarget {
+    /// Rank-1 constraint systems. Cost is (roughly) the number of constraints.
+    R1cs,
+    /// ABY. Cost is (roughly) the number of AND gates in a Boolean circuit.
+    Aby,
+}
+
+fn is_const(t: &Term) -> bool {
+    matches!(t.op, Op::Const(_))
+}
+
+impl CostTarget {
+    /// The cost model of this target.
+    pub fn model(&self) -> &'static dyn CostModel {
+        match self {
+            CostTarget::R1cs => &R1csCost,
+            CostTarget::Aby => &AbyBoolCost,
+        }
+    }
+
+    /// The cost of an ite whose branches have sort `s`.
+    fn ite_cost(&self, s: &Sort) -> usize {
+        self.model().mux_cost(s)
+    }
+
+    /// The cost of the top-most operator in `t` (not counting its children).
+    pub(crate) fn op_cost(&self, t: &Term) -> usize {
+        self.model().op_cost(t)
+    }
+}
+
+struct IteLifter {
+    target: CostTarget,
+    /// How many parents each term has. Rewritten terms inherit the count of their original.
+    uses: TermMap<usize>,
+    /// Maps (original) terms to their rewritten versions.
+    cache: TermMap<Term>,
+    lifts: usize,
+}
+
+impl IteLifter {
+    fn new(outputs: &[Term], target: CostTarget) -> Self {
+        let mut uses = TermMap::<usize>::new();
+        let mut seen = TermSet::new();
+        for o in outputs {
+            for t in PostOrderIter::new(o.clone()) {
+                if seen.insert(t.clone()) {
+                    for c in t.cs.iter().cloned() {
+                        *uses.entry(c).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        Self {
+            target,
+            uses,
+            cache: TermMap::new(),
+            lifts: 0,
+        }
+    }
+
+    fn uses(&self, t: &Term) -> usize {
+        self.uses.get(t).cloned().unwrap_or(1)
+    }
+
+    /// If `(ite _ t f)` can be profitably rewritten to `(op .. (ite _ t_i f_i) ..)`, return `i`.
+    fn lift_position(&self, t: &Term, f: &Term) -> Option<usize> {
+        if t.op != f.op || t.cs.len() != f.cs.len() || t.cs.is_empty() {
+            return None;
+        }
+        let mut diffs = (0..t.cs.len()).filter(|i| t.cs[*i] != f.cs[*i]);
+        let i = diffs.next()?;
+        if diffs.next().is_some() || check(&t.cs[i]) != check(&f.cs[i]) {
+            return None;
+        }
+        let op_cost = self.target.op_cost(t);
+        let unique = [t, f].iter().filter(|x| self.uses(x) <= 1).count();
+        let saved = unique * op_cost + self.target.ite_cost(&check(t));
+        let spent = op_cost + self.target.ite_cost(&check(&t.cs[i]));
+        if saved > spent {
+            Some(i)
+        } else {
+            None
+        }
+    }
+
+    /// Build `(ite c t f)`, simplifying and lifting it.
+    fn mk_ite(&mut self, c: &Term, t: &Term, f: &Term) -> Term {
+        if t == f {
+            return t.clone();
+        }
+        if c.op == Op::Not {
+            return self.mk_ite(&c.cs[0].clone(), f, t);
+        }
+        let t = if t.op == Op::Ite && &t.cs[0] == c {
+            t.cs[1].clone()
+        } else {
+            t.clone()
+        };
+        let f = if f.op == Op::Ite && &f.cs[0] == c {
+            f.cs[2].clone()
+        } else {
+            f.clone()
+        };
+        if t == f {
+            return t;
+        }
+        if let Some(i) = self.lift_position(&t, &f) {
+            debug!("Lifting ite through {}", t.op);
+            self.lifts += 1;
+            let inner = self.mk_ite(c, &t.cs[i], &f.cs[i]);
+            let mut cs = t.cs.clone();
+            cs[i] = inner;
+            return term(t.op.clone(), cs);
+        }
+        term![Op::Ite; c.clone(), t, f]
+    }
+
+    fn rewrite(&mut self, term_: &Term) -> Term {
+        for t in PostOrderIter::new(term_.clone()) {
+            if self.cache.contains_key(&t) {
+                continue;
+            }
+            let cs: Vec<Term> = t
+                .cs
+                .iter()
+                .map(|c| self.cache.get(c).unwrap().clone())
+                .collect();
+            let new_t = match &t.op {
+                Op::Ite => self.mk_ite(&cs[0], &cs[1], &cs[2]),
+                // Orient index comparisons the way `mem/lin.rs` does, so they are shared.
+                Op::Eq if !is_const(&cs[0]) && is_const(&cs[1]) => {
+                    term![Op::Eq; cs[1].clone(), cs[0].clone()]
+                }
+                _ => term(t.op.clone(), cs),
+            };
+            if new_t != t {
+                let n = self.uses(&t);
+                *self.uses.entry(new_t.clone()).or_insert(0) += n;
+            }
+            self.cache.insert(t, new_t);
+        }
+        self.cache.get(term_).unwrap().clone()
+    }
+}
+
+/// The indicator of `k = c`, for a `w`-bit key `k`. See module documentation.
+fn indicator(c: &Integer, k: &Term, w: usize) -> Term {
+    let literal = |j: usize| {
+        let bit = term![Op::BvBit(j); k.clone()];
+        if c.get_bit(j as u32) {
+            bit
+        } else {
+            term![NOT; bit]
+        }
+    };
+    (0..w - 1)
+        .rev()
+        .fold(literal(w - 1), |acc, j| term![AND; acc, literal(j)])
+}
+
+/// Replace the comparisons `(= CONST k)` of each bit-vector key `k` in `outputs` with an indicator
+/// vector, if that is cheaper for `target`. Returns the number of keys replaced.
+fn share_indicators(outputs: &mut Vec<Term>, target: CostTarget) -> usize {
+    let mut comparisons: TermMap<Vec<Term>> = TermMap::new();
+    for t in PostOrderIter::from_roots(outputs.iter().cloned()) {
+        if t.op == Op::Eq && is_const(&t.cs[0]) && !is_const(&t.cs[1]) {
+            if let Sort::BitVector(_) = check(&t.cs[1]) {
+                comparisons
+                    .entry(t.cs[1].clone())
+                    .or_insert_with(Vec::new)
+                    .push(t.clone());
+            }
+        }
+    }
+    let model = target.model();
+    let mut subs = TermMap::new();
+    let mut keys = 0;
+    for (k, eqs) in comparisons.iter() {
+        let w = check(k).as_bv();
+        let indicators: Vec<Term> = eqs
+            .iter()
+            .map(|e| indicator(e.cs[0].as_bv_opt().unwrap().uint(), k, w))
+            .collect();
+        // Both sets of terms are built on `k`, which is needed anyway.
+        let key_cost = model.cost(k);
+        if model.cost_all(&indicators) - key_cost < model.cost_all(eqs) - key_cost {
+            keys += 1;
+            subs.extend(eqs.iter().cloned().zip(indicators));
+        }
+    }
+    if !subs.is_empty() {
+        for o in outputs.iter_mut() {
+            *o = substitute_cache(o, &mut subs);
+        }
+    }
+    keys
+}
+
+/// Lift ites through common operators in `outputs`, guided by the costs of `target`.
+pub fn lift_ites(outputs: &mut Vec<Term>, target: CostTarget) {
+    let mut pass = IteLifter::new(outputs, target);
+    for o in outputs.iter_mut() {
+        *o = pass.rewrite(o);
+    }
+    let keys = share_indicators(outputs, target);
+    debug!(
+        "Lifted {} ites and built indicator vectors for {} keys (target: {:?})",
+        pass.lifts, keys, target
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::dist::test::*;
+    use ahash::AHashMap;
+    use quickcheck_macros::quickcheck;
+
+    fn bv_var(s: &str, w: usize) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn bool_var(s: &str) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::Bool))
+    }
+
+    fn lift(t: Term, target: CostTarget) -> Term {
+        let mut ts = vec![t];
+        lift_ites(&mut ts, target);
+        ts.pop().unwrap()
+    }
+
+    #[test]
+    fn lift_through_mul() {
+        let c = bool_var("c");
+        let a = bv_var("a", 8);
+        let x = bv_var("x", 8);
+        let y = bv_var("y", 8);
+        let t = term![ITE; c.clone(), term![BV_MUL; a.clone(), x.clone()], term![BV_MUL; a.clone(), y.clone()]];
+        let tt = term![BV_MUL; a, term![ITE; c, x, y]];
+        assert_eq!(tt, lift(t.clone(), CostTarget::R1cs));
+        assert_eq!(tt, lift(t, CostTarget::Aby));
+    }
+
+    #[test]
+    fn lift_nested() {
+        let c = bool_var("c");
+        let a = bv_var("a", 8);
+        let b = bv_var("b", 8);
+        let x = bv_var("x", 8);
+        let y = bv_var("y", 8);
+        let t = term![ITE; c.clone(),
+            term![BV_ADD; a.clone(), term![BV_MUL; b.clone(), x.clone()]],
+            term![BV_ADD; a.clone(), term![BV_MUL; b.clone(), y.clone()]]];
+        let tt = term![BV_ADD; a, term![BV_MUL; b, term![ITE; c, x, y]]];
+        assert_eq!(tt, lift(t, CostTarget::R1cs));
+    }
+
+    #[test]
+    fn no_lift_when_shared() {
+        // Both branches are needed anyway, and lifting would add a multiplier.
+        let c = bool_var("c");
+        let a = bv_var("a", 8);
+        let x = bv_var("x", 8);
+        let y = bv_var("y", 8);
+        let ax = term![BV_MUL; a.clone(), x];
+        let ay = term![BV_MUL; a, y];
+        let t = term![AND;
+            term![EQ; term![ITE; c, ax.clone(), ay.clone()], bv_var("z", 8)],
+            term![EQ; ax, ay]];
+        assert_eq!(t, lift(t.clone(), CostTarget::R1cs));
+    }
+
+    #[test]
+    fn bit_lift_depends_on_target() {
+        // For ABY a bit-vector mux costs a gate per bit; for R1CS it costs one constraint.
+        let c = bool_var("c");
+        let a = bv_var("a", 8);
+        let b = bv_var("b", 8);
+        let t = term![ITE; c.clone(), term![Op::BvBit(0); a.clone()], term![Op::BvBit(0); b.clone()]];
+        assert_eq!(t, lift(t.clone(), CostTarget::Aby));
+        let t = term![ITE; c.clone(),
+            term![BV_ULT; a.clone(), b.clone()],
+            term![BV_ULT; a.clone(), bv_var("d", 8)]];
+        let tt = term![BV_ULT; a, term![ITE; c, b, bv_var("d", 8)]];
+        assert_eq!(tt, lift(t, CostTarget::R1cs));
+    }
+
+    #[test]
+    fn merge_same_condition() {
+        let c = bool_var("c");
+        let a = bv_var("a", 4);
+        let b = bv_var("b", 4);
+        let d = bv_var("d", 4);
+        let t = term![ITE; c.clone(), term![ITE; c.clone(), a.clone(), b.clone()], d.clone()];
+        assert_eq!(term![ITE; c.clone(), a.clone(), d.clone()], lift(t, CostTarget::Aby));
+        let t = term![ITE; term![NOT; c.clone()], a.clone(), term![ITE; c.clone(), b.clone(), d]];
+        assert_eq!(term![ITE; c, b, a], lift(t, CostTarget::Aby));
+    }
+
+    #[test]
+    fn shared_comparisons() {
+        let k = bv_var("k", 4);
+        let t = term![AND;
+            term![EQ; k.clone(), bv_lit(3, 4)],
+            term![EQ; bv_lit(3, 4), k.clone()]];
+        let ind = term![EQ; bv_lit(3, 4), k];
+        assert_eq!(term![AND; ind.clone(), ind], lift(t, CostTarget::R1cs));
+    }
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    /// The distinct terms in `ts` whose operator is `op`.
+    fn count_op(ts: &[Term], op: &Op) -> usize {
+        PostOrderIter::from_roots(ts.iter().cloned())
+            .filter(|t| &t.op == op)
+            .count()
+    }
+
+    #[test]
+    fn shared_indicators() {
+        use crate::ir::opt::mem::lin::linearize;
+        let k = bv_var("k", 3);
+        let array = |name: &str| {
+            let s = Sort::Array(
+                Box::new(Sort::BitVector(3)),
+                Box::new(Sort::BitVector(4)),
+                8,
+            );
+            leaf_term(Op::Var(name.to_owned(), s))
+        };
+        // Two scans on `k`, and a comparison written the other way around
+        let scans = term![BV_ADD;
+            term![Op::Select; array("a"), k.clone()],
+            term![Op::Select; array("b"), k.clone()]];
+        let t = term![ITE;
+            term![EQ; k.clone(), bv_lit(5, 3)],
+            linearize(&scans, usize::MAX),
+            bv_var("x", 4)];
+        // Each scan compares `k` with 1..7.
+        assert_eq!(count_op(&[t.clone()], &Op::Eq), 8);
+        for target in vec![CostTarget::R1cs, CostTarget::Aby] {
+            let tt = lift(t.clone(), target);
+            // Seven comparisons (two ANDs each) cost more than seven indicators sharing the ANDs
+            // for four 2-bit prefixes.
+            assert_eq!(count_op(&[tt.clone()], &Op::Eq), 0);
+            assert_eq!(count_op(&[tt.clone()], &AND), 4 + 7);
+            for i in 0..8 {
+                let mut env = AHashMap::new();
+                env.insert("k".to_owned(), bv(i, 3));
+                env.insert("x".to_owned(), bv(9, 4));
+                for name in &["a", "b"] {
+                    for j in 0..8 {
+                        env.insert(format!("{}_{}", name, j), bv(i + j, 4));
+                    }
+                }
+                assert_eq!(eval(&t, &env), eval(&tt, &env));
+            }
+        }
+        // With a wide key and few constants, the comparisons are cheaper, but still shared.
+        let k = bv_var("k", 8);
+        let t = term![AND;
+            term![EQ; k.clone(), bv_lit(3, 8)],
+            term![EQ; bv_lit(3, 8), k.clone()],
+            term![EQ; bv_lit(200, 8), k.clone()]];
+        let tt = lift(t, CostTarget::R1cs);
+        assert_eq!(count_op(&[tt.clone()], &Op::Eq), 2);
+        assert_eq!(count_op(&[tt], &AND), 1);
+    }
+
+    #[quickcheck]
+    fn semantic_random_r1cs(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = lift(t.clone(), CostTarget::R1cs);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+
+    #[quickcheck]
+    fn semantic_random_aby(ArbitraryTermEnv(t, vs): ArbitraryTermEnv) -> bool {
+        let tt = lift(t.clone(), CostTarget::Aby);
+        eval(&t, &vs) == eval(&tt, &vs)
+    }
+}
@@ -8,8 +8,11 @@
 //!
 //! (select A k) => (ite (= k 2) A2 (ite (= k 1) A1 A0))
 //! (store A k v) => (ite (= k 0) v A0), (ite (= k 1) v A1), (ite (= k 2))
+//!
+//! [linearize_keeping] leaves SELECTs directly from some chosen array constants (lookup tables)
+//! alone, for backends which lower those more cheaply than an ITE chain (see [super::plan]).
 
-use super::visit::MemVisitor;
+use super::visit::{const_array_elems, MemVisitor};
 use crate::ir::term::*;
 
 use std::iter::repeat;
@@ -19,6 +22,14 @@ struct ArrayLinearizer {
     sequences: TermMap<Vec<Term>>,
     /// The maximum size of arrays that will be replaced.
     size_thresh: usize,
+    /// The array constants to leave as lookup tables.
+    tables: TermSet,
+}
+
+impl ArrayLinearizer {
+    fn is_table(&self, a: &Term) -> bool {
+        matches!(a.op, Op::Const(_)) && self.tables.contains(a)
+    }
 }
 
 impl MemVisitor for ArrayLinearizer {
@@ -28,6 +39,12 @@ impl MemVisitor for ArrayLinearizer {
                 .insert(orig.clone(), repeat(val).cloned().take(size).collect());
         }
     }
+    fn visit_const_value(&mut self, orig: &Term, val: &Value) {
+        let elems = const_array_elems(val);
+        if elems.len() <= self.size_thresh {
+            self.sequences.insert(orig.clone(), elems);
+        }
+    }
     fn visit_eq(&mut self, orig: &Term, _a: &Term, _b: &Term) -> Option<Term> {
         // don't map b/c self borrow lifetime & NLL
         if let Some(a_seq) = self.sequences.get(&orig.cs[0]) {
@@ -68,6 +85,9 @@ impl MemVisitor for ArrayLinearizer {
         }
     }
     fn visit_select(&mut self, orig: &Term, _a: &Term, k: &Term) -> Option<Term> {
+        if self.is_table(&orig.cs[0]) {
+            return Some(term![Op::Select; orig.cs[0].clone(), k.clone()]);
+        }
         if let Some(a_seq) = self.sequences.get(&orig.cs[0]) {
             let key_sort = check(k);
             let first = a_seq.first().expect("empty array in visit_select").clone();
@@ -103,6 +123,17 @@ pub fn linearize(t: &Term, size_thresh: usize) -> Term {
     let mut pass = ArrayLinearizer {
         size_thresh,
         sequences: TermMap::new(),
+        tables: TermSet::new(),
+    };
+    pass.traverse(t)
+}
+
+/// Eliminate arrays using linear scans, leaving selects from the array constants in `tables`.
+pub fn linearize_keeping(t: &Term, size_thresh: usize, tables: &TermSet) -> Term {
+    let mut pass = ArrayLinearizer {
+        size_thresh,
+        sequences: TermMap::new(),
+        tables: tables.clone(),
     };
     pass.traverse(t)
 }
@@ -110,7 +141,9 @@ pub fn linearize(t: &Term, size_thresh: usize) -> Term {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::ir::opt::cfold::fold;
     use crate::ir::term::field::TEST_FIELD;
+    use ahash::AHashMap;
     use rug::Integer;
     use std::sync::Arc;
 
@@ -167,4 +200,46 @@ mod test {
         assert!(array_free(&tt));
         assert_eq!(6 + 6 + 6 + 5, count_ites(&tt));
     }
+
+    #[test]
+    fn select_array_constant() {
+        let z = term![Op::ConstArray(Sort::BitVector(2), 4); bv_lit(0, 4)];
+        let a = fold(&term![Op::Store; z, bv_lit(1, 2), bv_lit(5, 4)]);
+        assert!(matches!(a.op, Op::Const(Value::Array(..))));
+        let k = leaf_term(Op::Var("k".to_owned(), Sort::BitVector(2)));
+        // By default, lookups are scanned too
+        let t = term![Op::Select; a.clone(), k.clone()];
+        assert!(array_free(&linearize(&t, 4)));
+        let v = leaf_term(Op::Var("v".to_owned(), Sort::BitVector(4)));
+        let t = term![Op::Select; term![Op::Store; a.clone(), bv_lit(2, 2), v], k];
+        let mut tables = TermSet::new();
+        tables.insert(a);
+        let tt = linearize_keeping(&t, 4, &tables);
+        assert!(array_free(&tt));
+        let mut env = AHashMap::new();
+        env.insert(
+            "k".to_owned(),
+            Value::BitVector(BitVector::new(Integer::from(1), 2)),
+        );
+        env.insert(
+            "v".to_owned(),
+            Value::BitVector(BitVector::new(Integer::from(7), 4)),
+        );
+        assert_eq!(
+            eval(&tt, &env),
+            Value::BitVector(BitVector::new(Integer::from(5), 4))
+        );
+    }
+
+    #[test]
+    fn scan_array_constant() {
+        let z = term![Op::ConstArray(Sort::BitVector(2), 4); bv_lit(0, 4)];
+        let a = fold(&term![Op::Store; z, bv_lit(1, 2), bv_lit(5, 4)]);
+        let k = leaf_term(Op::Var("k".to_owned(), Sort::BitVector(2)));
+        let t = term![Op::Select; a.clone(), k];
+        let mut tables = TermSet::new();
+        assert!(array_free(&linearize_keeping(&t, 4, &tables)));
+        tables.insert(a);
+        assert_eq!(linearize_keeping(&t, 4, &tables), t);
+    }
 }
@@ -1,7 +1,10 @@
 //! Memory optimizations
 
 pub mod lin;
+pub mod nest;
 pub mod obliv;
+pub mod perm;
+pub mod plan;
 mod visit;
 
 use crate::ir::term::*;
@@ -10,3 +13,15 @@ use crate::ir::term::*;
 pub fn array_elim(t: &Term) -> Term {
     lin::linearize(&obliv::elim_obliv(t), usize::MAX)
 }
+
+/// Eliminates arrays from `cs`: first oblivious ones, then those with at least `perm_thresh`
+/// elements (by permutation checking), and then all arrays. See [plan] to choose per array.
+pub fn array_elim_all(cs: &mut Computation, perm_thresh: usize) {
+    for o in cs.outputs.iter_mut() {
+        *o = obliv::elim_obliv(o);
+    }
+    perm::check_by_permutation(cs, perm_thresh);
+    for o in cs.outputs.iter_mut() {
+        *o = lin::linearize(o, usize::MAX);
+    }
+}
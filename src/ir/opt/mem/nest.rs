@@ -0,0 +1,836 @@
+//! Flattening nested arrays
+//!
+//! The other memory passes assume that array elements are scalars. This pass removes arrays whose
+//! elements are tuples or arrays:
+//!
+//!    * An array of tuples becomes a tuple of arrays. Array variables are suffixed like the tuple
+//!      pass does it: `x: (array k (tuple a b) n)` becomes `x.0: (array k a n)` and
+//!      `x.1: (array k b n)`.
+//!    * An array of arrays becomes one array, in row-major order: `x: (array k (array k' v m) n)`
+//!      becomes `x: (array k'' v n*m)`, and `(select (select x i) j)` becomes
+//!      `(select x (+ (* i m) j))`.
+//!
+//! Selecting a row from an array of arrays gives a *view*: a flat array and an offset into it.
+//! Views are only turned into arrays (with one store per element) when they are used as a whole,
+//! e.g., in an equality.
+//!
+//! Keys of flattened arrays of arrays are bit-vectors wide enough for both keys and twice the new
+//! size. A key past the end of its dimension would alias an element of another row, so it is
+//! guarded: such reads go to a key past the end of the flat array, and read its default, while
+//! such stores do nothing.
+//!
+//! Arrays of arrays whose keys are not both bit-vectors cannot be guarded that way (field keys
+//! cannot be compared), so they are not flattened. If a computation has any, this pass leaves the
+//! whole computation alone.
+
+use crate::ir::term::*;
+
+use log::debug;
+use rug::Integer;
+
+/// The component sorts of `s`, if it is a tuple or an array of tuples.
+fn components(s: &Sort) -> Option<Vec<Sort>> {
+    match s {
+        Sort::Tuple(ss) => Some(ss.clone()),
+        Sort::Array(k, v, n) => components(v).map(|cs| {
+            cs.into_iter()
+                .map(|c| Sort::Array(k.clone(), Box::new(c), *n))
+                .collect()
+        }),
+        _ => None,
+    }
+}
+
+/// The number of bits needed to represent `n`.
+fn bits(n: usize) -> usize {
+    let mut w = 1;
+    while n >> w > 0 {
+        w += 1;
+    }
+    w
+}
+
+/// The sort of `s`, with arrays of tuples replaced by tuples of arrays, and arrays of arrays
+/// (with bit-vector keys) replaced by arrays.
+fn flat_sort(s: &Sort) -> Sort {
+    if let Some(cs) = components(s) {
+        return Sort::Tuple(cs.iter().map(flat_sort).collect());
+    }
+    match s {
+        Sort::Array(k, v, n) => match (&**k, flat_sort(v)) {
+            (Sort::BitVector(a), Sort::Array(k2, v2, n2)) if matches!(*k2, Sort::BitVector(_)) => {
+                let b = k2.as_bv();
+                let w = *a.max(&b).max(&bits((2 * n * n2).saturating_sub(1)));
+                Sort::Array(Box::new(Sort::BitVector(w)), v2, n * n2)
+            }
+            (_, v) => Sort::Array(k.clone(), Box::new(v), *n),
+        },
+        s => s.clone(),
+    }
+}
+
+fn is_nested(s: &Sort) -> bool {
+    match s {
+        Sort::Array(_, v, _) => matches!(**v, Sort::Array(..) | Sort::Tuple(_)),
+        Sort::Tuple(ss) => ss.iter().any(is_nested),
+        _ => false,
+    }
+}
+
+/// The key sorts of `v`, if it is an array, or of its components, if it is a tuple.
+fn row_keys(v: &Sort) -> Vec<&Sort> {
+    match v {
+        Sort::Array(k, _, _) => vec![&**k],
+        Sort::Tuple(ss) => ss.iter().flat_map(row_keys).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Whether every array of arrays in `s` has bit-vector keys, in both dimensions.
+fn can_flatten(s: &Sort) -> bool {
+    match s {
+        Sort::Array(k, v, _) => {
+            let rows = row_keys(v);
+            can_flatten(v)
+                && (rows.is_empty()
+                    || (matches!(**k, Sort::BitVector(_))
+                        && rows.iter().all(|r| matches!(r, Sort::BitVector(_)))))
+        }
+        Sort::Tuple(ss) => ss.iter().all(can_flatten),
+        _ => true,
+    }
+}
+
+/// The number of scalars in a flattened `s`.
+fn flat_len(s: &Sort) -> usize {
+    match flat_sort(s) {
+        Sort::Array(_, _, n) => n,
+        _ => 1,
+    }
+}
+
+fn key_value(s: &Sort, i: usize) -> Value {
+    match s {
+        Sort::BitVector(w) => Value::BitVector(BitVector::new(Integer::from(i), *w)),
+        Sort::Field(m) => Value::Field(FieldElem::new(Integer::from(i), m.clone())),
+        _ => panic!("Cannot index with {}", s),
+    }
+}
+
+fn key_lit(s: &Sort, i: usize) -> Term {
+    leaf_term(Op::Const(key_value(s, i)))
+}
+
+fn const_index(t: &Term) -> Option<usize> {
+    match &t.op {
+        Op::Const(Value::BitVector(b)) => b.uint().to_usize(),
+        Op::Const(Value::Field(f)) => f.i().to_usize(),
+        _ => None,
+    }
+}
+
+/// The key `offset + k * stride`, in `key_sort`.
+fn index(offset: Option<&Term>, k: &Term, stride: usize, key_sort: &Sort) -> Term {
+    let off_const = match offset {
+        Some(o) => const_index(o),
+        None => Some(0),
+    };
+    if let (Some(o), Some(k)) = (off_const, const_index(k)) {
+        return key_lit(key_sort, o + k * stride);
+    }
+    let k = match (check(k), key_sort) {
+        (Sort::BitVector(a), Sort::BitVector(b)) if a < *b => {
+            term![Op::BvUext(b - a); k.clone()]
+        }
+        _ => k.clone(),
+    };
+    let scaled = if stride == 1 {
+        k
+    } else {
+        term![BV_MUL; k, key_lit(key_sort, stride)]
+    };
+    match offset {
+        Some(o) => term![BV_ADD; o.clone(), scaled],
+        None => scaled,
+    }
+}
+
+/// `in_range` if the key `k` is less than `size`, and `out_of_range` otherwise.
+fn guard(k: &Term, size: usize, in_range: Term, out_of_range: Term) -> Term {
+    match const_index(k) {
+        Some(i) if i < size => in_range,
+        Some(_) => out_of_range,
+        None => match check(k) {
+            Sort::BitVector(w) if bits(size) <= w => {
+                term![Op::Ite; term![BV_ULT; k.clone(), bv_lit(size, w)], in_range, out_of_range]
+            }
+            _ => in_range,
+        },
+    }
+}
+
+/// The size of the flat array `t`.
+fn array_size(t: &Term) -> usize {
+    match check(t) {
+        Sort::Array(_, _, n) => n,
+        s => panic!("{} is not an array", s),
+    }
+}
+
+/// The `i`th component of a value of tuple sort, or of array-of-tuples sort.
+fn component(v: &Value, i: usize) -> Value {
+    match v {
+        Value::Tuple(vs) => vs[i].clone(),
+        Value::Array(s, d, map, n) => Value::Array(
+            components(s).unwrap()[i].clone(),
+            Box::new(component(d, i)),
+            map.iter()
+                .map(|(k, v)| (k.clone(), component(v, i)))
+                .collect(),
+            *n,
+        ),
+        v => panic!("{} has no components", v),
+    }
+}
+
+/// The value `v`, flattened like its sort.
+fn flat_value(v: Value) -> Value {
+    match v {
+        Value::Tuple(vs) => Value::Tuple(vs.into_iter().map(flat_value).collect()),
+        Value::Array(s, d, map, n) => {
+            if let Some(cs) = components(&s) {
+                let v = Value::Array(s, d, map, n);
+                return Value::Tuple(
+                    (0..cs.len())
+                        .map(|i| flat_value(component(&v, i)))
+                        .collect(),
+                );
+            }
+            let (k, v_sort) = match &s {
+                Sort::Array(k, v, _) => ((**k).clone(), (**v).clone()),
+                _ => unreachable!(),
+            };
+            let (inner_key, stride) = match flat_sort(&v_sort) {
+                Sort::Array(k2, _, n2) => (*k2, n2),
+                _ => return Value::Array(s, d, map, n),
+            };
+            let flat_s = flat_sort(&s);
+            let flat_key = match &flat_s {
+                Sort::Array(k, _, _) => (**k).clone(),
+                _ => unreachable!(),
+            };
+            let (flat_d, inner_default) = match flat_value(*d) {
+                Value::Array(_, dd, m, _) => (dd, m),
+                _ => unreachable!(),
+            };
+            let mut flat_map = std::collections::BTreeMap::new();
+            for i in 0..n {
+                let (row_d, row) = match map.get(&key_value(&k, i)) {
+                    Some(row) => match flat_value(row.clone()) {
+                        Value::Array(_, rd, rm, _) => (rd, rm),
+                        _ => unreachable!(),
+                    },
+                    None => (flat_d.clone(), inner_default.clone()),
+                };
+                for j in 0..stride {
+                    let v = row.get(&key_value(&inner_key, j)).unwrap_or(&*row_d);
+                    if v != &*flat_d {
+                        flat_map.insert(key_value(&flat_key, i * stride + j), v.clone());
+                    }
+                }
+            }
+            Value::Array(flat_s, flat_d, flat_map, n * stride)
+        }
+        v => v,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A flattened term.
+enum Flat {
+    /// A term of the flattened sort
+    Term(Term),
+    /// A row of a flattened array of arrays: the flat array, and the key of the row's first element
+    View(Term, Term),
+    /// The components of a tuple, or of an array of tuples
+    Tuple(Vec<Flat>),
+}
+
+impl Flat {
+    fn split(&self, n: usize) -> Vec<Flat> {
+        match self {
+            Flat::Tuple(fs) => fs.clone(),
+            Flat::Term(t) => (0..n)
+                .map(|i| Flat::Term(term![Op::Field(i); t.clone()]))
+                .collect(),
+            Flat::View(..) => panic!("Cannot split a view"),
+        }
+    }
+
+    /// This, as a single term of the flattened version of `sort`.
+    fn materialize(&self, sort: &Sort) -> Term {
+        match self {
+            Flat::Term(t) => t.clone(),
+            Flat::Tuple(fs) => term(
+                Op::Tuple,
+                fs.iter()
+                    .zip(components(sort).unwrap())
+                    .map(|(f, c)| f.materialize(&c))
+                    .collect(),
+            ),
+            Flat::View(base, offset) => {
+                let (k, n) = match flat_sort(sort) {
+                    Sort::Array(k, _, n) => (*k, n),
+                    s => panic!("View of non-array {}", s),
+                };
+                let base_key = array_key(base);
+                let elem = |j: usize| {
+                    let idx = index(Some(offset), &key_lit(&base_key, j), 1, &base_key);
+                    term![Op::Select; base.clone(), idx]
+                };
+                (1..n).fold(
+                    term![Op::ConstArray(k.clone(), n); elem(0)],
+                    |a, j| term![Op::Store; a, key_lit(&k, j), elem(j)],
+                )
+            }
+        }
+    }
+
+    /// The `j`th scalar of this flattened array.
+    fn elem(&self, j: usize) -> Term {
+        match self {
+            Flat::Term(t) => {
+                let k = array_key(t);
+                term![Op::Select; t.clone(), key_lit(&k, j)]
+            }
+            Flat::View(base, offset) => {
+                let k = array_key(base);
+                let idx = index(Some(offset), &key_lit(&k, j), 1, &k);
+                term![Op::Select; base.clone(), idx]
+            }
+            Flat::Tuple(_) => panic!("Cannot index a tuple"),
+        }
+    }
+
+    fn base(&self) -> (&Term, Option<&Term>) {
+        match self {
+            Flat::Term(t) => (t, None),
+            Flat::View(b, o) => (b, Some(o)),
+            Flat::Tuple(_) => panic!("Not an array"),
+        }
+    }
+
+    fn with_base(&self, base: Term) -> Flat {
+        match self {
+            Flat::View(_, o) => Flat::View(base, o.clone()),
+            _ => Flat::Term(base),
+        }
+    }
+}
+
+fn array_key(t: &Term) -> Sort {
+    match check(t) {
+        Sort::Array(k, _, _) => *k,
+        s => panic!("{} is not an array", s),
+    }
+}
+
+fn var(name: &str, sort: &Sort) -> Flat {
+    match components(sort) {
+        Some(cs) => Flat::Tuple(
+            cs.iter()
+                .enumerate()
+                .map(|(i, c)| var(&format!("{}.{}", name, i), c))
+                .collect(),
+        ),
+        None => Flat::Term(leaf_term(Op::Var(name.to_owned(), flat_sort(sort)))),
+    }
+}
+
+/// The flattened variables (and their values) that replace `name`.
+fn var_leaves(
+    name: &str,
+    sort: &Sort,
+    value: Option<Value>,
+    out: &mut Vec<(String, Option<Value>)>,
+) {
+    match components(sort) {
+        Some(cs) => {
+            for (i, c) in cs.iter().enumerate() {
+                let v = value.as_ref().map(|v| component(v, i));
+                var_leaves(&format!("{}.{}", name, i), c, v, out);
+            }
+        }
+        None => out.push((name.to_owned(), value)),
+    }
+}
+
+/// `v` (of flattened sort) for a term of sort `sort`.
+fn constant(v: Value, sort: &Sort) -> Flat {
+    match components(sort) {
+        Some(cs) => Flat::Tuple(
+            cs.iter()
+                .enumerate()
+                .map(|(i, c)| constant(component(&v, i), c))
+                .collect(),
+        ),
+        None => Flat::Term(leaf_term(Op::Const(v))),
+    }
+}
+
+fn select(a: &Flat, a_sort: &Sort, k: &Term) -> Flat {
+    if let Some(cs) = components(a_sort) {
+        return Flat::Tuple(
+            a.split(cs.len())
+                .iter()
+                .zip(&cs)
+                .map(|(a, c)| select(a, c, k))
+                .collect(),
+        );
+    }
+    let (v_sort, n) = match a_sort {
+        Sort::Array(_, v, n) => (v, *n),
+        _ => unreachable!(),
+    };
+    let (base, offset) = a.base();
+    let key_sort = array_key(base);
+    let stride = flat_len(v_sort);
+    let mut idx = index(offset, k, stride, &key_sort);
+    if offset.is_some() || stride > 1 {
+        idx = guard(k, n, idx, key_lit(&key_sort, array_size(base)));
+    }
+    if let Sort::Array(..) = **v_sort {
+        Flat::View(base.clone(), idx)
+    } else {
+        Flat::Term(term![Op::Select; base.clone(), idx])
+    }
+}
+
+fn store(a: &Flat, a_sort: &Sort, k: &Term, v: &Flat) -> Flat {
+    if let Some(cs) = components(a_sort) {
+        return Flat::Tuple(
+            a.split(cs.len())
+                .iter()
+                .zip(v.split(cs.len()))
+                .zip(&cs)
+                .map(|((a, v), c)| store(a, c, k, &v))
+                .collect(),
+        );
+    }
+    let (v_sort, n) = match a_sort {
+        Sort::Array(_, v, n) => (v, *n),
+        _ => unreachable!(),
+    };
+    let (base, offset) = a.base();
+    let key_sort = array_key(base);
+    let stride = flat_len(v_sort);
+    let idx = index(offset, k, stride, &key_sort);
+    let mut new_base = if let Sort::Array(..) = **v_sort {
+        (0..stride).fold(base.clone(), |b, j| {
+            let idx_j = index(Some(&idx), &key_lit(&key_sort, j), 1, &key_sort);
+            term![Op::Store; b, idx_j, v.elem(j)]
+        })
+    } else {
+        term![Op::Store; base.clone(), idx, v.materialize(v_sort)]
+    };
+    if offset.is_some() || stride > 1 {
+        new_base = guard(k, n, new_base, base.clone());
+    }
+    a.with_base(new_base)
+}
+
+fn ite(c: &Term, t: &Flat, f: &Flat, sort: &Sort) -> Flat {
+    if let Some(cs) = components(sort) {
+        return Flat::Tuple(
+            t.split(cs.len())
+                .iter()
+                .zip(f.split(cs.len()))
+                .zip(&cs)
+                .map(|((t, f), s)| ite(c, t, &f, s))
+                .collect(),
+        );
+    }
+    match (t, f) {
+        (Flat::View(a, o1), Flat::View(b, o2)) if o1 == o2 => {
+            Flat::View(term![Op::Ite; c.clone(), a.clone(), b.clone()], o1.clone())
+        }
+        _ => Flat::Term(term![Op::Ite; c.clone(), t.materialize(sort), f.materialize(sort)]),
+    }
+}
+
+fn eq(a: &Flat, b: &Flat, sort: &Sort) -> Term {
+    match components(sort) {
+        Some(cs) => term(
+            AND,
+            a.split(cs.len())
+                .iter()
+                .zip(b.split(cs.len()))
+                .zip(&cs)
+                .map(|((a, b), s)| eq(a, &b, s))
+                .collect(),
+        ),
+        None => term![Op::Eq; a.materialize(sort), b.materialize(sort)],
+    }
+}
+
+fn const_array(key_sort: &Sort, n: usize, v: &Flat, v_sort: &Sort) -> Flat {
+    let a_sort = Sort::Array(Box::new(key_sort.clone()), Box::new(v_sort.clone()), n);
+    if let Some(cs) = components(v_sort) {
+        return Flat::Tuple(
+            v.split(cs.len())
+                .iter()
+                .zip(&cs)
+                .map(|(v, c)| const_array(key_sort, n, v, c))
+                .collect(),
+        );
+    }
+    let (flat_key, flat_n) = match flat_sort(&a_sort) {
+        Sort::Array(k, _, n) => (*k, n),
+        _ => unreachable!(),
+    };
+    if !matches!(v_sort, Sort::Array(..)) {
+        return Flat::Term(term![Op::ConstArray(flat_key, flat_n); v.materialize(v_sort)]);
+    }
+    let row = v.materialize(v_sort);
+    if let Op::ConstArray(..) = &row.op {
+        return Flat::Term(term![Op::ConstArray(flat_key, flat_n); row.cs[0].clone()]);
+    }
+    let stride = flat_len(v_sort);
+    let row = Flat::Term(row);
+    let init = term![Op::ConstArray(flat_key.clone(), flat_n); row.elem(0)];
+    Flat::Term((1..flat_n).fold(
+        init,
+        |a, i| term![Op::Store; a, key_lit(&flat_key, i), row.elem(i % stride)],
+    ))
+}
+
+struct Flattener {
+    map: TermMap<Flat>,
+}
+
+impl Flattener {
+    fn get(&self, t: &Term) -> &Flat {
+        self.map.get(t).expect("missing flattened term")
+    }
+
+    fn get_term(&self, t: &Term) -> Term {
+        self.get(t).materialize(&check(t))
+    }
+
+    fn visit(&mut self, t: &Term) {
+        let s = check(t);
+        let flat = match &t.op {
+            Op::Var(name, sort) if is_nested(sort) => var(name, sort),
+            Op::Const(v) if is_nested(&s) => constant(flat_value(v.clone()), &s),
+            Op::ConstArray(k, n) if is_nested(&s) => {
+                const_array(k, *n, self.get(&t.cs[0]), &check(&t.cs[0]))
+            }
+            Op::Select if is_nested(&check(&t.cs[0])) => select(
+                self.get(&t.cs[0]),
+                &check(&t.cs[0]),
+                &self.get_term(&t.cs[1]),
+            ),
+            Op::Store if is_nested(&s) => store(
+                self.get(&t.cs[0]),
+                &s,
+                &self.get_term(&t.cs[1]),
+                self.get(&t.cs[2]),
+            ),
+            Op::Ite if is_nested(&s) => ite(
+                &self.get_term(&t.cs[0]),
+                self.get(&t.cs[1]),
+                self.get(&t.cs[2]),
+                &s,
+            ),
+            Op::Eq if is_nested(&check(&t.cs[0])) => {
+                Flat::Term(eq(self.get(&t.cs[0]), self.get(&t.cs[1]), &check(&t.cs[0])))
+            }
+            Op::Field(i) if matches!(self.get(&t.cs[0]), Flat::Tuple(_)) => {
+                self.get(&t.cs[0]).split(0)[*i].clone()
+            }
+            Op::Tuple => Flat::Tuple(t.cs.iter().map(|c| self.get(c).clone()).collect()),
+            _ => {
+                let cs: Vec<Term> = t.cs.iter().map(|c| self.get_term(c)).collect();
+                if cs == t.cs {
+                    Flat::Term(t.clone())
+                } else {
+                    Flat::Term(term(t.op.clone(), cs))
+                }
+            }
+        };
+        self.map.insert(t.clone(), flat);
+    }
+}
+
+/// Flatten arrays of tuples and arrays of arrays in `cs`. See module documentation.
+pub fn flatten_nested(cs: &mut Computation) {
+    let mut nested_vars = Vec::new();
+    let mut any_nested = false;
+    for o in &cs.outputs {
+        for t in PostOrderIter::new(o.clone()) {
+            let s = check(&t);
+            if is_nested(&s) {
+                if !can_flatten(&s) {
+                    debug!("Not flattening nested arrays: cannot flatten {}", s);
+                    return;
+                }
+                any_nested = true;
+                if let Op::Var(name, sort) = &t.op {
+                    nested_vars.push((name.clone(), sort.clone()));
+                }
+            }
+        }
+    }
+    if !any_nested {
+        return;
+    }
+    nested_vars.sort();
+    nested_vars.dedup();
+    for (name, sort) in &nested_vars {
+        let value = cs
+            .values
+            .as_mut()
+            .and_then(|vs| vs.remove(name))
+            .map(flat_value);
+        let party = cs.metadata.remove_input(name);
+        let mut leaves = Vec::new();
+        var_leaves(name, sort, value, &mut leaves);
+        debug!("Flattening {} into {} variables", name, leaves.len());
+        for (leaf, value) in leaves {
+            if let Some(v) = value {
+                cs.values.as_mut().unwrap().insert(leaf.clone(), v);
+            }
+            if let Some(p) = party {
+                cs.metadata.inputs.insert(leaf, p);
+            }
+        }
+    }
+    let mut pass = Flattener {
+        map: TermMap::new(),
+    };
+    for o in &cs.outputs {
+        for t in PostOrderIter::new(o.clone()) {
+            if !pass.map.contains_key(&t) {
+                pass.visit(&t);
+            }
+        }
+    }
+    for o in &mut cs.outputs {
+        *o = pass.get_term(o);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::opt::mem::array_elim_all;
+    use crate::ir::term::field::TEST_FIELD;
+    use ahash::AHashMap;
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    fn bv_var(name: &str, w: usize) -> Term {
+        leaf_term(Op::Var(name.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn array_free(t: &Term) -> bool {
+        PostOrderIter::new(t.clone()).all(|c| !matches!(check(&c), Sort::Array(..)))
+    }
+
+    /// Flatten, eliminate arrays, and then evaluate.
+    fn eval_flat(outputs: Vec<Term>, env: &AHashMap<String, Value>) -> Vec<Value> {
+        let mut cs = Computation::new(false);
+        cs.outputs = outputs;
+        flatten_nested(&mut cs);
+        for o in &cs.outputs {
+            assert!(!PostOrderIter::new(o.clone()).any(|t| is_nested(&check(&t))));
+        }
+        array_elim_all(&mut cs, usize::MAX);
+        cs.outputs
+            .iter()
+            .map(|o| {
+                assert!(array_free(o));
+                eval(o, env)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sorts() {
+        let b4 = Box::new(Sort::BitVector(4));
+        let b2 = Box::new(Sort::BitVector(2));
+        let inner = Sort::Array(b2.clone(), b4.clone(), 3);
+        let nested = Sort::Array(b2.clone(), Box::new(inner), 3);
+        let b5 = Box::new(Sort::BitVector(5));
+        assert_eq!(flat_sort(&nested), Sort::Array(b5, b4.clone(), 9));
+        let tup = Sort::Array(
+            b2.clone(),
+            Box::new(Sort::Tuple(vec![Sort::Bool, Sort::BitVector(4)])),
+            3,
+        );
+        assert_eq!(
+            flat_sort(&tup),
+            Sort::Tuple(vec![
+                Sort::Array(b2.clone(), Box::new(Sort::Bool), 3),
+                Sort::Array(b2.clone(), b4.clone(), 3),
+            ])
+        );
+        assert!(is_nested(&tup));
+        assert!(!is_nested(&flat_sort(&tup)));
+    }
+
+    #[test]
+    fn two_dimensional() {
+        // a 3x3 matrix of zeros, with a[i][j] := v
+        let row = term![Op::ConstArray(Sort::BitVector(2), 3); bv_lit(0, 4)];
+        let m = term![Op::ConstArray(Sort::BitVector(2), 3); row];
+        let i = bv_var("i", 2);
+        let j = bv_var("j", 2);
+        let old_row = term![Op::Select; m.clone(), i.clone()];
+        let new_row = term![Op::Store; old_row, j.clone(), bv_var("v", 4)];
+        let m2 = term![Op::Store; m, i.clone(), new_row];
+        let read = |x: usize, y: usize| term![Op::Select; term![Op::Select; m2.clone(), bv_lit(x, 2)], bv_lit(y, 2)];
+        let outputs = vec![
+            read(1, 2),
+            read(2, 1),
+            term![Op::Select; term![Op::Select; m2.clone(), i], j],
+        ];
+        let mut env = AHashMap::new();
+        env.insert("i".to_owned(), bv(1, 2));
+        env.insert("j".to_owned(), bv(2, 2));
+        env.insert("v".to_owned(), bv(7, 4));
+        assert_eq!(eval_flat(outputs, &env), vec![bv(7, 4), bv(0, 4), bv(7, 4)]);
+    }
+
+    #[test]
+    fn array_of_tuples() {
+        let elem = Sort::Tuple(vec![Sort::Bool, Sort::BitVector(4)]);
+        let a_sort = Sort::Array(Box::new(Sort::BitVector(2)), Box::new(elem), 4);
+        let a = leaf_term(Op::Var("a".to_owned(), a_sort));
+        let k = bv_var("k", 2);
+        let pair = term![Op::Tuple; leaf_term(Op::Const(Value::Bool(true))), bv_var("v", 4)];
+        let a2 = term![Op::Store; a.clone(), k.clone(), pair];
+        let outputs = vec![
+            term![Op::Field(0); term![Op::Select; a2.clone(), k.clone()]],
+            term![Op::Field(1); term![Op::Select; a2.clone(), bv_lit(0, 2)]],
+            term![Op::Eq; a2.clone(), a2],
+        ];
+        let mut env = AHashMap::new();
+        env.insert("k".to_owned(), bv(3, 2));
+        env.insert("v".to_owned(), bv(9, 4));
+        for i in 0..4 {
+            env.insert(format!("a.0_{}", i), Value::Bool(false));
+            env.insert(format!("a.1_{}", i), bv(i, 4));
+        }
+        assert_eq!(
+            eval_flat(outputs, &env),
+            vec![Value::Bool(true), bv(0, 4), Value::Bool(true)]
+        );
+    }
+
+    #[test]
+    fn matrix_of_pairs_values() {
+        let elem = Sort::Tuple(vec![Sort::Bool, Sort::BitVector(4)]);
+        let row = Sort::Array(Box::new(Sort::BitVector(1)), Box::new(elem), 2);
+        let m_sort = Sort::Array(Box::new(Sort::BitVector(1)), Box::new(row.clone()), 2);
+        let pair = |b: bool, u: usize| Value::Tuple(vec![Value::Bool(b), bv(u, 4)]);
+        let mut row_map = std::collections::BTreeMap::new();
+        row_map.insert(bv(1, 1), pair(true, 3));
+        let row_v = Value::Array(row.clone(), Box::new(pair(false, 0)), row_map, 2);
+        let mut m_map = std::collections::BTreeMap::new();
+        m_map.insert(bv(1, 1), row_v);
+        let empty_row = Value::Array(row, Box::new(pair(false, 0)), Default::default(), 2);
+        let m_v = Value::Array(m_sort.clone(), Box::new(empty_row), m_map, 2);
+
+        let mut cs = Computation::new(true);
+        let m = cs.new_var("m", m_sort, || m_v.clone(), Some(0));
+        let cell = term![Op::Select; term![Op::Select; m, bv_lit(1, 1)], bv_lit(1, 1)];
+        cs.assert(term![Op::Field(0); cell]);
+        flatten_nested(&mut cs);
+        assert!(!cs.metadata.inputs.contains_key("m"));
+        assert!(cs.metadata.inputs.contains_key("m.0"));
+        assert!(cs.metadata.inputs.contains_key("m.1"));
+        let vs = cs.values.as_ref().unwrap();
+        match vs.get("m.1").unwrap() {
+            Value::Array(Sort::Array(_, _, 4), _, map, 4) => {
+                assert_eq!(map.len(), 1);
+                assert_eq!(map.values().next().unwrap(), &bv(3, 4));
+            }
+            v => panic!("bad value {}", v),
+        }
+        let o = cs.outputs[0].clone();
+        assert_eq!(
+            o,
+            term![Op::Select;
+                leaf_term(Op::Var("m.0".to_owned(), flat_sort(&Sort::Array(
+                    Box::new(Sort::BitVector(1)),
+                    Box::new(Sort::Array(Box::new(Sort::BitVector(1)), Box::new(Sort::Bool), 2)),
+                    2
+                )))),
+                bv_lit(3, 3)
+            ]
+        );
+    }
+
+    #[test]
+    fn unguardable_keys_left_nested() {
+        let field = Sort::Field(std::sync::Arc::new(Integer::from(TEST_FIELD)));
+        let f = |i: usize| leaf_term(Op::Const(key_value(&field, i)));
+        let b2 = Sort::BitVector(2);
+        for (outer, inner) in vec![
+            (field.clone(), field.clone()),
+            (b2.clone(), field.clone()),
+            (field.clone(), b2.clone()),
+        ] {
+            let row = Sort::Array(Box::new(inner.clone()), Box::new(Sort::BitVector(4)), 3);
+            let m_sort = Sort::Array(Box::new(outer.clone()), Box::new(row), 3);
+            let key = |s: &Sort| if s == &field { f(1) } else { bv_lit(1, 2) };
+            let m = leaf_term(Op::Var("m".to_owned(), m_sort));
+            // An array of tuples, which could be flattened on its own
+            let pairs = leaf_term(Op::Var(
+                "p".to_owned(),
+                Sort::Array(
+                    Box::new(b2.clone()),
+                    Box::new(Sort::Tuple(vec![Sort::Bool, Sort::Bool])),
+                    4,
+                ),
+            ));
+            let mut cs = Computation::new(false);
+            cs.outputs = vec![
+                term![Op::Select; term![Op::Select; m, key(&outer)], key(&inner)],
+                term![Op::Field(0); term![Op::Select; pairs, bv_var("k", 2)]],
+            ];
+            let outputs = cs.outputs.clone();
+            flatten_nested(&mut cs);
+            assert_eq!(cs.outputs, outputs);
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        // a 3x3 matrix of zeros, with a[1][2] := 7, read at (x, y), where 3 is out of range
+        let row = term![Op::ConstArray(Sort::BitVector(2), 3); bv_lit(0, 4)];
+        let m = term![Op::ConstArray(Sort::BitVector(2), 3); row.clone()];
+        let (i, j) = (bv_lit(1, 2), bv_lit(2, 2));
+        let new_row = term![Op::Store; term![Op::Select; m.clone(), i.clone()], j, bv_lit(7, 4)];
+        let m2 = term![Op::Store; m, i, new_row];
+        let (x, y) = (bv_var("x", 2), bv_var("y", 2));
+        let read = term![Op::Select; term![Op::Select; m2.clone(), x.clone()], y];
+        // clearing row x changes nothing unless it is row 1
+        let same = term![Op::Eq; term![Op::Store; m2.clone(), x, row], m2];
+        let mut cs = Computation::new(false);
+        cs.outputs = vec![read.clone(), same];
+        flatten_nested(&mut cs);
+        for x in 0..4 {
+            for y in 0..4 {
+                let mut env = AHashMap::new();
+                env.insert("x".to_owned(), bv(x, 2));
+                env.insert("y".to_owned(), bv(y, 2));
+                assert_eq!(eval(&read, &env), eval(&cs.outputs[0], &env));
+                assert_eq!(eval(&cs.outputs[1], &env), Value::Bool(x != 1));
+            }
+        }
+    }
+}
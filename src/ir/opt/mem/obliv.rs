@@ -138,6 +138,11 @@ impl MemVisitor for Replacer {
                 .insert(orig.clone(), repeat(val).cloned().take(size).collect());
         }
     }
+    fn visit_const_value(&mut self, orig: &Term, val: &Value) {
+        if self.should_replace(orig) {
+            self.sequences.insert(orig.clone(), const_array_elems(val));
+        }
+    }
     fn visit_eq(&mut self, orig: &Term, _a: &Term, _b: &Term) -> Option<Term> {
         if let Some(a_seq) = self.sequences.get(&orig.cs[0]) {
             let b_seq = self.sequences.get(&orig.cs[1]).expect("inconsistent eq");
@@ -224,6 +229,33 @@ pub fn elim_obliv(t: &Term) -> Term {
     replace_pass.traverse(t)
 }
 
+/// The arrays in `outputs` which are not oblivious, treating those in `keep` as non-oblivious.
+pub(super) fn non_oblivious(outputs: &[Term], keep: &TermSet) -> TermSet {
+    let mut prop_pass = NonOblivComputer::new();
+    for k in keep {
+        prop_pass.not_obliv.insert(k.clone());
+    }
+    while prop_pass.check_progress() {
+        prop_pass.reset_progress();
+        for o in outputs {
+            prop_pass.traverse(o);
+        }
+    }
+    prop_pass.not_obliv
+}
+
+/// Eliminate oblivious arrays from `outputs`, except for those in `keep` (and arrays which are
+/// equi-oblivious with them). See module documentation.
+pub fn elim_obliv_except(outputs: &mut [Term], keep: &TermSet) {
+    let mut replace_pass = Replacer {
+        not_obliv: non_oblivious(outputs, keep),
+        sequences: TermMap::new(),
+    };
+    for o in outputs.iter_mut() {
+        *o = replace_pass.traverse(o);
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -270,4 +302,18 @@ mod test {
         let tt = elim_obliv(&t);
         assert!(!array_free(&tt));
     }
+
+    #[test]
+    fn except_kept() {
+        let z = term![Op::ConstArray(Sort::BitVector(4), 6); bv_lit(0, 4)];
+        let a = term![Op::Store; z.clone(), bv_lit(3, 4), bv_lit(1, 4)];
+        let t = term![Op::Select; a, bv_lit(3, 4)];
+        let mut ts = vec![t.clone()];
+        let mut keep = TermSet::new();
+        keep.insert(z);
+        elim_obliv_except(&mut ts, &keep);
+        assert_eq!(ts[0], t);
+        elim_obliv_except(&mut ts, &TermSet::new());
+        assert_eq!(ts[0], bv_lit(1, 4));
+    }
 }
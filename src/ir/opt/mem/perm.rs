@@ -0,0 +1,731 @@
+//! Permutation-based memory checking.
+//!
+//! The linear scans of [super::lin] cost O(n) per access, so O(n·m) for m accesses to an array
+//! of size n. For large arrays, we instead build a *trace*: one entry per access, holding the
+//! index, the time, the value, and whether the access is a write. The initial contents of the
+//! array are writes at time 0. The value of each read is a new witness variable.
+//!
+//! We sort the trace by (index, time) with a Beneš network: a network of 2x2 switches, each of
+//! which passes its two inputs through or swaps them. For some setting of the switches, the
+//! network puts its inputs in any given order, and for every setting, its outputs are a
+//! permutation of its inputs. The prover chooses the setting of each switch, as a witness
+//! variable, and we check that:
+//!
+//!    * the sorted trace is in order: each entry's (index, time) is at least the previous one's,
+//!      and
+//!    * each read in the sorted trace has the value of the entry before it, if that entry has the
+//!      same index, and otherwise the array's default value.
+//!
+//! Since the sorted trace is a permutation of the program-order trace whatever the prover does,
+//! these checks hold iff each read has the value that the array has at that time.
+//!
+//! The network needs a power of two inputs, so the trace is padded with entries which sort after
+//! all the others and are not checked. For a padded trace of N entries, the network has
+//! N·log(N) - N/2 switches, each choosing between two entries. Each check on the sorted trace is
+//! a comparison, which costs a constraint per bit in R1CS, so the total is O(N·log(N)) when
+//! indices have O(log N) bits.
+//!
+//! If the computation has values, this pass computes the witness (the values read and the switch
+//! settings). Otherwise, the prover must supply it: the witness program
+//! ([crate::target::r1cs::witness]) cannot compute it. The witness variables are private to the
+//! prover, so this is not a strategy for MPC backends.
+//!
+//! Reads agree with `select` ([Value::select]), for which an array is a total map: a read at an
+//! index past the end of the array gets the value last stored there, if any, and otherwise the
+//! array's default. That is the value of a constant array, the default of an array constant, or
+//! for an array variable (whose elements are the variables `NAME_0`, `NAME_1`, ...), the default
+//! value of the element sort.
+//!
+//! Only arrays whose versions form a single chain of stores are handled: the chain must start at
+//! a variable, a constant array, or an array constant, and no version may be used by anything
+//! except a `select` or the next `store`. Indices must be bit-vectors and values must be scalars.
+//! Other arrays are left alone, as are array constants which are never stored to: those are
+//! scanned ([super::lin]) or lowered as lookup tables ([super::plan]).
+
+use super::visit::const_array_elems;
+use crate::ir::proof::PROVER_ID;
+use crate::ir::term::extras::substitute_cache;
+use crate::ir::term::*;
+
+use ahash::AHashMap;
+use log::debug;
+use rug::Integer;
+
+/// A reasonable default for the size at which permutation checking is cheaper than linear scans.
+pub const DEFAULT_SIZE_THRESH: usize = 256;
+
+#[derive(Clone)]
+/// An access in a trace.
+struct Entry {
+    /// The index (a bit-vector)
+    idx: Term,
+    /// The time (a bit-vector)
+    time: Term,
+    /// The value read or written
+    val: Term,
+    /// Whether this is a write
+    write: Term,
+    /// Whether this entry only pads the trace
+    pad: Term,
+}
+
+impl Entry {
+    /// `other` if `swap`, and otherwise this entry.
+    fn or_if(&self, swap: &Term, other: &Entry) -> Entry {
+        let ite = |a: &Term, b: &Term| term![Op::Ite; swap.clone(), b.clone(), a.clone()];
+        Entry {
+            idx: ite(&self.idx, &other.idx),
+            time: ite(&self.time, &other.time),
+            val: ite(&self.val, &other.val),
+            write: ite(&self.write, &other.write),
+            pad: ite(&self.pad, &other.pad),
+        }
+    }
+
+    /// The key which the sorted trace is ordered by: padding last, and then (index, time).
+    fn key(&self) -> Term {
+        term![BV_CONCAT;
+            term![Op::BoolToBv; self.pad.clone()],
+            self.idx.clone(),
+            self.time.clone()]
+    }
+}
+
+/// How the array terms in some computation are used.
+pub(super) struct ArrayUses {
+    /// The stores built on each array
+    pub stores: TermMap<Vec<Term>>,
+    /// The selects from each array
+    pub selects: TermMap<Vec<Term>>,
+    /// Arrays with some other use
+    pub other: TermSet,
+    /// Array variables, constant arrays, and array constants
+    pub roots: Vec<Term>,
+}
+
+fn is_array(t: &Term) -> bool {
+    matches!(check(t), Sort::Array(..))
+}
+
+impl ArrayUses {
+    pub fn new(outputs: &[Term]) -> Self {
+        let mut uses = ArrayUses {
+            stores: TermMap::new(),
+            selects: TermMap::new(),
+            other: TermSet::new(),
+            roots: Vec::new(),
+        };
+        let mut seen = TermSet::new();
+        for o in outputs {
+            if is_array(o) {
+                uses.other.insert(o.clone());
+            }
+            for t in PostOrderIter::new(o.clone()) {
+                if !seen.insert(t.clone()) {
+                    continue;
+                }
+                match &t.op {
+                    Op::Store => {
+                        uses.stores
+                            .entry(t.cs[0].clone())
+                            .or_insert_with(Vec::new)
+                            .push(t.clone());
+                        if is_array(&t.cs[2]) {
+                            uses.other.insert(t.cs[2].clone());
+                        }
+                    }
+                    Op::Select => {
+                        uses.selects
+                            .entry(t.cs[0].clone())
+                            .or_insert_with(Vec::new)
+                            .push(t.clone());
+                    }
+                    Op::ConstArray(..)
+                    | Op::Var(_, Sort::Array(..))
+                    | Op::Const(Value::Array(..)) => {
+                        uses.roots.push(t.clone());
+                        if let Some(c) = t.cs.first().filter(|c| is_array(c)) {
+                            uses.other.insert(c.clone());
+                        }
+                    }
+                    _ => {
+                        for c in &t.cs {
+                            if is_array(c) {
+                                uses.other.insert(c.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        uses
+    }
+
+    /// The versions of the array rooted at `root`, if they form a chain of stores.
+    pub fn chain(&self, root: &Term) -> Option<Vec<Term>> {
+        let mut versions = vec![root.clone()];
+        loop {
+            let cur = versions.last().unwrap();
+            if self.other.contains(cur) {
+                return None;
+            }
+            match self.stores.get(cur).map(|s| s.as_slice()) {
+                None | Some([]) => return Some(versions),
+                Some([next]) => {
+                    let next = next.clone();
+                    versions.push(next);
+                }
+                Some(_) => return None,
+            }
+        }
+    }
+}
+
+pub(super) fn default_value(s: &Sort) -> Option<Term> {
+    match s {
+        Sort::Bool => Some(leaf_term(Op::Const(Value::Bool(false)))),
+        Sort::BitVector(w) => Some(bv_lit(0, *w)),
+        Sort::Field(m) => Some(leaf_term(Op::Const(Value::Field(FieldElem::new(
+            Integer::from(0),
+            m.clone(),
+        ))))),
+        _ => None,
+    }
+}
+
+/// The number of bits needed to represent `n`.
+fn bits(n: usize) -> usize {
+    let mut w = 1;
+    while n >> w > 0 {
+        w += 1;
+    }
+    w
+}
+
+/// Can arrays with indices of sort `key` and values of sort `val` be permutation-checked?
+pub(super) fn fits(key: &Sort, val: &Sort) -> bool {
+    matches!(key, Sort::BitVector(_))
+        && matches!(val, Sort::Bool | Sort::BitVector(_) | Sort::Field(_))
+}
+
+/// The size of the Beneš network for a trace of `n` entries: the number of entries, padded to a
+/// power of two, and the number of switches.
+pub(super) fn network_size(n: usize) -> (usize, usize) {
+    let padded = n.next_power_of_two().max(2);
+    let log = padded.trailing_zeros() as usize;
+    (padded, padded * log - padded / 2)
+}
+
+/// Send `inputs` (a power of two of them, at least two) through a Beneš network, whose switches
+/// are built by `switch`, which gets the two inputs of a switch and returns its two outputs.
+///
+/// The network for two inputs is one switch. For n > 2 inputs, a column of switches sends one of
+/// inputs 2i and 2i+1 to input i of an upper network for n/2 inputs, and the other to input i of a
+/// lower one. Then a second column of switches sends output i of each of those to outputs 2i and
+/// 2i+1. Switches are built in that order: the first column, the upper network, the lower
+/// network, and then the second column.
+fn benes<T>(inputs: &[T], switch: &mut impl FnMut(&T, &T) -> (T, T)) -> Vec<T> {
+    if inputs.len() == 2 {
+        let (a, b) = switch(&inputs[0], &inputs[1]);
+        return vec![a, b];
+    }
+    let (upper, lower): (Vec<T>, Vec<T>) = inputs.chunks(2).map(|p| switch(&p[0], &p[1])).unzip();
+    let upper = benes(&upper, switch);
+    let lower = benes(&lower, switch);
+    upper
+        .iter()
+        .zip(&lower)
+        .flat_map(|(u, l)| {
+            let (a, b) = switch(u, l);
+            vec![a, b]
+        })
+        .collect()
+}
+
+/// Append to `swaps` whether each switch of a Beneš network (in the order that [benes] builds
+/// them) swaps its inputs, to send each input `i` to output `dest[i]`.
+fn route(dest: &[usize], swaps: &mut Vec<bool>) {
+    let n = dest.len();
+    if n == 2 {
+        swaps.push(dest[0] == 1);
+        return;
+    }
+    let mut src = vec![0; n];
+    for (i, d) in dest.iter().enumerate() {
+        src[*d] = i;
+    }
+    // Whether each input goes through the lower network. The two inputs of a switch in the first
+    // column go through different networks, and so do the two inputs bound for a switch in the
+    // second column. Follow each cycle of these constraints, alternating between the networks.
+    let mut lower: Vec<Option<bool>> = vec![None; n];
+    for start in 0..n {
+        let mut i = start;
+        while lower[i].is_none() {
+            lower[i] = Some(false);
+            lower[i ^ 1] = Some(true);
+            i = src[dest[i ^ 1] ^ 1];
+        }
+    }
+    let half = n / 2;
+    let mut upper_dest = vec![0; half];
+    let mut lower_dest = vec![0; half];
+    let mut out_swaps = vec![false; half];
+    for s in 0..half {
+        let swap = lower[2 * s].unwrap();
+        swaps.push(swap);
+        let (u, l) = if swap {
+            (2 * s + 1, 2 * s)
+        } else {
+            (2 * s, 2 * s + 1)
+        };
+        upper_dest[s] = dest[u] / 2;
+        lower_dest[s] = dest[l] / 2;
+        out_swaps[dest[u] / 2] = dest[u] % 2 == 1;
+    }
+    route(&upper_dest, swaps);
+    route(&lower_dest, swaps);
+    swaps.extend(out_swaps);
+}
+
+/// The values of the witness variables for one array.
+struct Witness {
+    reads: Vec<Value>,
+    swaps: Vec<bool>,
+}
+
+/// Compute the witness for the trace whose entries have the given times and write flags, and
+/// whose indices and values are given by `terms`: `[idx0, val0, idx1, val1, ...]`. The trace is
+/// padded to `padded` entries.
+fn compute_witness(
+    times_writes: &[(usize, bool)],
+    terms: Vec<Term>,
+    values: &AHashMap<String, Value>,
+    padded: usize,
+) -> Witness {
+    let evaluated = eval(&term(Op::Tuple, terms), values);
+    let entries = evaluated.as_tuple().chunks(2).zip(times_writes);
+    let reads = entries
+        .clone()
+        .filter(|(_, (_, write))| !write)
+        .map(|(iv, _)| iv[1].clone())
+        .collect();
+    // (pad, index, time) for each entry
+    let mut keys: Vec<(bool, Integer, usize)> = entries
+        .map(|(iv, (time, _))| (false, iv[0].as_bv().uint().clone(), *time))
+        .collect();
+    keys.resize(padded, (true, Integer::from(0), 0));
+    let mut order: Vec<usize> = (0..padded).collect();
+    order.sort_by(|a, b| keys[*a].cmp(&keys[*b]));
+    let mut dest = vec![0; padded];
+    for (position, i) in order.into_iter().enumerate() {
+        dest[i] = position;
+    }
+    let mut swaps = Vec::new();
+    route(&dest, &mut swaps);
+    Witness { reads, swaps }
+}
+
+/// A prefix for the names of the witness variables of a permutation-checked array.
+fn fresh_prefix(cs: &Computation) -> String {
+    (0..)
+        .map(|i| format!("__perm{}", i))
+        .find(|p| !cs.metadata.inputs.contains_key(&format!("{}_switch0", p)))
+        .unwrap()
+}
+
+/// Permutation-check the array with these versions (and selects from them), adding assertions
+/// to `cs`. Returns the witness variable which replaces each select.
+fn check_array(
+    cs: &mut Computation,
+    versions: &[Term],
+    selects: &TermMap<Vec<Term>>,
+    idx_width: usize,
+    val_sort: &Sort,
+    size: usize,
+) -> Vec<(Term, Term)> {
+    let root = &versions[0];
+    let (root_elems, default) = match &root.op {
+        Op::Const(v @ Value::Array(_, default, ..)) => (
+            Some(const_array_elems(v)),
+            leaf_term(Op::Const((**default).clone())),
+        ),
+        Op::ConstArray(..) => (None, root.cs[0].clone()),
+        _ => (None, default_value(val_sort).unwrap()),
+    };
+    let time_width = bits(2 * versions.len() + 1);
+    let tru = leaf_term(Op::Const(Value::Bool(true)));
+    let fls = leaf_term(Op::Const(Value::Bool(false)));
+
+    // The program-order trace, with the terms giving the index and value of each entry under the
+    // semantics of arrays. Reads from version j are at time 2j+1; the write making version j+1 is
+    // at time 2j+2.
+    let mut trace: Vec<Entry> = Vec::new();
+    let mut times_writes = Vec::new();
+    let mut origins = Vec::new();
+    for i in 0..size {
+        let idx = bv_lit(i, idx_width);
+        trace.push(Entry {
+            idx: idx.clone(),
+            time: bv_lit(0, time_width),
+            val: match &root.op {
+                Op::Var(name, _) => leaf_term(Op::Var(format!("{}_{}", name, i), val_sort.clone())),
+                Op::Const(_) => root_elems.as_ref().unwrap()[i].clone(),
+                _ => root.cs[0].clone(),
+            },
+            write: tru.clone(),
+            pad: fls.clone(),
+        });
+        times_writes.push((0, true));
+        origins.extend(vec![idx.clone(), term![Op::Select; root.clone(), idx]]);
+    }
+    let mut reads = Vec::new();
+    for (j, v) in versions.iter().enumerate() {
+        for s in selects.get(v).into_iter().flatten() {
+            reads.push((trace.len(), s.clone()));
+            trace.push(Entry {
+                idx: s.cs[1].clone(),
+                time: bv_lit(2 * j + 1, time_width),
+                // Replaced by a witness variable below
+                val: s.clone(),
+                write: fls.clone(),
+                pad: fls.clone(),
+            });
+            times_writes.push((2 * j + 1, false));
+            origins.extend(vec![s.cs[1].clone(), s.clone()]);
+        }
+        if let Some(next) = versions.get(j + 1) {
+            trace.push(Entry {
+                idx: next.cs[1].clone(),
+                time: bv_lit(2 * j + 2, time_width),
+                val: next.cs[2].clone(),
+                write: tru.clone(),
+                pad: fls.clone(),
+            });
+            times_writes.push((2 * j + 2, true));
+            origins.extend(vec![next.cs[1].clone(), next.cs[2].clone()]);
+        }
+    }
+    let (padded, _) = network_size(trace.len());
+    trace.resize(
+        padded,
+        Entry {
+            idx: bv_lit(0, idx_width),
+            time: bv_lit(0, time_width),
+            val: default.clone(),
+            write: tru.clone(),
+            pad: tru,
+        },
+    );
+
+    let witness = cs
+        .values
+        .as_ref()
+        .map(|values| compute_witness(&times_writes, origins, values, padded));
+    let w = || witness.as_ref().unwrap();
+    let prefix = fresh_prefix(cs);
+    let prover = Some(PROVER_ID);
+    let mut subs = Vec::new();
+    for (k, (pos, s)) in reads.into_iter().enumerate() {
+        let name = format!("{}_read{}", prefix, k);
+        let var = cs.new_var(&name, val_sort.clone(), || w().reads[k].clone(), prover);
+        trace[pos].val = var.clone();
+        subs.push((s, var));
+    }
+    let mut n_switches = 0;
+    let sorted = benes(&trace, &mut |a: &Entry, b: &Entry| {
+        let k = n_switches;
+        n_switches += 1;
+        let name = format!("{}_switch{}", prefix, k);
+        let swap = cs.new_var(&name, Sort::Bool, || Value::Bool(w().swaps[k]), prover);
+        (a.or_if(&swap, b), b.or_if(&swap, a))
+    });
+
+    for (k, e) in sorted.iter().enumerate() {
+        let is_default = term![EQ; e.val.clone(), default.clone()];
+        let read_ok = match k.checked_sub(1).map(|k| &sorted[k]) {
+            Some(prev) => {
+                cs.assert(term![BV_ULE; prev.key(), e.key()]);
+                term![ITE;
+                    term![EQ; prev.idx.clone(), e.idx.clone()],
+                    term![EQ; prev.val.clone(), e.val.clone()],
+                    is_default
+                ]
+            }
+            None => is_default,
+        };
+        cs.assert(term![OR; e.write.clone(), e.pad.clone(), read_ok]);
+    }
+    subs
+}
+
+/// Eliminate arrays of at least `size_thresh` elements from `cs` by permutation checking. See
+/// module documentation.
+pub fn check_by_permutation(cs: &mut Computation, size_thresh: usize) {
+    permute(cs, |_, size| size >= size_thresh)
+}
+
+/// Eliminate the arrays rooted at `roots` (array variables, constant arrays, or array constants)
+/// from `cs` by permutation checking. See module documentation.
+pub fn check_roots_by_permutation(cs: &mut Computation, roots: &TermSet) {
+    permute(cs, |root, _| roots.contains(root))
+}
+
+/// Permutation-check the arrays whose root and size satisfy `pick`.
+fn permute(cs: &mut Computation, pick: impl Fn(&Term, usize) -> bool) {
+    let uses = ArrayUses::new(&cs.outputs);
+    let mut subs = TermMap::new();
+    for root in &uses.roots {
+        if let Sort::Array(k, v, size) = check(root) {
+            if !pick(root, size) || !fits(&k, &v) {
+                continue;
+            }
+            if let Some(versions) = uses.chain(root) {
+                if versions.len() == 1 && matches!(root.op, Op::Const(_)) {
+                    continue;
+                }
+                let n_selects: usize = versions
+                    .iter()
+                    .map(|v| uses.selects.get(v).map(|s| s.len()).unwrap_or(0))
+                    .sum();
+                debug!(
+                    "Permutation checking an array of size {} with {} stores and {} selects",
+                    size,
+                    versions.len() - 1,
+                    n_selects
+                );
+                let idx_width = k.as_bv();
+                subs.extend(check_array(
+                    cs,
+                    &versions,
+                    &uses.selects,
+                    idx_width,
+                    &v,
+                    size,
+                ));
+            }
+        }
+    }
+    if !subs.is_empty() {
+        for o in cs.outputs.iter_mut() {
+            *o = substitute_cache(o, &mut subs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::collections::BTreeMap;
+
+    fn array_free(t: &Term) -> bool {
+        PostOrderIter::new(t.clone()).all(|c| !is_array(&c))
+    }
+
+    fn bv_var(name: &str, w: usize) -> Term {
+        leaf_term(Op::Var(name.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    /// A chain of stores to and selects from an array `a` of 4-bit values, with variable 3-bit
+    /// indices.
+    fn chain(a: Term) -> Term {
+        let a1 = term![Op::Store; a.clone(), bv_var("i0", 3), bv_var("v0", 4)];
+        let a2 = term![Op::Store; a1.clone(), bv_var("i1", 3), bv_var("v1", 4)];
+        let a3 = term![Op::Store; a2.clone(), bv_var("i2", 3), bv_var("v2", 4)];
+        term![BV_ADD;
+            term![Op::Select; a.clone(), bv_var("j0", 3)],
+            term![Op::Select; a1, bv_var("j1", 3)],
+            term![Op::Select; a2.clone(), bv_var("j2", 3)],
+            term![Op::Select; a2, bv_var("j3", 3)],
+            term![Op::Select; a3, bv_var("j4", 3)]
+        ]
+    }
+
+    fn var_array(size: usize) -> Term {
+        leaf_term(Op::Var(
+            "a".to_owned(),
+            Sort::Array(
+                Box::new(Sort::BitVector(3)),
+                Box::new(Sort::BitVector(4)),
+                size,
+            ),
+        ))
+    }
+
+    /// Random values for the indices and values of [chain], and for the array variable `a`.
+    fn random_env(rng: &mut StdRng) -> AHashMap<String, Value> {
+        let mut env = AHashMap::new();
+        for i in 0..5 {
+            for &(name, w) in &[("i", 3usize), ("j", 3), ("v", 4)] {
+                env.insert(format!("{}{}", name, i), bv(rng.gen_range(0..(1 << w)), w));
+            }
+        }
+        let mut elems = BTreeMap::new();
+        for i in 0..8 {
+            let v = bv(rng.gen_range(0..16), 4);
+            env.insert(format!("a_{}", i), v.clone());
+            elems.insert(bv(i, 3), v);
+        }
+        let sort = check(&var_array(8));
+        env.insert(
+            "a".to_owned(),
+            Value::Array(sort, Box::new(bv(0, 4)), elems, 8),
+        );
+        env
+    }
+
+    /// A computation asserting that `t` has its value under `env`.
+    fn computation(t: Term, env: AHashMap<String, Value>) -> Computation {
+        let mut cs = Computation::new(false);
+        let v = eval(&t, &env);
+        cs.outputs.push(term![EQ; t, leaf_term(Op::Const(v))]);
+        cs.values = Some(env);
+        cs
+    }
+
+    fn holds(outputs: &[Term], values: &AHashMap<String, Value>) -> bool {
+        outputs.iter().all(|o| eval(o, values).as_bool())
+    }
+
+    /// Permutation-check `t` under random values. The witness must satisfy the assertions, and
+    /// a prover which changes a read value must not.
+    fn check_chain(t: Term) {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..50 {
+            let mut cs = computation(t.clone(), random_env(&mut rng));
+            check_by_permutation(&mut cs, 0);
+            assert!(cs.outputs.iter().all(array_free));
+            let values = cs.values.clone().unwrap();
+            assert!(holds(&cs.outputs, &values));
+            let checks = &cs.outputs[1..];
+            for k in 0..5 {
+                let read = format!("__perm0_read{}", k);
+                let mut cheat = values.clone();
+                let old = cheat[&read].clone();
+                let new = bv((old.as_bv().uint().to_usize().unwrap() + 1) % 16, 4);
+                cheat.insert(read, new);
+                assert!(!holds(checks, &cheat));
+            }
+        }
+    }
+
+    #[test]
+    fn var_chain() {
+        check_chain(chain(var_array(8)));
+    }
+
+    #[test]
+    fn const_chain() {
+        let a = term![Op::ConstArray(Sort::BitVector(3), 8); bv_lit(5, 4)];
+        check_chain(chain(a));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        // Indices 5, 6, and 7 are past the end.
+        let a = term![Op::ConstArray(Sort::BitVector(3), 5); bv_lit(5, 4)];
+        check_chain(chain(a));
+    }
+
+    #[test]
+    fn below_thresh() {
+        let a = term![Op::ConstArray(Sort::BitVector(3), 8); bv_lit(5, 4)];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut cs = computation(chain(a), random_env(&mut rng));
+        let outputs = cs.outputs.clone();
+        check_by_permutation(&mut cs, 9);
+        assert_eq!(cs.outputs, outputs);
+    }
+
+    #[test]
+    fn routes() {
+        let mut rng = StdRng::seed_from_u64(0);
+        for &n in &[2, 4, 8, 16, 32] {
+            for _ in 0..20 {
+                let mut dest: Vec<usize> = (0..n).collect();
+                dest.shuffle(&mut rng);
+                let mut swaps = Vec::new();
+                route(&dest, &mut swaps);
+                assert_eq!((n, swaps.len()), network_size(n));
+                let mut swaps = swaps.into_iter();
+                let inputs: Vec<usize> = (0..n).collect();
+                let outputs = benes(&inputs, &mut |a: &usize, b: &usize| {
+                    if swaps.next().unwrap() {
+                        (*b, *a)
+                    } else {
+                        (*a, *b)
+                    }
+                });
+                for i in 0..n {
+                    assert_eq!(outputs[dest[i]], i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn no_forged_reads() {
+        // An array of size 2, one store, and one read: a trace of 4 entries, and 6 switches.
+        let sort = Sort::Array(
+            Box::new(Sort::BitVector(1)),
+            Box::new(Sort::BitVector(4)),
+            2,
+        );
+        let a = leaf_term(Op::Var("a".to_owned(), sort.clone()));
+        let a1 = term![Op::Store; a, bv_var("i0", 1), bv_var("v0", 4)];
+        let t = term![Op::Select; a1, bv_var("j0", 1)];
+        let mut env = AHashMap::new();
+        let mut elems = BTreeMap::new();
+        for (i, v) in vec![(0, 3), (1, 7)] {
+            env.insert(format!("a_{}", i), bv(v, 4));
+            elems.insert(bv(i, 1), bv(v, 4));
+        }
+        env.insert(
+            "a".to_owned(),
+            Value::Array(sort, Box::new(bv(0, 4)), elems, 2),
+        );
+        env.insert("i0".to_owned(), bv(1, 1));
+        env.insert("v0".to_owned(), bv(9, 4));
+        env.insert("j0".to_owned(), bv(0, 1));
+        let mut cs = computation(t, env);
+        check_by_permutation(&mut cs, 0);
+        let values = cs.values.clone().unwrap();
+        assert_eq!(values["__perm0_read0"], bv(3, 4));
+        let checks = &cs.outputs[1..];
+        // The checks hold for some setting of the switches iff the read is 3.
+        for read in 0..16 {
+            let mut env = values.clone();
+            env.insert("__perm0_read0".to_owned(), bv(read, 4));
+            let satisfiable = (0..1 << 6).any(|setting: usize| {
+                for k in 0..6 {
+                    let swap = Value::Bool(setting >> k & 1 == 1);
+                    env.insert(format!("__perm0_switch{}", k), swap);
+                }
+                holds(checks, &env)
+            });
+            assert_eq!(satisfiable, read == 3);
+        }
+    }
+
+    #[test]
+    fn branching_untouched() {
+        let a = term![Op::ConstArray(Sort::BitVector(3), 8); bv_lit(5, 4)];
+        let b = term![Op::Store; a.clone(), bv_var("i0", 3), bv_var("v0", 4)];
+        let c = term![Op::Store; a, bv_var("i1", 3), bv_var("v1", 4)];
+        let t = term![BV_ADD;
+            term![Op::Select; b, bv_var("j0", 3)],
+            term![Op::Select; c, bv_var("j1", 3)]
+        ];
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut cs = computation(t, random_env(&mut rng));
+        let outputs = cs.outputs.clone();
+        check_by_permutation(&mut cs, 0);
+        assert_eq!(cs.outputs, outputs);
+    }
+}
@@ -0,0 +1,523 @@
+//! Choosing how to eliminate each array
+//!
+//! [super::array_elim_all] runs every elimination strategy in a fixed order, with one size
+//! threshold for all arrays. Instead, [eliminate] looks at each array (its size, its selects and
+//! stores, its index sort, and whether its indices and contents are constant), estimates the cost
+//! of each strategy that applies with a [CostModel], and uses the cheapest:
+//!
+//!    * [Strategy::Oblivious]: every access is at a constant index, so the array becomes a list
+//!      of terms ([super::obliv]). Free.
+//!    * [Strategy::Lookup]: an array constant that is never stored to is left for the backend to
+//!      lower as a lookup table.
+//!    * [Strategy::Linear]: each access scans the whole array ([super::lin]). Always applies.
+//!    * [Strategy::Permutation]: accesses are checked against a trace sorted by a routing network
+//!      whose switches the prover sets ([super::perm]). Needs a single chain of stores, bit-vector
+//!      indices, and scalar values. Its witness is private to the prover, so it only applies to
+//!      R1CS, and since the witness program cannot compute that witness, it is never chosen
+//!      automatically: only by an override.
+//!
+//! Arrays are named by the variable at their root. Users can override the choice for an array
+//! variable; an override which does not apply is ignored, with a warning.
+
+use super::{lin, nest, obliv, perm};
+use crate::ir::cost::CostModel;
+use crate::ir::opt::ite::CostTarget;
+use crate::ir::opt::pass::Pass;
+use crate::ir::opt::Opt;
+use crate::ir::term::*;
+
+use ahash::AHashMap;
+use log::{debug, warn};
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// A way to eliminate an array. See module documentation.
+pub enum Strategy {
+    /// Replace the array with a list of terms
+    Oblivious,
+    /// Leave selects for the backend's lookup tables
+    Lookup,
+    /// Scan the array on each access
+    Linear,
+    /// Check accesses by permutation
+    Permutation,
+}
+
+impl Strategy {
+    /// All strategies, in the order that breaks ties between equal costs.
+    pub fn all() -> Vec<Strategy> {
+        vec![
+            Strategy::Oblivious,
+            Strategy::Lookup,
+            Strategy::Linear,
+            Strategy::Permutation,
+        ]
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Strategy::Oblivious => write!(f, "oblivious"),
+            Strategy::Lookup => write!(f, "lookup"),
+            Strategy::Linear => write!(f, "linear"),
+            Strategy::Permutation => write!(f, "permutation"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("unknown array strategy '{0}'; expected oblivious, lookup, linear, or permutation")]
+/// A strategy name which could not be parsed.
+pub struct UnknownStrategy(pub String);
+
+impl FromStr for Strategy {
+    type Err = UnknownStrategy;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oblivious" | "obliv" => Ok(Strategy::Oblivious),
+            "lookup" => Ok(Strategy::Lookup),
+            "linear" | "lin" => Ok(Strategy::Linear),
+            "permutation" | "perm" => Ok(Strategy::Permutation),
+            _ => Err(UnknownStrategy(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// What the planner knows about an array.
+pub struct ArrayInfo {
+    /// The array variable at the root, or a description of the constant there
+    pub name: String,
+    /// The number of elements
+    pub size: usize,
+    /// Stores built (transitively) on the root
+    pub stores: usize,
+    /// Selects from the root or the stores built on it
+    pub selects: usize,
+    /// Whether every access is at a constant index
+    pub oblivious: bool,
+    /// Whether the root is an array constant
+    pub constant: bool,
+    /// Whether permutation checking can handle the array
+    pub permutable: bool,
+}
+
+#[derive(Debug, Clone)]
+/// How the planner chose to eliminate an array.
+pub struct Decision {
+    /// The array variable, constant array, or array constant at the root of the array
+    pub root: Term,
+    /// What the planner knows about the array
+    pub info: ArrayInfo,
+    /// The estimated cost of each strategy that applies
+    pub costs: Vec<(Strategy, usize)>,
+    /// The chosen strategy
+    pub strategy: Strategy,
+    /// Whether the strategy was chosen by the user
+    pub overridden: bool,
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({} elements, {} stores, {} selects): {}",
+            self.info.name, self.info.size, self.info.stores, self.info.selects, self.strategy
+        )?;
+        if self.overridden {
+            write!(f, " (override)")?;
+        }
+        let costs: Vec<String> = self
+            .costs
+            .iter()
+            .map(|(s, c)| format!("{} {}", s, c))
+            .collect();
+        write!(f, "; estimated costs: {}", costs.join(", "))
+    }
+}
+
+/// A term of sort `s`, for asking the cost model about operators.
+fn sample(s: &Sort) -> Term {
+    leaf_term(Op::Var("sample".to_owned(), s.clone()))
+}
+
+/// The estimated cost of each strategy which applies to an array, for `target`.
+fn costs(info: &ArrayInfo, key: &Sort, val: &Sort, target: CostTarget) -> Vec<(Strategy, usize)> {
+    let model: &dyn CostModel = target.model();
+    let mux = model.mux_cost(val);
+    let accesses = info.selects + info.stores;
+    let mut costs = Vec::new();
+    for s in Strategy::all() {
+        let cost = match s {
+            Strategy::Oblivious if info.oblivious => 0,
+            Strategy::Lookup if info.constant && info.stores == 0 => info.selects * info.size * mux,
+            Strategy::Linear => {
+                let eq = model.op_cost(&term![Op::Eq; sample(key), sample(key)]);
+                accesses * info.size * (eq + mux)
+            }
+            Strategy::Permutation if info.permutable && target == CostTarget::R1cs => {
+                let binary =
+                    |op: Op, s: &Sort| model.op_cost(&term(op, vec![sample(s), sample(s)]));
+                let time_width = (usize::BITS - (2 * info.stores + 3).leading_zeros()) as usize;
+                let time_sort = Sort::BitVector(time_width);
+                let key_sort = Sort::BitVector(1 + key.as_bv() + time_width);
+                let (entries, switches) = perm::network_size(info.size + accesses);
+                // Each switch chooses between two entries, twice.
+                let entry = Sort::Tuple(vec![
+                    key.clone(),
+                    time_sort,
+                    val.clone(),
+                    Sort::Bool,
+                    Sort::Bool,
+                ]);
+                let switch = 2 * model.mux_cost(&entry);
+                // Each sorted entry is ordered after the previous one, and (if a read) checked
+                // against the previous one's value or the default.
+                let sorted = binary(BV_ULE, &key_sort)
+                    + binary(EQ, key)
+                    + 2 * binary(EQ, val)
+                    + model.mux_cost(&Sort::Bool)
+                    + model.op_cost(
+                        &term![OR; sample(&Sort::Bool), sample(&Sort::Bool), sample(&Sort::Bool)],
+                    );
+                switches * switch + entries * sorted
+            }
+            _ => continue,
+        };
+        costs.push((s, cost));
+    }
+    costs
+}
+
+/// Choose a strategy for each array in `cs`, for `target`. See module documentation.
+pub fn plan(
+    cs: &Computation,
+    target: CostTarget,
+    overrides: &AHashMap<String, Strategy>,
+) -> Vec<Decision> {
+    let uses = perm::ArrayUses::new(&cs.outputs);
+    let non_oblivious = obliv::non_oblivious(&cs.outputs, &TermSet::new());
+    let mut decisions = Vec::new();
+    for root in &uses.roots {
+        let (key, val, size) = match check(root) {
+            Sort::Array(k, v, size) => ((*k).clone(), (*v).clone(), size),
+            _ => unreachable!(),
+        };
+        // All versions built by stores, whether or not they form a chain
+        let mut versions = vec![root.clone()];
+        let mut i = 0;
+        while i < versions.len() {
+            versions.extend(uses.stores.get(&versions[i]).into_iter().flatten().cloned());
+            i += 1;
+        }
+        let selects = versions
+            .iter()
+            .map(|v| uses.selects.get(v).map_or(0, |s| s.len()))
+            .sum();
+        let constant = matches!(root.op, Op::Const(_));
+        let info = ArrayInfo {
+            name: match &root.op {
+                Op::Var(name, _) => name.clone(),
+                Op::Const(_) => "(array constant)".to_owned(),
+                _ => "(constant array)".to_owned(),
+            },
+            size,
+            stores: versions.len() - 1,
+            selects,
+            oblivious: !non_oblivious.contains(root),
+            constant,
+            permutable: perm::fits(&key, &val)
+                && uses.chain(root).is_some()
+                && !(constant && versions.len() == 1),
+        };
+        let costs = costs(&info, &key, &val, target);
+        let requested = match &root.op {
+            Op::Var(name, _) => overrides.get(name),
+            _ => None,
+        };
+        let (strategy, overridden) = match requested {
+            Some(s) if costs.iter().any(|(c, _)| c == s) => (*s, true),
+            _ => {
+                if let Some(s) = requested {
+                    warn!(
+                        "Cannot eliminate array {} by {}; choosing a strategy",
+                        info.name, s
+                    );
+                }
+                let cheapest = costs
+                    .iter()
+                    .filter(|(s, _)| *s != Strategy::Permutation)
+                    .min_by_key(|(_, c)| *c)
+                    .unwrap();
+                (cheapest.0, false)
+            }
+        };
+        decisions.push(Decision {
+            root: root.clone(),
+            info,
+            costs,
+            strategy,
+            overridden,
+        });
+    }
+    decisions
+}
+
+/// Eliminate the arrays in `cs`, choosing a strategy for each, for `target`. Returns the
+/// decisions made.
+pub fn eliminate(
+    cs: &mut Computation,
+    target: CostTarget,
+    overrides: &AHashMap<String, Strategy>,
+) -> Vec<Decision> {
+    let mut decisions = plan(cs, target, overrides);
+    let mut keep = TermSet::new();
+    let mut permuted = TermSet::new();
+    let mut tables = TermSet::new();
+    for d in &decisions {
+        match d.strategy {
+            Strategy::Oblivious => {}
+            Strategy::Lookup => {
+                keep.insert(d.root.clone());
+                tables.insert(d.root.clone());
+            }
+            Strategy::Linear => {
+                keep.insert(d.root.clone());
+            }
+            Strategy::Permutation => {
+                keep.insert(d.root.clone());
+                permuted.insert(d.root.clone());
+            }
+        }
+    }
+    // An oblivious array mixed (by ite or =) with one kept for another strategy gets scanned.
+    let non_oblivious = obliv::non_oblivious(&cs.outputs, &keep);
+    for d in &mut decisions {
+        if d.strategy == Strategy::Oblivious && non_oblivious.contains(&d.root) {
+            d.strategy = Strategy::Linear;
+        }
+    }
+    obliv::elim_obliv_except(&mut cs.outputs, &keep);
+    perm::check_roots_by_permutation(cs, &permuted);
+    for o in cs.outputs.iter_mut() {
+        *o = lin::linearize_keeping(o, usize::MAX, &tables);
+    }
+    decisions
+}
+
+/// Memory elimination with a strategy chosen for each array, as a [Pass]. See module
+/// documentation.
+///
+/// [PassManager](crate::ir::opt::pass::PassManager)s register one without overrides for each
+/// [CostTarget]; register another to replace it.
+pub struct PlannedArrayElim {
+    target: CostTarget,
+    overrides: AHashMap<String, Strategy>,
+}
+
+impl PlannedArrayElim {
+    /// Choose strategies with the cost model of `target`.
+    pub fn new(target: CostTarget) -> Self {
+        Self {
+            target,
+            overrides: AHashMap::new(),
+        }
+    }
+
+    /// Eliminate the array variable `name` by `strategy`, if it applies.
+    pub fn set_override(&mut self, name: String, strategy: Strategy) {
+        self.overrides.insert(name, strategy);
+    }
+}
+
+impl Pass for PlannedArrayElim {
+    fn name(&self) -> Cow<str> {
+        Opt::MemAuto(self.target).name()
+    }
+
+    fn run(&self, mut cs: Computation) -> Computation {
+        nest::flatten_nested(&mut cs);
+        for d in eliminate(&mut cs, self.target, &self.overrides) {
+            debug!("Array {}", d);
+        }
+        cs
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::opt::cfold::fold;
+    use rug::Integer;
+
+    fn array_free(t: &Term) -> bool {
+        PostOrderIter::new(t.clone()).all(|c| !matches!(check(&c), Sort::Array(..)))
+    }
+
+    fn bv_var(name: &str, w: usize) -> Term {
+        leaf_term(Op::Var(name.to_owned(), Sort::BitVector(w)))
+    }
+
+    fn array_var() -> Term {
+        leaf_term(Op::Var(
+            "a".to_owned(),
+            Sort::Array(
+                Box::new(Sort::BitVector(2)),
+                Box::new(Sort::BitVector(4)),
+                4,
+            ),
+        ))
+    }
+
+    fn bv(u: usize, w: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), w))
+    }
+
+    /// Eliminate the arrays in a computation, returning it and the decision.
+    fn run_cs(
+        outputs: Vec<Term>,
+        values: Option<AHashMap<String, Value>>,
+        overrides: &[(&str, Strategy)],
+    ) -> (Computation, Decision) {
+        let overrides = overrides.iter().map(|(n, s)| (n.to_string(), *s)).collect();
+        let mut cs = Computation::new(false);
+        cs.outputs = outputs;
+        cs.values = values;
+        let mut ds = eliminate(&mut cs, CostTarget::R1cs, &overrides);
+        assert_eq!(ds.len(), 1);
+        (cs, ds.pop().unwrap())
+    }
+
+    fn run(t: &Term, overrides: &[(&str, Strategy)]) -> (Term, Decision) {
+        let (cs, d) = run_cs(vec![t.clone()], None, overrides);
+        (cs.outputs[0].clone(), d)
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("perm".parse(), Ok(Strategy::Permutation));
+        for s in Strategy::all() {
+            assert_eq!(format!("{}", s).parse(), Ok(s));
+        }
+        assert_eq!(
+            "sorted".parse::<Strategy>(),
+            Err(UnknownStrategy("sorted".to_owned()))
+        );
+    }
+
+    #[test]
+    fn constant_indices() {
+        let v = bv_var("v", 4);
+        let a = term![Op::Store; array_var(), bv_lit(1, 2), v.clone()];
+        let t = term![Op::Select; a, bv_lit(1, 2)];
+        let (tt, d) = run(&t, &[]);
+        assert_eq!(d.strategy, Strategy::Oblivious);
+        assert_eq!(d.info.stores, 1);
+        assert_eq!(tt, v);
+    }
+
+    #[test]
+    fn small_array_scanned() {
+        let t = term![Op::Select; array_var(), bv_var("k", 2)];
+        let (tt, d) = run(&t, &[]);
+        assert_eq!(d.info.name, "a");
+        assert_eq!(d.strategy, Strategy::Linear);
+        assert!(!d.overridden);
+        let cost = |s| d.costs.iter().find(|(c, _)| *c == s).unwrap().1;
+        assert!(cost(Strategy::Linear) < cost(Strategy::Permutation));
+        assert!(array_free(&tt));
+    }
+
+    #[test]
+    fn table_looked_up() {
+        let z = term![Op::ConstArray(Sort::BitVector(2), 4); bv_lit(0, 4)];
+        let table = fold(&term![Op::Store; z, bv_lit(1, 2), bv_lit(5, 4)]);
+        let t = term![Op::Select; table, bv_var("k", 2)];
+        let (tt, d) = run(&t, &[]);
+        assert_eq!(d.strategy, Strategy::Lookup);
+        assert_eq!(tt, t);
+        let (tt, d) = run(&t, &[("(array constant)", Strategy::Linear)]);
+        // Only variables can be overridden
+        assert_eq!(d.strategy, Strategy::Lookup);
+        assert_eq!(tt, t);
+    }
+
+    #[test]
+    fn overrides() {
+        let t = term![Op::Select;
+            term![Op::Store; array_var(), bv_var("i", 2), bv_var("v", 4)],
+            bv_var("k", 2)];
+        let (lin, _) = run(&t, &[]);
+        for (i, k) in vec![(0, 0), (1, 2), (3, 3)] {
+            let mut env = AHashMap::new();
+            let mut elems = std::collections::BTreeMap::new();
+            for j in 0..4 {
+                env.insert(format!("a_{}", j), bv(j + 8, 4));
+                elems.insert(bv(j, 2), bv(j + 8, 4));
+            }
+            let sort = check(&array_var());
+            env.insert(
+                "a".to_owned(),
+                Value::Array(sort, Box::new(bv(0, 4)), elems, 4),
+            );
+            env.insert("i".to_owned(), bv(i, 2));
+            env.insert("k".to_owned(), bv(k, 2));
+            env.insert("v".to_owned(), bv(1, 4));
+            let out = leaf_term(Op::Const(eval(&lin, &env)));
+            let (cs, d) = run_cs(
+                vec![term![EQ; t.clone(), out]],
+                Some(env),
+                &[("a", Strategy::Permutation)],
+            );
+            assert_eq!(d.strategy, Strategy::Permutation);
+            assert!(d.overridden);
+            assert!(cs.outputs.iter().all(array_free));
+            let values = cs.values.as_ref().unwrap();
+            assert!(cs.outputs.iter().all(|o| eval(o, values).as_bool()));
+        }
+        // Lookup does not apply to variables
+        let (_, d) = run(&t, &[("a", Strategy::Lookup)]);
+        assert_eq!(d.strategy, Strategy::Linear);
+        assert!(!d.overridden);
+    }
+
+    #[test]
+    fn permutation_by_override_only() {
+        // A large array with many accesses, for which permutation checking is cheaper
+        let sort = Sort::Array(
+            Box::new(Sort::BitVector(8)),
+            Box::new(Sort::BitVector(8)),
+            256,
+        );
+        let a = leaf_term(Op::Var("a".to_owned(), sort));
+        let selects = (0..256)
+            .map(|i| term![Op::Select; a.clone(), bv_var(&format!("k{}", i), 8)])
+            .collect();
+        let mut cs = Computation::new(false);
+        cs.outputs.push(term(BV_ADD, selects));
+        let plan_one = |target, overrides: &[(&str, Strategy)]| {
+            let overrides = overrides.iter().map(|(n, s)| (n.to_string(), *s)).collect();
+            let mut ds = plan(&cs, target, &overrides);
+            assert_eq!(ds.len(), 1);
+            ds.pop().unwrap()
+        };
+        let d = plan_one(CostTarget::R1cs, &[]);
+        let cost = |s| d.costs.iter().find(|(c, _)| *c == s).unwrap().1;
+        assert!(cost(Strategy::Permutation) < cost(Strategy::Linear));
+        assert_eq!(d.strategy, Strategy::Linear);
+        let d = plan_one(CostTarget::R1cs, &[("a", Strategy::Permutation)]);
+        assert_eq!(d.strategy, Strategy::Permutation);
+        assert!(d.overridden);
+        // Not for ABY, even by override
+        for overrides in &[vec![], vec![("a", Strategy::Permutation)]] {
+            let d = plan_one(CostTarget::Aby, overrides);
+            assert!(d.costs.iter().all(|(s, _)| *s != Strategy::Permutation));
+            assert_eq!(d.strategy, Strategy::Linear);
+            assert!(!d.overridden);
+        }
+    }
+}
@@ -4,7 +4,7 @@ use crate::ir::term::*;
 ///
 /// Visits:
 /// * EQs over arrays
-/// * Constant arrays
+/// * Constant arrays, and array constants
 /// * ITEs over arrays
 /// * array variables
 /// * STOREs
@@ -18,6 +18,8 @@ use crate::ir::term::*;
 pub trait MemVisitor {
     /// Visit a const array
     fn visit_const_array(&mut self, _orig: &Term, _key_sort: &Sort, _val: &Term, _size: usize) {}
+    /// Visit an array constant (see [const_array_elems])
+    fn visit_const_value(&mut self, _orig: &Term, _val: &Value) {}
     /// Visit an equality, whose children are `a` and `b`.
     fn visit_eq(&mut self, _orig: &Term, _a: &Term, _b: &Term) -> Option<Term> {
         None
@@ -60,6 +62,9 @@ pub trait MemVisitor {
                             Op::ConstArray(s, n) => {
                                 self.visit_const_array(&t, s, get(0), *n);
                             }
+                            Op::Const(v) => {
+                                self.visit_const_value(&t, v);
+                            }
                             _ => {}
                         };
                         None
@@ -84,6 +89,21 @@ pub trait MemVisitor {
     }
 }
 
+/// The elements of the array constant `v`, in key order.
+pub fn const_array_elems(v: &Value) -> Vec<Term> {
+    match v {
+        Value::Array(Sort::Array(k, _, size), ..) => k
+            .elems_iter()
+            .take(*size)
+            .map(|key| match &key.op {
+                Op::Const(k) => leaf_term(Op::Const(v.select(k))),
+                _ => unreachable!(),
+            })
+            .collect(),
+        _ => panic!("{} is not an array", v),
+    }
+}
+
 pub trait ProgressVisitor: MemVisitor {
     fn reset_progress(&mut self);
     fn check_progress(&self) -> bool;
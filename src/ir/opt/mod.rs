@@ -1,15 +1,24 @@
 //! Optimizations
+pub mod aig;
+pub mod bitblast;
+pub mod bitwise;
 pub mod cfold;
+pub mod dead_input;
 pub mod flat;
 pub mod inline;
+pub mod ite;
 pub mod mem;
+pub mod pass;
 pub mod sha;
+pub mod specialize;
 pub mod tuple;
+pub mod validate;
 
 use super::term::*;
 use log::debug;
+use pass::Pass;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// An optimization pass
 pub enum Opt {
     /// Fold constants
@@ -18,65 +27,110 @@ pub enum Opt {
     Flatten,
     /// SHA-2 peephole optimizations
     Sha,
-    /// Memory elimination
-    Mem,
+    /// Re-synthesize small bitwise functions, with costs from the given target
+    Bitwise(ite::CostTarget),
+    /// Rewrite bit-vector operations into Boolean circuits, using the given adders
+    BitBlast(bitblast::Adder),
+    /// Minimize the ANDs in Boolean circuits, for MPC
+    Aig,
+    /// Memory elimination. Arrays of at least `perm_thresh` elements are eliminated by
+    /// permutation checking; smaller ones by linear scans. Named `mem` when no array is large
+    /// enough, `mem-perm` with the default threshold, and `mem-perm:N` otherwise.
+    Mem {
+        /// The array size at which to switch to permutation checking
+        perm_thresh: usize,
+    },
+    /// Memory elimination, choosing a strategy for each array with the costs of the given target
+    MemAuto(ite::CostTarget),
     /// Extract top-level ANDs as distinct outputs
     FlattenAssertions,
     /// Find outputs like `(= variable term)`, and substitute out `variable`
     Inline,
     /// Eliminate tuples
     Tuple,
+    /// Lift ites through common operators, with costs from the given target
+    IteLift(ite::CostTarget),
+    /// Remove unused inputs and values. Keep unused public inputs iff `keep_public`.
+    DeadInputs {
+        /// Whether to keep unused public inputs
+        keep_public: bool,
+    },
 }
 
 /// Run optimizations on `cs`, in this order, returning the new constraint system.
 pub fn opt<I: IntoIterator<Item = Opt>>(mut cs: Computation, optimizations: I) -> Computation {
     for i in optimizations {
-        debug!("Applying: {:?}", i);
-        match i {
-            Opt::ConstantFold => {
-                let mut cache = TermMap::new();
-                for a in &mut cs.outputs {
-                    *a = cfold::fold_cache(a, &mut cache);
-                }
-            }
-            Opt::Sha => {
-                for a in &mut cs.outputs {
-                    *a = sha::sha_rewrites(a);
-                }
-            }
-            Opt::Mem => {
-                for a in &mut cs.outputs {
-                    *a = mem::array_elim(a);
-                }
+        cs = apply(cs, &i);
+    }
+    garbage_collect();
+    cs
+}
+
+/// Run the single optimization `i` on `cs`.
+fn apply(mut cs: Computation, i: &Opt) -> Computation {
+    debug!("Applying: {:?}", i);
+    match i {
+        Opt::ConstantFold => {
+            let mut cache = TermMap::new();
+            for a in &mut cs.outputs {
+                *a = cfold::fold_cache(a, &mut cache);
             }
-            Opt::FlattenAssertions => {
-                let mut new_outputs = Vec::new();
-                for a in std::mem::take(&mut cs.outputs) {
-                    assert_eq!(check(&a), Sort::Bool, "Non-bool in {:?}", i);
-                    if &a.op == &Op::BoolNaryOp(BoolNaryOp::And) {
-                        new_outputs.extend(a.cs.iter().cloned());
-                    } else {
-                        new_outputs.push(a)
-                    }
-                }
-                cs.outputs = new_outputs;
+        }
+        Opt::Sha => {
+            for a in &mut cs.outputs {
+                *a = sha::sha_rewrites(a);
             }
-            Opt::Flatten => {
-                let mut cache = flat::Cache::new();
-                for a in &mut cs.outputs {
-                    *a = flat::flatten_nary_ops_cached(a.clone(), &mut cache);
+        }
+        Opt::Bitwise(target) => {
+            bitwise::resynthesize_bitwise(&mut cs.outputs, *target);
+        }
+        Opt::BitBlast(adder) => {
+            bitblast::bitblast(&mut cs.outputs, *adder);
+        }
+        Opt::Aig => {
+            let report = aig::minimize_ands(&mut cs.outputs);
+            debug!("AIG: {}", report);
+        }
+        Opt::Mem { perm_thresh } => {
+            mem::nest::flatten_nested(&mut cs);
+            mem::array_elim_all(&mut cs, *perm_thresh);
+        }
+        Opt::MemAuto(target) => {
+            cs = mem::plan::PlannedArrayElim::new(*target).run(cs);
+        }
+        Opt::FlattenAssertions => {
+            let mut new_outputs = Vec::new();
+            for a in std::mem::take(&mut cs.outputs) {
+                assert_eq!(check(&a), Sort::Bool, "Non-bool in {:?}", i);
+                if &a.op == &Op::BoolNaryOp(BoolNaryOp::And) {
+                    new_outputs.extend(a.cs.iter().cloned());
+                } else {
+                    new_outputs.push(a)
                 }
             }
-            Opt::Inline => {
-                let public_inputs = cs.metadata.public_inputs().map(ToOwned::to_owned).collect();
-                inline::inline(&mut cs.outputs, &public_inputs);
-            }
-            Opt::Tuple => {
-                cs = tuple::eliminate_tuples(cs);
+            cs.outputs = new_outputs;
+        }
+        Opt::Flatten => {
+            let mut cache = flat::Cache::new();
+            for a in &mut cs.outputs {
+                *a = flat::flatten_nary_ops_cached(a.clone(), &mut cache);
             }
         }
-        debug!("After {:?}: {}", i, cs.terms());
+        Opt::Inline => {
+            let public_inputs = cs.metadata.public_inputs().map(ToOwned::to_owned).collect();
+            inline::inline(&mut cs.outputs, &public_inputs);
+        }
+        Opt::Tuple => {
+            cs = tuple::eliminate_tuples(cs);
+        }
+        Opt::IteLift(target) => {
+            ite::lift_ites(&mut cs.outputs, *target);
+        }
+        Opt::DeadInputs { keep_public } => {
+            let report = dead_input::remove_dead_inputs(&mut cs, *keep_public);
+            debug!("Dead inputs: {}", report);
+        }
     }
-    garbage_collect();
+    debug!("After {:?}: {}", i, cs.terms());
     cs
 }
@@ -0,0 +1,632 @@
+//! A pass manager: textual pipelines of optimization passes
+//!
+//! A pipeline is a comma-separated list of pass names. Parenthesized sub-pipelines may be
+//! followed by `*`, meaning "repeat until the outputs stop changing", or by `?`, meaning "undo
+//! unless the computation got no more expensive". So may single passes. The latter needs a cost
+//! model (see [PassManager::cost_model]); without one, `?` has no effect.
+//!
+//! E.g., `flatten,sha?,cfold,(inline,cfold)*,mem`
+//!
+//! Built-in passes are named after [Opt]s (see [Opt::name]). Others can be added with
+//! [PassManager::register]. Permutation-based memory elimination takes an optional array size
+//! threshold: `mem-perm:512`.
+
+use super::validate::{validate_pass, Validation, ValidationConfig};
+use super::{apply, bitblast::Adder, ite::CostTarget, mem, Opt};
+use crate::ir::cost::CostModel;
+use crate::ir::term::*;
+use ahash::AHashMap;
+use log::{debug, warn};
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The most times a `*` group will be repeated.
+const MAX_FIXPOINT_ITERATIONS: usize = 100;
+
+/// An optimization pass which can be run by a [PassManager].
+pub trait Pass {
+    /// The name of the pass, as written in pipelines.
+    fn name(&self) -> Cow<str>;
+    /// Run the pass.
+    fn run(&self, cs: Computation) -> Computation;
+}
+
+impl Opt {
+    /// The name of this optimization, as written in pipelines.
+    pub fn name(&self) -> Cow<'static, str> {
+        Cow::Borrowed(match self {
+            Opt::ConstantFold => "cfold",
+            Opt::Flatten => "flatten",
+            Opt::Sha => "sha",
+            Opt::Bitwise(CostTarget::R1cs) => "bitwise-r1cs",
+            Opt::Bitwise(CostTarget::Aby) => "bitwise-aby",
+            Opt::BitBlast(Adder::RippleCarry) => "bitblast",
+            Opt::BitBlast(Adder::KoggeStone) => "bitblast-ks",
+            Opt::Aig => "aig",
+            Opt::Mem {
+                perm_thresh: usize::MAX,
+            } => "mem",
+            Opt::Mem {
+                perm_thresh: mem::perm::DEFAULT_SIZE_THRESH,
+            } => "mem-perm",
+            Opt::Mem { perm_thresh } => return Cow::Owned(format!("mem-perm:{}", perm_thresh)),
+            Opt::MemAuto(CostTarget::R1cs) => "mem-auto-r1cs",
+            Opt::MemAuto(CostTarget::Aby) => "mem-auto-aby",
+            Opt::FlattenAssertions => "flatten-assertions",
+            Opt::Inline => "inline",
+            Opt::Tuple => "tuple",
+            Opt::IteLift(CostTarget::R1cs) => "ite-r1cs",
+            Opt::IteLift(CostTarget::Aby) => "ite-aby",
+            Opt::DeadInputs { keep_public: false } => "dead-inputs",
+            Opt::DeadInputs { keep_public: true } => "dead-inputs-keep-public",
+        })
+    }
+
+    /// The optimization named `name`, if any. The inverse of [Opt::name].
+    pub fn from_name(name: &str) -> Option<Opt> {
+        match name.strip_prefix("mem-perm:") {
+            Some(n) => n.parse().ok().map(|perm_thresh| Opt::Mem { perm_thresh }),
+            None => Opt::all().into_iter().find(|o| o.name() == name),
+        }
+    }
+
+    /// All built-in optimizations.
+    pub fn all() -> Vec<Opt> {
+        vec![
+            Opt::ConstantFold,
+            Opt::Flatten,
+            Opt::Sha,
+            Opt::Bitwise(CostTarget::R1cs),
+            Opt::Bitwise(CostTarget::Aby),
+            Opt::BitBlast(Adder::RippleCarry),
+            Opt::BitBlast(Adder::KoggeStone),
+            Opt::Aig,
+            Opt::Mem {
+                perm_thresh: usize::MAX,
+            },
+            Opt::Mem {
+                perm_thresh: mem::perm::DEFAULT_SIZE_THRESH,
+            },
+            Opt::MemAuto(CostTarget::R1cs),
+            Opt::MemAuto(CostTarget::Aby),
+            Opt::FlattenAssertions,
+            Opt::Inline,
+            Opt::Tuple,
+            Opt::IteLift(CostTarget::R1cs),
+            Opt::IteLift(CostTarget::Aby),
+            Opt::DeadInputs { keep_public: false },
+            Opt::DeadInputs { keep_public: true },
+        ]
+    }
+}
+
+impl Pass for Opt {
+    fn name(&self) -> Cow<str> {
+        Opt::name(self)
+    }
+    fn run(&self, cs: Computation) -> Computation {
+        apply(cs, self)
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+/// An error in parsing a pipeline
+pub enum PipelineError {
+    #[error("Unknown pass '{0}'")]
+    /// No pass has this name
+    UnknownPass(String),
+    #[error("Expected {0} at offset {1} in '{2}'")]
+    /// Malformed pipeline: (what was expected, where, pipeline)
+    Syntax(String, usize, String),
+    #[error("Malformed pipeline configuration line '{0}'")]
+    /// Malformed configuration line
+    Config(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A step in a pipeline.
+pub enum Stage {
+    /// Run the named pass.
+    Pass(String),
+    /// Run these stages until the outputs stop changing.
+    Fixpoint(Vec<Stage>),
+    /// Run these stages, and undo them if they raise the cost of the computation.
+    Optional(Vec<Stage>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A sequence of passes. See the module documentation.
+pub struct Pipeline(pub Vec<Stage>);
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Stage::Pass(name) => write!(f, "{}", name),
+            Stage::Fixpoint(stages) => write!(f, "({})*", Pipeline(stages.clone())),
+            Stage::Optional(stages) => write!(f, "({})?", Pipeline(stages.clone())),
+        }
+    }
+}
+
+impl Display for Pipeline {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (i, s) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}", s)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+/// Statistics about one run of a pass.
+pub struct PassStats {
+    /// The pass name
+    pub name: String,
+    /// Unique terms before the pass
+    pub terms_before: usize,
+    /// Unique terms after the pass
+    pub terms_after: usize,
+    /// Time taken by the pass
+    pub elapsed: Duration,
+    /// Cost before and after the pass, if the manager has a cost model
+    pub cost: Option<(usize, usize)>,
+    /// The outcome of validating the pass, if the manager validates passes
+    pub validation: Option<Validation>,
+    /// Whether the pass was undone, as part of an optional stage which raised the cost
+    pub rejected: bool,
+}
+
+impl Display for PassStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} terms",
+            self.name, self.terms_before, self.terms_after
+        )?;
+        if let Some((before, after)) = self.cost {
+            write!(f, ", cost {} -> {}", before, after)?;
+        }
+        write!(f, " in {:?}", self.elapsed)?;
+        if let Some(v) = &self.validation {
+            write!(f, " ({})", v)?;
+        }
+        if self.rejected {
+            write!(f, " (rejected)")?;
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    chars: Vec<(usize, char)>,
+    i: usize,
+    passes: &'a AHashMap<String, Box<dyn Pass>>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.i).map(|(_, c)| *c)
+    }
+    fn offset(&self) -> usize {
+        self.chars
+            .get(self.i)
+            .map(|(o, _)| *o)
+            .unwrap_or_else(|| self.src.len())
+    }
+    fn err(&self, expected: &str) -> PipelineError {
+        PipelineError::Syntax(expected.to_owned(), self.offset(), self.src.to_owned())
+    }
+    /// Parse a sequence of stages, ending at ')' if `nested` and the end of input otherwise.
+    fn seq(&mut self, nested: bool) -> Result<Vec<Stage>, PipelineError> {
+        let mut stages = Vec::new();
+        loop {
+            stages.extend(self.item()?);
+            match self.peek() {
+                Some(',') => self.i += 1,
+                Some(')') if nested => {
+                    self.i += 1;
+                    return Ok(stages);
+                }
+                None if !nested => return Ok(stages),
+                _ if nested => return Err(self.err("',' or ')'")),
+                _ => return Err(self.err("','")),
+            }
+        }
+    }
+    fn item(&mut self) -> Result<Vec<Stage>, PipelineError> {
+        let mut stages = self.unrepeated_item()?;
+        loop {
+            stages = match self.peek() {
+                Some('*') => vec![Stage::Fixpoint(stages)],
+                Some('?') => vec![Stage::Optional(stages)],
+                _ => return Ok(stages),
+            };
+            self.i += 1;
+        }
+    }
+    /// Parse a pass or a parenthesized sub-pipeline, without its suffixes.
+    fn unrepeated_item(&mut self) -> Result<Vec<Stage>, PipelineError> {
+        if self.peek() == Some('(') {
+            self.i += 1;
+            self.seq(true)
+        } else {
+            let mut name = String::new();
+            while let Some(c) = self.peek() {
+                if c.is_alphanumeric() || c == '-' || c == '_' {
+                    name.push(c);
+                    self.i += 1;
+                } else {
+                    break;
+                }
+            }
+            if name.is_empty() {
+                return Err(self.err("a pass name"));
+            }
+            if self.peek() == Some(':') {
+                self.i += 1;
+                name.push(':');
+                let start = name.len();
+                while let Some(c) = self.peek().filter(|c| c.is_ascii_digit()) {
+                    name.push(c);
+                    self.i += 1;
+                }
+                if name.len() == start {
+                    return Err(self.err("a number"));
+                }
+            }
+            if !self.passes.contains_key(&name) && Opt::from_name(&name).is_none() {
+                return Err(PipelineError::UnknownPass(name));
+            }
+            Ok(vec![Stage::Pass(name)])
+        }
+    }
+}
+
+/// Runs pipelines of registered passes, recording statistics.
+pub struct PassManager {
+    passes: AHashMap<String, Box<dyn Pass>>,
+    stats: Vec<PassStats>,
+    validation: Option<ValidationConfig>,
+    cost_model: Option<Box<dyn CostModel>>,
+}
+
+impl std::default::Default for PassManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PassManager {
+    /// Create a pass manager with all built-in passes registered.
+    pub fn new() -> Self {
+        let mut m = Self {
+            passes: AHashMap::new(),
+            stats: Vec::new(),
+            validation: None,
+            cost_model: None,
+        };
+        for o in Opt::all() {
+            m.register(o);
+        }
+        m
+    }
+
+    /// Register `pass` under its name, replacing any pass with the same name.
+    pub fn register<P: Pass + 'static>(&mut self, pass: P) {
+        self.passes.insert(pass.name().into_owned(), Box::new(pass));
+    }
+
+    /// Check that every pass preserves the meaning of the computation. See
+    /// [validate](super::validate).
+    pub fn validate(&mut self, cfg: ValidationConfig) {
+        self.validation = Some(cfg);
+    }
+
+    /// Record the cost of the computation under `model` before and after each pass, and undo
+    /// optional stages which raise it.
+    pub fn cost_model(&mut self, model: Box<dyn CostModel>) {
+        self.cost_model = Some(model);
+    }
+
+    /// Parse a pipeline of registered passes.
+    pub fn parse(&self, src: &str) -> Result<Pipeline, PipelineError> {
+        let mut p = Parser {
+            src,
+            chars: src
+                .char_indices()
+                .filter(|(_, c)| !c.is_whitespace())
+                .collect(),
+            i: 0,
+            passes: &self.passes,
+        };
+        Ok(Pipeline(p.seq(false)?))
+    }
+
+    /// Run `pipeline` on `cs`.
+    pub fn run(&mut self, mut cs: Computation, pipeline: &Pipeline) -> Computation {
+        for s in &pipeline.0 {
+            cs = self.run_stage(cs, s);
+        }
+        garbage_collect();
+        cs
+    }
+
+    fn run_stage(&mut self, mut cs: Computation, stage: &Stage) -> Computation {
+        match stage {
+            Stage::Pass(name) => {
+                let parameterized;
+                let pass: &dyn Pass = match self.passes.get(name) {
+                    Some(pass) => pass.as_ref(),
+                    None => {
+                        parameterized = Opt::from_name(name).expect("unregistered pass");
+                        &parameterized
+                    }
+                };
+                let terms_before = cs.terms();
+                let cost_before = self.cost_model.as_ref().map(|m| m.cost_all(&cs.outputs));
+                let old = self.validation.as_ref().map(|_| cs.clone());
+                let start = Instant::now();
+                cs = pass.run(cs);
+                let elapsed = start.elapsed();
+                let validation = match (old, self.validation.as_ref()) {
+                    (Some(old), Some(cfg)) => match validate_pass(name, &old, &cs, cfg) {
+                        Ok(v) => Some(v),
+                        Err(e) => panic!("{}", e),
+                    },
+                    _ => None,
+                };
+                let stats = PassStats {
+                    name: name.clone(),
+                    terms_before,
+                    terms_after: cs.terms(),
+                    elapsed,
+                    cost: cost_before
+                        .map(|b| (b, self.cost_model.as_ref().unwrap().cost_all(&cs.outputs))),
+                    validation,
+                    rejected: false,
+                };
+                debug!("{}", stats);
+                self.stats.push(stats);
+                cs
+            }
+            Stage::Fixpoint(stages) => {
+                for _ in 0..MAX_FIXPOINT_ITERATIONS {
+                    let old_outputs = cs.outputs.clone();
+                    for s in stages {
+                        cs = self.run_stage(cs, s);
+                    }
+                    if cs.outputs == old_outputs {
+                        return cs;
+                    }
+                }
+                warn!(
+                    "{} did not reach a fixpoint in {} iterations",
+                    stage, MAX_FIXPOINT_ITERATIONS
+                );
+                cs
+            }
+            Stage::Optional(stages) => {
+                let before = self
+                    .cost_model
+                    .as_ref()
+                    .map(|m| (cs.clone(), m.cost_all(&cs.outputs)));
+                let first_stats = self.stats.len();
+                for s in stages {
+                    cs = self.run_stage(cs, s);
+                }
+                if let Some((old, cost_before)) = before {
+                    let cost_after = self.cost_model.as_ref().unwrap().cost_all(&cs.outputs);
+                    if cost_after > cost_before {
+                        debug!("Undoing {}: cost {} -> {}", stage, cost_before, cost_after);
+                        for s in &mut self.stats[first_stats..] {
+                            s.rejected = true;
+                        }
+                        return old;
+                    }
+                }
+                cs
+            }
+        }
+    }
+
+    /// Statistics for each pass run so far, in order.
+    pub fn stats(&self) -> &[PassStats] {
+        &self.stats
+    }
+}
+
+/// Parse a pipeline configuration: a map from targets to pipelines.
+///
+/// Each non-empty line has the form `TARGET: PIPELINE`. Lines starting with `#` are comments.
+pub fn parse_pipeline_config(text: &str) -> Result<AHashMap<String, String>, PipelineError> {
+    let mut pipelines = AHashMap::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(target), Some(pipeline)) if !target.trim().is_empty() => {
+                pipelines.insert(target.trim().to_owned(), pipeline.trim().to_owned());
+            }
+            _ => return Err(PipelineError::Config(line.to_owned())),
+        }
+    }
+    Ok(pipelines)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::cost::AbyBoolCost;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn p(s: &str) -> Stage {
+        Stage::Pass(s.to_owned())
+    }
+
+    #[test]
+    fn parse() {
+        let m = PassManager::new();
+        assert_eq!(
+            m.parse("flatten, sha,cfold,(inline,cfold)*,mem").unwrap(),
+            Pipeline(vec![
+                p("flatten"),
+                p("sha"),
+                p("cfold"),
+                Stage::Fixpoint(vec![p("inline"), p("cfold")]),
+                p("mem"),
+            ])
+        );
+        assert_eq!(
+            m.parse("(flatten,(cfold)),inline*").unwrap(),
+            Pipeline(vec![p("flatten"), p("cfold"), Stage::Fixpoint(vec![p("inline")])])
+        );
+        let s = "flatten,(inline,cfold)*";
+        assert_eq!(format!("{}", m.parse(s).unwrap()), s);
+    }
+
+    #[test]
+    fn parse_errors() {
+        let m = PassManager::new();
+        assert_eq!(
+            m.parse("cfold,nonsense"),
+            Err(PipelineError::UnknownPass("nonsense".to_owned()))
+        );
+        assert!(matches!(m.parse("cfold,"), Err(PipelineError::Syntax(..))));
+        assert!(matches!(m.parse("(cfold"), Err(PipelineError::Syntax(..))));
+        assert!(matches!(m.parse("cfold)"), Err(PipelineError::Syntax(..))));
+        assert!(matches!(
+            m.parse("mem-perm:"),
+            Err(PipelineError::Syntax(..))
+        ));
+        assert_eq!(
+            m.parse("cfold:3"),
+            Err(PipelineError::UnknownPass("cfold:3".to_owned()))
+        );
+    }
+
+    #[test]
+    fn names() {
+        for o in Opt::all() {
+            assert_eq!(o.name(), Opt::from_name(&o.name()).unwrap().name());
+        }
+        let o = Opt::from_name("mem-perm:512").unwrap();
+        assert!(matches!(o, Opt::Mem { perm_thresh: 512 }));
+        assert_eq!(o.name(), "mem-perm:512");
+        let m = PassManager::new();
+        assert_eq!(
+            m.parse("mem-perm:512,mem-perm").unwrap(),
+            Pipeline(vec![p("mem-perm:512"), p("mem-perm")])
+        );
+    }
+
+    /// Drops the last output, while there are at least two.
+    struct DropOutput(Rc<Cell<usize>>);
+
+    impl Pass for DropOutput {
+        fn name(&self) -> Cow<str> {
+            Cow::Borrowed("drop-output")
+        }
+        fn run(&self, mut cs: Computation) -> Computation {
+            self.0.set(self.0.get() + 1);
+            if cs.outputs.len() > 1 {
+                cs.outputs.pop();
+            }
+            cs
+        }
+    }
+
+    #[test]
+    fn fixpoint_and_stats() {
+        let runs = Rc::new(Cell::new(0));
+        let mut m = PassManager::new();
+        m.register(DropOutput(runs.clone()));
+        let mut cs = Computation::new(false);
+        for i in 0..4 {
+            let v = cs.new_var(&format!("v{}", i), Sort::Bool, || Value::Bool(true), None);
+            cs.assert(v);
+        }
+        let pipeline = m.parse("(drop-output,cfold)*").unwrap();
+        let cs = m.run(cs, &pipeline);
+        assert_eq!(cs.outputs.len(), 1);
+        // three drops, and one run to see that nothing changed
+        assert_eq!(runs.get(), 4);
+        assert_eq!(m.stats().len(), 8);
+        assert_eq!(m.stats()[0].name, "drop-output");
+        assert_eq!(m.stats()[0].terms_before, 4);
+        assert_eq!(m.stats()[0].terms_after, 3);
+        assert_eq!(m.stats()[0].cost, None);
+    }
+
+    #[test]
+    fn stats_with_costs() {
+        let mut m = PassManager::new();
+        m.cost_model(Box::new(AbyBoolCost));
+        let mut cs = Computation::new(false);
+        let a = cs.new_var("a", Sort::Bool, || Value::Bool(true), None);
+        let b = cs.new_var("b", Sort::Bool, || Value::Bool(true), None);
+        let ab = term![AND; a.clone(), b.clone()];
+        cs.assert(term![OR; term![AND; ab.clone(), a], term![AND; ab, b]]);
+        let pipeline = m.parse("aig").unwrap();
+        m.run(cs, &pipeline);
+        assert_eq!(m.stats()[0].cost, Some((4, 1)));
+    }
+
+    /// Doubles every output.
+    struct Double;
+
+    impl Pass for Double {
+        fn name(&self) -> Cow<str> {
+            Cow::Borrowed("double")
+        }
+        fn run(&self, mut cs: Computation) -> Computation {
+            for o in &mut cs.outputs {
+                *o = term![AND; o.clone(), o.clone()];
+            }
+            cs
+        }
+    }
+
+    #[test]
+    fn optional() {
+        let mut m = PassManager::new();
+        m.register(Double);
+        assert_eq!(
+            m.parse("double?,(aig,cfold)?*").unwrap(),
+            Pipeline(vec![
+                Stage::Optional(vec![p("double")]),
+                Stage::Fixpoint(vec![Stage::Optional(vec![p("aig"), p("cfold")])]),
+            ])
+        );
+        assert_eq!(format!("{}", m.parse("double?").unwrap()), "(double)?");
+        let mut cs = Computation::new(false);
+        let a = cs.new_var("a", Sort::Bool, || Value::Bool(true), None);
+        let b = cs.new_var("b", Sort::Bool, || Value::Bool(true), None);
+        cs.assert(term![AND; a, b]);
+        let pipeline = m.parse("double?").unwrap();
+        // Without a cost model, the pass stays.
+        let doubled = m.run(cs.clone(), &pipeline);
+        assert_ne!(doubled.outputs, cs.outputs);
+        m.cost_model(Box::new(AbyBoolCost));
+        let undone = m.run(cs.clone(), &pipeline);
+        assert_eq!(undone.outputs, cs.outputs);
+        assert!(m.stats()[1].rejected);
+    }
+
+    #[test]
+    fn config() {
+        let c = parse_pipeline_config("# proofs\nr1cs: flatten,cfold\n\naby:sha, mem\n").unwrap();
+        assert_eq!(c.get("r1cs").unwrap(), "flatten,cfold");
+        assert_eq!(c.get("aby").unwrap(), "sha, mem");
+        assert!(parse_pipeline_config("r1cs flatten").is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Partial evaluation: specializing a computation to known input values
+//!
+//! Only inputs of the computation can be fixed, by the names of their variables. Front-ends may
+//! mangle source names: e.g., ZoKrates parameter `x` of `main` is `main_f0_lex0_x_v0`, and its
+//! element `i` (or field `f`) is `main_f0_lex0_x_v0.i` (or `main_f0_lex0_x_v0.f`).
+
+use super::{opt, Opt};
+use crate::ir::term::extras::substitute_cache;
+use crate::ir::term::*;
+use ahash::AHashMap;
+use log::debug;
+use rug::Integer;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+/// An assignment which cannot be used to specialize a computation.
+pub enum SpecializeError {
+    #[error("Cannot fix {0}: the computation has no such input{}", candidates(.1))]
+    /// No input of the computation has this name: (name, inputs with similar names)
+    UnknownInput(String, Vec<String>),
+    #[error("Cannot fix {0}: it has sort {1}, but {2} does not")]
+    /// The value has the wrong sort: (name, sort, value)
+    WrongSort(String, Sort, Value),
+    #[error("Cannot interpret {0} as a {1}")]
+    /// An integer cannot be a value of this sort: the sort is not a scalar, or the integer is out
+    /// of its range
+    NotAnInt(Integer, Sort),
+    #[error("Cannot fix {0} to {1}: its witness value is {2}")]
+    /// The computation's values disagree: (name, fixed value, witness value)
+    Witness(String, Value, Value),
+}
+
+fn candidates(names: &[String]) -> String {
+    if names.is_empty() {
+        String::new()
+    } else {
+        format!(" (did you mean {}?)", names.join(" or "))
+    }
+}
+
+/// The sorts of the variables in `cs`'s outputs.
+pub fn var_sorts(cs: &Computation) -> AHashMap<String, Sort> {
+    let mut sorts = AHashMap::new();
+    for o in &cs.outputs {
+        for t in PostOrderIter::new(o.clone()) {
+            if let Op::Var(name, sort) = &t.op {
+                sorts.insert(name.clone(), sort.clone());
+            }
+        }
+    }
+    sorts
+}
+
+/// The sorts of the inputs of `cs` which its outputs use.
+fn input_sorts(cs: &Computation) -> AHashMap<String, Sort> {
+    let mut sorts = var_sorts(cs);
+    sorts.retain(|name, _| cs.metadata.inputs.contains_key(name));
+    sorts
+}
+
+/// An error for `name`, which is not an input of `cs`.
+fn unknown_input(cs: &Computation, name: &str) -> SpecializeError {
+    // Mangled versions of the name
+    let mangled = format!("_{}_v", name);
+    let mut similar: Vec<String> = cs
+        .metadata
+        .inputs
+        .keys()
+        .filter(|i| i.contains(&mangled))
+        .cloned()
+        .collect();
+    similar.sort();
+    SpecializeError::UnknownInput(name.to_owned(), similar)
+}
+
+/// Interpret the integer `i` as a value of sort `s`: 0 or 1 for a Boolean, and a non-negative
+/// integer less than 2^w or the modulus for a bit-vector or a field element.
+pub fn int_value(s: &Sort, i: Integer) -> Result<Value, SpecializeError> {
+    let in_range = match s {
+        Sort::Bool => i == 0 || i == 1,
+        Sort::BitVector(w) => i >= 0 && i.significant_bits() as usize <= *w,
+        Sort::Field(m) => i >= 0 && i < **m,
+        Sort::Int => true,
+        _ => false,
+    };
+    if !in_range {
+        return Err(SpecializeError::NotAnInt(i, s.clone()));
+    }
+    match s {
+        Sort::Bool => Ok(Value::Bool(i == 1)),
+        Sort::BitVector(w) => Ok(Value::BitVector(BitVector::new(i, *w))),
+        Sort::Field(m) => Ok(Value::Field(FieldElem::new(i, m.clone()))),
+        _ => Ok(Value::Int(i)),
+    }
+}
+
+/// Interpret an assignment of integers to the inputs of `cs` as an assignment of values.
+pub fn int_values(
+    cs: &Computation,
+    ints: impl IntoIterator<Item = (String, Integer)>,
+) -> Result<AHashMap<String, Value>, SpecializeError> {
+    let sorts = input_sorts(cs);
+    ints.into_iter()
+        .map(|(name, i)| {
+            let sort = sorts.get(&name).ok_or_else(|| unknown_input(cs, &name))?;
+            let v = int_value(sort, i)?;
+            Ok((name, v))
+        })
+        .collect()
+}
+
+/// Specialize `cs` to the partial assignment `fixed` of its inputs.
+///
+/// The fixed inputs are replaced by constants, and removed from the inputs and values of the
+/// computation. Then constant folding, flattening, and (if the outputs are `assertions`) inlining
+/// are run until the outputs stop changing.
+pub fn specialize(
+    mut cs: Computation,
+    fixed: &AHashMap<String, Value>,
+    assertions: bool,
+) -> Result<Computation, SpecializeError> {
+    let sorts = input_sorts(&cs);
+    let mut subs = TermMap::new();
+    for (name, v) in fixed {
+        let sort = sorts.get(name).ok_or_else(|| unknown_input(&cs, name))?;
+        if &v.sort() != sort {
+            return Err(SpecializeError::WrongSort(
+                name.clone(),
+                sort.clone(),
+                v.clone(),
+            ));
+        }
+        if let Some(old_v) = cs.values.as_ref().and_then(|vs| vs.get(name)) {
+            if old_v != v {
+                return Err(SpecializeError::Witness(
+                    name.clone(),
+                    v.clone(),
+                    old_v.clone(),
+                ));
+            }
+        }
+        subs.insert(
+            leaf_term(Op::Var(name.clone(), sort.clone())),
+            leaf_term(Op::Const(v.clone())),
+        );
+    }
+    for o in &mut cs.outputs {
+        *o = substitute_cache(o, &mut subs);
+    }
+    for name in fixed.keys() {
+        cs.metadata.remove_input(name);
+        if let Some(vs) = cs.values.as_mut() {
+            vs.remove(name);
+        }
+    }
+    let mut passes = vec![Opt::ConstantFold, Opt::Flatten];
+    if assertions {
+        passes.push(Opt::Inline);
+    }
+    loop {
+        let old_outputs = cs.outputs.clone();
+        cs = opt(cs, passes.iter().cloned());
+        if cs.outputs == old_outputs {
+            break;
+        }
+        debug!("Specializing again: {} terms", cs.terms());
+    }
+    Ok(cs)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::extras::free_in;
+
+    fn bv_val(u: usize) -> Value {
+        Value::BitVector(BitVector::new(Integer::from(u), 4))
+    }
+
+    fn comp() -> Computation {
+        let mut cs = Computation::new(true);
+        let a = cs.new_var("a", Sort::BitVector(4), || bv_val(3), None);
+        let b = cs.new_var("b", Sort::BitVector(4), || bv_val(5), Some(0));
+        let c = cs.assign("c", term![BV_ADD; a.clone(), b.clone()], Some(0));
+        cs.assert(term![BV_ULT; a, c]);
+        cs
+    }
+
+    #[test]
+    fn fix_public() {
+        let cs = comp();
+        let fixed = int_values(&cs, vec![("a".to_owned(), Integer::from(3))]).unwrap();
+        let cs = specialize(cs, &fixed, true).unwrap();
+        assert!(!cs.metadata.inputs.contains_key("a"));
+        assert!(!cs.values.as_ref().unwrap().contains_key("a"));
+        let vs = cs.values.clone().unwrap();
+        for o in &cs.outputs {
+            assert_eq!(eval(o, &vs), Value::Bool(true));
+            assert!(!free_in("a", o.clone()));
+        }
+    }
+
+    #[test]
+    fn fix_everything() {
+        let cs = comp();
+        let fixed = int_values(
+            &cs,
+            vec![
+                ("a".to_owned(), Integer::from(3)),
+                ("b".to_owned(), Integer::from(5)),
+            ],
+        )
+        .unwrap();
+        let cs = specialize(cs, &fixed, true).unwrap();
+        for o in &cs.outputs {
+            assert_eq!(o, &leaf_term(Op::Const(Value::Bool(true))));
+        }
+    }
+
+    #[test]
+    fn inconsistent_witness() {
+        let cs = comp();
+        let fixed = int_values(&cs, vec![("a".to_owned(), Integer::from(4))]).unwrap();
+        assert_eq!(
+            specialize(cs, &fixed, true).unwrap_err(),
+            SpecializeError::Witness("a".to_owned(), bv_val(4), bv_val(3))
+        );
+    }
+
+    #[test]
+    fn only_inputs() {
+        let mut cs = comp();
+        // d is a variable, but not an input
+        let d = leaf_term(Op::Var("d".to_owned(), Sort::BitVector(4)));
+        cs.assert(term![EQ; d, bv_lit(8, 4)]);
+        assert_eq!(
+            int_values(&cs, vec![("d".to_owned(), Integer::from(8))]).unwrap_err(),
+            SpecializeError::UnknownInput("d".to_owned(), vec![])
+        );
+        let mut fixed = AHashMap::new();
+        fixed.insert("d".to_owned(), bv_val(8));
+        assert!(matches!(
+            specialize(cs.clone(), &fixed, true),
+            Err(SpecializeError::UnknownInput(..))
+        ));
+        fixed.clear();
+        fixed.insert("a".to_owned(), Value::Bool(true));
+        assert!(matches!(
+            specialize(cs, &fixed, true),
+            Err(SpecializeError::WrongSort(..))
+        ));
+    }
+
+    #[test]
+    fn out_of_range() {
+        let field = Sort::Field(std::sync::Arc::new(Integer::from(11)));
+        for (sort, good, bad) in vec![
+            (Sort::Bool, vec![0, 1], vec![-1, 2]),
+            (Sort::BitVector(4), vec![0, 15], vec![-1, 16, 300]),
+            (field, vec![0, 10], vec![-1, 11]),
+        ] {
+            for i in good {
+                assert!(int_value(&sort, Integer::from(i)).is_ok());
+            }
+            for i in bad {
+                assert_eq!(
+                    int_value(&sort, Integer::from(i)).unwrap_err(),
+                    SpecializeError::NotAnInt(Integer::from(i), sort.clone())
+                );
+            }
+        }
+        let cs = comp();
+        let e = int_values(&cs, vec![("a".to_owned(), Integer::from(300))]).unwrap_err();
+        assert_eq!(e.to_string(), "Cannot interpret 300 as a (bv 4)");
+    }
+
+    #[test]
+    fn mangled_names() {
+        let mut cs = Computation::new(false);
+        let x = cs.new_var("main_f0_lex0_x_v0", Sort::Bool, || Value::Bool(true), None);
+        cs.assert(x);
+        let e = int_values(&cs, vec![("x".to_owned(), Integer::from(1))]).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Cannot fix x: the computation has no such input (did you mean main_f0_lex0_x_v0?)"
+        );
+    }
+}
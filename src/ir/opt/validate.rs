@@ -0,0 +1,564 @@
+//! Translation validation of optimization passes
+//!
+//! After a pass, we ask whether the old and new outputs can differ.
+//!
+//! If every term can be given to the SMT solver, we ask CVC4 (with a time limit). Otherwise (e.g.
+//! for computations with field elements, even inside arrays or tuples), we evaluate both versions
+//! on the computation's own values (if it has them), and on random inputs.
+//!
+//! Some passes replace array or tuple variables `x` with element variables (`x_i` and `x.i`). We
+//! link each such variable to the corresponding element of `x` before comparing.
+//!
+//! If the number of outputs changes, outputs are treated as assertions, and their conjunctions
+//! are compared. If the pass also eliminated variables (as inlining does), we first substitute
+//! the old assertions' definitions (`(= x t)`) of those variables into the old assertions. The
+//! result holds iff the old assertions hold for some values of the eliminated variables, so it
+//! should be equivalent to the new assertions.
+//!
+//! Sometimes we cannot tell whether a pass is correct, and say so with [Validation::Unknown]:
+//!
+//!    * the solver gives up,
+//!    * we could only evaluate the versions on some inputs: the pass is tested, not proved,
+//!    * some eliminated variable has no definition, so we only check that the old assertions
+//!      imply the new ones,
+//!    * there are no values to use and some variable cannot be sampled, or
+//!    * no input we tried satisfies the assertions, so comparing them on those inputs says
+//!      nothing.
+
+use crate::ir::term::dist::UniformValue;
+use crate::ir::term::extras::{free_in, free_variables, substitute_cache};
+use crate::ir::term::*;
+use crate::target::smt::{check_sat_timeout, SmtResult};
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use rand::distributions::Distribution;
+use rand::SeedableRng;
+use std::fmt::{self, Display, Formatter};
+use std::time::Duration;
+
+#[derive(Clone, Debug)]
+/// Configuration for translation validation
+pub struct ValidationConfig {
+    /// Time limit for each SMT query
+    pub timeout: Duration,
+    /// The number of random inputs to try, when SMT cannot be used
+    pub samples: usize,
+}
+
+impl std::default::Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            samples: 100,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// The outcome of validating a pass which was not found to change the computation.
+pub enum Validation {
+    /// The pass preserved the meaning of the computation
+    Valid,
+    /// The pass could not be validated, for this reason
+    Unknown(String),
+}
+
+impl Display for Validation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Validation::Valid => write!(f, "validated"),
+            Validation::Unknown(why) => write!(f, "not validated: {}", why),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+/// A pass that changed the meaning of a computation.
+pub struct ValidationFailure {
+    /// The pass name
+    pub pass: String,
+    /// Inputs on which the old and new computations differ, if known
+    pub model: Option<AHashMap<String, Value>>,
+    /// The index of the (first) differing output, if the outputs could be matched up
+    pub output: Option<usize>,
+    /// The old and new values of the differing output (or conjunction), if known
+    pub values: Option<(Value, Value)>,
+}
+
+impl Display for ValidationFailure {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "Pass {} changed the computation", self.pass)?;
+        match self.output {
+            Some(i) => writeln!(f, " differing output: {}", i)?,
+            None => writeln!(f, " the conjunction of the outputs differs")?,
+        }
+        if let Some((old, new)) = &self.values {
+            writeln!(f, " old value: {}", old)?;
+            writeln!(f, " new value: {}", new)?;
+        }
+        match &self.model {
+            Some(m) => {
+                let mut vars: Vec<_> = m.iter().collect();
+                vars.sort_by(|a, b| a.0.cmp(b.0));
+                writeln!(f, " counterexample:")?;
+                for (n, v) in vars {
+                    writeln!(f, "  {} = {}", n, v)?;
+                }
+            }
+            None => writeln!(f, " no counterexample available")?,
+        }
+        Ok(())
+    }
+}
+
+fn var_sorts(ts: &[Term]) -> AHashMap<String, Sort> {
+    let mut sorts = AHashMap::new();
+    for t in ts {
+        for c in PostOrderIter::new(t.clone()) {
+            if let Op::Var(n, s) = &c.op {
+                sorts.insert(n.clone(), s.clone());
+            }
+        }
+    }
+    sorts
+}
+
+/// If `name` is an element variable of some old variable, the term for that element.
+///
+/// E.g. `x_2` is `(select x 2)` and `x.1` is `((_ tupSel 1) x)`.
+fn link(name: &str, sort: &Sort, old_vars: &AHashMap<String, Sort>) -> Option<Term> {
+    for (p, _) in name.char_indices().filter(|(_, c)| *c == '_' || *c == '.') {
+        if let Some(base_sort) = old_vars.get(&name[..p]) {
+            let mut t = leaf_term(Op::Var(name[..p].to_owned(), base_sort.clone()));
+            let mut rest = &name[p..];
+            while !rest.is_empty() {
+                let sep = rest.chars().next().unwrap();
+                let digits = rest[1..]
+                    .find(|c: char| !c.is_ascii_digit())
+                    .map(|i| i + 1)
+                    .unwrap_or_else(|| rest.len());
+                let i: usize = rest[1..digits].parse().ok()?;
+                t = match (sep, check(&t)) {
+                    ('_', Sort::Array(k, _, size)) if i < size => {
+                        term![Op::Select; t, k.elems_iter().nth(i)?]
+                    }
+                    ('.', Sort::Tuple(fs)) if i < fs.len() => term![Op::Field(i); t],
+                    _ => return None,
+                };
+                rest = &rest[digits..];
+            }
+            if &check(&t) == sort {
+                return Some(t);
+            }
+        }
+    }
+    None
+}
+
+/// Does `s` have field elements in it?
+fn has_field(s: &Sort) -> bool {
+    match s {
+        Sort::Field(_) => true,
+        Sort::Array(k, v, _) => has_field(k) || has_field(v),
+        Sort::Tuple(fs) => fs.iter().any(has_field),
+        _ => false,
+    }
+}
+
+fn smt_printable(t: &Term) -> bool {
+    PostOrderIter::new(t.clone()).all(|c| {
+        let sort_ok = !has_field(&check(&c));
+        let op_ok = match &c.op {
+            Op::PfToBv(_) | Op::PfUnOp(_) | Op::PfNaryOp(_) | Op::ConstArray(..) => false,
+            Op::FpBinOp(_) | Op::FpBinPred(_) | Op::FpUnPred(_) | Op::FpUnOp(_) => false,
+            Op::BvToFp | Op::UbvToFp(_) | Op::SbvToFp(_) | Op::FpToFp(_) => false,
+            _ => true,
+        };
+        sort_ok && op_ok
+    })
+}
+
+fn samplable(s: &Sort) -> bool {
+    match s {
+        Sort::Bool | Sort::BitVector(_) | Sort::Field(_) | Sort::F32 | Sort::F64 => true,
+        Sort::Tuple(fs) => fs.iter().all(samplable),
+        _ => false,
+    }
+}
+
+/// Substitute the definitions (`(= x t)`) in `outputs` of the variables in `vars` into
+/// `outputs`, until none are left.
+fn substitute_definitions(outputs: &[Term], vars: &AHashSet<String>) -> Vec<Term> {
+    let mut outputs = outputs.to_vec();
+    loop {
+        // Definitions which can be substituted at once: none uses a variable defined by another.
+        let mut subs = TermMap::new();
+        let mut defined = AHashSet::new();
+        let mut used = AHashSet::new();
+        for o in &outputs {
+            if o.op != Op::Eq {
+                continue;
+            }
+            for (v, t) in vec![(&o.cs[0], &o.cs[1]), (&o.cs[1], &o.cs[0])] {
+                if let Op::Var(n, _) = &v.op {
+                    if !vars.contains(n) || defined.contains(n) || used.contains(n) {
+                        continue;
+                    }
+                    let free = free_variables(t.clone());
+                    if free.contains(n) || free.iter().any(|f| defined.contains(f)) {
+                        continue;
+                    }
+                    defined.insert(n.clone());
+                    used.extend(free);
+                    subs.insert(v.clone(), t.clone());
+                    break;
+                }
+            }
+        }
+        if subs.is_empty() {
+            return outputs;
+        }
+        outputs = outputs
+            .iter()
+            .map(|o| substitute_cache(o, &mut subs))
+            .collect();
+    }
+}
+
+fn and(ts: &[Term]) -> Term {
+    match ts.len() {
+        0 => leaf_term(Op::Const(Value::Bool(true))),
+        1 => ts[0].clone(),
+        _ => term(AND, ts.to_vec()),
+    }
+}
+
+/// Check that `new` (the result of running `pass` on `old`) means the same thing as `old`.
+pub fn validate_pass(
+    pass: &str,
+    old: &Computation,
+    new: &Computation,
+    cfg: &ValidationConfig,
+) -> Result<Validation, ValidationFailure> {
+    let old_vars = var_sorts(&old.outputs);
+    let new_vars = var_sorts(&new.outputs);
+    let mut links = Vec::new();
+    let mut linked = AHashMap::new();
+    for (n, s) in &new_vars {
+        if !old_vars.contains_key(n) {
+            if let Some(t) = link(n, s, &old_vars) {
+                links.push(term![EQ; leaf_term(Op::Var(n.clone(), s.clone())), t.clone()]);
+                linked.insert(n.clone(), t);
+            }
+        }
+    }
+    let same_shape = old.outputs.len() == new.outputs.len();
+    // Variables the pass eliminated that are not represented by element variables
+    let eliminated: AHashSet<String> = old_vars
+        .keys()
+        .filter(|n| !new_vars.contains_key(*n) && !linked.values().any(|t| free_in(n, t.clone())))
+        .cloned()
+        .collect();
+    let old_outputs = if same_shape || eliminated.is_empty() {
+        old.outputs.clone()
+    } else {
+        substitute_definitions(&old.outputs, &eliminated)
+    };
+    let mut undefined: Vec<&String> = eliminated
+        .iter()
+        .filter(|n| old_outputs.iter().any(|o| free_in(n, o.clone())))
+        .collect();
+    undefined.sort();
+    // Without definitions for the eliminated variables, we can only check one direction.
+    let one_way = !same_shape && !undefined.is_empty();
+    let checked = || {
+        if one_way {
+            let undefined: Vec<&str> = undefined.iter().map(|n| n.as_str()).collect();
+            Validation::Unknown(format!(
+                "only checked that the old assertions imply the new ones, since {} {} \
+                 eliminated without a definition",
+                undefined.join(", "),
+                if undefined.len() == 1 { "was" } else { "were" }
+            ))
+        } else {
+            Validation::Valid
+        }
+    };
+    // pairs of (old, new) terms to compare; the second component is the output index
+    let pairs: Vec<(Term, Term, Option<usize>)> = if same_shape {
+        old_outputs
+            .iter()
+            .zip(&new.outputs)
+            .enumerate()
+            .filter(|(_, (o, n))| o != n)
+            .map(|(i, (o, n))| (o.clone(), n.clone(), Some(i)))
+            .collect()
+    } else {
+        vec![(and(&old_outputs), and(&new.outputs), None)]
+    };
+    let differs = |o: &Term, n: &Term| {
+        if one_way {
+            term![AND; o.clone(), term![NOT; n.clone()]]
+        } else {
+            term![NOT; term![EQ; o.clone(), n.clone()]]
+        }
+    };
+    if pairs.is_empty() {
+        return Ok(Validation::Valid);
+    }
+    let fail = |model: Option<AHashMap<String, Value>>| {
+        // Models include every variable, so we can find the pair that differs.
+        let (output, values) = model
+            .as_ref()
+            .and_then(|m| {
+                pairs.iter().find_map(|(o, n, i)| {
+                    let (ov, nv) = (eval(o, m), eval(n, m));
+                    if ov != nv {
+                        Some((*i, Some((ov, nv))))
+                    } else {
+                        None
+                    }
+                })
+            })
+            .unwrap_or((pairs[0].2, None));
+        ValidationFailure {
+            pass: pass.to_owned(),
+            model,
+            output,
+            values,
+        }
+    };
+    if pairs
+        .iter()
+        .all(|(o, n, _)| smt_printable(o) && smt_printable(n))
+    {
+        let mut query: Vec<Term> = links.clone();
+        let diffs: Vec<Term> = pairs.iter().map(|(o, n, _)| differs(o, n)).collect();
+        query.push(if diffs.len() == 1 {
+            diffs[0].clone()
+        } else {
+            term(OR, diffs)
+        });
+        match check_sat_timeout(&and(&query), cfg.timeout) {
+            SmtResult::Unsat => Ok(checked()),
+            SmtResult::Sat(model) => Err(fail(model.map(|m| m.into_iter().collect()))),
+            SmtResult::Unknown => Ok(Validation::Unknown("the solver gave up".to_owned())),
+        }
+    } else {
+        let mut sorts: AHashMap<String, Sort> = var_sorts(&old_outputs);
+        for (n, s) in &new_vars {
+            if !linked.contains_key(n) {
+                sorts.insert(n.clone(), s.clone());
+            }
+        }
+        let mut envs: Vec<AHashMap<String, Value>> = Vec::new();
+        // The computation's own values satisfy its assertions, so they are the most telling.
+        if let Some(values) = &old.values {
+            let mut env = values.clone();
+            for (n, v) in new.values.iter().flatten() {
+                env.entry(n.clone()).or_insert_with(|| v.clone());
+            }
+            if sorts.keys().all(|n| env.contains_key(n)) {
+                envs.push(env);
+            }
+        }
+        match sorts.iter().find(|(_, s)| !samplable(s)) {
+            Some((n, s)) if envs.is_empty() => {
+                return Ok(Validation::Unknown(format!("cannot sample {}: {}", n, s)));
+            }
+            Some(_) => {}
+            None => {
+                let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+                for _ in 0..cfg.samples {
+                    envs.push(
+                        sorts
+                            .iter()
+                            .map(|(n, s)| (n.clone(), UniformValue(s).sample(&mut rng)))
+                            .collect(),
+                    );
+                }
+            }
+        }
+        debug!("Validating {} with {} inputs", pass, envs.len());
+        let fls = Value::Bool(false);
+        // Did any input satisfy some assertion (or give some output that is not an assertion)?
+        let mut informative = false;
+        for mut env in envs {
+            for (n, t) in &linked {
+                let v = eval(t, &env);
+                env.insert(n.clone(), v);
+            }
+            for (o, n, i) in &pairs {
+                let ov = eval(o, &env);
+                let nv = eval(n, &env);
+                let bad = if one_way {
+                    informative |= ov != fls;
+                    ov.as_bool() && !nv.as_bool()
+                } else {
+                    informative |= ov != fls || nv != fls;
+                    ov != nv
+                };
+                if bad {
+                    return Err(ValidationFailure {
+                        pass: pass.to_owned(),
+                        model: Some(env),
+                        output: *i,
+                        values: Some((ov, nv)),
+                    });
+                }
+            }
+        }
+        if informative {
+            Ok(match checked() {
+                Validation::Valid => Validation::Unknown("tested, not proved".to_owned()),
+                unknown => unknown,
+            })
+        } else {
+            Ok(Validation::Unknown(
+                "no input tried satisfies the assertions".to_owned(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::field::TEST_FIELD;
+    use rug::Integer;
+    use std::sync::Arc;
+
+    fn bv_var(s: &str) -> Term {
+        leaf_term(Op::Var(s.to_owned(), Sort::BitVector(4)))
+    }
+
+    fn comp(outputs: Vec<Term>) -> Computation {
+        let mut cs = Computation::new(false);
+        cs.outputs = outputs;
+        cs
+    }
+
+    #[test]
+    fn equivalent() {
+        let a = bv_var("a");
+        let old = comp(vec![
+            term![BV_ULT; term![BV_ADD; a.clone(), bv_lit(0, 4)], bv_lit(3, 4)],
+        ]);
+        let new = comp(vec![term![BV_ULT; a, bv_lit(3, 4)]]);
+        let v = validate_pass("test", &old, &new, &ValidationConfig::default()).unwrap();
+        assert_eq!(v, Validation::Valid);
+    }
+
+    #[test]
+    fn broken() {
+        let a = bv_var("a");
+        let old = comp(vec![term![BV_ULT; a.clone(), bv_lit(3, 4)]]);
+        let new = comp(vec![term![BV_ULE; a, bv_lit(3, 4)]]);
+        let e = validate_pass("test", &old, &new, &ValidationConfig::default()).unwrap_err();
+        assert_eq!(e.output, Some(0));
+        let m = e.model.unwrap();
+        assert_eq!(m.get("a").unwrap().as_bv().uint(), &Integer::from(3));
+    }
+
+    #[test]
+    fn inlining() {
+        let a = bv_var("a");
+        let b = bv_var("b");
+        let old = comp(vec![
+            term![EQ; b.clone(), term![BV_ADD; a.clone(), bv_lit(1, 4)]],
+            term![BV_ULT; b, bv_lit(3, 4)],
+        ]);
+        let new = comp(vec![
+            term![BV_ULT; term![BV_ADD; a, bv_lit(1, 4)], bv_lit(3, 4)],
+        ]);
+        let v = validate_pass("inline", &old, &new, &ValidationConfig::default()).unwrap();
+        assert_eq!(v, Validation::Valid);
+    }
+
+    #[test]
+    fn inlining_must_not_weaken() {
+        let a = bv_var("a");
+        let b = bv_var("b");
+        let old = comp(vec![
+            term![EQ; term![BV_ADD; a.clone(), bv_lit(1, 4)], b.clone()],
+            term![BV_ULT; b, bv_lit(3, 4)],
+        ]);
+        let new = comp(vec![
+            term![BV_ULT; term![BV_ADD; a, bv_lit(1, 4)], bv_lit(4, 4)],
+        ]);
+        let e = validate_pass("inline", &old, &new, &ValidationConfig::default()).unwrap_err();
+        let m = e.model.unwrap();
+        assert_eq!(m.get("a").unwrap().as_bv().uint(), &Integer::from(2));
+    }
+
+    #[test]
+    fn undefined_elimination() {
+        let a = bv_var("a");
+        let b = bv_var("b");
+        let old = comp(vec![
+            term![BV_ULT; b, a.clone()],
+            term![BV_ULT; a.clone(), bv_lit(3, 4)],
+        ]);
+        let new = comp(vec![term![BV_ULT; a, bv_lit(3, 4)]]);
+        let v = validate_pass("drop", &old, &new, &ValidationConfig::default()).unwrap();
+        assert!(matches!(v, Validation::Unknown(why) if why.contains("b was eliminated")));
+    }
+
+    #[test]
+    fn tuple_links() {
+        let t = leaf_term(Op::Var(
+            "t".to_owned(),
+            Sort::Tuple(vec![Sort::Bool, Sort::BitVector(4)]),
+        ));
+        let old = comp(vec![term![Op::Field(0); t]]);
+        let new = comp(vec![leaf_term(Op::Var("t.0".to_owned(), Sort::Bool))]);
+        assert!(validate_pass("tuple", &old, &new, &ValidationConfig::default()).is_ok());
+    }
+
+    #[test]
+    fn field_arrays() {
+        let m = Arc::new(Integer::from(TEST_FIELD));
+        let sort = Sort::Array(Box::new(Sort::BitVector(4)), Box::new(Sort::Field(m)), 4);
+        let a = leaf_term(Op::Var("a".to_owned(), sort.clone()));
+        let b = leaf_term(Op::Var("b".to_owned(), sort));
+        let old = comp(vec![term![EQ; a.clone(), b.clone()]]);
+        let new = comp(vec![term![EQ; b, a]]);
+        let v = validate_pass("swap", &old, &new, &ValidationConfig::default()).unwrap();
+        assert!(matches!(v, Validation::Unknown(why) if why.starts_with("cannot sample")));
+    }
+
+    #[test]
+    fn field_random() {
+        let m = Arc::new(Integer::from(TEST_FIELD));
+        let x = leaf_term(Op::Var("x".to_owned(), Sort::Field(m.clone())));
+        let one = leaf_term(Op::Const(Value::Field(FieldElem::new(
+            Integer::from(1),
+            m.clone(),
+        ))));
+        let old = comp(vec![
+            term![EQ; term![PF_MUL; x.clone(), one.clone()], one.clone()],
+        ]);
+        let good = comp(vec![term![EQ; x.clone(), one.clone()]]);
+        let bad = comp(vec![term![NOT; term![EQ; x, one]]]);
+        let cfg = ValidationConfig::default();
+        // x is almost never 1 at random
+        let v = validate_pass("good", &old, &good, &cfg).unwrap();
+        assert!(matches!(v, Validation::Unknown(_)));
+        let mut values = AHashMap::new();
+        values.insert(
+            "x".to_owned(),
+            Value::Field(FieldElem::new(Integer::from(1), m)),
+        );
+        let old = Computation {
+            values: Some(values),
+            ..old
+        };
+        assert_eq!(
+            validate_pass("good", &old, &good, &cfg).unwrap(),
+            Validation::Unknown("tested, not proved".to_owned())
+        );
+        let e = validate_pass("bad", &old, &bad, &cfg).unwrap_err();
+        assert_eq!(e.pass, "bad");
+        assert!(e.model.is_some());
+    }
+}
@@ -0,0 +1,256 @@
+//! Cone-of-influence slicing of computations
+//!
+//! A slice of a computation keeps some of its outputs, the terms they depend on, and the inputs
+//! (with metadata and values) that those terms use.
+
+use crate::ir::term::*;
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use std::fmt::{self, Display, Formatter};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+/// What to slice a computation to.
+pub enum SliceRoot {
+    /// The output with this index.
+    Output(usize),
+    /// All outputs that mention this variable.
+    Var(String),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+/// Statistics about a slice: how much of the original computation it kept.
+pub struct SliceStats {
+    /// Outputs in the slice
+    pub outputs: usize,
+    /// Outputs in the original computation
+    pub total_outputs: usize,
+    /// Unique terms in the slice
+    pub terms: usize,
+    /// Unique terms in the original computation
+    pub total_terms: usize,
+    /// Inputs in the slice
+    pub inputs: usize,
+    /// Inputs in the original computation
+    pub total_inputs: usize,
+}
+
+impl Display for SliceStats {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}/{} outputs, {}/{} terms, {}/{} inputs",
+            self.outputs,
+            self.total_outputs,
+            self.terms,
+            self.total_terms,
+            self.inputs,
+            self.total_inputs
+        )
+    }
+}
+
+impl Computation {
+    /// The indices of the outputs selected by `roots`, in order.
+    fn slice_output_idxs<I: IntoIterator<Item = SliceRoot>>(&self, roots: I) -> Vec<usize> {
+        let mut idxs = AHashSet::new();
+        let mut vars = AHashSet::new();
+        for r in roots {
+            match r {
+                SliceRoot::Output(i) => {
+                    assert!(
+                        i < self.outputs.len(),
+                        "Output {} out of bounds: there are {} outputs",
+                        i,
+                        self.outputs.len()
+                    );
+                    idxs.insert(i);
+                }
+                SliceRoot::Var(v) => {
+                    vars.insert(v);
+                }
+            }
+        }
+        if !vars.is_empty() {
+            // The terms which mention some variable in `vars`
+            let mut mentions = TermSet::new();
+            for t in PostOrderIter::from_roots(self.outputs.iter().cloned()) {
+                let mentioned = match &t.op {
+                    Op::Var(name, _) => vars.contains(name),
+                    _ => t.cs.iter().any(|c| mentions.contains(c)),
+                };
+                if mentioned {
+                    mentions.insert(t);
+                }
+            }
+            for (i, o) in self.outputs.iter().enumerate() {
+                if mentions.contains(o) {
+                    idxs.insert(i);
+                }
+            }
+        }
+        let mut idxs: Vec<usize> = idxs.into_iter().collect();
+        idxs.sort();
+        idxs
+    }
+
+    /// Slice this computation down to the fan-in of `roots`.
+    ///
+    /// The result has only the selected outputs, and only the inputs (and values) they use.
+    pub fn slice<I: IntoIterator<Item = SliceRoot>>(&self, roots: I) -> (Computation, SliceStats) {
+        let outputs: Vec<Term> = self
+            .slice_output_idxs(roots)
+            .into_iter()
+            .map(|i| self.outputs[i].clone())
+            .collect();
+        let vars: AHashSet<String> = PostOrderIter::from_roots(outputs.iter().cloned())
+            .filter_map(|t| match &t.op {
+                Op::Var(name, _) => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+        let metadata = ComputationMetadata {
+            party_ids: self.metadata.party_ids.clone(),
+            next_party_id: self.metadata.next_party_id,
+            inputs: self
+                .metadata
+                .inputs
+                .iter()
+                .filter(|(name, _)| vars.contains(*name))
+                .map(|(name, party)| (name.clone(), *party))
+                .collect(),
+            field: self.metadata.field.clone(),
+        };
+        let values = self.values.as_ref().map(|vs| {
+            vs.iter()
+                .filter(|(name, _)| vars.contains(*name))
+                .map(|(name, v)| (name.clone(), v.clone()))
+                .collect::<AHashMap<_, _>>()
+        });
+        let sliced = Computation {
+            outputs,
+            values,
+            metadata,
+        };
+        let stats = SliceStats {
+            outputs: sliced.outputs.len(),
+            total_outputs: self.outputs.len(),
+            terms: sliced.terms(),
+            total_terms: self.terms(),
+            inputs: sliced.metadata.inputs.len(),
+            total_inputs: self.metadata.inputs.len(),
+        };
+        debug!("Slice: {}", stats);
+        (sliced, stats)
+    }
+
+    /// Partition the outputs into groups which share no variables.
+    ///
+    /// Each group can be sliced out as an independent sub-computation.
+    pub fn independent_output_groups(&self) -> Vec<Vec<usize>> {
+        // union-find over variables
+        let mut parent: Vec<usize> = Vec::new();
+        fn find(parent: &mut Vec<usize>, i: usize) -> usize {
+            let mut root = i;
+            while parent[root] != root {
+                root = parent[root];
+            }
+            let mut j = i;
+            while parent[j] != root {
+                let next = parent[j];
+                parent[j] = root;
+                j = next;
+            }
+            root
+        }
+        // For each term over some variable, one of its variables
+        let mut rep: TermMap<usize> = TermMap::new();
+        for t in PostOrderIter::from_roots(self.outputs.iter().cloned()) {
+            if let Op::Var(..) = &t.op {
+                rep.insert(t, parent.len());
+                parent.push(parent.len());
+            } else {
+                let reps: Vec<usize> = t.cs.iter().filter_map(|c| rep.get(c).cloned()).collect();
+                if let Some(&first) = reps.first() {
+                    for &r in &reps[1..] {
+                        let ri = find(&mut parent, r);
+                        let rf = find(&mut parent, first);
+                        parent[ri] = rf;
+                    }
+                    rep.insert(t, first);
+                }
+            }
+        }
+        // Group by the class of an output's variables; outputs over no variables stand alone.
+        let mut groups: AHashMap<usize, Vec<usize>> = AHashMap::new();
+        let mut var_free = Vec::new();
+        for (i, o) in self.outputs.iter().enumerate() {
+            match rep.get(o).cloned() {
+                Some(r) => {
+                    let r = find(&mut parent, r);
+                    groups.entry(r).or_insert_with(Vec::new).push(i);
+                }
+                None => var_free.push(vec![i]),
+            }
+        }
+        let mut groups: Vec<Vec<usize>> = groups.into_iter().map(|(_, g)| g).collect();
+        groups.extend(var_free);
+        groups.sort();
+        groups
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn comp() -> Computation {
+        let mut cs = Computation::new(true);
+        let a = cs.new_var("a", Sort::Bool, || Value::Bool(true), None);
+        let b = cs.new_var("b", Sort::Bool, || Value::Bool(false), Some(0));
+        let c = cs.new_var("c", Sort::Bool, || Value::Bool(true), Some(0));
+        let d = cs.new_var("d", Sort::Bool, || Value::Bool(true), Some(0));
+        cs.assert(term![OR; a.clone(), b.clone()]);
+        cs.assert(term![IMPLIES; b, c]);
+        cs.assert(term![AND; a, d.clone()]);
+        cs.assert(d);
+        cs
+    }
+
+    #[test]
+    fn by_output() {
+        let cs = comp();
+        let (s, stats) = cs.slice(vec![SliceRoot::Output(1)]);
+        assert_eq!(s.outputs, vec![cs.outputs[1].clone()]);
+        let mut inputs: Vec<_> = s.metadata.inputs.keys().cloned().collect();
+        inputs.sort();
+        assert_eq!(inputs, vec!["b".to_owned(), "c".to_owned()]);
+        assert_eq!(s.values.as_ref().unwrap().len(), 2);
+        assert_eq!(stats.outputs, 1);
+        assert_eq!(stats.total_outputs, 4);
+        assert_eq!(stats.terms, 3);
+        assert_eq!(stats.total_inputs, 4);
+    }
+
+    #[test]
+    fn by_var() {
+        let cs = comp();
+        let (s, stats) = cs.slice(vec![SliceRoot::Var("d".to_owned())]);
+        assert_eq!(
+            s.outputs,
+            vec![cs.outputs[2].clone(), cs.outputs[3].clone()]
+        );
+        assert_eq!(stats.inputs, 2);
+        assert!(s.metadata.is_input_public("a"));
+    }
+
+    #[test]
+    fn groups() {
+        let mut cs = comp();
+        let e = cs.new_var("e", Sort::Bool, || Value::Bool(true), None);
+        cs.assert(e);
+        assert_eq!(
+            cs.independent_output_groups(),
+            vec![vec![0, 1, 2, 3], vec![4]]
+        );
+    }
+}
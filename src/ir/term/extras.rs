@@ -134,6 +134,16 @@ pub fn free_in(v: &str, t: Term) -> bool {
     return false;
 }
 
+/// The names of the variables in `t`.
+pub fn free_variables(t: Term) -> ahash::AHashSet<String> {
+    PostOrderIter::new(t)
+        .filter_map(|n| match &n.op {
+            Op::Var(name, _) => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod test {
 
@@ -4,11 +4,70 @@ use rug::ops::RemRounding;
 use rug::Integer;
 
 use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 use std::sync::Arc;
+use thiserror::Error;
 
 /// Test modulus.
 pub const TEST_FIELD: usize = 1014088787;
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+/// A pairing-friendly curve, named for the prime field of its scalars.
+///
+/// Proofs over a curve's pairing are for constraints in that field.
+pub enum Curve {
+    /// BN254 (a.k.a. BN128, or alt_bn128), the curve with Ethereum precompiles
+    Bn128,
+    /// BLS12-381
+    Bls12_381,
+}
+
+impl Curve {
+    /// The order of the curve's scalar field.
+    pub fn modulus(&self) -> Integer {
+        let digits = match self {
+            Curve::Bn128 => {
+                "21888242871839275222246405745257275088548364400416034343698204186575808495617"
+            }
+            Curve::Bls12_381 => {
+                "52435875175126190479447740508185965837690552500527637822603658699938581184513"
+            }
+        };
+        Integer::from_str_radix(digits, 10).unwrap()
+    }
+}
+
+impl Default for Curve {
+    fn default() -> Self {
+        Curve::Bls12_381
+    }
+}
+
+impl Display for Curve {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Curve::Bn128 => write!(f, "bn128"),
+            Curve::Bls12_381 => write!(f, "bls12_381"),
+        }
+    }
+}
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("unknown curve '{0}'; expected bn128 or bls12_381")]
+/// A curve name which could not be parsed.
+pub struct UnknownCurve(pub String);
+
+impl FromStr for Curve {
+    type Err = UnknownCurve;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bn128" | "bn254" | "alt_bn128" => Ok(Curve::Bn128),
+            "bls12_381" | "bls12-381" => Ok(Curve::Bls12_381),
+            _ => Err(UnknownCurve(s.to_owned())),
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Eq, Hash, Debug, PartialOrd, Ord)]
 /// A prime field element
 pub struct FieldElem {
@@ -101,3 +160,23 @@ impl std::ops::Neg for FieldElem {
         r
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn curves() {
+        for (c, bits) in vec![(Curve::Bn128, 254), (Curve::Bls12_381, 255)] {
+            let m = c.modulus();
+            assert_eq!(m.significant_bits(), bits);
+            assert_ne!(m.is_probably_prime(30), rug::integer::IsPrime::No);
+            assert_eq!(c.to_string().parse::<Curve>(), Ok(c));
+        }
+        assert_eq!("bn254".parse::<Curve>(), Ok(Curve::Bn128));
+        assert_eq!(
+            "bls12_377".parse::<Curve>(),
+            Err(UnknownCurve("bls12_377".to_owned()))
+        );
+    }
+}
@@ -38,7 +38,7 @@ pub mod field;
 pub mod ty;
 
 pub use bv::BitVector;
-pub use field::FieldElem;
+pub use field::{Curve, FieldElem};
 pub use ty::{check, check_rec, TypeError, TypeErrorReason};
 
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -985,6 +985,32 @@ impl Value {
             panic!("Not a tuple: {}", self)
         }
     }
+    #[track_caller]
+    /// Get the element of this array at `key`, or panic!
+    pub fn select(&self, key: &Value) -> Value {
+        if let Value::Array(_, default, map, _) = self {
+            map.get(key).unwrap_or(&**default).clone()
+        } else {
+            panic!("Not an array: {}", self)
+        }
+    }
+    #[track_caller]
+    /// Get this array, with the element at `key` replaced by `val`, or panic!
+    ///
+    /// Elements equal to the default are not stored explicitly.
+    pub fn store(&self, key: Value, val: Value) -> Value {
+        if let Value::Array(s, default, map, size) = self {
+            let mut map = map.clone();
+            if &val == &**default {
+                map.remove(&key);
+            } else {
+                map.insert(key, val);
+            }
+            Value::Array(s.clone(), default.clone(), map, *size)
+        } else {
+            panic!("Not an array: {}", self)
+        }
+    }
 
     /// Get the underlying boolean constant, if possible.
     pub fn as_bool_opt(&self) -> Option<bool> {
@@ -1139,6 +1165,20 @@ pub fn eval(t: &Term, h: &AHashMap<String, Value>) -> Value {
                 )
             }),
             Op::Tuple => Value::Tuple(c.cs.iter().map(|c| vs.get(c).unwrap().clone()).collect()),
+            Op::ConstArray(key_sort, size) => {
+                let v = vs.get(&c.cs[0]).unwrap().clone();
+                Value::Array(
+                    Sort::Array(Box::new(key_sort.clone()), Box::new(v.sort()), *size),
+                    Box::new(v),
+                    BTreeMap::new(),
+                    *size,
+                )
+            }
+            Op::Store => vs.get(&c.cs[0]).unwrap().store(
+                vs.get(&c.cs[1]).unwrap().clone(),
+                vs.get(&c.cs[2]).unwrap().clone(),
+            ),
+            Op::Select => vs.get(&c.cs[0]).unwrap().select(vs.get(&c.cs[1]).unwrap()),
             Op::Field(i) => {
                 let t = vs.get(&c.cs[0]).unwrap().as_tuple();
                 assert!(i < &t.len(), "{} out of bounds for {}", i, c.cs[0]);
@@ -1210,6 +1250,17 @@ impl PostOrderIter {
             visited: TermSet::new(),
         }
     }
+
+    /// Make an iterator over the descendents of all of `roots`, in order, visiting each shared
+    /// descendent once.
+    pub fn from_roots<I: IntoIterator<Item = Term>>(roots: I) -> Self {
+        let mut stack: Vec<(bool, Term)> = roots.into_iter().map(|r| (false, r)).collect();
+        stack.reverse();
+        Self {
+            stack,
+            visited: TermSet::new(),
+        }
+    }
 }
 
 impl std::iter::Iterator for PostOrderIter {
@@ -1246,6 +1297,8 @@ pub struct ComputationMetadata {
     pub next_party_id: PartyId,
     /// All inputs, including who knows them. If no visibility is set, the input is public.
     pub inputs: AHashMap<String, Option<PartyId>>,
+    /// The modulus of the prime field the computation is over, if its front end chose one.
+    pub field: Option<Arc<Integer>>,
 }
 
 impl ComputationMetadata {
@@ -1266,6 +1319,10 @@ impl ComputationMetadata {
         );
         self.inputs.insert(input_name, party);
     }
+    /// Remove an input from the computation, returning its visibility, if it was present.
+    pub fn remove_input(&mut self, input_name: &str) -> Option<Option<PartyId>> {
+        self.inputs.remove(input_name)
+    }
     /// Returns None if the value is public. Otherwise, the unique party that knows it.
     pub fn get_input_visibility(&self, input_name: &str) -> Option<PartyId> {
         self.inputs
@@ -55,6 +55,25 @@ mod type_ {
                 .collect::<Vec<_>>()
         );
     }
+
+    #[test]
+    fn traversal_from_roots() {
+        let b = leaf_term(Op::Var("b".to_owned(), Sort::BitVector(4)));
+        let tt = t();
+        assert_eq!(
+            vec![
+                Op::Var("c".to_owned(), Sort::Bool),
+                Op::BoolToBv,
+                Op::Var("b".to_owned(), Sort::BitVector(4)),
+                Op::BvConcat,
+                Op::BvBit(4),
+                Op::BvUnOp(BvUnOp::Not),
+            ],
+            PostOrderIter::from_roots(vec![tt, term![BV_NOT; b]])
+                .map(|t| t.op.clone())
+                .collect::<Vec<_>>()
+        );
+    }
 }
 
 fn bool(b: bool) -> Term {
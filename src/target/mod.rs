@@ -0,0 +1,6 @@
+//! Target circuit representations (and lowering passes)
+
+pub mod aby;
+pub mod ilp;
+pub mod r1cs;
+pub mod smt;
@@ -0,0 +1,243 @@
+//! Groth16 proofs of our R1CS, via bellman
+//!
+//! The public inputs of a proof are the values of the public signals, in increasing index order
+//! (see [public_values]).
+//!
+//! The R1CS must be over the scalar field of the pairing's curve.
+
+use super::bellman::{int_to_ff, modulus_as_int};
+use super::*;
+
+use ::bellman::groth16::{self, Parameters, Proof, VerifyingKey};
+use ::bellman::SynthesisError;
+use group::WnafGroup;
+use pairing::MultiMillerLoop;
+use rand::RngCore;
+use thiserror::Error;
+
+use std::io::{self, BufRead, Write};
+
+#[derive(Debug, Error)]
+/// An error in proving or verifying
+pub enum Groth16Error {
+    #[error("Synthesis error: {0}")]
+    Synthesis(#[from] SynthesisError),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("The constraint system has no witness values")]
+    MissingValues,
+    #[error("The witness does not satisfy the constraint system: {0}")]
+    Unsatisfied(#[from] Unsatisfied),
+    #[error("Expected {0} public inputs, but got {1}")]
+    PublicInputCount(usize, usize),
+    #[error("Bad public input '{0}'")]
+    BadPublicInput(String),
+    #[error("The R1CS is over the field of order {0}, but the curve's scalars have order {1}")]
+    FieldMismatch(Integer, Integer),
+}
+
+/// Check that `r1cs` is over the scalar field of `E`.
+fn check_field<E: MultiMillerLoop, S: Hash + Eq>(r1cs: &R1cs<S>) -> Result<(), Groth16Error> {
+    let engine_modulus = modulus_as_int::<E::Fr>();
+    if *r1cs.modulus == engine_modulus {
+        Ok(())
+    } else {
+        Err(Groth16Error::FieldMismatch(
+            (*r1cs.modulus).clone(),
+            engine_modulus,
+        ))
+    }
+}
+
+/// Generate parameters (a proving key, which includes the verifying key) for `r1cs`.
+pub fn setup<E, S, R>(r1cs: &R1cs<S>, rng: &mut R) -> Result<Parameters<E>, Groth16Error>
+where
+    E: MultiMillerLoop,
+    E::G1: WnafGroup,
+    E::G2: WnafGroup,
+    S: Display + Eq + Hash,
+    R: RngCore,
+{
+    check_field::<E, S>(r1cs)?;
+    Ok(groth16::generate_random_parameters::<E, _, _>(r1cs, rng)?)
+}
+
+/// Prove that `r1cs`, which must have values, is satisfied. If it is not, reports the violated
+/// constraints, rather than making a proof which does not verify.
+pub fn prove<E, S, R>(
+    r1cs: &R1cs<S>,
+    params: &Parameters<E>,
+    rng: &mut R,
+) -> Result<Proof<E>, Groth16Error>
+where
+    E: MultiMillerLoop,
+    S: Display + Eq + Hash,
+    R: RngCore,
+{
+    check_field::<E, S>(r1cs)?;
+    if r1cs.values.is_none() {
+        return Err(Groth16Error::MissingValues);
+    }
+    r1cs.check_all()?;
+    Ok(groth16::create_random_proof(r1cs, params, rng)?)
+}
+
+/// Check `proof` against a verifying key and the values of the public signals, in increasing index
+/// order.
+pub fn verify<E: MultiMillerLoop>(
+    vk: &VerifyingKey<E>,
+    proof: &Proof<E>,
+    public: &[Integer],
+) -> Result<bool, Groth16Error> {
+    let expected = vk.ic.len() - 1;
+    if public.len() != expected {
+        return Err(Groth16Error::PublicInputCount(expected, public.len()));
+    }
+    let pvk = groth16::prepare_verifying_key(vk);
+    let inputs: Vec<E::Fr> = public.iter().map(int_to_ff).collect();
+    Ok(groth16::verify_proof(&pvk, proof, &inputs).is_ok())
+}
+
+/// The values of `r1cs`'s public signals, in increasing index order.
+pub fn public_values<S: Hash + Eq>(r1cs: &R1cs<S>) -> Result<Vec<Integer>, Groth16Error> {
+    let values = r1cs.values.as_ref().ok_or(Groth16Error::MissingValues)?;
+    let mut public: Vec<usize> = r1cs.public_idxs.iter().cloned().collect();
+    public.sort_unstable();
+    public
+        .into_iter()
+        .map(|i| values.get(&i).cloned().ok_or(Groth16Error::MissingValues))
+        .collect()
+}
+
+/// Write public input values, one (decimal) per line.
+pub fn write_public<W: Write>(public: &[Integer], out: &mut W) -> io::Result<()> {
+    for v in public {
+        writeln!(out, "{}", v)?;
+    }
+    Ok(())
+}
+
+/// Read public input values, one (decimal) per line.
+pub fn read_public<R: BufRead>(r: R) -> Result<Vec<Integer>, Groth16Error> {
+    let mut public = Vec::new();
+    for line in r.lines() {
+        let line = line?;
+        let line = line.trim();
+        if !line.is_empty() {
+            let v = Integer::from_str_radix(line, 10)
+                .map_err(|_| Groth16Error::BadPublicInput(line.to_owned()))?;
+            public.push(v);
+        }
+    }
+    Ok(public)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::*;
+    use crate::target::r1cs::trans::to_r1cs;
+    use bls12_381::Bls12;
+    use rand::SeedableRng;
+
+    fn modulus() -> Integer {
+        Integer::from_str_radix(
+            "73eda753299d7d483339d80809a1d80553bda402fffe5bfeffffffff00000001",
+            16,
+        )
+        .unwrap()
+    }
+
+    /// `a * b = c`, with `c` public.
+    fn product(a: usize, b: usize, c: usize) -> R1cs<String> {
+        let v = |n: &str| leaf_term(Op::Var(n.to_owned(), Sort::BitVector(8)));
+        let lit = |i: usize| Value::BitVector(BitVector::new(Integer::from(i), 8));
+        let cs = Computation::from_constraint_system_parts(
+            vec![term![Op::Eq; term![BV_MUL; v("a"), v("b")], v("c")]],
+            vec!["c".to_owned()].into_iter().collect(),
+            Some(
+                vec![
+                    ("a".to_owned(), lit(a)),
+                    ("b".to_owned(), lit(b)),
+                    ("c".to_owned(), lit(c)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        to_r1cs(cs, modulus())
+    }
+
+    #[test]
+    fn prove_and_verify() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let r1cs = product(3, 5, 15);
+        let params = setup::<Bls12, _, _>(&r1cs, &mut rng).unwrap();
+        let proof = prove(&r1cs, &params, &mut rng).unwrap();
+        let public = public_values(&r1cs).unwrap();
+        assert_eq!(public, vec![Integer::from(15)]);
+        assert!(verify(&params.vk, &proof, &public).unwrap());
+        assert!(!verify(&params.vk, &proof, &[Integer::from(16)]).unwrap());
+        assert!(matches!(
+            verify(&params.vk, &proof, &[]),
+            Err(Groth16Error::PublicInputCount(1, 0))
+        ));
+
+        // The same parameters prove other assignments
+        let r1cs = product(4, 6, 24);
+        let proof = prove(&r1cs, &params, &mut rng).unwrap();
+        assert!(verify(&params.vk, &proof, &[Integer::from(24)]).unwrap());
+    }
+
+    #[test]
+    fn serialize() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let r1cs = product(3, 5, 15);
+        let params = setup::<Bls12, _, _>(&r1cs, &mut rng).unwrap();
+        let proof = prove(&r1cs, &params, &mut rng).unwrap();
+
+        let mut bytes = Vec::new();
+        params.vk.write(&mut bytes).unwrap();
+        let vk = VerifyingKey::<Bls12>::read(&bytes[..]).unwrap();
+        let mut bytes = Vec::new();
+        proof.write(&mut bytes).unwrap();
+        let proof = Proof::<Bls12>::read(&bytes[..]).unwrap();
+        let mut bytes = Vec::new();
+        write_public(&public_values(&r1cs).unwrap(), &mut bytes).unwrap();
+        let public = read_public(&bytes[..]).unwrap();
+        assert!(verify(&vk, &proof, &public).unwrap());
+    }
+
+    #[test]
+    fn no_values() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut r1cs = product(3, 5, 15);
+        r1cs.values = None;
+        let params = setup::<Bls12, _, _>(&r1cs, &mut rng).unwrap();
+        assert!(matches!(
+            prove(&r1cs, &params, &mut rng),
+            Err(Groth16Error::MissingValues)
+        ));
+    }
+
+    #[test]
+    fn unsatisfied() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let r1cs = product(3, 5, 16);
+        let params = setup::<Bls12, _, _>(&r1cs, &mut rng).unwrap();
+        match prove(&r1cs, &params, &mut rng) {
+            Err(Groth16Error::Unsatisfied(u)) => assert!(!u.violations.is_empty()),
+            r => panic!("expected unsatisfied constraints, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn wrong_field() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let r1cs: R1cs<String> = R1cs::new(Curve::Bn128.modulus(), false);
+        assert!(matches!(
+            setup::<Bls12, _, _>(&r1cs, &mut rng),
+            Err(Groth16Error::FieldMismatch(_, _))
+        ));
+    }
+}
@@ -0,0 +1,491 @@
+//! The iden3 binary formats for constraint systems (`.r1cs`) and witnesses (`.wtns`), and circom's
+//! symbol files (`.sym`)
+//!
+//! These let external tools (e.g., snarkjs) prove and verify our constraint systems, and let us
+//! read circom's output.
+//!
+//! Wire 0 is the constant 1. It is followed by our public signals and then our private ones, each
+//! in increasing index order. Public signals are written as public inputs (we have no public
+//! outputs), and private signals as internal wires. Each wire is its own label.
+//!
+//! When reading, signal `i` is wire `i + 1`.
+//!
+//! See <https://github.com/iden3/r1csfile/blob/master/doc/r1cs_bin_format.md>.
+
+use super::*;
+
+use rug::integer::Order;
+use thiserror::Error;
+
+use std::io::{self, BufRead, Read, Write};
+
+const R1CS_MAGIC: &[u8; 4] = b"r1cs";
+const R1CS_VERSION: u32 = 1;
+const WTNS_MAGIC: &[u8; 4] = b"wtns";
+const WTNS_VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+/// An error in reading or writing an iden3 file
+pub enum Iden3Error {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Not a '{0}' file")]
+    BadMagic(String),
+    #[error("Unsupported {0} version {1}")]
+    BadVersion(String, u32),
+    #[error("Missing section {0}")]
+    MissingSection(u32),
+    #[error("Malformed file: {0}")]
+    Malformed(String),
+    #[error("The constraint system has no witness values")]
+    MissingValues,
+}
+
+/// The number of bytes in a field element: the size of `modulus`, rounded up to 8-byte words.
+pub(super) fn field_bytes(modulus: &Integer) -> usize {
+    (modulus.significant_bits() as usize + 63) / 64 * 8
+}
+
+fn put_u32(buf: &mut Vec<u8>, x: u32) {
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, x: u64) {
+    buf.extend_from_slice(&x.to_le_bytes());
+}
+
+fn put_int(buf: &mut Vec<u8>, i: &Integer, n8: usize) {
+    let mut digits = i.to_digits::<u8>(Order::Lsf);
+    digits.resize(n8, 0);
+    buf.extend(digits);
+}
+
+fn put_file<W: Write>(
+    out: &mut W,
+    magic: &[u8; 4],
+    version: u32,
+    sections: &[&[u8]],
+) -> io::Result<()> {
+    let mut buf = magic.to_vec();
+    put_u32(&mut buf, version);
+    put_u32(&mut buf, sections.len() as u32);
+    for (i, s) in sections.iter().enumerate() {
+        put_u32(&mut buf, i as u32 + 1);
+        put_u64(&mut buf, s.len() as u64);
+        buf.extend_from_slice(s);
+    }
+    out.write_all(&buf)
+}
+
+/// Our signal indices in wire order (starting from wire 1), and the number of public signals.
+fn wire_order<S: Hash + Eq>(r1cs: &R1cs<S>) -> (Vec<usize>, usize) {
+    let mut order: Vec<usize> = r1cs.public_idxs.iter().cloned().collect();
+    order.sort_unstable();
+    let n_public = order.len();
+    let mut private: Vec<usize> = r1cs
+        .idxs_signals
+        .keys()
+        .filter(|i| !r1cs.public_idxs.contains(i))
+        .cloned()
+        .collect();
+    private.sort_unstable();
+    order.extend(private);
+    (order, n_public)
+}
+
+fn put_lc(buf: &mut Vec<u8>, lc: &Lc, wires: &HashMap<usize, u32>, n8: usize) {
+    let mut terms: Vec<(u32, Integer)> = Vec::new();
+    let constant = lc.constant.clone().rem_floor(&*lc.modulus);
+    if constant != 0 {
+        terms.push((0, constant));
+    }
+    for (i, c) in &lc.monomials {
+        let c = c.clone().rem_floor(&*lc.modulus);
+        if c != 0 {
+            terms.push((wires[i], c));
+        }
+    }
+    terms.sort_by_key(|(w, _)| *w);
+    put_u32(buf, terms.len() as u32);
+    for (w, c) in terms {
+        put_u32(buf, w);
+        put_int(buf, &c, n8);
+    }
+}
+
+/// Write `r1cs` in the `.r1cs` format.
+pub fn write_r1cs<S: Hash + Eq, W: Write>(r1cs: &R1cs<S>, out: &mut W) -> io::Result<()> {
+    let (order, n_public) = wire_order(r1cs);
+    let wires: HashMap<usize, u32> = order
+        .iter()
+        .enumerate()
+        .map(|(w, i)| (*i, w as u32 + 1))
+        .collect();
+    let n_wires = order.len() + 1;
+    let n8 = field_bytes(&r1cs.modulus);
+
+    let mut header = Vec::new();
+    put_u32(&mut header, n8 as u32);
+    put_int(&mut header, &r1cs.modulus, n8);
+    put_u32(&mut header, n_wires as u32);
+    // public outputs, public inputs, private inputs
+    put_u32(&mut header, 0);
+    put_u32(&mut header, n_public as u32);
+    put_u32(&mut header, 0);
+    put_u64(&mut header, n_wires as u64);
+    put_u32(&mut header, r1cs.constraints.len() as u32);
+
+    let mut constraints = Vec::new();
+    for (a, b, c) in &r1cs.constraints {
+        for lc in &[a, b, c] {
+            put_lc(&mut constraints, lc, &wires, n8);
+        }
+    }
+
+    let mut labels = Vec::new();
+    for w in 0..n_wires {
+        put_u64(&mut labels, w as u64);
+    }
+    put_file(
+        out,
+        R1CS_MAGIC,
+        R1CS_VERSION,
+        &[&header[..], &constraints[..], &labels[..]],
+    )
+}
+
+/// Write the values of `r1cs`'s signals in the `.wtns` format.
+pub fn write_wtns<S: Hash + Eq, W: Write>(r1cs: &R1cs<S>, out: &mut W) -> Result<(), Iden3Error> {
+    let values = r1cs.values.as_ref().ok_or(Iden3Error::MissingValues)?;
+    let (order, _) = wire_order(r1cs);
+    let n8 = field_bytes(&r1cs.modulus);
+
+    let mut header = Vec::new();
+    put_u32(&mut header, n8 as u32);
+    put_int(&mut header, &r1cs.modulus, n8);
+    put_u32(&mut header, order.len() as u32 + 1);
+
+    let mut witness = Vec::new();
+    put_int(&mut witness, &Integer::from(1), n8);
+    for i in &order {
+        let v = values.get(i).ok_or(Iden3Error::MissingValues)?;
+        put_int(&mut witness, &v.clone().rem_floor(&*r1cs.modulus), n8);
+    }
+    Ok(put_file(
+        out,
+        WTNS_MAGIC,
+        WTNS_VERSION,
+        &[&header[..], &witness[..]],
+    )?)
+}
+
+/// Write a `.sym` file for `r1cs`, naming each wire by its signal.
+///
+/// Lines are `LABEL,WIRE,COMPONENT,NAME`; we have no components, so that is always 0.
+pub fn write_sym<S: Hash + Eq + Display, W: Write>(r1cs: &R1cs<S>, out: &mut W) -> io::Result<()> {
+    let (order, _) = wire_order(r1cs);
+    for (w, i) in order.iter().enumerate() {
+        writeln!(out, "{},{},0,{}", w + 1, w + 1, r1cs.idxs_signals[i])?;
+    }
+    Ok(())
+}
+
+/// A reader over the bytes of a section.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Iden3Error> {
+        if self.0.len() < n {
+            return Err(Iden3Error::Malformed("unexpected end of section".into()));
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+    fn u32(&mut self) -> Result<u32, Iden3Error> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(b))
+    }
+    fn u64(&mut self) -> Result<u64, Iden3Error> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(b))
+    }
+    fn int(&mut self, n8: usize) -> Result<Integer, Iden3Error> {
+        Ok(Integer::from_digits(self.take(n8)?, Order::Lsf))
+    }
+}
+
+/// Read a file with the given `magic`, returning its version and its sections, by type. If a type
+/// is repeated, the first such section is kept.
+fn read_file<R: Read>(
+    r: &mut R,
+    magic: &[u8; 4],
+) -> Result<(u32, HashMap<u32, Vec<u8>>), Iden3Error> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    let mut bytes = Bytes(&bytes);
+    if bytes.take(4).ok() != Some(&magic[..]) {
+        return Err(Iden3Error::BadMagic(
+            String::from_utf8_lossy(magic).into_owned(),
+        ));
+    }
+    let version = bytes.u32()?;
+    let n_sections = bytes.u32()?;
+    let mut sections = HashMap::new();
+    for _ in 0..n_sections {
+        let ty = bytes.u32()?;
+        let size = bytes.u64()? as usize;
+        let content = bytes.take(size)?;
+        sections.entry(ty).or_insert_with(|| content.to_vec());
+    }
+    Ok((version, sections))
+}
+
+fn section(sections: &HashMap<u32, Vec<u8>>, ty: u32) -> Result<Bytes, Iden3Error> {
+    sections
+        .get(&ty)
+        .map(|s| Bytes(&s[..]))
+        .ok_or(Iden3Error::MissingSection(ty))
+}
+
+/// Read the field size and prime at the start of a header.
+fn read_field(header: &mut Bytes) -> Result<(usize, Integer), Iden3Error> {
+    let n8 = header.u32()? as usize;
+    let prime = header.int(n8)?;
+    Ok((n8, prime))
+}
+
+/// Read a `.r1cs` file. Wires are named by `names` (e.g., from [read_sym]), or else `wWIRE`.
+pub fn read_r1cs<R: Read>(
+    r: &mut R,
+    names: &HashMap<usize, String>,
+) -> Result<R1cs<String>, Iden3Error> {
+    let (version, sections) = read_file(r, R1CS_MAGIC)?;
+    if version != R1CS_VERSION {
+        return Err(Iden3Error::BadVersion("r1cs".into(), version));
+    }
+    let mut header = section(&sections, 1)?;
+    let (n8, prime) = read_field(&mut header)?;
+    let n_wires = header.u32()? as usize;
+    let n_public = header.u32()? as usize + header.u32()? as usize;
+    let _n_private_inputs = header.u32()?;
+    let _n_labels = header.u64()?;
+    let n_constraints = header.u32()? as usize;
+    if n_wires == 0 || n_public >= n_wires {
+        return Err(Iden3Error::Malformed(format!(
+            "{} public wires, of {}",
+            n_public, n_wires
+        )));
+    }
+
+    let mut r1cs = R1cs::new(prime, false);
+    for w in 1..n_wires {
+        let name = names.get(&w).cloned().unwrap_or_else(|| format!("w{}", w));
+        r1cs.add_signal(name, None);
+        if w <= n_public {
+            r1cs.public_idxs.insert(w - 1);
+        }
+    }
+    let mut constraints = section(&sections, 2)?;
+    for _ in 0..n_constraints {
+        let mut lcs = Vec::new();
+        for _ in 0..3 {
+            let mut lc = r1cs.zero();
+            for _ in 0..constraints.u32()? {
+                let w = constraints.u32()? as usize;
+                let c = constraints.int(n8)?;
+                if w == 0 {
+                    lc += &c;
+                } else if w < n_wires {
+                    lc.monomials.insert(w - 1, c);
+                } else {
+                    return Err(Iden3Error::Malformed(format!("no wire {}", w)));
+                }
+            }
+            lcs.push(lc);
+        }
+        let c = lcs.pop().unwrap();
+        let b = lcs.pop().unwrap();
+        let a = lcs.pop().unwrap();
+        r1cs.constraint(a, b, c);
+    }
+    Ok(r1cs)
+}
+
+/// Read a `.wtns` file for `r1cs` (as produced by [read_r1cs]), returning the values of its
+/// signals. Set them with [R1cs::set_values].
+pub fn read_wtns<S: Clone + Hash + Eq + Display, R: Read>(
+    r: &mut R,
+    r1cs: &R1cs<S>,
+) -> Result<HashMap<usize, Integer>, Iden3Error> {
+    let (version, sections) = read_file(r, WTNS_MAGIC)?;
+    if version != WTNS_VERSION {
+        return Err(Iden3Error::BadVersion("wtns".into(), version));
+    }
+    let mut header = section(&sections, 1)?;
+    let (n8, prime) = read_field(&mut header)?;
+    let n_witness = header.u32()? as usize;
+    if &prime != r1cs.modulus() {
+        return Err(Iden3Error::Malformed(format!(
+            "witness is mod {}, but the constraint system is mod {}",
+            prime,
+            r1cs.modulus()
+        )));
+    }
+    if n_witness != r1cs.next_idx + 1 {
+        return Err(Iden3Error::Malformed(format!(
+            "{} witness values for {} wires",
+            n_witness,
+            r1cs.next_idx + 1
+        )));
+    }
+    let mut witness = section(&sections, 2)?;
+    if witness.int(n8)? != 1 {
+        return Err(Iden3Error::Malformed("wire 0 is not 1".into()));
+    }
+    let mut values = HashMap::new();
+    for i in 0..n_witness - 1 {
+        values.insert(i, witness.int(n8)?);
+    }
+    Ok(values)
+}
+
+/// Read a `.sym` file, mapping wires to signal names. Labels that were optimized away (wire -1)
+/// are skipped; if several labels share a wire, the first names it.
+pub fn read_sym<R: BufRead>(r: R) -> Result<HashMap<usize, String>, Iden3Error> {
+    let mut names = HashMap::new();
+    for line in r.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let parts: Vec<&str> = line.splitn(4, ',').collect();
+        if parts.len() != 4 {
+            return Err(Iden3Error::Malformed(format!("bad symbol line '{}'", line)));
+        }
+        let wire: i64 = parts[1]
+            .trim()
+            .parse()
+            .map_err(|_| Iden3Error::Malformed(format!("bad wire in '{}'", line)))?;
+        if wire >= 0 {
+            names
+                .entry(wire as usize)
+                .or_insert_with(|| parts[3].trim().to_owned());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::*;
+
+    /// `x * y = z` and `z + 1 = out`, with `out` public.
+    fn small() -> R1cs<String> {
+        let mut r1cs = R1cs::new(Integer::from(101), true);
+        for (s, v) in vec![("x", 3), ("y", 5), ("z", 15), ("out", 16)] {
+            r1cs.add_signal(s.to_owned(), Some(Integer::from(v)));
+        }
+        r1cs.publicize(&"out".to_owned());
+        let lc = |s: &str| r1cs.signal_lc(&s.to_owned());
+        let (x, y, z, out) = (lc("x"), lc("y"), lc("z"), lc("out"));
+        r1cs.constraint(x, y, z.clone());
+        r1cs.constraint(r1cs.zero(), r1cs.zero(), z + 1 - &out);
+        r1cs
+    }
+
+    fn round_trip(r1cs: &R1cs<String>) -> R1cs<String> {
+        let mut r1cs_bytes = Vec::new();
+        write_r1cs(r1cs, &mut r1cs_bytes).unwrap();
+        let mut sym_bytes = Vec::new();
+        write_sym(r1cs, &mut sym_bytes).unwrap();
+        let mut wtns_bytes = Vec::new();
+        write_wtns(r1cs, &mut wtns_bytes).unwrap();
+        let names = read_sym(&sym_bytes[..]).unwrap();
+        let mut read = read_r1cs(&mut &r1cs_bytes[..], &names).unwrap();
+        let values = read_wtns(&mut &wtns_bytes[..], &read).unwrap();
+        read.set_values(values);
+        read
+    }
+
+    #[test]
+    fn header() {
+        let mut bytes = Vec::new();
+        write_r1cs(&small(), &mut bytes).unwrap();
+        assert_eq!(&bytes[..4], b"r1cs");
+        assert_eq!(bytes[4..8], 1u32.to_le_bytes());
+        assert_eq!(bytes[8..12], 3u32.to_le_bytes());
+        // header section: type, size, and an 8-byte field
+        assert_eq!(bytes[12..16], 1u32.to_le_bytes());
+        assert_eq!(bytes[24..28], 8u32.to_le_bytes());
+        assert_eq!(bytes[28], 101);
+    }
+
+    #[test]
+    fn sym() {
+        let mut bytes = Vec::new();
+        write_sym(&small(), &mut bytes).unwrap();
+        let text = String::from_utf8(bytes).unwrap();
+        assert_eq!(text, "1,1,0,out\n2,2,0,x\n3,3,0,y\n4,4,0,z\n");
+    }
+
+    #[test]
+    fn round_trip_small() {
+        let read = round_trip(&small());
+        read.check_all().unwrap();
+        assert_eq!(read.constraints().len(), 2);
+        assert_eq!(read.public_idxs.len(), 1);
+        assert!(read.public_idxs.contains(&read.signal_idxs["out"]));
+        assert_eq!(read.values.as_ref().unwrap()[&read.signal_idxs["z"]], 15);
+    }
+
+    #[test]
+    fn round_trip_lowered() {
+        let cs = Computation::from_constraint_system_parts(
+            vec![term![Op::Eq;
+                term![Op::BvBinOp(BvBinOp::Udiv);
+                    leaf_term(Op::Var("a".to_owned(), Sort::BitVector(4))),
+                    leaf_term(Op::Const(Value::BitVector(BitVector::new(Integer::from(3), 4))))
+                ],
+                leaf_term(Op::Const(Value::BitVector(BitVector::new(Integer::from(4), 4))))
+            ]],
+            vec!["a".to_owned()].into_iter().collect(),
+            Some(
+                vec![(
+                    "a".to_owned(),
+                    Value::BitVector(BitVector::new(Integer::from(13), 4)),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let r1cs = trans::to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        let read = round_trip(&r1cs);
+        read.check_all().unwrap();
+        assert_eq!(read.constraints().len(), r1cs.constraints().len());
+        assert_eq!(read.public_idxs.len(), r1cs.public_idxs.len());
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut bytes = Vec::new();
+        write_r1cs(&small(), &mut bytes).unwrap();
+        assert!(matches!(
+            read_wtns(&mut &bytes[..], &small()),
+            Err(Iden3Error::BadMagic(_))
+        ));
+    }
+
+    #[test]
+    fn missing_values() {
+        let mut r1cs = small();
+        r1cs.values = None;
+        assert!(matches!(
+            write_wtns(&r1cs, &mut Vec::new()),
+            Err(Iden3Error::MissingValues)
+        ));
+    }
+}
@@ -0,0 +1,524 @@
+//! JSON serializations of R1CS
+//!
+//! There are two layouts. Both number variables as ZoKrates and zkInterface do: variable 0 is the
+//! constant one, and signal `i` is variable `i + 1`.
+//!
+//! Our own layout is meant to be read by people, so field elements are decimal strings. Here it is
+//! without the line breaks that [to_json] adds:
+//!
+//! ```text
+//! {
+//!   "modulus": "101",
+//!   "variables": ["~one", "x", "y", "z"],
+//!   "public": [3],
+//!   "constraints": [{"A": [[1, "1"]], "B": [[2, "1"]], "C": [[3, "1"]]}],
+//!   "witness": ["1", "3", "5", "15"]
+//! }
+//! ```
+//!
+//! Each linear combination is a list of `[VARIABLE, COEFFICIENT]` pairs. The witness is optional.
+//!
+//! The zkInterface layout follows the JSON rendering of its `CircuitHeader`, `ConstraintSystem`
+//! and `Witness` messages: field elements are little-endian byte arrays, concatenated within each
+//! list of values. It carries no signal names; when read, variable `v` is named `vV`.
+
+use super::iden3::field_bytes;
+use super::*;
+
+use rug::integer::Order;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// An error in reading a JSON R1CS
+pub enum JsonError {
+    #[error("Invalid JSON: {0}")]
+    /// Not JSON, or not in the expected layout
+    Json(#[from] serde_json::Error),
+    #[error("Invalid R1CS: {0}")]
+    /// JSON in the expected layout, which does not describe an R1CS
+    Format(String),
+}
+
+/// The name of variable 0, in ZoKrates.
+const ONE: &str = "~one";
+
+/// An integer, written as a decimal string.
+struct Decimal(Integer);
+
+impl Serialize for Decimal {
+    fn serialize<Ser: Serializer>(&self, s: Ser) -> Result<Ser::Ok, Ser::Error> {
+        s.collect_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(d)?;
+        Integer::from_str_radix(&s, 10)
+            .map(Decimal)
+            .map_err(|_| D::Error::custom(format!("expected an integer, got '{}'", s)))
+    }
+}
+
+/// A linear combination in our layout: `[VARIABLE, COEFFICIENT]` pairs.
+type JsonLc = Vec<(usize, Decimal)>;
+
+#[derive(Serialize, Deserialize)]
+struct JsonConstraint {
+    #[serde(rename = "A")]
+    a: JsonLc,
+    #[serde(rename = "B")]
+    b: JsonLc,
+    #[serde(rename = "C")]
+    c: JsonLc,
+}
+
+/// Our layout.
+#[derive(Serialize, Deserialize)]
+struct JsonR1cs {
+    modulus: Decimal,
+    variables: Vec<String>,
+    public: Vec<usize>,
+    constraints: Vec<JsonConstraint>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    witness: Option<Vec<Decimal>>,
+}
+
+/// zkInterface `Variables`: ids, and their concatenated little-endian values (if any).
+#[derive(Serialize, Deserialize)]
+struct ZkifVariables {
+    variable_ids: Vec<usize>,
+    #[serde(default)]
+    values: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZkifHeader {
+    instance_variables: ZkifVariables,
+    free_variable_id: usize,
+    field_maximum: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZkifConstraint {
+    linear_combination_a: ZkifVariables,
+    linear_combination_b: ZkifVariables,
+    linear_combination_c: ZkifVariables,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZkifConstraintSystem {
+    constraints: Vec<ZkifConstraint>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ZkifWitness {
+    assigned_variables: ZkifVariables,
+}
+
+/// The zkInterface layout.
+#[derive(Serialize, Deserialize)]
+struct Zkif {
+    circuit_header: ZkifHeader,
+    constraint_system: ZkifConstraintSystem,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    witness: Option<ZkifWitness>,
+}
+
+/// The combination's terms, as (variable, coefficient) pairs, in variable order. The constant is
+/// variable 0. Zero coefficients are dropped.
+fn lc_terms(lc: &Lc) -> Vec<(usize, Integer)> {
+    let mut terms: Vec<(usize, Integer)> = lc
+        .monomials
+        .iter()
+        .map(|(i, c)| (i + 1, c.clone().rem_floor(&*lc.modulus)))
+        .collect();
+    terms.push((0, lc.constant.clone().rem_floor(&*lc.modulus)));
+    terms.retain(|(_, c)| *c != 0);
+    terms.sort_by_key(|(v, _)| *v);
+    terms
+}
+
+/// The value of each variable, if `r1cs` has values.
+fn witness<S: Hash + Eq>(r1cs: &R1cs<S>) -> Option<Vec<Integer>> {
+    r1cs.values.as_ref().map(|values| {
+        std::iter::once(Integer::from(1))
+            .chain((0..r1cs.next_idx).map(|i| values[&i].clone().rem_floor(&*r1cs.modulus)))
+            .collect()
+    })
+}
+
+fn public_vars<S: Hash + Eq>(r1cs: &R1cs<S>) -> Vec<usize> {
+    let mut public: Vec<usize> = r1cs.public_idxs.iter().map(|i| i + 1).collect();
+    public.sort_unstable();
+    public
+}
+
+fn render<T: Serialize>(t: &T) -> String {
+    let mut out = serde_json::to_string_pretty(t).unwrap();
+    out.push('\n');
+    out
+}
+
+/// Serialize `r1cs` (and its witness, if it has values) in our JSON layout.
+pub fn to_json<S: Hash + Eq + Display>(r1cs: &R1cs<S>) -> String {
+    let lc = |lc: &Lc| {
+        lc_terms(lc)
+            .into_iter()
+            .map(|(v, c)| (v, Decimal(c)))
+            .collect()
+    };
+    render(&JsonR1cs {
+        modulus: Decimal((*r1cs.modulus).clone()),
+        variables: std::iter::once(ONE.to_owned())
+            .chain((0..r1cs.next_idx).map(|i| r1cs.idxs_signals[&i].to_string()))
+            .collect(),
+        public: public_vars(r1cs),
+        constraints: r1cs
+            .constraints
+            .iter()
+            .map(|(a, b, c)| JsonConstraint {
+                a: lc(a),
+                b: lc(b),
+                c: lc(c),
+            })
+            .collect(),
+        witness: witness(r1cs).map(|w| w.into_iter().map(Decimal).collect()),
+    })
+}
+
+/// Build an R1CS with the given variable names (excluding variable 0), public variables,
+/// constraints (as lists of variable-coefficient pairs), and witness (including variable 0).
+fn build(
+    modulus: Integer,
+    names: Vec<String>,
+    public: Vec<usize>,
+    constraints: Vec<[Vec<(usize, Integer)>; 3]>,
+    witness: Option<Vec<Integer>>,
+) -> Result<R1cs<String>, JsonError> {
+    let n_vars = names.len() + 1;
+    let check_var = |v: usize| {
+        if v < n_vars {
+            Ok(v)
+        } else {
+            Err(JsonError::Format(format!("no variable {}", v)))
+        }
+    };
+    let mut r1cs = R1cs::new(modulus, false);
+    for name in names {
+        r1cs.add_signal(name, None);
+    }
+    for v in public {
+        if check_var(v)? == 0 {
+            return Err(JsonError::Format("variable 0 cannot be public".into()));
+        }
+        r1cs.public_idxs.insert(v - 1);
+    }
+    for abc in constraints {
+        let mut lcs = Vec::new();
+        for terms in abc.iter() {
+            let mut lc = r1cs.zero();
+            for (v, c) in terms {
+                match check_var(*v)? {
+                    0 => lc += c,
+                    v => {
+                        *lc.monomials
+                            .entry(v - 1)
+                            .or_insert_with(|| Integer::from(0)) += c;
+                    }
+                }
+            }
+            lcs.push(lc);
+        }
+        let c = lcs.pop().unwrap();
+        let b = lcs.pop().unwrap();
+        let a = lcs.pop().unwrap();
+        r1cs.constraint(a, b, c);
+    }
+    if let Some(w) = witness {
+        if w.len() != n_vars {
+            return Err(JsonError::Format(format!(
+                "{} witness values for {} variables",
+                w.len(),
+                n_vars
+            )));
+        }
+        if w[0] != 1 {
+            return Err(JsonError::Format("variable 0 is not 1".into()));
+        }
+        r1cs.set_values(w.into_iter().skip(1).enumerate().collect());
+    }
+    Ok(r1cs)
+}
+
+/// Read an R1CS (and its witness, if present) in our JSON layout.
+pub fn from_json(s: &str) -> Result<R1cs<String>, JsonError> {
+    let j: JsonR1cs = serde_json::from_str(s)?;
+    if j.variables.first().map(|v| v.as_str()) != Some(ONE) {
+        return Err(JsonError::Format(format!("variable 0 must be '{}'", ONE)));
+    }
+    let lc = |lc: JsonLc| lc.into_iter().map(|(v, c)| (v, c.0)).collect();
+    build(
+        j.modulus.0,
+        j.variables.into_iter().skip(1).collect(),
+        j.public,
+        j.constraints
+            .into_iter()
+            .map(|c| [lc(c.a), lc(c.b), lc(c.c)])
+            .collect(),
+        j.witness.map(|w| w.into_iter().map(|x| x.0).collect()),
+    )
+}
+
+/// zkInterface `Variables` for `ids`, with `values` (if any), each `n8` bytes long.
+fn zkif_vars(ids: &[usize], values: Option<&[Integer]>, n8: usize) -> ZkifVariables {
+    let mut bytes = Vec::new();
+    for v in values.unwrap_or(&[]) {
+        let mut digits = v.to_digits::<u8>(Order::Lsf);
+        digits.resize(n8, 0);
+        bytes.extend(digits);
+    }
+    ZkifVariables {
+        variable_ids: ids.to_vec(),
+        values: bytes,
+    }
+}
+
+/// Read zkInterface `Variables`, as (variable, value) pairs. If there are no values, they are
+/// zero.
+fn zkif_read_vars(vars: ZkifVariables) -> Result<Vec<(usize, Integer)>, JsonError> {
+    let ZkifVariables {
+        variable_ids: ids,
+        values: bytes,
+    } = vars;
+    if bytes.is_empty() {
+        return Ok(ids.into_iter().map(|v| (v, Integer::from(0))).collect());
+    }
+    if ids.is_empty() || bytes.len() % ids.len() != 0 {
+        return Err(JsonError::Format(format!(
+            "{} bytes of values for {} variables",
+            bytes.len(),
+            ids.len()
+        )));
+    }
+    let n8 = bytes.len() / ids.len();
+    Ok(ids
+        .into_iter()
+        .zip(bytes.chunks(n8))
+        .map(|(v, b)| (v, Integer::from_digits(b, Order::Lsf)))
+        .collect())
+}
+
+/// Serialize `r1cs` (and its witness, if it has values) in the zkInterface layout.
+pub fn to_zkif_json<S: Hash + Eq>(r1cs: &R1cs<S>) -> String {
+    let n8 = field_bytes(&r1cs.modulus);
+    let witness = witness(r1cs);
+    let public = public_vars(r1cs);
+    let values_of = |vs: &[usize]| {
+        witness
+            .as_ref()
+            .map(|w| vs.iter().map(|v| w[*v].clone()).collect::<Vec<_>>())
+    };
+    let lc = |lc: &Lc| {
+        let (ids, values): (Vec<usize>, Vec<Integer>) = lc_terms(lc).into_iter().unzip();
+        zkif_vars(&ids, Some(&values[..]), n8)
+    };
+    let field_max = Integer::from(&*r1cs.modulus - 1);
+    let private: Vec<usize> = (1..=r1cs.next_idx)
+        .filter(|v| !r1cs.public_idxs.contains(&(v - 1)))
+        .collect();
+    render(&Zkif {
+        circuit_header: ZkifHeader {
+            instance_variables: zkif_vars(&public, values_of(&public).as_deref(), n8),
+            free_variable_id: r1cs.next_idx + 1,
+            field_maximum: field_max.to_digits::<u8>(Order::Lsf),
+        },
+        constraint_system: ZkifConstraintSystem {
+            constraints: r1cs
+                .constraints
+                .iter()
+                .map(|(a, b, c)| ZkifConstraint {
+                    linear_combination_a: lc(a),
+                    linear_combination_b: lc(b),
+                    linear_combination_c: lc(c),
+                })
+                .collect(),
+        },
+        witness: values_of(&private).map(|values| ZkifWitness {
+            assigned_variables: zkif_vars(&private, Some(&values[..]), n8),
+        }),
+    })
+}
+
+/// Read an R1CS (and its witness, if present) in the zkInterface layout.
+///
+/// The witness is only read if there is a `witness` message, and the instance variables have
+/// values.
+pub fn from_zkif_json(s: &str) -> Result<R1cs<String>, JsonError> {
+    let j: Zkif = serde_json::from_str(s)?;
+    let header = j.circuit_header;
+    let modulus = Integer::from_digits(&header.field_maximum, Order::Lsf) + 1;
+    let n_vars = header.free_variable_id;
+    if n_vars == 0 {
+        return Err(JsonError::Format(
+            "free_variable_id must be positive".into(),
+        ));
+    }
+    let has_instance_values = !header.instance_variables.values.is_empty()
+        || header.instance_variables.variable_ids.is_empty();
+    let instance = zkif_read_vars(header.instance_variables)?;
+    let constraints = j
+        .constraint_system
+        .constraints
+        .into_iter()
+        .map(|c| -> Result<_, JsonError> {
+            Ok([
+                zkif_read_vars(c.linear_combination_a)?,
+                zkif_read_vars(c.linear_combination_b)?,
+                zkif_read_vars(c.linear_combination_c)?,
+            ])
+        })
+        .collect::<Result<Vec<_>, JsonError>>()?;
+    let witness = match j.witness {
+        Some(w) if has_instance_values => {
+            let mut values = vec![None; n_vars];
+            values[0] = Some(Integer::from(1));
+            for (v, x) in instance
+                .iter()
+                .cloned()
+                .chain(zkif_read_vars(w.assigned_variables)?)
+            {
+                match values.get_mut(v) {
+                    Some(slot) => *slot = Some(x),
+                    None => return Err(JsonError::Format(format!("no variable {}", v))),
+                }
+            }
+            Some(
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(v, x)| {
+                        x.ok_or_else(|| JsonError::Format(format!("no value for variable {}", v)))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            )
+        }
+        _ => None,
+    };
+    build(
+        modulus,
+        (1..n_vars).map(|v| format!("v{}", v)).collect(),
+        instance.into_iter().map(|(v, _)| v).collect(),
+        constraints,
+        witness,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::target::r1cs::opt::reduce_linearities;
+    use serde_json::{json, Value};
+
+    /// `x * y = z` and `z + 1 = out`, with `out` public.
+    fn small(values: bool) -> R1cs<String> {
+        let mut r1cs = R1cs::new(Integer::from(101), values);
+        for (s, v) in vec![("x", 3), ("y", 5), ("z", 15), ("out", 16)] {
+            r1cs.add_signal(s.to_owned(), Some(Integer::from(v)).filter(|_| values));
+        }
+        r1cs.publicize(&"out".to_owned());
+        let lc = |s: &str| r1cs.signal_lc(&s.to_owned());
+        let (x, y, z, out) = (lc("x"), lc("y"), lc("z"), lc("out"));
+        r1cs.constraint(x, y, z.clone());
+        r1cs.constraint(r1cs.zero(), r1cs.zero(), z + 1 - &out);
+        r1cs
+    }
+
+    #[test]
+    fn layout() {
+        let j: Value = serde_json::from_str(&to_json(&small(true))).unwrap();
+        assert_eq!(
+            j,
+            json!({
+                "modulus": "101",
+                "variables": ["~one", "x", "y", "z", "out"],
+                "public": [4],
+                "constraints": [
+                    {"A": [[1, "1"]], "B": [[2, "1"]], "C": [[3, "1"]]},
+                    {"A": [], "B": [], "C": [[0, "1"], [3, "1"], [4, "100"]]}
+                ],
+                "witness": ["1", "3", "5", "15", "16"]
+            })
+        );
+        let j: Value = serde_json::from_str(&to_json(&small(false))).unwrap();
+        assert!(j.get("witness").is_none());
+    }
+
+    #[test]
+    fn round_trip() {
+        let r1cs = small(true);
+        let read = from_json(&to_json(&r1cs)).unwrap();
+        assert_eq!(to_json(&read), to_json(&r1cs));
+        read.check_all().unwrap();
+        let reduced = reduce_linearities(read);
+        reduced.check_all().unwrap();
+        assert_eq!(reduced.constraints().len(), 1);
+
+        let read = from_json(&to_json(&small(false))).unwrap();
+        assert!(read.values.is_none());
+        assert_eq!(read.public_idxs.len(), 1);
+    }
+
+    #[test]
+    fn zkif_round_trip() {
+        let r1cs = small(true);
+        let text = to_zkif_json(&r1cs);
+        let j: Value = serde_json::from_str(&text).unwrap();
+        assert_eq!(j["circuit_header"]["field_maximum"], json!([100]));
+        assert_eq!(j["circuit_header"]["free_variable_id"], json!(5));
+        assert_eq!(
+            j["circuit_header"]["instance_variables"],
+            json!({"variable_ids": [4], "values": [16]})
+        );
+        let read = from_zkif_json(&text).unwrap();
+        read.check_all().unwrap();
+        assert_eq!(read.constraints().len(), 2);
+        assert!(read.public_idxs.contains(&3));
+        assert_eq!(read.values.as_ref().unwrap()[&2], 15);
+        assert_eq!(to_zkif_json(&read), text);
+
+        let read = from_zkif_json(&to_zkif_json(&small(false))).unwrap();
+        assert!(read.values.is_none());
+    }
+
+    /// `small(values)` in our layout, edited by `f`.
+    fn edited(values: bool, f: impl FnOnce(&mut Value)) -> String {
+        let mut j: Value = serde_json::from_str(&to_json(&small(values))).unwrap();
+        f(&mut j);
+        j.to_string()
+    }
+
+    #[test]
+    #[should_panic]
+    fn bad_witness() {
+        let text = edited(true, |j| j["witness"][4] = json!("17"));
+        from_json(&text).unwrap().check_all().unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(from_json("[1, 2"), Err(JsonError::Json(_))));
+        assert!(matches!(
+            from_json(r#"{"modulus": "7"}"#),
+            Err(JsonError::Json(_))
+        ));
+        let text = edited(false, |j| j["modulus"] = json!("0x65"));
+        assert!(matches!(from_json(&text), Err(JsonError::Json(_))));
+        let text = edited(false, |j| j["variables"][0] = json!("one"));
+        assert!(matches!(from_json(&text), Err(JsonError::Format(_))));
+        let text = edited(false, |j| j["public"] = json!([9]));
+        assert!(matches!(from_json(&text), Err(JsonError::Format(_))));
+    }
+}
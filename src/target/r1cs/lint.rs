@@ -0,0 +1,560 @@
+//! Finding under-constrained signals
+//!
+//! A private signal is under-constrained if two witnesses which agree on the inputs (and the
+//! public signals) can disagree on it. That is usually a soundness bug: a prover may choose its
+//! value.
+//!
+//! [underconstrained] propagates "determined" through the constraints, with rules that only ever
+//! conclude that a signal is determined when it is. Signals it cannot reach are suspects. Some may
+//! be false alarms, so on small systems, [confirm] checks each with an SMT solver.
+
+use super::*;
+use crate::ir::term::{bv_lit, leaf_term, term, Op, Sort, Term, AND, BV_ADD, BV_MUL, BV_UREM};
+use crate::ir::term::{BV_ULT, EQ, NOT};
+use crate::target::smt::{check_sat_timeout, SmtResult};
+use crate::util::once::OnceQueue;
+
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+/// A signal which the constraints might not determine
+pub struct Suspect<S> {
+    /// The signal's index
+    pub idx: usize,
+    /// The signal
+    pub signal: S,
+    /// Whether an SMT solver found two witnesses which agree on the inputs, but not on this signal
+    pub confirmed: bool,
+}
+
+struct Propagator<'a, S: Hash + Eq> {
+    r1cs: &'a R1cs<S>,
+    known: Vec<bool>,
+    /// Signals constrained to be zero or one
+    bits: Vec<bool>,
+    /// Signals constrained to be non-zero
+    nonzero: Vec<bool>,
+    /// For each signal, the constraints which mention it
+    occs: Vec<Vec<usize>>,
+    queue: OnceQueue<usize>,
+}
+
+impl<'a, S: Hash + Eq + Clone + Display> Propagator<'a, S> {
+    fn new(r1cs: &'a R1cs<S>, inputs: &[usize]) -> Self {
+        let n = r1cs.next_idx;
+        let mut known = vec![false; n];
+        for i in inputs.iter().chain(&r1cs.public_idxs) {
+            known[*i] = true;
+        }
+        let mut bits = vec![false; n];
+        let mut nonzero = vec![false; n];
+        let mut occs = vec![Vec::new(); n];
+        for (i, (a, b, c)) in r1cs.constraints.iter().enumerate() {
+            let mut vars: Vec<usize> = a
+                .monomials
+                .keys()
+                .chain(b.monomials.keys())
+                .chain(c.monomials.keys())
+                .cloned()
+                .collect();
+            vars.sort_unstable();
+            vars.dedup();
+            for x in vars {
+                occs[x].push(i);
+            }
+            if let Some(x) = as_bit(a, b, c, &r1cs.modulus) {
+                bits[x] = true;
+            }
+            // x * B = k, for non-zero k
+            if c.as_const().map_or(false, |k| *k != 0) {
+                for lc in &[a, b] {
+                    if let Some(x) = as_scaled_signal(lc) {
+                        nonzero[x] = true;
+                    }
+                }
+            }
+        }
+        let queue = (0..r1cs.constraints.len()).collect();
+        Self {
+            r1cs,
+            known,
+            bits,
+            nonzero,
+            occs,
+            queue,
+        }
+    }
+
+    fn unknowns(&self, lc: &Lc) -> Vec<usize> {
+        let mut vars: Vec<usize> = lc
+            .monomials
+            .keys()
+            .filter(|x| !self.known[**x])
+            .cloned()
+            .collect();
+        vars.sort_unstable();
+        vars
+    }
+
+    fn learn(&mut self, x: usize) {
+        if !self.known[x] {
+            self.known[x] = true;
+            for i in &self.occs[x] {
+                self.queue.push(*i);
+            }
+        }
+    }
+
+    /// Is `lc` determined, and non-zero?
+    fn known_nonzero(&self, lc: &Lc) -> bool {
+        match (lc.as_const(), as_scaled_signal(lc)) {
+            (Some(k), _) => *k != 0,
+            (_, Some(x)) => self.known[x] && self.nonzero[x],
+            _ => false,
+        }
+    }
+
+    /// If `xs` are bits, with coefficients in `lc` whose subset sums are distinct, then the value
+    /// of `lc` determines them.
+    fn is_bit_decomposition(&self, lc: &Lc, xs: &[usize]) -> bool {
+        let m = &*self.r1cs.modulus;
+        let half: Integer = m.clone() / 2;
+        if !xs.iter().all(|x| self.bits[*x]) {
+            return false;
+        }
+        let mut coeffs: Vec<Integer> = xs.iter().map(|x| lc.monomials[x].clone()).collect();
+        if coeffs.iter().all(|c| *c > half) {
+            for c in &mut coeffs {
+                *c = m.clone() - &*c;
+            }
+        } else if !coeffs.iter().all(|c| *c <= half) {
+            return false;
+        }
+        coeffs.sort();
+        let mut sum = Integer::from(0);
+        for c in coeffs {
+            if c <= sum {
+                return false;
+            }
+            sum += c;
+        }
+        sum < *m
+    }
+
+    /// Given that `lc` is determined, learn its undetermined signals `xs`, if they follow.
+    fn learn_from(&mut self, lc: &Lc, xs: &[usize]) {
+        if xs.len() == 1 || (xs.len() > 1 && self.is_bit_decomposition(lc, xs)) {
+            for x in xs {
+                self.learn(*x);
+            }
+        }
+    }
+
+    /// If `z_con` is `Z * X = 0` and `m_con` is `M * X' = C`, where X is determined, X' is a
+    /// multiple of X, and some `z` is the only undetermined signal of Z and of C, then `z` is
+    /// determined: by Z = 0 if X is non-zero, and by C = 0 if it is zero.
+    fn is_zero_rule(&mut self, z_con: usize, m_con: usize) {
+        let r1cs = self.r1cs;
+        let (za, zb, zc) = &r1cs.constraints[z_con];
+        let (ma, mb, mc) = &r1cs.constraints[m_con];
+        if !zc.is_zero() {
+            return;
+        }
+        let uc = self.unknowns(mc);
+        for (z, x) in &[(za, zb), (zb, za)] {
+            if self.unknowns(x).is_empty()
+                && x.as_const().is_none()
+                && self.unknowns(z) == uc
+                && uc.len() == 1
+                && (proportional(ma, x, &r1cs.modulus) || proportional(mb, x, &r1cs.modulus))
+            {
+                self.learn(uc[0]);
+                return;
+            }
+        }
+    }
+
+    fn visit(&mut self, i: usize) {
+        let r1cs = self.r1cs;
+        let (a, b, c) = &r1cs.constraints[i];
+        let (ua, ub, uc) = (self.unknowns(a), self.unknowns(b), self.unknowns(c));
+        if ua.is_empty() && ub.is_empty() {
+            // A * B is determined, and so is C.
+            self.learn_from(c, &uc);
+        } else if uc.is_empty() {
+            // Divide C by a determined, non-zero factor.
+            if ua.is_empty() && self.known_nonzero(a) {
+                self.learn_from(b, &ub);
+            } else if ub.is_empty() && self.known_nonzero(b) {
+                self.learn_from(a, &ua);
+            }
+        }
+        for u in &[ua, ub, uc] {
+            if u.len() == 1 {
+                for j in self.occs[u[0]].clone() {
+                    if j != i {
+                        self.is_zero_rule(i, j);
+                        self.is_zero_rule(j, i);
+                    }
+                }
+            }
+        }
+    }
+
+    fn run(&mut self) {
+        while let Some(i) = self.queue.pop() {
+            self.visit(i);
+        }
+    }
+
+    /// Is undetermined `x` irrelevant? That is, does only one constraint, `X * B = C`, mention it,
+    /// with B and C determined, and X determined but for `x`? Then `x` is determined where it
+    /// matters: if B is non-zero, X is C / B, and if B is zero, nothing depends on `x`. The
+    /// inverse in an is-zero gadget is such a signal.
+    fn irrelevant(&self, x: usize) -> bool {
+        if self.occs[x].len() != 1 {
+            return false;
+        }
+        let (a, b, c) = &self.r1cs.constraints[self.occs[x][0]];
+        let only_x = |lc: &Lc| self.unknowns(lc) == [x];
+        self.unknowns(c).is_empty()
+            && ((only_x(a) && self.unknowns(b).is_empty())
+                || (only_x(b) && self.unknowns(a).is_empty()))
+    }
+}
+
+/// If `a * b = c` forces some signal to be a bit, get it.
+fn as_bit(a: &Lc, b: &Lc, c: &Lc, m: &Integer) -> Option<usize> {
+    if !c.is_zero() {
+        return None;
+    }
+    // The root of k * x + j
+    let root = |lc: &Lc| {
+        if lc.monomials.len() != 1 {
+            return None;
+        }
+        let (x, k) = lc.monomials.iter().next().unwrap();
+        let r = (-lc.constant.clone() * k.clone().invert(m).ok()?).rem_floor(m);
+        Some((*x, r))
+    };
+    match (root(a), root(b)) {
+        (Some((x, r)), Some((y, s))) if x == y && r.clone() * &s == 0 && r.clone() + &s == 1 => {
+            Some(x)
+        }
+        _ => None,
+    }
+}
+
+/// If `lc` is `k * x`, get `x`.
+fn as_scaled_signal(lc: &Lc) -> Option<usize> {
+    if lc.monomials.len() == 1 && lc.constant == 0 {
+        lc.monomials.keys().next().cloned()
+    } else {
+        None
+    }
+}
+
+/// Is `a` a (non-zero) multiple of `b`?
+fn proportional(a: &Lc, b: &Lc, m: &Integer) -> bool {
+    if a.monomials.len() != b.monomials.len() {
+        return false;
+    }
+    let ratio = match b.monomials.iter().next() {
+        Some((x, k)) => match (a.monomials.get(x), k.clone().invert(m)) {
+            (Some(j), Ok(k_inv)) => (j.clone() * k_inv).rem_floor(m),
+            _ => return false,
+        },
+        None => return false,
+    };
+    let scaled = |k: &Integer| (k.clone() * &ratio).rem_floor(m);
+    scaled(&b.constant) == a.constant
+        && b.monomials
+            .iter()
+            .all(|(x, k)| a.monomials.get(x) == Some(&scaled(k)))
+}
+
+/// Find the private signals of `r1cs` which its constraints might not determine, given the
+/// signals `inputs` and the public signals.
+///
+/// Starting from those signals, a constraint `A * B = C` determines its undetermined signals by
+/// these rules:
+///
+///    * If A and B are determined, so is C. So is its one undetermined signal, if it has one.
+///    * If C is determined, and A is determined and non-zero, so is B; and vice versa. A is
+///      non-zero if it is a non-zero constant, or a determined signal `x` with some `x * B = k`
+///      constraint for a non-zero constant `k`.
+///    * When a combination is determined, so are its undetermined signals, if they are bits (by
+///      `x * (x - 1) = 0` constraints) whose coefficients have distinct subset sums.
+///    * In an is-zero gadget, `Z * X = 0` and `M * X' = C`, where X is determined and X' is a
+///      multiple of X, the only undetermined signal of both Z and C is determined.
+///
+/// Signals which matter to only one constraint (like the inverse in an is-zero gadget) are not
+/// reported.
+pub fn underconstrained<S: Hash + Eq + Clone + Display>(
+    r1cs: &R1cs<S>,
+    inputs: &[usize],
+) -> Vec<Suspect<S>> {
+    let mut p = Propagator::new(r1cs, inputs);
+    p.run();
+    (0..r1cs.next_idx)
+        .filter(|x| !p.known[*x] && !p.irrelevant(*x))
+        .map(|idx| Suspect {
+            idx,
+            signal: r1cs.idxs_signals[&idx].clone(),
+            confirmed: false,
+        })
+        .collect()
+}
+
+/// Bit-vector terms for the field arithmetic of a system.
+struct Encoding {
+    width: usize,
+    modulus: Term,
+}
+
+impl Encoding {
+    fn new<S: Hash + Eq>(r1cs: &R1cs<S>) -> Self {
+        let n_terms = r1cs
+            .constraints
+            .iter()
+            .flat_map(|(a, b, c)| vec![a, b, c])
+            .map(|lc| lc.monomials.len() + 1)
+            .max()
+            .unwrap_or(1);
+        let width = 2 * r1cs.modulus.significant_bits() as usize
+            + Integer::from(n_terms).significant_bits() as usize;
+        Self {
+            width,
+            modulus: bv_lit(r1cs.modulus.as_ref().clone(), width),
+        }
+    }
+
+    fn var(&self, name: String) -> Term {
+        leaf_term(Op::Var(name, Sort::BitVector(self.width)))
+    }
+
+    fn in_field(&self, x: &Term) -> Term {
+        term![BV_ULT; x.clone(), self.modulus.clone()]
+    }
+
+    fn lc(&self, lc: &Lc, vars: &[Term]) -> Term {
+        let mut terms = vec![bv_lit(lc.constant.clone(), self.width)];
+        for (x, k) in &lc.monomials {
+            terms.push(term![BV_MUL; bv_lit(k.clone(), self.width), vars[*x].clone()]);
+        }
+        let sum = if terms.len() == 1 {
+            terms.pop().unwrap()
+        } else {
+            term(BV_ADD, terms)
+        };
+        term![BV_UREM; sum, self.modulus.clone()]
+    }
+
+    fn constraint(&self, (a, b, c): &(Lc, Lc, Lc), vars: &[Term]) -> Term {
+        let ab = term![BV_MUL; self.lc(a, vars), self.lc(b, vars)];
+        term![EQ; term![BV_UREM; ab, self.modulus.clone()], self.lc(c, vars)]
+    }
+}
+
+/// Check each suspect with an SMT solver, giving up after `timeout`: do two witnesses which agree
+/// on `inputs` (and the public signals) disagree on it? Drops the suspects which are determined
+/// after all, and marks those which are not as confirmed.
+///
+/// Field elements are encoded as bit-vectors more than twice as wide as the modulus, so this is
+/// only practical for small systems, over small fields.
+pub fn confirm<S: Hash + Eq + Clone + Display>(
+    r1cs: &R1cs<S>,
+    inputs: &[usize],
+    suspects: Vec<Suspect<S>>,
+    timeout: Duration,
+) -> Vec<Suspect<S>> {
+    let enc = Encoding::new(r1cs);
+    let n = r1cs.next_idx;
+    let mut fixed = vec![false; n];
+    for i in inputs.iter().chain(&r1cs.public_idxs) {
+        fixed[*i] = true;
+    }
+    let left: Vec<Term> = (0..n).map(|i| enc.var(format!("l{}", i))).collect();
+    let right: Vec<Term> = (0..n)
+        .map(|i| {
+            if fixed[i] {
+                left[i].clone()
+            } else {
+                enc.var(format!("r{}", i))
+            }
+        })
+        .collect();
+    let mut witnesses: Vec<Term> = left.iter().map(|x| enc.in_field(x)).collect();
+    witnesses.extend(
+        (0..n)
+            .filter(|i| !fixed[*i])
+            .map(|i| enc.in_field(&right[i])),
+    );
+    for con in &r1cs.constraints {
+        witnesses.push(enc.constraint(con, &left));
+        witnesses.push(enc.constraint(con, &right));
+    }
+    suspects
+        .into_iter()
+        .filter_map(|mut s| {
+            let mut query = witnesses.clone();
+            query.push(term![NOT; term![EQ; left[s.idx].clone(), right[s.idx].clone()]]);
+            match check_sat_timeout(&term(AND, query), timeout) {
+                SmtResult::Unsat => None,
+                SmtResult::Sat(_) => {
+                    s.confirmed = true;
+                    Some(s)
+                }
+                SmtResult::Unknown => Some(s),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::{Computation, FieldElem, Value, PF_MUL};
+    use crate::target::r1cs::trans::to_r1cs_with_program;
+    use std::sync::Arc;
+
+    fn system(public: &[&str], private: &[&str]) -> (R1cs<String>, Vec<Lc>) {
+        let mut r1cs = R1cs::new(Integer::from(17), false);
+        let mut lcs = Vec::new();
+        for (i, n) in public.iter().chain(private).enumerate() {
+            r1cs.add_signal(n.to_string(), None);
+            if i < public.len() {
+                r1cs.publicize(&n.to_string());
+            }
+            lcs.push(r1cs.signal_lc(&n.to_string()));
+        }
+        (r1cs, lcs)
+    }
+
+    fn names(suspects: &[Suspect<String>]) -> Vec<&str> {
+        suspects.iter().map(|s| s.signal.as_str()).collect()
+    }
+
+    #[test]
+    fn bit_decomposition() {
+        let (mut r1cs, v) = system(&["x"], &["b0", "b1", "b2"]);
+        for b in &v[1..] {
+            r1cs.constraint(b.clone(), b.clone() - 1, r1cs.zero());
+        }
+        let sum = v[1].clone() + &(v[2].clone() * 2) + &(v[3].clone() * 4);
+        r1cs.constraint(r1cs.zero() + 1, sum, v[0].clone());
+        assert!(underconstrained(&r1cs, &[]).is_empty());
+        // 1, 2, 3: 1 + 2 = 3
+        let (mut r1cs, v) = system(&["x"], &["b0", "b1", "b2"]);
+        for b in &v[1..] {
+            r1cs.constraint(b.clone(), b.clone() - 1, r1cs.zero());
+        }
+        let sum = v[1].clone() + &(v[2].clone() * 2) + &(v[3].clone() * 3);
+        r1cs.constraint(r1cs.zero() + 1, sum, v[0].clone());
+        assert_eq!(names(&underconstrained(&r1cs, &[])), vec!["b0", "b1", "b2"]);
+    }
+
+    #[test]
+    fn products() {
+        // y = x * x; x * z = 1; w * x = y
+        let (mut r1cs, v) = system(&["x"], &["y", "z", "w"]);
+        let (x, y, z, w) = (v[0].clone(), v[1].clone(), v[2].clone(), v[3].clone());
+        r1cs.constraint(x.clone(), x.clone(), y.clone());
+        r1cs.constraint(x.clone(), z, r1cs.zero() + 1);
+        r1cs.constraint(w.clone(), x, y);
+        assert!(underconstrained(&r1cs, &[]).is_empty());
+        // y * y = x: two square roots
+        let (mut r1cs, v) = system(&["x"], &["y"]);
+        r1cs.constraint(v[1].clone(), v[1].clone(), v[0].clone());
+        assert_eq!(names(&underconstrained(&r1cs, &[])), vec!["y"]);
+    }
+
+    #[test]
+    fn is_zero_gadget() {
+        // m * x = 1 - z; z * x = 0
+        let (mut r1cs, v) = system(&["x"], &["m", "z"]);
+        let (x, m, z) = (v[0].clone(), v[1].clone(), v[2].clone());
+        r1cs.constraint(m.clone(), x.clone(), -z.clone() + 1);
+        r1cs.constraint(z.clone(), x.clone() * 3, r1cs.zero());
+        assert!(underconstrained(&r1cs, &[]).is_empty());
+        // Something depends on m, which is free when x is zero.
+        r1cs.add_signal("y".to_owned(), None);
+        let y = r1cs.signal_lc(&"y".to_owned());
+        r1cs.constraint(r1cs.zero() + 1, m + &z, y);
+        assert_eq!(names(&underconstrained(&r1cs, &[])), vec!["m", "y"]);
+    }
+
+    #[test]
+    fn inputs() {
+        let (mut r1cs, v) = system(&[], &["i", "y"]);
+        r1cs.constraint(v[0].clone(), v[0].clone(), v[1].clone());
+        assert_eq!(names(&underconstrained(&r1cs, &[])), vec!["i", "y"]);
+        assert!(underconstrained(&r1cs, &[0]).is_empty());
+    }
+
+    #[test]
+    fn lowering() {
+        let var = |n: &str| {
+            leaf_term(Op::Var(
+                n.to_owned(),
+                Sort::Field(Arc::new(Integer::from(17))),
+            ))
+        };
+        let value = |i: u32| {
+            Value::Field(FieldElem::new(
+                Integer::from(i),
+                Arc::new(Integer::from(17)),
+            ))
+        };
+        let cs = Computation::from_constraint_system_parts(
+            vec![term![EQ; term![PF_MUL; var("x"), var("y")], var("z")]],
+            vec!["z".to_owned()].into_iter().collect(),
+            Some(
+                vec![
+                    ("x".to_owned(), value(2)),
+                    ("y".to_owned(), value(3)),
+                    ("z".to_owned(), value(6)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let declared = cs.metadata.inputs.clone();
+        let (r1cs, program) = to_r1cs_with_program(cs, Integer::from(17));
+        let inputs = program.inputs_where(|n| declared.contains_key(n));
+        assert!(underconstrained(&r1cs, &inputs).is_empty());
+    }
+
+    #[test]
+    fn undeclared_variable() {
+        let field = Sort::Field(Arc::new(Integer::from(17)));
+        let var = |n: &str| leaf_term(Op::Var(n.to_owned(), field.clone()));
+        // z = x * t * t, where t is not an input of the computation
+        let mut cs = Computation::from_constraint_system_parts(
+            vec![term![EQ; term![PF_MUL; var("x"), term![PF_MUL; var("t"), var("t")]], var("z")]],
+            vec!["z".to_owned()].into_iter().collect(),
+            None,
+        );
+        cs.metadata.remove_input("t");
+        let declared = cs.metadata.inputs.clone();
+        let (r1cs, program) = to_r1cs_with_program(cs, Integer::from(17));
+        // Lowered variables are not all inputs
+        assert!(underconstrained(&r1cs, &program.inputs()).is_empty());
+        let inputs = program.inputs_where(|n| declared.contains_key(n));
+        let suspects = underconstrained(&r1cs, &inputs);
+        assert!(names(&suspects).iter().any(|n| n.starts_with("t_")));
+    }
+
+    #[test]
+    fn smt_confirms() {
+        // y * y = x has two solutions for x = 4, and y * y = 0 has one.
+        let (mut r1cs, v) = system(&["x"], &["y", "w"]);
+        r1cs.constraint(v[1].clone(), v[1].clone(), v[0].clone());
+        r1cs.constraint(v[2].clone(), v[2].clone(), r1cs.zero());
+        let suspects = underconstrained(&r1cs, &[]);
+        assert_eq!(names(&suspects), vec!["y", "w"]);
+        let confirmed = confirm(&r1cs, &[], suspects, Duration::from_secs(10));
+        assert_eq!(names(&confirmed), vec!["y"]);
+        assert!(confirmed[0].confirmed);
+    }
+}
@@ -0,0 +1,1033 @@
+//! Optimizations over R1CS
+use super::*;
+use crate::util::once::OnceQueue;
+use log::debug;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+/// A linear combination, as a constant and `(signal, coefficient)` pairs sorted by signal.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct SparseLc {
+    constant: Integer,
+    terms: Vec<(usize, Integer)>,
+}
+
+impl SparseLc {
+    fn from_lc(lc: &Lc) -> Self {
+        let mut terms: Vec<(usize, Integer)> =
+            lc.monomials.iter().map(|(i, c)| (*i, c.clone())).collect();
+        terms.sort_unstable_by_key(|(i, _)| *i);
+        Self {
+            constant: lc.constant.clone(),
+            terms,
+        }
+    }
+
+    fn into_lc(self, modulus: &Rc<Integer>) -> Lc {
+        Lc {
+            modulus: modulus.clone(),
+            constant: self.constant,
+            monomials: self.terms.into_iter().collect(),
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.terms.is_empty() && self.constant == 0
+    }
+
+    fn as_const(&self) -> Option<&Integer> {
+        self.terms.is_empty().then(|| &self.constant)
+    }
+
+    fn vars(&self) -> impl Iterator<Item = usize> + '_ {
+        self.terms.iter().map(|(i, _)| *i)
+    }
+
+    fn mentions(&self, var: usize) -> bool {
+        self.terms.binary_search_by_key(&var, |(i, _)| *i).is_ok()
+    }
+
+    /// The inverse of the first coefficient, if there is one.
+    fn lead_inv(&self, m: &Integer) -> Option<Integer> {
+        self.terms
+            .first()
+            .map(|(_, c)| c.clone().invert(m).unwrap())
+    }
+
+    /// `self *= s`
+    fn scale(&mut self, s: &Integer, m: &Integer) {
+        self.constant *= s;
+        self.constant.rem_floor_assign(m);
+        if *s == 0 {
+            self.terms.clear();
+        } else {
+            for (_, c) in &mut self.terms {
+                *c *= s;
+                c.rem_floor_assign(m);
+            }
+        }
+    }
+
+    /// `self += s * other`. Pushes the signals which `self` did not mention before onto `added`.
+    fn add_scaled(&mut self, other: &SparseLc, s: &Integer, m: &Integer, added: &mut Vec<usize>) {
+        self.constant += Integer::from(&other.constant * s);
+        self.constant.rem_floor_assign(m);
+        if *s == 0 || other.terms.is_empty() {
+            return;
+        }
+        if other.terms.len() * 4 < self.terms.len() {
+            // Few insertions: update in place.
+            for (i, c) in &other.terms {
+                let mut sum = Integer::from(c * s);
+                match self.terms.binary_search_by_key(i, |(j, _)| *j) {
+                    Ok(pos) => {
+                        sum += &self.terms[pos].1;
+                        sum.rem_floor_assign(m);
+                        if sum == 0 {
+                            self.terms.remove(pos);
+                        } else {
+                            self.terms[pos].1 = sum;
+                        }
+                    }
+                    Err(pos) => {
+                        sum.rem_floor_assign(m);
+                        added.push(*i);
+                        self.terms.insert(pos, (*i, sum));
+                    }
+                }
+            }
+            return;
+        }
+        let mut old = std::mem::take(&mut self.terms).into_iter().peekable();
+        let mut terms = Vec::with_capacity(old.len() + other.terms.len());
+        for (i, c) in &other.terms {
+            while let Some(t) = old.next_if(|(j, _)| j < i) {
+                terms.push(t);
+            }
+            let mut sum = Integer::from(c * s);
+            if let Some((_, d)) = old.next_if(|(j, _)| j == i) {
+                sum += d;
+                sum.rem_floor_assign(m);
+                if sum != 0 {
+                    terms.push((*i, sum));
+                }
+            } else {
+                sum.rem_floor_assign(m);
+                added.push(*i);
+                terms.push((*i, sum));
+            }
+        }
+        terms.extend(old);
+        self.terms = terms;
+    }
+
+    /// Replace `var` with `val`, returning whether `var` was present.
+    fn substitute(
+        &mut self,
+        var: usize,
+        val: &SparseLc,
+        m: &Integer,
+        added: &mut Vec<usize>,
+    ) -> bool {
+        match self.terms.binary_search_by_key(&var, |(i, _)| *i) {
+            Ok(pos) => {
+                let (_, sc) = self.terms.remove(pos);
+                self.add_scaled(val, &sc, m, added);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+type Constraint = (SparseLc, SparseLc, SparseLc);
+
+/// Substitutes out signals defined by linear constraints.
+///
+/// Substitutions are applied lazily: the C of a non-linear constraint is only rewritten when the
+/// constraint becomes linear, or at the end. Substitutions are themselves rewritten (in terms of
+/// live signals) on demand, and the result is kept, as in path compression.
+struct LinReducer<S: Eq + Hash> {
+    r1cs: R1cs<S>,
+    modulus: Rc<Integer>,
+    /// Normalized constraints. A linear constraint has zero A and B, and a C which mentions no
+    /// eliminated signal.
+    constraints: Vec<Constraint>,
+    /// For each signal, the constraints which mention it in A or B, or in C, if linear.
+    /// May have duplicates and stale entries.
+    occs: Vec<Vec<usize>>,
+    public: Vec<bool>,
+    /// For each eliminated signal, a linear combination equal to it.
+    subs: Vec<Option<SparseLc>>,
+    /// For each eliminated signal, the number of eliminations when its substitution was last
+    /// rewritten.
+    fresh: Vec<usize>,
+    n_elims: usize,
+    queue: OnceQueue<usize>,
+}
+
+impl<S: Eq + Hash + Display + Clone> LinReducer<S> {
+    fn new(mut r1cs: R1cs<S>) -> Self {
+        let modulus = r1cs.modulus.clone();
+        let n_signals = r1cs.next_idx;
+        let constraints: Vec<Constraint> = std::mem::take(&mut r1cs.constraints)
+            .iter()
+            .map(|(a, b, c)| {
+                let mut con = (
+                    SparseLc::from_lc(a),
+                    SparseLc::from_lc(b),
+                    SparseLc::from_lc(c),
+                );
+                normalize(&mut con, &modulus);
+                con
+            })
+            .collect();
+        let mut occs = vec![Vec::new(); n_signals];
+        for (i, (a, b, c)) in constraints.iter().enumerate() {
+            let c_vars = a.is_zero().then(|| c.vars()).into_iter().flatten();
+            for x in a.vars().chain(b.vars()).chain(c_vars) {
+                occs[x].push(i);
+            }
+        }
+        let mut public = vec![false; n_signals];
+        for i in &r1cs.public_idxs {
+            public[*i] = true;
+        }
+        let queue = (0..constraints.len()).collect::<OnceQueue<usize>>();
+        Self {
+            r1cs,
+            modulus,
+            constraints,
+            occs,
+            public,
+            subs: vec![None; n_signals],
+            fresh: vec![0; n_signals],
+            n_elims: 0,
+            queue,
+        }
+    }
+
+    /// Rewrite the substitution for `var` so that it mentions no eliminated signals.
+    fn refresh(&mut self, var: usize) {
+        let mut stack = vec![var];
+        while let Some(&x) = stack.last() {
+            if self.fresh[x] == self.n_elims {
+                stack.pop();
+                continue;
+            }
+            let stale: Vec<usize> = self.subs[x]
+                .as_ref()
+                .unwrap()
+                .vars()
+                .filter(|y| self.subs[*y].is_some() && self.fresh[*y] != self.n_elims)
+                .collect();
+            if stale.is_empty() {
+                let mut sub = self.subs[x].take().unwrap();
+                substitute_all(&mut sub, &self.subs, &self.modulus);
+                self.subs[x] = Some(sub);
+                self.fresh[x] = self.n_elims;
+                stack.pop();
+            } else {
+                stack.extend(stale);
+            }
+        }
+    }
+
+    /// Rewrite `lc` so that it mentions no eliminated signals.
+    fn resolve(&mut self, lc: &mut SparseLc) {
+        let eliminated: Vec<usize> = lc.vars().filter(|x| self.subs[*x].is_some()).collect();
+        for x in eliminated {
+            self.refresh(x);
+        }
+        substitute_all(lc, &self.subs, &self.modulus);
+    }
+
+    /// Eliminate `var`, which is equal to `val`, and which constraint `con_id` defined.
+    fn eliminate(&mut self, var: usize, val: SparseLc, con_id: usize) {
+        self.n_elims += 1;
+        self.subs[var] = Some(val.clone());
+        self.fresh[var] = self.n_elims;
+        let mut uses = std::mem::take(&mut self.occs[var]);
+        uses.sort_unstable();
+        uses.dedup();
+        let mut added = Vec::new();
+        for use_id in uses {
+            if use_id == con_id {
+                continue;
+            }
+            let (a, b, c) = &mut self.constraints[use_id];
+            let linear = a.is_zero();
+            let in_a = a.substitute(var, &val, &self.modulus, &mut added);
+            let in_b = b.substitute(var, &val, &self.modulus, &mut added);
+            let in_c = linear && c.substitute(var, &val, &self.modulus, &mut added);
+            for x in added.drain(..) {
+                self.occs[x].push(use_id);
+            }
+            if !(in_a || in_b || in_c) {
+                continue;
+            }
+            if !linear && (a.as_const().is_some() || b.as_const().is_some()) {
+                let mut c = std::mem::take(&mut self.constraints[use_id].2);
+                self.resolve(&mut c);
+                self.constraints[use_id].2 = c;
+                normalize(&mut self.constraints[use_id], &self.modulus);
+                for x in self.constraints[use_id].2.vars() {
+                    self.occs[x].push(use_id);
+                }
+                self.queue.push(use_id);
+            } else if linear {
+                self.queue.push(use_id);
+            }
+        }
+    }
+
+    fn run(mut self) -> R1cs<S> {
+        while let Some(con_id) = self.queue.pop() {
+            if let Some((var, lc)) =
+                as_linear_sub(&self.constraints[con_id], &self.public, &self.modulus)
+            {
+                debug!(
+                    "Elim: {} -> {}",
+                    self.r1cs.idxs_signals.get(&var).unwrap(),
+                    self.r1cs.format_lc(&lc.clone().into_lc(&self.modulus))
+                );
+                self.constraints[con_id] = Constraint::default();
+                self.eliminate(var, lc, con_id);
+            }
+        }
+        let mut constraints = std::mem::take(&mut self.constraints);
+        for (a, _, c) in &mut constraints {
+            if !a.is_zero() {
+                self.resolve(c);
+            }
+        }
+        let origins = std::mem::take(&mut self.r1cs.origins);
+        let (constraints, origins) = prune(constraints, origins, &self.modulus, false);
+        self.r1cs.constraints = into_lcs(constraints, &self.modulus);
+        self.r1cs.origins = origins;
+        self.r1cs
+    }
+}
+
+/// Drop the constraints (and their origins) which are trivially true, or (if `dedup`) scalar
+/// multiples of an earlier constraint.
+fn prune(
+    constraints: Vec<Constraint>,
+    origins: Vec<Option<Term>>,
+    m: &Integer,
+    dedup: bool,
+) -> (Vec<Constraint>, Vec<Option<Term>>) {
+    let mut seen = HashSet::new();
+    constraints
+        .into_iter()
+        .zip(origins)
+        .filter(|(c, _)| !constantly_true(c, m) && (!dedup || seen.insert(canonical(c, m))))
+        .unzip()
+}
+
+fn into_lcs(constraints: Vec<Constraint>, modulus: &Rc<Integer>) -> Vec<(Lc, Lc, Lc)> {
+    constraints
+        .into_iter()
+        .map(|(a, b, c)| (a.into_lc(modulus), b.into_lc(modulus), c.into_lc(modulus)))
+        .collect()
+}
+
+/// Substitute for each eliminated signal in `lc`. Their substitutions must mention no eliminated
+/// signals.
+fn substitute_all(lc: &mut SparseLc, subs: &[Option<SparseLc>], m: &Integer) {
+    let eliminated: Vec<usize> = lc.vars().filter(|x| subs[*x].is_some()).collect();
+    let mut added = Vec::new();
+    for x in eliminated {
+        lc.substitute(x, subs[x].as_ref().unwrap(), m, &mut added);
+    }
+}
+
+/// If this constraint is linear, and defines a private signal, get that signal (the one with the
+/// highest index, which is usually the newest) and its value.
+fn as_linear_sub(
+    (a, b, c): &Constraint,
+    public: &[bool],
+    m: &Integer,
+) -> Option<(usize, SparseLc)> {
+    if a.is_zero() || b.is_zero() {
+        let pos = c.terms.iter().rposition(|(i, _)| !public[*i])?;
+        let mut lc = c.clone();
+        let (i, v) = lc.terms.remove(pos);
+        lc.scale(&(-v.invert(m).unwrap()), m);
+        Some((i, lc))
+    } else {
+        None
+    }
+}
+
+/// If A or B is constant, move their product into C, leaving A and B zero.
+fn normalize((a, b, c): &mut Constraint, m: &Integer) {
+    let mut added = Vec::new();
+    match (a.as_const(), b.as_const()) {
+        (Some(ac), _) => {
+            let s = -ac.clone();
+            c.add_scaled(&std::mem::take(b), &s, m, &mut added);
+            *a = SparseLc::default();
+        }
+        (_, Some(bc)) => {
+            let s = -bc.clone();
+            c.add_scaled(&std::mem::take(a), &s, m, &mut added);
+            *b = SparseLc::default();
+        }
+        _ => {}
+    }
+}
+
+fn constantly_true((a, b, c): &Constraint, m: &Integer) -> bool {
+    match (a.as_const(), b.as_const(), c.as_const()) {
+        (Some(x), Some(y), Some(z)) => (x.clone() * y - z).rem_floor(m) == 0,
+        _ => false,
+    }
+}
+
+/// Attempt to shrink this system by reducing linearities.
+///
+/// Repeatedly finds a linear constraint which mentions a private signal, and substitutes that
+/// signal (the one with the highest index) out of the whole system. Constraints are visited in
+/// order, and then in the order that substitutions make them linear.
+pub fn reduce_linearities<S: Eq + Hash + Clone + Display>(r1cs: R1cs<S>) -> R1cs<S> {
+    LinReducer::new(r1cs).run()
+}
+
+/// Sparse Gaussian elimination over the linear constraints of a system.
+///
+/// Pivots are chosen greedily by their Markowitz cost: eliminating signal `x` with linear
+/// constraint `r` adds at most `(|r| - 1) * (uses(x) - 1)` entries to the system.
+struct GaussReducer<S: Eq + Hash> {
+    r1cs: R1cs<S>,
+    modulus: Rc<Integer>,
+    /// Normalized constraints. A linear constraint has zero A and B.
+    constraints: Vec<Constraint>,
+    /// For each signal, the constraints which mention it. May have duplicates and stale entries.
+    occs: Vec<Vec<usize>>,
+    public: Vec<bool>,
+    max_fill: usize,
+    /// Candidate pivots, as `(cost, constraint, signal)`, cheapest first. Costs may be stale.
+    pivots: BinaryHeap<Reverse<(usize, usize, usize)>>,
+}
+
+impl<S: Eq + Hash + Display + Clone> GaussReducer<S> {
+    fn new(mut r1cs: R1cs<S>, max_fill: usize) -> Self {
+        let modulus = r1cs.modulus.clone();
+        let n_signals = r1cs.next_idx;
+        let constraints: Vec<Constraint> = std::mem::take(&mut r1cs.constraints)
+            .iter()
+            .map(|(a, b, c)| {
+                let mut con = (
+                    SparseLc::from_lc(a),
+                    SparseLc::from_lc(b),
+                    SparseLc::from_lc(c),
+                );
+                normalize(&mut con, &modulus);
+                con
+            })
+            .collect();
+        let mut occs = vec![Vec::new(); n_signals];
+        for (i, (a, b, c)) in constraints.iter().enumerate() {
+            for x in a.vars().chain(b.vars()).chain(c.vars()) {
+                occs[x].push(i);
+            }
+        }
+        let mut public = vec![false; n_signals];
+        for i in &r1cs.public_idxs {
+            public[*i] = true;
+        }
+        let mut this = Self {
+            r1cs,
+            modulus,
+            constraints,
+            occs,
+            public,
+            max_fill,
+            pivots: BinaryHeap::new(),
+        };
+        for con_id in 0..this.constraints.len() {
+            this.push_pivots(con_id);
+        }
+        this
+    }
+
+    /// The number of constraints which mention `var`. Cleans up its occurrence list.
+    fn uses(&mut self, var: usize) -> usize {
+        let mut occs = std::mem::take(&mut self.occs[var]);
+        occs.sort_unstable();
+        occs.dedup();
+        occs.retain(|i| {
+            let (a, b, c) = &self.constraints[*i];
+            a.mentions(var) || b.mentions(var) || c.mentions(var)
+        });
+        let n = occs.len();
+        self.occs[var] = occs;
+        n
+    }
+
+    /// The cost of eliminating `var` with linear constraint `con_id`, which mentions it.
+    fn cost(&mut self, con_id: usize, var: usize) -> usize {
+        let len = self.constraints[con_id].2.terms.len();
+        (len - 1).saturating_mul(self.uses(var) - 1)
+    }
+
+    /// If constraint `con_id` is linear, make its private signals candidate pivots.
+    fn push_pivots(&mut self, con_id: usize) {
+        let (a, _, c) = &self.constraints[con_id];
+        if !a.is_zero() {
+            return;
+        }
+        let vars: Vec<usize> = c.vars().filter(|x| !self.public[*x]).collect();
+        for var in vars {
+            let cost = self.cost(con_id, var);
+            if cost <= self.max_fill {
+                self.pivots.push(Reverse((cost, con_id, var)));
+            }
+        }
+    }
+
+    /// Eliminate `var` using linear constraint `con_id`, substituting for it everywhere.
+    fn eliminate(&mut self, con_id: usize, var: usize) {
+        let modulus = self.modulus.clone();
+        let m = &*modulus;
+        let mut val = std::mem::take(&mut self.constraints[con_id]).2;
+        let pos = val.terms.binary_search_by_key(&var, |(i, _)| *i).unwrap();
+        let (_, v) = val.terms.remove(pos);
+        val.scale(&(-v.invert(m).unwrap()), m);
+        debug!(
+            "Elim: {} -> {}",
+            self.r1cs.idxs_signals.get(&var).unwrap(),
+            self.r1cs.format_lc(&val.clone().into_lc(&self.modulus))
+        );
+        let uses = std::mem::take(&mut self.occs[var]);
+        let mut added = Vec::new();
+        for use_id in uses {
+            if use_id == con_id {
+                continue;
+            }
+            let con = &mut self.constraints[use_id];
+            con.0.substitute(var, &val, m, &mut added);
+            con.1.substitute(var, &val, m, &mut added);
+            con.2.substitute(var, &val, m, &mut added);
+            normalize(con, m);
+            for x in added.drain(..) {
+                self.occs[x].push(use_id);
+            }
+            self.push_pivots(use_id);
+        }
+    }
+
+    fn run(mut self) -> R1cs<S> {
+        while let Some(Reverse((cost, con_id, var))) = self.pivots.pop() {
+            let (a, _, c) = &self.constraints[con_id];
+            if !a.is_zero() || !c.mentions(var) {
+                continue;
+            }
+            let actual = self.cost(con_id, var);
+            if actual > self.max_fill {
+                continue;
+            } else if actual > cost {
+                self.pivots.push(Reverse((actual, con_id, var)));
+            } else {
+                self.eliminate(con_id, var);
+            }
+        }
+        let constraints = std::mem::take(&mut self.constraints);
+        let origins = std::mem::take(&mut self.r1cs.origins);
+        let (constraints, origins) = prune(constraints, origins, &self.modulus, true);
+        self.r1cs.constraints = into_lcs(constraints, &self.modulus);
+        self.r1cs.origins = origins;
+        self.r1cs
+    }
+}
+
+/// Scale a normalized constraint so that it is equal to its scalar multiples: A, B, and (if
+/// linear) C lead with coefficient one. Also order A and B.
+fn canonical((a, b, c): &Constraint, m: &Integer) -> Constraint {
+    let mut con = (a.clone(), b.clone(), c.clone());
+    match (a.lead_inv(m), b.lead_inv(m), c.lead_inv(m)) {
+        (Some(ai), Some(bi), _) => {
+            con.0.scale(&ai, m);
+            con.1.scale(&bi, m);
+            con.2.scale(&(ai * &bi), m);
+            if con.1 < con.0 {
+                std::mem::swap(&mut con.0, &mut con.1);
+            }
+        }
+        (None, None, Some(ci)) => con.2.scale(&ci, m),
+        _ => {}
+    }
+    con
+}
+
+/// Attempt to shrink this system by Gaussian elimination over its linear constraints.
+///
+/// Each private signal of a linear constraint is a candidate pivot. The cheapest pivot (by
+/// Markowitz cost, an upper bound on the entries the elimination adds) is eliminated from the
+/// whole system, linear and quadratic constraints alike, until no pivot costs at most `max_fill`.
+/// Public signals are never eliminated. Finally, constraints which are trivially true, or scalar
+/// multiples of an earlier constraint, are dropped.
+///
+/// With a `max_fill` of `usize::MAX`, no linear constraint which mentions a private signal
+/// remains, as with [reduce_linearities].
+pub fn gauss_reduce<S: Eq + Hash + Clone + Display>(r1cs: R1cs<S>, max_fill: usize) -> R1cs<S> {
+    GaussReducer::new(r1cs, max_fill).run()
+}
+
+/// Clean up this system, after lowering and reducing linearities.
+///
+/// Normalizes the constraints, and drops those which are trivially true or scalar multiples of an
+/// earlier one. Then drops the private signals which no constraint mentions (with their values),
+/// and numbers the remaining signals densely, in their old order.
+///
+/// Returns the system and, for each old signal, its new index, if it was kept. Use the latter to
+/// [renumber](super::witness::WitnessProgram::renumber) a witness program.
+pub fn clean_up<S: Eq + Hash + Clone + Display>(
+    mut r1cs: R1cs<S>,
+) -> (R1cs<S>, Vec<Option<usize>>) {
+    let modulus = r1cs.modulus.clone();
+    let constraints: Vec<Constraint> = std::mem::take(&mut r1cs.constraints)
+        .iter()
+        .map(|(a, b, c)| {
+            let mut con = (
+                SparseLc::from_lc(a),
+                SparseLc::from_lc(b),
+                SparseLc::from_lc(c),
+            );
+            normalize(&mut con, &modulus);
+            con
+        })
+        .collect();
+    let origins = std::mem::take(&mut r1cs.origins);
+    let (constraints, origins) = prune(constraints, origins, &modulus, true);
+    r1cs.origins = origins;
+    let mut kept = vec![false; r1cs.next_idx];
+    for i in &r1cs.public_idxs {
+        kept[*i] = true;
+    }
+    for (a, b, c) in &constraints {
+        for x in a.vars().chain(b.vars()).chain(c.vars()) {
+            kept[x] = true;
+        }
+    }
+    let mut map = vec![None; r1cs.next_idx];
+    let mut next_idx = 0;
+    for (i, k) in kept.iter().enumerate() {
+        if *k {
+            map[i] = Some(next_idx);
+            next_idx += 1;
+        }
+    }
+    let renumber = |lc: SparseLc| {
+        SparseLc {
+            constant: lc.constant,
+            terms: lc
+                .terms
+                .into_iter()
+                .map(|(i, c)| (map[i].unwrap(), c))
+                .collect(),
+        }
+        .into_lc(&modulus)
+    };
+    r1cs.constraints = constraints
+        .into_iter()
+        .map(|(a, b, c)| (renumber(a), renumber(b), renumber(c)))
+        .collect();
+    r1cs.signal_idxs = std::mem::take(&mut r1cs.signal_idxs)
+        .into_iter()
+        .filter_map(|(s, i)| map[i].map(|j| (s, j)))
+        .collect();
+    r1cs.idxs_signals = std::mem::take(&mut r1cs.idxs_signals)
+        .into_iter()
+        .filter_map(|(i, s)| map[i].map(|j| (j, s)))
+        .collect();
+    r1cs.public_idxs = r1cs.public_idxs.iter().map(|i| map[*i].unwrap()).collect();
+    if let Some(values) = r1cs.values.as_mut() {
+        *values = std::mem::take(values)
+            .into_iter()
+            .filter_map(|(i, v)| map[i].map(|j| (j, v)))
+            .collect();
+    }
+    r1cs.next_idx = next_idx;
+    (r1cs, map)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use ahash::AHashSet as HashSet;
+
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+
+    #[derive(Clone, Debug)]
+    pub struct SatR1cs(R1cs<String>);
+
+    impl Arbitrary for SatR1cs {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let m = 101;
+            let modulus = Integer::from(m);
+            let n_vars = g.size() + 1;
+            let vars: Vec<_> = (0..n_vars).map(|i| format!("v{}", i)).collect();
+            let mut r1cs = R1cs::new(modulus.clone(), true);
+            let mut rug_rng = rug::rand::RandState::new_mersenne_twister();
+            let s: u32 = Arbitrary::arbitrary(g);
+            rug_rng.seed(&Integer::from(s));
+            for v in &vars {
+                r1cs.add_signal(v.clone(), Some(modulus.clone().random_below(&mut rug_rng)));
+            }
+            for _ in 0..(2 * g.size()) {
+                let mut ac: isize = Arbitrary::arbitrary(g);
+                ac.rem_floor_assign(m);
+                let a = if Arbitrary::arbitrary(g) {
+                    r1cs.signal_lc(g.choose(&vars[..]).unwrap())
+                } else {
+                    r1cs.zero()
+                } + ac;
+                let mut bc: isize = Arbitrary::arbitrary(g);
+                bc.rem_floor_assign(m);
+                let b = if Arbitrary::arbitrary(g) {
+                    r1cs.signal_lc(g.choose(&vars[..]).unwrap())
+                } else {
+                    r1cs.zero()
+                } + bc;
+                let mut cc: isize = Arbitrary::arbitrary(g);
+                cc.rem_floor_assign(m);
+                let mut c = if Arbitrary::arbitrary(g) {
+                    r1cs.signal_lc(g.choose(&vars[..]).unwrap())
+                } else {
+                    r1cs.zero()
+                } + cc;
+                let off = r1cs.eval(&a).unwrap() * r1cs.eval(&b).unwrap() - r1cs.eval(&c).unwrap();
+                c += &off;
+                r1cs.constraint(a, b, c);
+            }
+            SatR1cs(r1cs)
+        }
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let c = self.clone();
+            Box::new((0..self.0.constraints.len()).rev().map(move |i| {
+                let mut this = c.clone();
+                this.0.constraints.truncate(i);
+                this
+            }))
+        }
+    }
+
+    #[quickcheck]
+    fn random(SatR1cs(r1cs): SatR1cs) {
+        let r1cs2 = reduce_linearities(r1cs);
+        r1cs2.check_all().unwrap();
+    }
+
+    /// The reducer that [LinReducer] replaced: hash-map linear combinations, and eager
+    /// substitution. It is unchanged, except that two choices it left to hash-map iteration order
+    /// are pinned to the ones [LinReducer] makes: which private signal a linear constraint
+    /// defines (the highest-indexed one), and the order in which the uses of an eliminated signal
+    /// are revisited (increasing). With those fixed, both must give the same system.
+    #[allow(clippy::collapsible_if)]
+    mod baseline {
+        use super::super::super::*;
+        use crate::util::once::OnceQueue;
+        use log::debug;
+
+        struct LinReducer<S: Eq + Hash> {
+            r1cs: R1cs<S>,
+            uses: HashMap<usize, HashSet<usize>>,
+            queue: OnceQueue<usize>,
+        }
+
+        impl<S: Eq + Hash + Display + Clone> LinReducer<S> {
+            fn new(mut r1cs: R1cs<S>) -> Self {
+                let sigs: HashSet<usize> = r1cs
+                    .constraints
+                    .iter()
+                    .flat_map(|(a, b, c)| {
+                        a.monomials
+                            .keys()
+                            .chain(b.monomials.keys().chain(c.monomials.keys()))
+                    })
+                    .cloned()
+                    .collect();
+                let mut uses: HashMap<usize, HashSet<usize>> =
+                    sigs.into_iter().map(|i| (i, HashSet::new())).collect();
+                for (i, (a, b, c)) in r1cs.constraints.iter().enumerate() {
+                    let mut add = |y: &Lc| {
+                        for x in y.monomials.keys() {
+                            uses.get_mut(x).unwrap().insert(i);
+                        }
+                    };
+                    add(a);
+                    add(b);
+                    add(c);
+                }
+                let queue = (0..r1cs.constraints.len()).collect::<OnceQueue<usize>>();
+                for c in &mut r1cs.constraints {
+                    normalize(c);
+                }
+                Self { r1cs, uses, queue }
+            }
+
+            /// Substitute `val` for `var` in constraint with id `con_id`.
+            /// Updates uses conservatively (not precisely)
+            /// Returns whether a sub happened.
+            fn sub_in(&mut self, var: usize, val: &Lc, con_id: usize) -> bool {
+                let (a, b, c) = &mut self.r1cs.constraints[con_id];
+                let uses = &mut self.uses;
+                let mut do_in = |a: &mut Lc| {
+                    if let Some(sc) = a.monomials.remove(&var) {
+                        a.constant += val.constant.clone() * &sc;
+                        a.constant.rem_floor_assign(&*val.modulus);
+                        for (i, v) in &val.monomials {
+                            match a.monomials.entry(*i) {
+                                Entry::Occupied(mut e) => {
+                                    let m = e.get_mut();
+                                    *m += v.clone() * &sc;
+                                    m.rem_floor_assign(&*val.modulus);
+                                    if e.get() == &Integer::from(0) {
+                                        uses.get_mut(i).unwrap().remove(&con_id);
+                                        e.remove_entry();
+                                    }
+                                }
+                                Entry::Vacant(e) => {
+                                    let m = e.insert(v.clone() * &sc);
+                                    m.rem_floor_assign(&*val.modulus);
+                                    uses.get_mut(i).unwrap().insert(con_id);
+                                }
+                            }
+                        }
+                        true
+                    } else {
+                        false
+                    }
+                };
+                let change_a = do_in(a);
+                let change_b = do_in(b);
+                let change_c = do_in(c);
+                let change = change_a || change_b || change_c;
+                self.uses.get_mut(&var).unwrap().remove(&con_id);
+                if change {
+                    normalize(&mut self.r1cs.constraints[con_id]);
+                }
+                change
+            }
+
+            fn clear_constraint(&mut self, i: usize) {
+                for v in self.r1cs.constraints[i].0.monomials.keys() {
+                    self.uses.get_mut(v).unwrap().remove(&i);
+                }
+                self.r1cs.constraints[i].0.clear();
+                for v in self.r1cs.constraints[i].1.monomials.keys() {
+                    self.uses.get_mut(v).unwrap().remove(&i);
+                }
+                self.r1cs.constraints[i].1.clear();
+                for v in self.r1cs.constraints[i].2.monomials.keys() {
+                    self.uses.get_mut(v).unwrap().remove(&i);
+                }
+                self.r1cs.constraints[i].2.clear();
+            }
+
+            fn run(mut self) -> R1cs<S> {
+                while let Some(con_id) = self.queue.pop() {
+                    if let Some((var, lc)) =
+                        as_linear_sub(&self.r1cs.constraints[con_id], &self.r1cs.public_idxs)
+                    {
+                        debug!(
+                            "Elim: {} -> {}",
+                            self.r1cs.idxs_signals.get(&var).unwrap(),
+                            self.r1cs.format_lc(&lc)
+                        );
+                        self.clear_constraint(con_id);
+                        // Pinned: this code visited the uses in hash-set order.
+                        let mut uses: Vec<usize> = self.uses[&var].iter().cloned().collect();
+                        uses.sort_unstable();
+                        for use_id in uses {
+                            if self.sub_in(var, &lc, use_id) {
+                                if self.r1cs.constraints[use_id].0.is_zero()
+                                    || self.r1cs.constraints[use_id].1.is_zero()
+                                {
+                                    self.queue.push(use_id);
+                                }
+                            }
+                        }
+                        debug_assert_eq!(0, self.uses[&var].len());
+                    }
+                }
+                self.r1cs.constraints.retain(|c| !constantly_true(c));
+                self.r1cs
+            }
+        }
+
+        fn as_linear_sub((a, b, c): &(Lc, Lc, Lc), public: &HashSet<usize>) -> Option<(usize, Lc)> {
+            if a.is_zero() || b.is_zero() {
+                // Pinned: this code took the first private signal in hash-map order.
+                if let Some(i) = c.monomials.keys().filter(|i| !public.contains(i)).max() {
+                    let mut lc = c.clone();
+                    let v = lc.monomials.remove(i).unwrap();
+                    lc *= &(-v.invert(&*lc.modulus).unwrap());
+                    return Some((*i, lc));
+                }
+                None
+            } else {
+                None
+            }
+        }
+
+        fn normalize((a, b, c): &mut (Lc, Lc, Lc)) {
+            match (a.as_const(), b.as_const()) {
+                (Some(ac), _) => {
+                    *c -= &(b.take() * ac);
+                    a.clear();
+                }
+                (_, Some(bc)) => {
+                    *c -= &(a.take() * bc);
+                    b.clear();
+                }
+                _ => {}
+            }
+        }
+
+        fn constantly_true((a, b, c): &(Lc, Lc, Lc)) -> bool {
+            match (a.as_const(), b.as_const(), c.as_const()) {
+                (Some(x), Some(y), Some(z)) => (x.clone() * y - z).rem_floor(&*a.modulus) == 0,
+                _ => false,
+            }
+        }
+
+        /// Attempt to shrink this system by reducing linearities.
+        pub fn reduce_linearities<S: Eq + Hash + Clone + Display>(r1cs: R1cs<S>) -> R1cs<S> {
+            LinReducer::new(r1cs).run()
+        }
+    }
+
+    fn sorted(lc: &Lc) -> (Integer, Vec<(usize, Integer)>) {
+        let mut terms: Vec<_> = lc.monomials.iter().map(|(i, c)| (*i, c.clone())).collect();
+        terms.sort();
+        (lc.constant.clone(), terms)
+    }
+
+    #[quickcheck]
+    fn same_as_baseline(SatR1cs(mut r1cs): SatR1cs, public: Vec<u8>) {
+        for p in public {
+            let name = format!("v{}", p as usize % r1cs.next_idx);
+            r1cs.publicize(&name);
+        }
+        let expected = baseline::reduce_linearities(r1cs.clone());
+        let actual = reduce_linearities(r1cs);
+        assert_eq!(expected.constraints.len(), actual.constraints.len());
+        for ((ea, eb, ec), (aa, ab, ac)) in expected.constraints.iter().zip(&actual.constraints) {
+            assert_eq!(sorted(ea), sorted(aa));
+            assert_eq!(sorted(eb), sorted(ab));
+            assert_eq!(sorted(ec), sorted(ac));
+        }
+    }
+
+    #[quickcheck]
+    fn gauss_random(SatR1cs(r1cs): SatR1cs, max_fill: u8) {
+        gauss_reduce(r1cs.clone(), usize::MAX).check_all().unwrap();
+        gauss_reduce(r1cs, max_fill as usize).check_all().unwrap();
+    }
+
+    #[quickcheck]
+    fn gauss_leaves_no_private_linearity(SatR1cs(mut r1cs): SatR1cs, public: Vec<u8>) {
+        for p in public {
+            let name = format!("v{}", p as usize % r1cs.next_idx);
+            r1cs.publicize(&name);
+        }
+        let r1cs = gauss_reduce(r1cs, usize::MAX);
+        for (a, b, c) in &r1cs.constraints {
+            if a.as_const().is_some() || b.as_const().is_some() {
+                assert!(c.monomials.keys().all(|i| r1cs.public_idxs.contains(i)));
+            }
+        }
+    }
+
+    fn signals(r1cs: &mut R1cs<String>, names: &[&str], public: bool) -> Vec<Lc> {
+        names
+            .iter()
+            .map(|n| {
+                r1cs.add_signal(n.to_string(), None);
+                if public {
+                    r1cs.publicize(&n.to_string());
+                }
+                r1cs.signal_lc(&n.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn gauss_scalar_multiples() {
+        let mut r1cs = R1cs::new(Integer::from(101), false);
+        let v = signals(&mut r1cs, &["x", "y", "z"], true);
+        let (x, y, z) = (v[0].clone(), v[1].clone(), v[2].clone());
+        r1cs.constraint(x.clone(), y.clone(), z.clone());
+        r1cs.constraint(y.clone() * 3, x.clone() * 2, z.clone() * 6);
+        r1cs.constraint(r1cs.zero() + 1, x.clone() + &y, z.clone());
+        r1cs.constraint(r1cs.zero(), r1cs.zero(), x * 2 + &(y * 2) - &(z * 2));
+        r1cs.constraint(r1cs.zero() + 2, r1cs.zero() + 3, r1cs.zero() + 6);
+        let r1cs = gauss_reduce(r1cs, usize::MAX);
+        assert_eq!(r1cs.constraints.len(), 2);
+    }
+
+    #[test]
+    fn gauss_fill_limit() {
+        let mut r1cs = R1cs::new(Integer::from(101), false);
+        let v = signals(&mut r1cs, &["x", "y", "a", "b"], true);
+        let t = signals(&mut r1cs, &["t"], false).pop().unwrap();
+        let (x, y, a, b) = (v[0].clone(), v[1].clone(), v[2].clone(), v[3].clone());
+        r1cs.constraint(r1cs.zero() + 1, t.clone(), x.clone() + &y);
+        r1cs.constraint(t.clone(), t.clone(), a);
+        r1cs.constraint(t, x, b);
+        // Eliminating t costs (3 - 1) * (3 - 1).
+        assert_eq!(gauss_reduce(r1cs.clone(), 3).constraints.len(), 3);
+        assert_eq!(gauss_reduce(r1cs, 4).constraints.len(), 2);
+    }
+
+    #[quickcheck]
+    fn clean_up_random(SatR1cs(mut r1cs): SatR1cs, public: Vec<u8>) {
+        for p in public {
+            let name = format!("v{}", p as usize % r1cs.next_idx);
+            r1cs.publicize(&name);
+        }
+        let n_public = r1cs.public_idxs.len();
+        let (r1cs, map) = clean_up(reduce_linearities(r1cs));
+        r1cs.check_all().unwrap();
+        assert_eq!(r1cs.public_idxs.len(), n_public);
+        assert_eq!(map.iter().flatten().count(), r1cs.next_idx);
+        let mut used: HashSet<usize> = r1cs.public_idxs.clone();
+        for (a, b, c) in &r1cs.constraints {
+            let vars = a.monomials.keys().chain(b.monomials.keys());
+            used.extend(vars.chain(c.monomials.keys()).cloned());
+        }
+        assert_eq!(used.len(), r1cs.next_idx);
+        assert_eq!(r1cs.idxs_signals.len(), r1cs.next_idx);
+        assert_eq!(r1cs.values.as_ref().unwrap().len(), r1cs.next_idx);
+    }
+
+    #[test]
+    fn clean_up_compacts() {
+        let mut r1cs = R1cs::new(Integer::from(101), true);
+        for (name, v) in &[("t", 1), ("b", 2), ("u", 3), ("a", 4), ("c", 8)] {
+            r1cs.add_signal(name.to_string(), Some(Integer::from(*v)));
+        }
+        r1cs.publicize(&"a".to_owned());
+        r1cs.publicize(&"b".to_owned());
+        let lc = |n: &str| r1cs.signal_lc(&n.to_owned());
+        let (a, b, c) = (lc("a"), lc("b"), lc("c"));
+        r1cs.constraint(a.clone(), b.clone(), c.clone());
+        r1cs.constraint(b.clone() * 2, a.clone(), c.clone() * 2);
+        r1cs.constraint(r1cs.zero() + 2, a.clone(), c.clone());
+        r1cs.constraint(r1cs.zero() + 2, r1cs.zero() + 3, r1cs.zero() + 6);
+        let (r1cs, map) = clean_up(r1cs);
+        assert_eq!(map, vec![None, Some(0), None, Some(1), Some(2)]);
+        assert_eq!(r1cs.constraints.len(), 2);
+        assert_eq!(r1cs.next_idx, 3);
+        assert_eq!(r1cs.signal_idxs["b"], 0);
+        assert_eq!(r1cs.idxs_signals[&1], "a");
+        assert!(r1cs.public_idxs.contains(&0) && r1cs.public_idxs.contains(&1));
+        assert_eq!(r1cs.values.as_ref().unwrap()[&2], 8);
+        r1cs.check_all().unwrap();
+    }
+}
@@ -0,0 +1,1419 @@
+//! Lowering IR to R1CS
+//!
+//! [Ben Braun's
+//! thesis](https://citeseerx.ist.psu.edu/viewdoc/download?doi=10.1.1.683.6940&rep=rep1&type=pdf)
+//! is a good intro to how this process works.
+use crate::ir::term::extras::Letified;
+use crate::ir::term::*;
+use crate::target::r1cs::witness::{Step, WitnessProgram};
+use crate::target::r1cs::*;
+
+use ahash::{AHashMap, AHashSet};
+use log::debug;
+use rug::ops::Pow;
+use rug::Integer;
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use std::fmt::Display;
+use std::iter::ExactSizeIterator;
+
+struct BvEntry {
+    width: usize,
+    uint: Lc,
+    bits: Vec<Lc>,
+}
+
+#[derive(Clone)]
+enum EmbeddedTerm {
+    Bv(Rc<RefCell<BvEntry>>),
+    Bool(Lc),
+    Field(Lc),
+    #[allow(dead_code)]
+    Tuple(Vec<EmbeddedTerm>),
+}
+
+struct ToR1cs {
+    r1cs: R1cs<String>,
+    cache: TermMap<EmbeddedTerm>,
+    values: Option<AHashMap<String, Value>>,
+    public_inputs: AHashSet<String>,
+    next_idx: usize,
+    /// One-hot encodings of indices, by index and table size
+    one_hots: AHashMap<(Term, usize), Vec<Lc>>,
+    /// How to compute each signal
+    program: WitnessProgram,
+}
+
+impl ToR1cs {
+    fn new(
+        modulus: Integer,
+        values: Option<AHashMap<String, Value>>,
+        public_inputs: AHashSet<String>,
+    ) -> Self {
+        let r1cs = R1cs::new(modulus, values.is_some());
+        Self {
+            program: WitnessProgram::new(r1cs.modulus.clone()),
+            r1cs,
+            cache: TermMap::new(),
+            values,
+            public_inputs,
+            next_idx: 0,
+            one_hots: AHashMap::new(),
+        }
+    }
+
+    /// Get a new variable, with name dependent on `d`, computed by `step`.
+    fn fresh_var<D: Display + ?Sized>(&mut self, ctx: &D, step: Step) -> Lc {
+        let n = format!("{}_v{}", ctx, self.next_idx);
+        self.next_idx += 1;
+        let value = match (&self.values, &self.r1cs.values) {
+            (Some(inputs), Some(values)) => Some(
+                step.eval(inputs, values)
+                    .unwrap_or_else(|e| panic!("{}", e)),
+            ),
+            _ => None,
+        };
+        self.r1cs.add_signal(n.clone(), value);
+        if let Step::Input(name) = &step {
+            if self.public_inputs.contains(name) {
+                self.r1cs.publicize(&n);
+            }
+        }
+        self.program.push(step);
+        self.r1cs.signal_lc(&n)
+    }
+
+    /// Enforce `x` to be bit-valued
+    fn enforce_bit(&mut self, b: Lc) {
+        self.r1cs.constraint(b.clone(), b - 1, self.r1cs.zero());
+    }
+
+    /// Get a new bit-valued variable, with name dependent on `d`, computed by `step`.
+    fn fresh_bit<D: Display + ?Sized>(&mut self, ctx: &D, step: Step) -> Lc {
+        let v = self.fresh_var(ctx, step);
+        //debug!("Fresh bit: {}", self.r1cs.format_lc(&v));
+        self.enforce_bit(v.clone());
+        v
+    }
+
+    /// Return a bit indicating whether wire `x` is non-zero.
+    fn is_zero(&mut self, x: Lc) -> Lc {
+        // m * x - 1 + is_zero == 0
+        // is_zero * x == 0
+        let m = self.fresh_var("is_zero_inv", Step::Inv(x.clone()));
+        let is_zero = self.fresh_var("is_zero", Step::IsZero(x.clone()));
+        self.r1cs.constraint(m, x.clone(), -is_zero.clone() + 1);
+        self.r1cs.constraint(is_zero.clone(), x, self.r1cs.zero());
+        is_zero
+    }
+
+    /// Return a bit indicating whether wires `x` and `y` are equal.
+    fn are_equal(&mut self, x: Lc, y: &Lc) -> Lc {
+        self.is_zero(x - y)
+    }
+
+    /// Return a bit indicating whether wires `x` and `y` are equal.
+    fn bits_are_equal(&mut self, x: &Lc, y: &Lc) -> Lc {
+        self.mul(x.clone() * 2, y.clone()) - x - y + 1
+    }
+
+    /// Given wire `x`, returns a vector of `n` wires which are the bits of `x`.
+    /// They *have not* been constrained to sum to `x`.
+    /// They have values according the the (infinite) two's complement representation of `x`.
+    /// The LSB is at index 0.
+    fn decomp<D: Display + ?Sized>(&mut self, d: &D, x: &Lc, n: usize) -> Vec<Lc> {
+        (0..n)
+            .map(|i| {
+                // We get the right repr here because of infinite two's complement.
+                self.fresh_bit(&format!("{}_b{}", d, i), Step::Bit(x.clone(), i))
+            })
+            .collect::<Vec<_>>()
+    }
+
+    /// Given wire `x`, returns a vector of `n` wires which are the bits of `x`.
+    /// Constrains `x` to fit in `n` (`signed`) bits.
+    /// The LSB is at index 0.
+    fn bitify<D: Display + ?Sized>(&mut self, d: &D, x: &Lc, n: usize, signed: bool) -> Vec<Lc> {
+        debug!("Bitify({}): {}", n, self.r1cs.format_lc(&x));
+        let bits = self.decomp(d, x, n);
+        let sum = self.debitify(bits.iter().cloned(), signed);
+        self.assert_zero(sum - x);
+        bits
+    }
+
+    /// Given wire `x`, returns whether `x` fits in `n` `signed` bits.
+    fn fits_in_bits<D: Display + ?Sized>(&mut self, d: &D, x: &Lc, n: usize, signed: bool) -> Lc {
+        let bits = self.decomp(d, x, n);
+        let sum = self.debitify(bits.iter().cloned(), signed);
+        self.are_equal(sum, x)
+    }
+
+    /// Given a sequence of `bits`, returns a wire which represents their sum,
+    /// `\sum_{i>0} b_i2^i`.
+    ///
+    /// If `signed` is set, then the MSB is negated; i.e., the two's-complement sum is returned.
+    fn debitify<I: ExactSizeIterator<Item = Lc>>(&self, bits: I, signed: bool) -> Lc {
+        let n = bits.len();
+        bits.enumerate().fold(self.r1cs.zero(), |sum, (i, bit)| {
+            let summand = bit * &Integer::from(2).pow(i as u32);
+            if signed && i + 1 == n {
+                sum - &summand
+            } else {
+                sum + &summand
+            }
+        })
+    }
+
+    /// Given `xs`, an iterator of bit-valued wires, returns the XOR of all of them.
+    fn nary_xor<I: ExactSizeIterator<Item = Lc>>(&mut self, mut xs: I) -> Lc {
+        let n = xs.len();
+        if n > 3 {
+            let sum = xs.into_iter().fold(self.r1cs.zero(), |s, i| s + &i);
+            let sum_bits = self.bitify("sum", &sum, bitsize(n), false);
+            assert!(n > 0);
+            assert!(self.r1cs.modulus() > &n);
+            sum_bits.into_iter().next().unwrap() // safe b/c assert
+        } else {
+            let first = xs.next().expect("empty XOR");
+            xs.fold(first, |a, b| a.clone() + &b - &(self.mul(a, b) * 2))
+        }
+    }
+
+    /// Return the product of `a` and `b`.
+    fn mul(&mut self, a: Lc, b: Lc) -> Lc {
+        let c = self.fresh_var("mul", Step::Mul(a.clone(), b.clone()));
+        self.r1cs.constraint(a, b, c.clone());
+        c
+    }
+
+    /// Given a bit-values `a`, returns its (boolean) not.
+    fn bool_not(&self, a: &Lc) -> Lc {
+        self.r1cs.zero() + 1 - a
+    }
+
+    /// Given `xs`, an iterator of bit-valued wires, returns the AND of all of them.
+    fn nary_and<I: ExactSizeIterator<Item = Lc>>(&mut self, mut xs: I) -> Lc {
+        let n = xs.len();
+        if n <= 3 {
+            let first = xs.next().expect("empty AND").clone();
+            xs.fold(first, |a, x| self.mul(a, x))
+        } else {
+            let negs: Vec<Lc> = xs.map(|x| self.bool_not(&x)).collect();
+            let a = self.nary_or(negs.into_iter());
+            self.bool_not(&a)
+        }
+    }
+
+    /// Given `xs`, an iterator of bit-valued wires, returns the OR of all of them.
+    fn nary_or<I: ExactSizeIterator<Item = Lc>>(&mut self, xs: I) -> Lc {
+        let n = xs.len();
+        if n <= 3 {
+            let negs: Vec<Lc> = xs.map(|x| self.bool_not(&x)).collect();
+            let a = self.nary_and(negs.into_iter());
+            self.bool_not(&a)
+        } else {
+            let sum = xs.fold(self.r1cs.zero(), |s, x| s + &x);
+            let z = self.is_zero(sum);
+            self.bool_not(&z)
+        }
+    }
+
+    /// Given a bit-valued `c`, and branches `t` and `f`, returns a wire which is `t` iff `c`, else
+    /// `f`.
+    fn ite(&mut self, c: Lc, t: Lc, f: &Lc) -> Lc {
+        self.mul(c, t - f) + f
+    }
+
+    /// Returns `n` bit-valued wires, of which only the `k`th is set. If `k` is a bit-vector which
+    /// can be `n` or more, there is one more wire, set iff it is; otherwise, `k` is constrained to
+    /// be less than `n`.
+    ///
+    /// Costs `n + 2` constraints (plus a comparison, if `k` can be out of range), but is shared by
+    /// all lookups with index `k` into tables of size `n`.
+    fn one_hot(&mut self, k: &Term, n: usize) -> Vec<Lc> {
+        if let Some(bits) = self.one_hots.get(&(k.clone(), n)) {
+            return bits.clone();
+        }
+        let k_lc = match check(k) {
+            Sort::BitVector(_) => self.get_bv_uint(k),
+            Sort::Field(_) => self.get_pf(k).clone(),
+            s => panic!("Cannot index a table with sort {}", s),
+        };
+        let out_of_range = match check(k) {
+            Sort::BitVector(w) if w < usize::BITS as usize && n >= 1 << w => None,
+            Sort::BitVector(w) => {
+                let past_end = term![BV_ULE; bv_lit(n, w), k.clone()];
+                let origin = self.r1cs.origin.take();
+                self.embed(past_end.clone());
+                self.r1cs.origin = origin;
+                Some(self.get_bool(&past_end).clone())
+            }
+            _ => None,
+        };
+        let mut bits: Vec<Lc> = (0..n)
+            .map(|i| self.fresh_bit(&format!("one_hot_{}", i), Step::Eq(k_lc.clone(), i)))
+            .collect();
+        let count = bits.iter().fold(self.r1cs.zero(), |s, b| s + b);
+        let idx = bits.iter().enumerate().fold(self.r1cs.zero(), |s, (i, b)| {
+            s + &(b.clone() * &Integer::from(i))
+        });
+        match out_of_range {
+            // Out of range, no bit is set, and `k` is unconstrained.
+            Some(oob) => {
+                let in_range = self.r1cs.zero() + 1 - &oob;
+                self.assert_zero(count - &in_range);
+                let in_range_k = self.mul(in_range, k_lc);
+                self.assert_zero(idx - &in_range_k);
+                bits.push(oob);
+            }
+            None => {
+                self.assert_zero(count - 1);
+                self.assert_zero(idx - &k_lc);
+            }
+        }
+        self.one_hots.insert((k.clone(), n), bits.clone());
+        bits
+    }
+
+    /// Embed `(select table k)` for an array constant `table`: the inner product of the table with
+    /// the one-hot encoding of `k`. Keys past the end of the table get its default.
+    fn select_const(&mut self, table: &Term, k: &Term) -> Lc {
+        let (table, key_sort, default, n) = match &table.op {
+            Op::Const(v @ Value::Array(Sort::Array(key_sort, _, n), default, ..)) => {
+                (v, key_sort, default, *n)
+            }
+            _ => panic!("Cannot embed select from non-constant array {}", table),
+        };
+        let bits = self.one_hot(k, n);
+        key_sort
+            .elems_iter()
+            .take(n)
+            .map(|key| match &key.op {
+                Op::Const(key) => table.select(key),
+                _ => unreachable!(),
+            })
+            .chain(std::iter::once((**default).clone()))
+            .zip(bits)
+            .fold(self.r1cs.zero(), |s, (v, b)| {
+                let v = match v {
+                    Value::Bool(b) => Integer::from(b),
+                    Value::BitVector(bv) => bv.uint().clone(),
+                    Value::Field(f) => f.i().clone(),
+                    v => panic!("Cannot embed table value {}", v),
+                };
+                s + &(b * &v)
+            })
+    }
+
+    fn embed(&mut self, t: Term) {
+        debug!("Embed: {}", Letified(t.clone()));
+        for c in PostOrderIter::new(t) {
+            debug!("Embed op: {}", c.op);
+            self.r1cs.origin = Some(c.clone());
+            // Handle field access once and for all
+            if let Op::Field(i) = &c.op {
+                if !self.cache.contains_key(&c) {
+                    let t = self.get_field(&c.cs[0], *i);
+                    self.cache.insert(c, t);
+                }
+            } else {
+                match check(&c) {
+                    Sort::Bool => {
+                        self.embed_bool(c);
+                    }
+                    Sort::BitVector(_) => {
+                        self.embed_bv(c);
+                    }
+                    Sort::Field(_) => {
+                        self.embed_pf(c);
+                    }
+                    Sort::Tuple(_) => {
+                        self.embed_tuple(c);
+                    }
+                    // Array constants are embedded by the selects which use them
+                    Sort::Array(..) if matches!(c.op, Op::Const(_)) => {}
+                    s => panic!("Unsupported sort in embed: {:?}", s),
+                }
+            }
+        }
+    }
+
+    #[allow(unreachable_code)]
+    #[allow(unused_variables)]
+    fn embed_tuple(&mut self, a: Term) {
+        if !self.cache.contains_key(&a) {
+            let t = match &a.op {
+                // May want to support cunstor operators here...
+                _ => panic!("Cannot embed tuple term: {}", a),
+            };
+            self.cache.insert(a, t);
+        }
+    }
+
+    fn get_field(&self, tuple_term: &Term, field: usize) -> EmbeddedTerm {
+        match self.cache.get(tuple_term) {
+            Some(EmbeddedTerm::Tuple(v)) => v[field].clone(),
+            _ => panic!("No tuple for {}", tuple_term),
+        }
+    }
+
+    fn embed_eq(&mut self, a: &Term, b: &Term) -> Lc {
+        match check(a) {
+            Sort::Bool => {
+                let a = self.get_bool(a).clone();
+                let b = self.get_bool(b).clone();
+                self.bits_are_equal(&a, &b)
+            }
+            Sort::BitVector(_) => {
+                let a = self.get_bv_uint(a).clone();
+                let b = self.get_bv_uint(b).clone();
+                self.are_equal(a, &b)
+            }
+            Sort::Field(_) => {
+                let a = self.get_pf(a).clone();
+                let b = self.get_pf(b).clone();
+                self.are_equal(a, &b)
+            }
+            Sort::Tuple(sorts) => {
+                let n = sorts.len();
+                let eqs: Vec<Term> = (0..n).map(|i| {
+                    let t = term![Op::Eq; term![Op::Field(i); a.clone()], term![Op::Field(i); b.clone()]];
+                    t
+                }).collect();
+                let conj = term(Op::BoolNaryOp(BoolNaryOp::And), eqs);
+                self.embed(conj.clone());
+                self.get_bool(&conj).clone()
+            }
+            s => panic!("Unimplemented sort for Eq: {:?}", s),
+        }
+    }
+
+    fn embed_bool(&mut self, c: Term) -> &Lc {
+        //println!("Embed: {}", c);
+        debug_assert!(check(&c) == Sort::Bool);
+        // TODO: skip if already embedded
+        if !self.cache.contains_key(&c) {
+            let lc = match &c.op {
+                Op::Var(name, Sort::Bool) => {
+                    let v = self.fresh_var(name, Step::Input(name.clone()));
+                    if !self.public_inputs.contains(name) {
+                        self.enforce_bit(v.clone());
+                    }
+                    v
+                }
+                Op::Const(Value::Bool(b)) => self.r1cs.zero() + *b as isize,
+                Op::Eq => self.embed_eq(&c.cs[0], &c.cs[1]),
+                Op::Select => self.select_const(&c.cs[0], &c.cs[1]),
+                Op::Ite => {
+                    let a = self.get_bool(&c.cs[0]).clone();
+                    let b = self.get_bool(&c.cs[1]).clone();
+                    let c = self.get_bool(&c.cs[2]).clone();
+                    self.ite(a, b, &c)
+                }
+                Op::BoolMaj => {
+                    let a = self.get_bool(&c.cs[0]).clone();
+                    let b = self.get_bool(&c.cs[1]).clone();
+                    let c = self.get_bool(&c.cs[2]).clone();
+                    // m = ab + bc + ca - 2abc
+                    // m = ab + c(b + a - 2ab)
+                    //   where i = ab
+                    // m = i + c(b + a - 2i)
+                    let i = self.mul(a.clone(), b.clone());
+                    self.mul(c, b + &a - &(i.clone() * 2)) - &i
+                }
+                Op::Not => {
+                    let a = self.get_bool(&c.cs[0]);
+                    self.bool_not(a)
+                }
+                Op::Implies => {
+                    let a = self.get_bool(&c.cs[0]).clone();
+                    let b = self.get_bool(&c.cs[1]).clone();
+                    let not_a = self.bool_not(&a);
+                    self.nary_or(vec![not_a, b].into_iter())
+                }
+                Op::BoolNaryOp(o) => {
+                    let args =
+                        c.cs.iter()
+                            .map(|c| self.get_bool(c).clone())
+                            .collect::<Vec<_>>();
+                    match o {
+                        BoolNaryOp::Or => self.nary_or(args.into_iter()),
+                        BoolNaryOp::And => self.nary_and(args.into_iter()),
+                        BoolNaryOp::Xor => self.nary_xor(args.into_iter()),
+                    }
+                }
+                Op::BvBit(i) => {
+                    let a = self.get_bv_bits(&c.cs[0]);
+                    a[*i].clone()
+                }
+                Op::BvBinPred(o) => {
+                    let n = check(&c.cs[0]).as_bv();
+                    use BvBinPred::*;
+                    match o {
+                        Sge => self.bv_cmp(n, true, false, &c.cs[0], &c.cs[1]),
+                        Sgt => self.bv_cmp(n, true, true, &c.cs[0], &c.cs[1]),
+                        Uge => self.bv_cmp(n, false, false, &c.cs[0], &c.cs[1]),
+                        Ugt => self.bv_cmp(n, false, true, &c.cs[0], &c.cs[1]),
+                        Sle => self.bv_cmp(n, true, false, &c.cs[1], &c.cs[0]),
+                        Slt => self.bv_cmp(n, true, true, &c.cs[1], &c.cs[0]),
+                        Ule => self.bv_cmp(n, false, false, &c.cs[1], &c.cs[0]),
+                        Ult => self.bv_cmp(n, false, true, &c.cs[1], &c.cs[0]),
+                    }
+                }
+                _ => panic!("Non-boolean in embed_bool: {}", c),
+            };
+            self.cache.insert(c.clone(), EmbeddedTerm::Bool(lc));
+        }
+        //println!("=> {}", self.r1cs.format_lc(self.bools.get(&c).unwrap()));
+
+        //        self.r1cs.eval(self.bools.get(&c).unwrap()).map(|v| {
+        //            println!("-> {}", v);
+        //        });
+        self.get_bool(&c)
+    }
+
+    /// Returns whether `a - b` fits in `size` non-negative bits.
+    /// i.e. is in `{0, 1, ..., 2^n-1}`.
+    fn bv_ge(&mut self, a: Lc, b: &Lc, size: usize) -> Lc {
+        self.fits_in_bits("ge", &(a - b), size, false)
+    }
+
+    /// Returns whether `a` is (`strict`ly) (`signed`ly) greater than `b`.
+    /// Assumes they are each `w`-bit bit-vectors.
+    fn bv_cmp(&mut self, w: usize, signed: bool, strict: bool, a: &Term, b: &Term) -> Lc {
+        let a = if signed {
+            self.get_bv_signed_int(a)
+        } else {
+            self.get_bv_uint(a).clone()
+        };
+        let b = if signed {
+            self.get_bv_signed_int(b)
+        } else {
+            self.get_bv_uint(b).clone()
+        };
+        // Use the fact: a > b <=> a - 1 >= b
+        self.bv_ge(if strict { a - 1 } else { a }, &b, w)
+    }
+
+    /// Shift `x` left by `2^y`, if bit-valued `c` is true.
+    fn const_pow_shift_bv(&mut self, x: &Lc, y: usize, c: Lc) -> Lc {
+        self.ite(c, x.clone() * (1 << (1 << y)), x)
+    }
+
+    /// Shift `x` left by `y`, filling the blank spots with bit-valued `ext_bit`.
+    /// Returns an *oversized* number
+    fn shift_bv(&mut self, x: Lc, y: Vec<Lc>, ext_bit: Option<Lc>) -> Lc {
+        if let Some(b) = ext_bit {
+            let left = self.shift_bv(x, y.clone(), None);
+            let right = self.shift_bv(b.clone(), y, None) - 1;
+            left + &self.mul(b, right)
+        } else {
+            y.into_iter()
+                .enumerate()
+                .fold(x, |x, (i, yi)| self.const_pow_shift_bv(&x, i, yi))
+        }
+    }
+
+    /// Shift `x` left by `y`, filling the blank spots with bit-valued `ext_bit`.
+    /// Returns a bit sequence.
+    fn shift_bv_bits(&mut self, x: Lc, y: Vec<Lc>, ext_bit: Option<Lc>, n: usize) -> Vec<Lc> {
+        let s = self.shift_bv(x, y, ext_bit);
+        let mut bits = self.bitify("shift", &s, 2 * n - 1, false);
+        bits.truncate(n);
+        bits
+    }
+
+    fn embed_bv(&mut self, bv: Term) {
+        //println!("Embed: {}", bv);
+        //let bv2=  bv.clone();
+        if let Sort::BitVector(n) = check(&bv) {
+            if !self.cache.contains_key(&bv) {
+                match &bv.op {
+                    Op::Var(name, Sort::BitVector(_)) => {
+                        let var = self.fresh_var(name, Step::Input(name.clone()));
+                        self.set_bv_uint(bv.clone(), var, n);
+                        if !self.public_inputs.contains(name) {
+                            self.get_bv_bits(&bv);
+                        }
+                    }
+                    Op::Const(Value::BitVector(b)) => {
+                        let bit_lcs = (0..b.width())
+                            .map(|i| self.r1cs.zero() + b.uint().get_bit(i as u32) as isize)
+                            .collect();
+                        self.set_bv_bits(bv, bit_lcs);
+                    }
+                    Op::Ite => {
+                        let c = self.get_bool(&bv.cs[0]).clone();
+                        let t = self.get_bv_uint(&bv.cs[1]).clone();
+                        let f = self.get_bv_uint(&bv.cs[2]).clone();
+                        let ite = self.ite(c, t, &f);
+                        self.set_bv_uint(bv, ite, n);
+                    }
+                    Op::Select => {
+                        let lookup = self.select_const(&bv.cs[0], &bv.cs[1]);
+                        self.set_bv_uint(bv, lookup, n);
+                    }
+                    Op::BvUnOp(BvUnOp::Not) => {
+                        let bits = self.get_bv_bits(&bv.cs[0]).clone();
+                        let not_bits = bits.iter().map(|bit| self.bool_not(bit)).collect();
+                        self.set_bv_bits(bv, not_bits);
+                    }
+                    Op::BvUnOp(BvUnOp::Neg) => {
+                        let x = self.get_bv_uint(&bv.cs[0]).clone();
+                        // Wrong for x == 0
+                        let almost_neg_x = self.r1cs.zero() + &Integer::from(2).pow(n as u32) - &x;
+                        let is_zero = self.is_zero(x);
+                        let neg_x = self.ite(is_zero, self.r1cs.zero(), &almost_neg_x);
+                        self.set_bv_uint(bv, neg_x, n);
+                    }
+                    Op::BvUext(extra_n) => {
+                        if self.bv_has_bits(&bv.cs[0]) {
+                            let bits = self.get_bv_bits(&bv.cs[0]);
+                            let ext_bits = std::iter::repeat(self.r1cs.zero()).take(*extra_n);
+                            self.set_bv_bits(bv, bits.into_iter().chain(ext_bits).collect());
+                        } else {
+                            let x = self.get_bv_uint(&bv.cs[0]).clone();
+                            self.set_bv_uint(bv, x, n);
+                        }
+                    }
+                    Op::BvSext(extra_n) => {
+                        let mut bits = self.get_bv_bits(&bv.cs[0]).into_iter().rev();
+                        let ext_bits =
+                            std::iter::repeat(bits.next().expect("sign ext empty").clone())
+                                .take(extra_n + 1);
+
+                        self.set_bv_bits(bv, bits.rev().chain(ext_bits).collect());
+                    }
+                    Op::PfToBv(nbits) => {
+                        let lc = self.get_pf(&bv.cs[0]).clone();
+                        let bits = self.bitify("pf2bv", &lc, *nbits, false);
+                        self.set_bv_bits(bv.clone(), bits);
+                    }
+                    Op::BoolToBv => {
+                        let b = self.get_bool(&bv.cs[0]).clone();
+                        self.set_bv_bits(bv, vec![b]);
+                    }
+                    Op::BvNaryOp(o) => match o {
+                        BvNaryOp::Xor | BvNaryOp::Or | BvNaryOp::And => {
+                            let mut bits_by_bv = bv
+                                .cs
+                                .iter()
+                                .map(|c| self.get_bv_bits(c))
+                                .collect::<Vec<_>>();
+                            let mut bits_bv_idx: Vec<Vec<Lc>> = Vec::new();
+                            while bits_by_bv[0].len() > 0 {
+                                bits_bv_idx.push(
+                                    bits_by_bv.iter_mut().map(|bv| bv.pop().unwrap()).collect(),
+                                );
+                            }
+                            bits_bv_idx.reverse();
+                            let f = |v: Vec<Lc>| match o {
+                                BvNaryOp::And => self.nary_and(v.into_iter()),
+                                BvNaryOp::Or => self.nary_or(v.into_iter()),
+                                BvNaryOp::Xor => self.nary_xor(v.into_iter()),
+                                _ => unreachable!(),
+                            };
+                            let res = bits_bv_idx.into_iter().map(f).collect();
+                            self.set_bv_bits(bv, res);
+                        }
+                        BvNaryOp::Add | BvNaryOp::Mul => {
+                            let f_width = self.r1cs.modulus().significant_bits() as usize - 1;
+                            let values = bv
+                                .cs
+                                .iter()
+                                .map(|c| self.get_bv_uint(c).clone())
+                                .collect::<Vec<_>>();
+                            let (res, width) = match o {
+                                BvNaryOp::Add => {
+                                    let sum =
+                                        values.into_iter().fold(self.r1cs.zero(), |s, v| s + &v);
+                                    let extra_width = bitsize(bv.cs.len().saturating_sub(1));
+                                    (sum, n + extra_width)
+                                }
+                                BvNaryOp::Mul => {
+                                    if bv.cs.len() * n < f_width {
+                                        let z = self.r1cs.zero() + 1;
+                                        (
+                                            values.into_iter().fold(z, |acc, v| self.mul(acc, v)),
+                                            bv.cs.len() * n,
+                                        )
+                                    } else {
+                                        let z = self.r1cs.zero() + 1;
+                                        let p = values.into_iter().fold(z, |acc, v| {
+                                            let p = self.mul(acc, v);
+                                            let mut bits = self.bitify("binMul", &p, 2 * n, false);
+                                            bits.truncate(n);
+                                            self.debitify(bits.into_iter(), false)
+                                        });
+                                        (p, n)
+                                    }
+                                }
+                                _ => unreachable!(),
+                            };
+                            let mut bits = self.bitify("arith", &res, width, false);
+                            bits.truncate(n);
+                            self.set_bv_bits(bv, bits);
+                        }
+                    },
+                    Op::BvBinOp(o) => {
+                        let a = self.get_bv_uint(&bv.cs[0]);
+                        let b = self.get_bv_uint(&bv.cs[1]);
+                        match o {
+                            BvBinOp::Sub => {
+                                let sum = a.clone() + &(Integer::from(1) << n as u32) - &b;
+                                let mut bits = self.bitify("sub", &sum, n + 1, false);
+                                bits.truncate(n);
+                                self.set_bv_bits(bv, bits);
+                            }
+                            BvBinOp::Udiv | BvBinOp::Urem => {
+                                let b = b.clone();
+                                let a = a.clone();
+                                let is_zero = self.is_zero(b.clone());
+                                let q = self.fresh_var("div_q", Step::Div(a.clone(), b.clone(), n));
+                                let r = self.fresh_var("div_q", Step::Rem(a.clone(), b.clone()));
+                                let qb = self.bitify("div_q", &q, n, false);
+                                let rb = self.bitify("div_r", &r, n, false);
+                                self.r1cs.constraint(q.clone(), b.clone(), a - &r);
+                                let is_gt = self.bv_ge(b - 1, &r, n);
+                                let is_not_ge = self.bool_not(&is_gt);
+                                let is_not_zero = self.bool_not(&is_zero);
+                                self.r1cs
+                                    .constraint(is_not_ge, is_not_zero, self.r1cs.zero());
+                                let bits = match o {
+                                    BvBinOp::Udiv => qb,
+                                    BvBinOp::Urem => rb,
+                                    _ => unreachable!(),
+                                };
+                                self.set_bv_bits(bv, bits);
+                            }
+                            // Shift cases
+                            _ => {
+                                let r = b.clone();
+                                let a = a.clone();
+                                let b = bitsize(n - 1);
+                                assert!(1 << b == n);
+                                let mut rb = self.get_bv_bits(&bv.cs[1]);
+                                rb.truncate(b);
+                                let sum = self.debitify(rb.clone().into_iter(), false);
+                                self.assert_zero(sum - &r);
+                                let bits = match o {
+                                    BvBinOp::Shl => self.shift_bv_bits(a, rb, None, n),
+                                    BvBinOp::Lshr | BvBinOp::Ashr => {
+                                        let mut lb = self.get_bv_bits(&bv.cs[0]);
+                                        lb.reverse();
+                                        let ext_bit = match o {
+                                            BvBinOp::Ashr => Some(lb.first().unwrap().clone()),
+                                            _ => None,
+                                        };
+                                        let l = self.debitify(lb.into_iter(), false);
+                                        let mut bits = self.shift_bv_bits(l, rb, ext_bit, n);
+                                        bits.reverse();
+                                        bits
+                                    }
+                                    _ => unreachable!(),
+                                };
+                                self.set_bv_bits(bv, bits);
+                            }
+                        }
+                    }
+                    Op::BvConcat => {
+                        let mut bits = Vec::new();
+                        for c in bv.cs.iter().rev() {
+                            bits.extend(self.get_bv_bits(c));
+                        }
+                        self.set_bv_bits(bv, bits);
+                    }
+                    // inclusive!
+                    Op::BvExtract(high, low) => {
+                        let bits = self
+                            .get_bv_bits(&bv.cs[0])
+                            .into_iter()
+                            .skip(*low)
+                            .take(*high - *low + 1)
+                            .collect();
+                        self.set_bv_bits(bv, bits);
+                    }
+                    _ => panic!("Non-bv in embed_bv: {}", Letified(bv)),
+                }
+            }
+        //self.r1cs.eval(self.get_bv_uint(&bv2)).map(|v| {
+        //    println!("-> {:b}", v);
+        //});
+        } else {
+            panic!("{} is not a bit-vector in embed_bv", bv);
+        }
+    }
+
+    #[allow(dead_code)]
+    fn debug_lc<D: Display + ?Sized>(&self, tag: &D, lc: &Lc) {
+        if let Some(v) = self.r1cs.eval(lc) {
+            println!("{}: {} (value {},{:b})", tag, self.r1cs.format_lc(lc), v, v);
+        } else {
+            println!("{}: {} (novalue)", tag, self.r1cs.format_lc(lc));
+        }
+    }
+
+    fn get_bool(&self, t: &Term) -> &Lc {
+        match self
+            .cache
+            .get(t)
+            .unwrap_or_else(|| panic!("Missing wire for {:?}", t))
+        {
+            EmbeddedTerm::Bool(b) => &b,
+            _ => panic!("Non-boolean for {:?}", t),
+        }
+    }
+
+    fn set_bv_bits(&mut self, t: Term, bits: Vec<Lc>) {
+        let sum = self.debitify(bits.iter().cloned(), false);
+        assert!(!self.cache.contains_key(&t));
+        self.cache.insert(
+            t,
+            EmbeddedTerm::Bv(Rc::new(RefCell::new(BvEntry {
+                uint: sum,
+                width: bits.len(),
+                bits,
+            }))),
+        );
+    }
+
+    fn set_bv_uint(&mut self, t: Term, uint: Lc, width: usize) {
+        assert!(!self.cache.contains_key(&t));
+        self.cache.insert(
+            t,
+            EmbeddedTerm::Bv(Rc::new(RefCell::new(BvEntry {
+                uint,
+                width,
+                bits: Vec::new(),
+            }))),
+        );
+    }
+
+    fn get_bv(&self, t: &Term) -> Rc<RefCell<BvEntry>> {
+        match self
+            .cache
+            .get(t)
+            .unwrap_or_else(|| panic!("Missing wire for {:?}", t))
+        {
+            EmbeddedTerm::Bv(b) => b.clone(),
+            _ => panic!("Non-bv for {:?}", t),
+        }
+    }
+
+    fn bv_has_bits(&self, t: &Term) -> bool {
+        self.get_bv(t).borrow().bits.len() > 0
+    }
+
+    fn get_bv_uint(&self, t: &Term) -> Lc {
+        self.get_bv(t).borrow().uint.clone()
+    }
+
+    fn get_bv_signed_int(&mut self, t: &Term) -> Lc {
+        let bits = self.get_bv_bits(t).clone();
+        self.debitify(bits.into_iter(), true)
+    }
+
+    fn get_bv_bits(&mut self, t: &Term) -> Vec<Lc> {
+        let entry_rc = self.get_bv(t);
+        let mut entry = entry_rc.borrow_mut();
+        if entry.bits.len() == 0 {
+            entry.bits = self.bitify("getbits", &entry.uint, entry.width, false);
+        }
+        entry.bits.clone()
+    }
+
+    fn get_pf(&self, t: &Term) -> &Lc {
+        match self
+            .cache
+            .get(t)
+            .unwrap_or_else(|| panic!("Missing wire for {:?}", t))
+        {
+            EmbeddedTerm::Field(b) => b,
+            _ => panic!("Non-field for {:?}", t),
+        }
+    }
+
+    fn embed_pf(&mut self, c: Term) -> &Lc {
+        //println!("Embed: {}", c);
+        // TODO: skip if already embedded
+        if !self.cache.contains_key(&c) {
+            let lc = match &c.op {
+                Op::Var(name, Sort::Field(_)) => self.fresh_var(name, Step::Input(name.clone())),
+                Op::Const(Value::Field(r)) => self.r1cs.zero() + r.i(),
+                Op::Ite => {
+                    let cond = self.get_bool(&c.cs[0]).clone();
+                    let t = self.get_pf(&c.cs[1]).clone();
+                    let f = self.get_pf(&c.cs[2]).clone();
+                    self.ite(cond, t, &f)
+                }
+                Op::PfNaryOp(o) => {
+                    let args = c.cs.iter().map(|c| self.get_pf(c));
+                    match o {
+                        PfNaryOp::Add => args.fold(self.r1cs.zero(), std::ops::Add::add),
+                        PfNaryOp::Mul => {
+                            let args = args.cloned().collect::<Vec<_>>();
+                            let mut args_iter = args.into_iter();
+                            let first = args_iter.next().unwrap();
+                            args_iter.fold(first, |a, b| self.mul(a, b.clone()))
+                        }
+                    }
+                }
+                Op::PfUnOp(PfUnOp::Neg) => -self.get_pf(&c.cs[0]).clone(),
+                Op::Select => self.select_const(&c.cs[0], &c.cs[1]),
+                Op::PfUnOp(PfUnOp::Recip) => {
+                    let x = self.get_pf(&c.cs[0]).clone();
+                    let inv_x = self.fresh_var("recip", Step::Inv(x.clone()));
+                    self.r1cs.constraint(x, inv_x.clone(), self.r1cs.zero() + 1);
+                    inv_x
+                }
+                _ => panic!("Non-field in embed_pf: {}", c),
+            };
+            self.cache.insert(c.clone(), EmbeddedTerm::Field(lc));
+        }
+        self.get_pf(&c)
+    }
+
+    fn assert_zero(&mut self, x: Lc) {
+        self.r1cs.constraint(self.r1cs.zero(), self.r1cs.zero(), x);
+    }
+    fn assert(&mut self, t: Term) {
+        debug!("Assert: {}", Letified(t.clone()));
+        self.embed(t.clone());
+        let lc = self.get_bool(&t).clone();
+        self.assert_zero(lc - 1);
+    }
+}
+
+/// Convert this (IR) constraint system `cs` to R1CS, over a prime field defined by `modulus`.
+pub fn to_r1cs(cs: Computation, modulus: Integer) -> R1cs<String> {
+    to_r1cs_with_program(cs, modulus).0
+}
+
+/// Convert this (IR) constraint system `cs` to R1CS, over a prime field defined by `modulus`.
+///
+/// Also returns a program which computes a witness for the R1CS from the inputs to `cs`.
+///
+/// Panics if `cs` was built over a different field.
+pub fn to_r1cs_with_program(cs: Computation, modulus: Integer) -> (R1cs<String>, WitnessProgram) {
+    let Computation {
+        outputs: assertions,
+        metadata,
+        values,
+    } = cs;
+    if let Some(field) = &metadata.field {
+        assert_eq!(
+            **field, modulus,
+            "The computation is over the field of order {}, not {}",
+            field, modulus
+        );
+    }
+    let public_inputs = metadata.public_inputs().map(ToOwned::to_owned).collect();
+    let mut converter = ToR1cs::new(modulus, values, public_inputs);
+    debug!(
+        "Term count: {}",
+        assertions
+            .iter()
+            .map(|c| PostOrderIter::new(c.clone()).count())
+            .sum::<usize>()
+    );
+    println!("Printing assertions");
+    for c in assertions {
+        converter.assert(c);
+    }
+    (converter.r1cs, converter.program)
+}
+
+/// Returns the number of bits needed to hold `n`.
+pub fn bitsize(mut n: usize) -> usize {
+    let mut acc = 0;
+    while n > 0 {
+        n >>= 1;
+        acc += 1;
+    }
+    acc
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::ir::proof::Constraints;
+    use crate::ir::term::dist::test::*;
+    use crate::ir::term::dist::*;
+    use crate::target::r1cs::opt::reduce_linearities;
+    use quickcheck::{Arbitrary, Gen};
+    use quickcheck_macros::quickcheck;
+    use rand::distributions::Distribution;
+    use rand::SeedableRng;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn init() {
+        let _ = env_logger::builder().is_test(true).try_init();
+    }
+
+    #[test]
+    fn bool() {
+        let cs = Computation::from_constraint_system_parts(
+            vec![
+                leaf_term(Op::Var("a".to_owned(), Sort::Bool)),
+                term![Op::Not; leaf_term(Op::Var("b".to_owned(), Sort::Bool))],
+            ],
+            vec!["a", "b"].into_iter().map(|a| a.to_owned()).collect(),
+            Some(
+                vec![
+                    ("a".to_owned(), Value::Bool(true)),
+                    ("b".to_owned(), Value::Bool(false)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let r1cs = to_r1cs(cs, Integer::from(17));
+        r1cs.check_all().unwrap();
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct PureBool(pub Term, pub AHashMap<String, Value>);
+
+    impl Arbitrary for PureBool {
+        fn arbitrary(g: &mut Gen) -> Self {
+            let mut rng = rand::rngs::StdRng::seed_from_u64(u64::arbitrary(g));
+            let t = PureBoolDist(g.size()).sample(&mut rng);
+            let values: AHashMap<String, Value> = PostOrderIter::new(t.clone())
+                .filter_map(|c| {
+                    if let Op::Var(n, _) = &c.op {
+                        Some((n.clone(), Value::Bool(bool::arbitrary(g))))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+            PureBool(t, values)
+        }
+
+        fn shrink(&self) -> Box<dyn Iterator<Item = Self>> {
+            let vs = self.1.clone();
+            let ts = PostOrderIter::new(self.0.clone()).collect::<Vec<_>>();
+
+            Box::new(
+                ts.into_iter()
+                    .rev()
+                    .skip(1)
+                    .map(move |t| PureBool(t, vs.clone())),
+            )
+        }
+    }
+
+    #[quickcheck]
+    fn random_pure_bool(PureBool(t, values): PureBool) {
+        let t = if eval(&t, &values).as_bool() {
+            t
+        } else {
+            term![Op::Not; t]
+        };
+        let cs = Computation::from_constraint_system_parts(vec![t], AHashSet::new(), Some(values));
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+    }
+
+    #[quickcheck]
+    fn random_bool(ArbitraryTermEnv(t, values): ArbitraryTermEnv) {
+        let v = eval(&t, &values);
+        let t = term![Op::Eq; t, leaf_term(Op::Const(v))];
+        let cs = Computation::from_constraint_system_parts(vec![t], AHashSet::new(), Some(values));
+        let cs = crate::ir::opt::tuple::eliminate_tuples(cs);
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+    }
+
+    #[quickcheck]
+    fn random_pure_bool_opt(ArbitraryBoolEnv(t, values): ArbitraryBoolEnv) {
+        let v = eval(&t, &values);
+        let t = term![Op::Eq; t, leaf_term(Op::Const(v))];
+        let cs = Computation::from_constraint_system_parts(vec![t], AHashSet::new(), Some(values));
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+        let r1cs2 = reduce_linearities(r1cs);
+        r1cs2.check_all().unwrap();
+    }
+
+    #[quickcheck]
+    fn random_witness_program(ArbitraryTermEnv(t, values): ArbitraryTermEnv) {
+        let v = eval(&t, &values);
+        let t = term![Op::Eq; t, leaf_term(Op::Const(v))];
+        let cs = Computation::from_constraint_system_parts(
+            vec![t],
+            AHashSet::new(),
+            Some(values.clone()),
+        );
+        let cs = crate::ir::opt::tuple::eliminate_tuples(cs);
+        let (r1cs, program) =
+            to_r1cs_with_program(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        let expected = r1cs.values.unwrap();
+        let actual = program.eval(&values).unwrap();
+        assert_eq!(expected.len(), actual.len());
+        for (i, v) in &expected {
+            assert_eq!(v, &actual[i]);
+        }
+    }
+
+    #[quickcheck]
+    fn random_bool_opt(ArbitraryTermEnv(t, values): ArbitraryTermEnv) {
+        let v = eval(&t, &values);
+        let t = term![Op::Eq; t, leaf_term(Op::Const(v))];
+        let cs = Computation::from_constraint_system_parts(vec![t], AHashSet::new(), Some(values));
+        let cs = crate::ir::opt::tuple::eliminate_tuples(cs);
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+        let r1cs2 = reduce_linearities(r1cs);
+        r1cs2.check_all().unwrap();
+    }
+
+    #[test]
+    fn eq_test() {
+        let cs = Computation::from_constraint_system_parts(
+            vec![term![Op::Not; term![Op::Eq; bv(0b10110, 8),
+                              term![Op::BvUnOp(BvUnOp::Neg); leaf_term(Op::Var("b".to_owned(), Sort::BitVector(8)))]]]],
+            vec!["a"].into_iter().map(|a| a.to_owned()).collect(),
+            Some(
+                vec![(
+                    "b".to_owned(),
+                    Value::BitVector(BitVector::new(Integer::from(152), 8)),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+    }
+
+    #[test]
+    fn not_opt_test() {
+        init();
+        let t = term![Op::Not; leaf_term(Op::Var("b".to_owned(), Sort::Bool))];
+        let values: AHashMap<String, Value> = vec![("b".to_owned(), Value::Bool(true))]
+            .into_iter()
+            .collect();
+        let v = eval(&t, &values);
+        let t = term![Op::Eq; t, leaf_term(Op::Const(v))];
+        let cs = Computation::from_constraint_system_parts(vec![t], AHashSet::new(), Some(values));
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+        let r1cs2 = reduce_linearities(r1cs);
+        r1cs2.check_all().unwrap();
+    }
+
+    /// A bit-vector literal with value `u` and size `w`
+    pub fn bv(u: usize, w: usize) -> Term {
+        leaf_term(Op::Const(Value::BitVector(BitVector::new(
+            Integer::from(u),
+            w,
+        ))))
+    }
+
+    fn pf(i: isize) -> Term {
+        leaf_term(Op::Const(Value::Field(FieldElem::new(
+            Integer::from(i),
+            Arc::new(Integer::from(crate::ir::term::field::TEST_FIELD)),
+        ))))
+    }
+
+    fn const_test(term: Term) {
+        let mut cs = Computation::new(true);
+        cs.assert(term);
+        let r1cs = to_r1cs(cs, Integer::from(crate::ir::term::field::TEST_FIELD));
+        r1cs.check_all().unwrap();
+    }
+
+    #[test]
+    fn div_test() {
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Udiv); bv(0b1111,4), bv(0b1111,4)],
+            bv(0b0001, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Udiv); bv(0b1111,4), bv(0b0001,4)],
+            bv(0b1111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Udiv); bv(0b0111,4), bv(0b0000,4)],
+            bv(0b1111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Udiv); bv(0b1111,4), bv(0b0010,4)],
+            bv(0b0111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Urem); bv(0b1111,4), bv(0b1111,4)],
+            bv(0b0000, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Urem); bv(0b1111,4), bv(0b0001,4)],
+            bv(0b0000, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Urem); bv(0b0111,4), bv(0b0000,4)],
+            bv(0b0111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Urem); bv(0b1111,4), bv(0b0010,4)],
+            bv(0b0001, 4)
+        ]);
+    }
+
+    #[test]
+    fn sh_test() {
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Shl); bv(0b1111,4), bv(0b0011,4)],
+            bv(0b1000, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Shl); bv(0b1101,4), bv(0b0010,4)],
+            bv(0b0100, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Ashr); bv(0b1111,4), bv(0b0011,4)],
+            bv(0b1111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Ashr); bv(0b0111,4), bv(0b0010,4)],
+            bv(0b0001, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Lshr); bv(0b0111,4), bv(0b0010,4)],
+            bv(0b0001, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::BvBinOp(BvBinOp::Lshr); bv(0b1111,4), bv(0b0011,4)],
+            bv(0b0001, 4)
+        ]);
+    }
+
+    #[test]
+    fn pf2bv() {
+        const_test(term![
+            Op::Eq;
+            term![Op::PfToBv(4); pf(8)],
+            bv(0b1000, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::PfToBv(4); pf(15)],
+            bv(0b1111, 4)
+        ]);
+        const_test(term![
+            Op::Eq;
+            term![Op::PfToBv(8); pf(15)],
+            bv(0b1111, 8)
+        ]);
+    }
+
+    #[test]
+    fn tuple() {
+        let cs = Computation::from_constraint_system_parts(
+            vec![
+                term![Op::Field(0); term![Op::Tuple; leaf_term(Op::Var("a".to_owned(), Sort::Bool)), leaf_term(Op::Const(Value::Bool(false)))]],
+                term![Op::Not; leaf_term(Op::Var("b".to_owned(), Sort::Bool))],
+            ],
+            vec!["a", "b"].into_iter().map(|a| a.to_owned()).collect(),
+            Some(
+                vec![
+                    ("a".to_owned(), Value::Bool(true)),
+                    ("b".to_owned(), Value::Bool(false)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+        );
+        let cs = crate::ir::opt::tuple::eliminate_tuples(cs);
+        let r1cs = to_r1cs(cs, Integer::from(17));
+        r1cs.check_all().unwrap();
+    }
+
+    /// A table from 3-bit keys to 4-bit values
+    fn table(f: impl Fn(usize) -> usize) -> Term {
+        sized_table(8, 0, f)
+    }
+
+    /// A table of `size` entries from 3-bit keys to 4-bit values, with the given default
+    fn sized_table(size: usize, default: usize, f: impl Fn(usize) -> usize) -> Term {
+        let a = Sort::Array(
+            Box::new(Sort::BitVector(3)),
+            Box::new(Sort::BitVector(4)),
+            size,
+        );
+        let default = Value::BitVector(BitVector::new(Integer::from(default), 4));
+        let empty = Value::Array(a, Box::new(default), BTreeMap::new(), size);
+        let v = (0..size).fold(empty, |v, i| {
+            v.store(
+                Value::BitVector(BitVector::new(Integer::from(i), 3)),
+                Value::BitVector(BitVector::new(Integer::from(f(i)), 4)),
+            )
+        });
+        leaf_term(Op::Const(v))
+    }
+
+    fn lookup_cs(k: usize, expected: usize) -> Computation {
+        let key = leaf_term(Op::Var("k".to_owned(), Sort::BitVector(3)));
+        Computation::from_constraint_system_parts(
+            vec![term![Op::Eq; term![Op::Select; table(|i| (5 * i + 3) % 16), key], bv(expected, 4)]],
+            AHashSet::new(),
+            Some(
+                vec![(
+                    "k".to_owned(),
+                    Value::BitVector(BitVector::new(Integer::from(k), 3)),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+        )
+    }
+
+    #[test]
+    fn lookup() {
+        for k in 0..8 {
+            let r1cs = to_r1cs(lookup_cs(k, (5 * k + 3) % 16), Integer::from(17));
+            r1cs.check_all().unwrap();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn lookup_wrong() {
+        let r1cs = to_r1cs(lookup_cs(2, 12), Integer::from(17));
+        r1cs.check_all().unwrap();
+    }
+
+    #[test]
+    fn lookup_wrong_report() {
+        let cs = lookup_cs(2, 12);
+        let assertion = cs.outputs[0].clone();
+        let r1cs = to_r1cs(cs, Integer::from(17));
+        let report = r1cs.check_all().unwrap_err();
+        assert_eq!(report.n_constraints, r1cs.constraints().len());
+        let v = report.violations.last().unwrap();
+        assert_eq!(v.index, r1cs.constraints().len() - 1);
+        assert_eq!(v.origin.as_ref(), Some(&assertion));
+        assert_ne!(v.ab, v.c);
+        assert!(!v.signals.is_empty());
+        let text = report.to_string();
+        assert!(text.contains(&format!("Constraint {}: ", v.index)));
+    }
+
+    #[test]
+    fn lookup_past_end() {
+        let t = sized_table(5, 9, |i| i + 1);
+        let key = leaf_term(Op::Var("k".to_owned(), Sort::BitVector(3)));
+        let lookup = term![Op::Select; t, key];
+        for k in 0..8 {
+            let values: AHashMap<String, Value> = vec![(
+                "k".to_owned(),
+                Value::BitVector(BitVector::new(Integer::from(k), 3)),
+            )]
+            .into_iter()
+            .collect();
+            let expected = leaf_term(Op::Const(eval(&lookup, &values)));
+            let cs = Computation::from_constraint_system_parts(
+                vec![term![Op::Eq; lookup.clone(), expected]],
+                AHashSet::new(),
+                Some(values),
+            );
+            let r1cs = to_r1cs(cs, Integer::from(17));
+            r1cs.check_all().unwrap();
+        }
+    }
+
+    #[test]
+    fn lookups_share_one_hot() {
+        let key = leaf_term(Op::Var("k".to_owned(), Sort::BitVector(3)));
+        let out = leaf_term(Op::Var("o".to_owned(), Sort::BitVector(4)));
+        let lookup = |f: fn(usize) -> usize| term![Op::Select; table(f), key.clone()];
+        let one = Computation::from_constraint_system_parts(
+            vec![term![Op::Eq; lookup(|i| i ^ 5), out.clone()]],
+            AHashSet::new(),
+            None,
+        );
+        let two = Computation::from_constraint_system_parts(
+            vec![
+                term![Op::Eq; lookup(|i| i ^ 5), out.clone()],
+                term![Op::Eq; lookup(|i| 15 - i), out],
+            ],
+            AHashSet::new(),
+            None,
+        );
+        let one = to_r1cs(one, Integer::from(17)).constraints().len();
+        let two = to_r1cs(two, Integer::from(17)).constraints().len();
+        // The second lookup doesn't need another 8 indicator bits
+        assert!(two - one < 8);
+    }
+
+    #[test]
+    fn compile_once_witness_many() {
+        let m = Arc::new(Integer::from(crate::ir::term::field::TEST_FIELD));
+        let bv_var = |n: &str, w: usize| leaf_term(Op::Var(n.to_owned(), Sort::BitVector(w)));
+        let pf_var = |n: &str| leaf_term(Op::Var(n.to_owned(), Sort::Field(m.clone())));
+        let cs = Computation::from_constraint_system_parts(
+            vec![
+                term![Op::Eq;
+                    term![Op::Select; table(|i| (5 * i + 3) % 16), bv_var("k", 3)],
+                    bv_var("o", 4)
+                ],
+                term![Op::Eq;
+                    term![Op::BvBinOp(BvBinOp::Udiv); bv_var("o", 4), bv_var("d", 4)],
+                    bv_var("q", 4)
+                ],
+                term![Op::Eq; term![Op::PfUnOp(PfUnOp::Recip); pf_var("x")], pf_var("y")],
+            ],
+            AHashSet::new(),
+            None,
+        );
+        let (mut r1cs, program) = to_r1cs_with_program(cs, (*m).clone());
+        let bv_val = |i: usize, w: usize| Value::BitVector(BitVector::new(Integer::from(i), w));
+        let pf_val = |i: Integer| Value::Field(FieldElem::new(i, m.clone()));
+        for k in 0..8 {
+            for d in 0..4 {
+                let o = (5 * k + 3) % 16;
+                let q = if d == 0 { 15 } else { o / d };
+                let x = Integer::from(k + 1);
+                let y = x.clone().invert(&m).unwrap();
+                let values: AHashMap<String, Value> = vec![
+                    ("k".to_owned(), bv_val(k, 3)),
+                    ("o".to_owned(), bv_val(o, 4)),
+                    ("d".to_owned(), bv_val(d, 4)),
+                    ("q".to_owned(), bv_val(q, 4)),
+                    ("x".to_owned(), pf_val(x)),
+                    ("y".to_owned(), pf_val(y)),
+                ]
+                .into_iter()
+                .collect();
+                r1cs.set_values(program.eval(&values).unwrap());
+                r1cs.check_all().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "The computation is over the field of order")]
+    fn field_mismatch() {
+        let mut cs = Computation::from_constraint_system_parts(vec![], AHashSet::new(), None);
+        cs.metadata.field = Some(Arc::new(Curve::Bn128.modulus()));
+        to_r1cs(cs, Curve::Bls12_381.modulus());
+    }
+}
@@ -0,0 +1,508 @@
+//! Witness programs
+//!
+//! Lowering to R1CS introduces auxiliary signals (inverses, bit decompositions, quotients, ...)
+//! whose values are determined by the inputs. A [WitnessProgram] records, for each signal in
+//! order, how its value is computed from the inputs and from earlier signals. Running it on new
+//! inputs produces a full witness, without re-compiling.
+//!
+//! Programs have a line-based textual form (see the [Display](std::fmt::Display) and
+//! [FromStr](std::str::FromStr) implementations):
+//!
+//! ```text
+//! modulus 101
+//! input x
+//! inv 0 1*0
+//! is_zero 0 1*0
+//! mul 0 1*0 ; 3 2*2
+//! ```
+//!
+//! The first line gives the field; each following line computes the next signal. A linear
+//! combination is written as a constant, followed by `COEFF*SIGNAL` terms.
+//!
+//! If the system's signals were renumbered after lowering (see
+//! [clean_up](super::opt::clean_up)), a final `keep` line lists, for each signal of the system in
+//! order, the signal of the program which gives its value.
+
+use super::*;
+use crate::ir::term::Value;
+
+use thiserror::Error;
+
+use std::fmt::{self, Formatter};
+use std::str::FromStr;
+
+/// How to compute the value of a single signal.
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// The value of the named input (booleans are 0 or 1)
+    Input(String),
+    /// The product of two combinations
+    Mul(Lc, Lc),
+    /// The inverse of a combination, or zero if it is zero
+    Inv(Lc),
+    /// One if a combination is zero, else zero
+    IsZero(Lc),
+    /// Bit `i` of a combination
+    Bit(Lc, usize),
+    /// One if a combination is equal to `i`, else zero
+    Eq(Lc, usize),
+    /// The unsigned quotient of two combinations; all ones (at the given width) if the divisor is
+    /// zero
+    Div(Lc, Lc, usize),
+    /// The unsigned remainder of two combinations; the dividend if the divisor is zero
+    Rem(Lc, Lc),
+}
+
+#[derive(Debug, Error)]
+/// An error in parsing or running a witness program
+pub enum WitnessError {
+    #[error("Missing value for input '{0}'")]
+    MissingInput(String),
+    #[error("Input '{0}' has value {1}, which is not a boolean, bit-vector, or field element")]
+    BadInput(String, Value),
+    #[error("Missing value for signal {0}")]
+    MissingSignal(usize),
+    #[error("Line {0} of witness program: {1}")]
+    Parse(usize, String),
+}
+
+fn eval_lc(lc: &Lc, values: &HashMap<usize, Integer>) -> Result<Integer, WitnessError> {
+    let mut acc = lc.constant.clone();
+    for (var, coeff) in &lc.monomials {
+        let val = values.get(var).ok_or(WitnessError::MissingSignal(*var))?;
+        acc += Integer::from(val * coeff);
+    }
+    Ok(acc.rem_floor(&*lc.modulus))
+}
+
+impl Step {
+    /// Compute this step's value, given `inputs` and the values of the earlier signals.
+    pub fn eval(
+        &self,
+        inputs: &HashMap<String, Value>,
+        values: &HashMap<usize, Integer>,
+    ) -> Result<Integer, WitnessError> {
+        Ok(match self {
+            Step::Input(name) => match inputs.get(name) {
+                Some(Value::Bool(b)) => Integer::from(*b),
+                Some(Value::BitVector(b)) => b.uint().clone(),
+                Some(Value::Field(f)) => f.i().clone(),
+                Some(v) => return Err(WitnessError::BadInput(name.clone(), v.clone())),
+                None => return Err(WitnessError::MissingInput(name.clone())),
+            },
+            Step::Mul(a, b) => (eval_lc(a, values)? * eval_lc(b, values)?).rem_floor(&*a.modulus),
+            Step::Inv(x) => eval_lc(x, values)?
+                .invert(&*x.modulus)
+                .unwrap_or_else(|_| Integer::from(0)),
+            Step::IsZero(x) => Integer::from(eval_lc(x, values)? == 0),
+            Step::Bit(x, i) => Integer::from(eval_lc(x, values)?.get_bit(*i as u32)),
+            Step::Eq(x, i) => Integer::from(eval_lc(x, values)? == *i),
+            Step::Div(a, b, w) => {
+                let b = eval_lc(b, values)?;
+                if b == 0 {
+                    (Integer::from(1) << *w as u32) - 1
+                } else {
+                    eval_lc(a, values)? / b
+                }
+            }
+            Step::Rem(a, b) => {
+                let a = eval_lc(a, values)?;
+                let b = eval_lc(b, values)?;
+                if b == 0 {
+                    a
+                } else {
+                    a % b
+                }
+            }
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+/// An ordered list of [Step]s: the `i`th computes signal `i` of an [R1cs], unless the program has
+/// been renumbered.
+pub struct WitnessProgram {
+    modulus: Rc<Integer>,
+    steps: Vec<Step>,
+    /// For each signal of the system, the step which computes it, if renumbered.
+    keep: Option<Vec<usize>>,
+}
+
+impl WitnessProgram {
+    /// An empty program over the field defined by `modulus`.
+    pub fn new(modulus: Rc<Integer>) -> Self {
+        Self {
+            modulus,
+            steps: Vec::new(),
+            keep: None,
+        }
+    }
+
+    /// The field modulus.
+    pub fn modulus(&self) -> &Integer {
+        &self.modulus
+    }
+
+    /// Add a step, computing the next signal.
+    pub fn push(&mut self, step: Step) {
+        self.steps.push(step);
+    }
+
+    /// The steps, in order.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// The number of signals computed.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    /// Is this program empty?
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Follow a renumbering of the system's signals: signal `i` becomes signal `map[i]`, or is
+    /// dropped. Every step is still run, since later steps may use dropped signals.
+    pub fn renumber(&mut self, map: &[Option<usize>]) {
+        let n = map.iter().flatten().count();
+        let mut keep = vec![0; n];
+        for (old, new) in map.iter().enumerate() {
+            if let Some(new) = new {
+                keep[*new] = self.keep.as_ref().map_or(old, |k| k[old]);
+            }
+        }
+        self.keep = Some(keep);
+    }
+
+    /// The signals of the system which are inputs.
+    pub fn inputs(&self) -> Vec<usize> {
+        self.inputs_where(|_| true)
+    }
+
+    /// The signals of the system which are inputs whose names satisfy `pick`.
+    pub fn inputs_where(&self, pick: impl Fn(&str) -> bool) -> Vec<usize> {
+        let is_input =
+            |step: usize| matches!(&self.steps[step], Step::Input(n) if pick(n.as_str()));
+        match &self.keep {
+            Some(keep) => (0..keep.len()).filter(|i| is_input(keep[*i])).collect(),
+            None => (0..self.steps.len()).filter(|i| is_input(*i)).collect(),
+        }
+    }
+
+    /// Compute the values of all signals from the values of the `inputs`.
+    pub fn eval(
+        &self,
+        inputs: &HashMap<String, Value>,
+    ) -> Result<HashMap<usize, Integer>, WitnessError> {
+        let mut values = HashMap::new();
+        for (i, step) in self.steps.iter().enumerate() {
+            let v = step.eval(inputs, &values)?;
+            values.insert(i, v);
+        }
+        match &self.keep {
+            Some(keep) => Ok(keep
+                .iter()
+                .enumerate()
+                .map(|(new, old)| (new, values[old].clone()))
+                .collect()),
+            None => Ok(values),
+        }
+    }
+
+    fn parse_lc(&self, line: usize, s: &str) -> Result<Lc, WitnessError> {
+        let err = |msg: String| WitnessError::Parse(line, msg);
+        let parse_int = |s: &str| {
+            Integer::from_str_radix(s, 10).map_err(|e| err(format!("bad integer '{}': {}", s, e)))
+        };
+        let mut tokens = s.split_whitespace();
+        let constant = parse_int(
+            tokens
+                .next()
+                .ok_or_else(|| err("empty combination".into()))?,
+        )?;
+        let mut monomials = HashMap::new();
+        for t in tokens {
+            let mut parts = t.splitn(2, '*');
+            let coeff = parse_int(parts.next().unwrap())?;
+            let idx = parts
+                .next()
+                .ok_or_else(|| err(format!("expected COEFF*SIGNAL, got '{}'", t)))?;
+            let idx: usize = idx
+                .parse()
+                .map_err(|_| err(format!("bad signal '{}'", idx)))?;
+            if idx >= self.steps.len() {
+                return Err(err(format!("signal {} is not yet computed", idx)));
+            }
+            monomials.insert(idx, coeff);
+        }
+        Ok(Lc {
+            modulus: self.modulus.clone(),
+            constant,
+            monomials,
+        })
+    }
+
+    fn parse_lcs(&self, line: usize, s: &str) -> Result<(Lc, Lc), WitnessError> {
+        let mut parts = s.splitn(2, ';');
+        let a = self.parse_lc(line, parts.next().unwrap())?;
+        let b = parts
+            .next()
+            .ok_or_else(|| WitnessError::Parse(line, "expected two combinations".into()))?;
+        Ok((a, self.parse_lc(line, b)?))
+    }
+
+    fn parse_keep(&self, line: usize, s: &str) -> Result<Vec<usize>, WitnessError> {
+        s.split_whitespace()
+            .map(|i| match i.parse::<usize>() {
+                Ok(i) if i < self.steps.len() => Ok(i),
+                _ => Err(WitnessError::Parse(line, format!("bad signal '{}'", i))),
+            })
+            .collect()
+    }
+
+    fn parse_step(&self, line: usize, s: &str) -> Result<Step, WitnessError> {
+        let err = |msg: String| WitnessError::Parse(line, msg);
+        let mut parts = s.trim().splitn(2, ' ');
+        let kind = parts.next().unwrap();
+        let rest = parts.next().unwrap_or("").trim();
+        Ok(match kind {
+            "input" if !rest.is_empty() => Step::Input(rest.to_owned()),
+            "input" => return Err(err("missing input name".into())),
+            "mul" => {
+                let (a, b) = self.parse_lcs(line, rest)?;
+                Step::Mul(a, b)
+            }
+            "inv" => Step::Inv(self.parse_lc(line, rest)?),
+            "is_zero" => Step::IsZero(self.parse_lc(line, rest)?),
+            "bit" => {
+                let (i, rest) = split_num(line, rest)?;
+                Step::Bit(self.parse_lc(line, rest)?, i)
+            }
+            "eq" => {
+                let (i, rest) = split_num(line, rest)?;
+                Step::Eq(self.parse_lc(line, rest)?, i)
+            }
+            "div" => {
+                let (w, rest) = split_num(line, rest)?;
+                let (a, b) = self.parse_lcs(line, rest)?;
+                Step::Div(a, b, w)
+            }
+            "rem" => {
+                let (a, b) = self.parse_lcs(line, rest)?;
+                Step::Rem(a, b)
+            }
+            _ => return Err(err(format!("unknown step '{}'", kind))),
+        })
+    }
+}
+
+/// Split the leading number (of a `bit`, `eq`, or `div` step) from the rest of the line.
+fn split_num(line: usize, s: &str) -> Result<(usize, &str), WitnessError> {
+    let mut parts = s.splitn(2, ' ');
+    let n = parts.next().unwrap();
+    let n = n
+        .parse()
+        .map_err(|_| WitnessError::Parse(line, format!("bad number '{}'", n)))?;
+    Ok((n, parts.next().unwrap_or("")))
+}
+
+struct LcText<'a>(&'a Lc);
+
+impl Display for LcText<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.constant)?;
+        let mut monomials: Vec<_> = self.0.monomials.iter().collect();
+        monomials.sort_by_key(|(i, _)| **i);
+        for (i, c) in monomials {
+            write!(f, " {}*{}", c, i)?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Step::Input(name) => write!(f, "input {}", name),
+            Step::Mul(a, b) => write!(f, "mul {} ; {}", LcText(a), LcText(b)),
+            Step::Inv(x) => write!(f, "inv {}", LcText(x)),
+            Step::IsZero(x) => write!(f, "is_zero {}", LcText(x)),
+            Step::Bit(x, i) => write!(f, "bit {} {}", i, LcText(x)),
+            Step::Eq(x, i) => write!(f, "eq {} {}", i, LcText(x)),
+            Step::Div(a, b, w) => write!(f, "div {} {} ; {}", w, LcText(a), LcText(b)),
+            Step::Rem(a, b) => write!(f, "rem {} ; {}", LcText(a), LcText(b)),
+        }
+    }
+}
+
+impl Display for WitnessProgram {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(f, "modulus {}", self.modulus)?;
+        for s in &self.steps {
+            writeln!(f, "{}", s)?;
+        }
+        if let Some(keep) = &self.keep {
+            write!(f, "keep")?;
+            for i in keep {
+                write!(f, " {}", i)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for WitnessProgram {
+    type Err = WitnessError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+        let modulus = match lines.next() {
+            Some((_, l)) if l.trim().starts_with("modulus ") => {
+                let m = l.trim()["modulus ".len()..].trim();
+                Integer::from_str_radix(m, 10)
+                    .map_err(|e| WitnessError::Parse(1, format!("bad modulus '{}': {}", m, e)))?
+            }
+            _ => return Err(WitnessError::Parse(1, "expected 'modulus P'".into())),
+        };
+        let mut program = WitnessProgram::new(Rc::new(modulus));
+        for (i, l) in lines {
+            if program.keep.is_some() {
+                return Err(WitnessError::Parse(i + 1, "steps after 'keep'".into()));
+            } else if l.trim() == "keep" || l.trim().starts_with("keep ") {
+                program.keep = Some(program.parse_keep(i + 1, &l.trim()["keep".len()..])?);
+            } else {
+                let step = program.parse_step(i + 1, l)?;
+                program.push(step);
+            }
+        }
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ir::term::FieldElem;
+    use std::sync::Arc;
+
+    fn lc(m: &Rc<Integer>, constant: isize, monomials: &[(usize, isize)]) -> Lc {
+        Lc {
+            modulus: m.clone(),
+            constant: Integer::from(constant),
+            monomials: monomials
+                .iter()
+                .map(|(i, c)| (*i, Integer::from(*c)))
+                .collect(),
+        }
+    }
+
+    fn program() -> WitnessProgram {
+        let m = Rc::new(Integer::from(101));
+        let mut p = WitnessProgram::new(m.clone());
+        p.push(Step::Input("x".into()));
+        p.push(Step::Inv(lc(&m, 0, &[(0, 1)])));
+        p.push(Step::IsZero(lc(&m, 0, &[(0, 1)])));
+        p.push(Step::Mul(lc(&m, 0, &[(0, 1)]), lc(&m, 3, &[(2, 2)])));
+        p.push(Step::Bit(lc(&m, 0, &[(0, 1)]), 2));
+        p.push(Step::Eq(lc(&m, -1, &[(0, 1)]), 4));
+        p.push(Step::Div(lc(&m, 0, &[(0, 1)]), lc(&m, 2, &[]), 8));
+        p.push(Step::Rem(lc(&m, 0, &[(0, 1)]), lc(&m, 0, &[(2, 1)])));
+        p
+    }
+
+    #[test]
+    fn eval() {
+        let p = program();
+        let mut inputs = HashMap::new();
+        let x = FieldElem::new(Integer::from(5), Arc::new(Integer::from(101)));
+        inputs.insert("x".to_owned(), Value::Field(x));
+        let values = p.eval(&inputs).unwrap();
+        let expected = [5, 81, 0, 15, 1, 1, 2, 5];
+        for (i, e) in expected.iter().enumerate() {
+            assert_eq!(values[&i], *e, "signal {}", i);
+        }
+        inputs.insert("x".to_owned(), Value::Bool(false));
+        let values = p.eval(&inputs).unwrap();
+        let expected = [0, 0, 1, 0, 0, 0, 0, 0];
+        for (i, e) in expected.iter().enumerate() {
+            assert_eq!(values[&i], *e, "signal {}", i);
+        }
+    }
+
+    #[test]
+    fn missing_input() {
+        let p = program();
+        assert!(matches!(
+            p.eval(&HashMap::new()),
+            Err(WitnessError::MissingInput(n)) if n == "x"
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let p = program();
+        let text = p.to_string();
+        let p2: WitnessProgram = text.parse().unwrap();
+        assert_eq!(p2.len(), p.len());
+        assert_eq!(p2.to_string(), text);
+        let mut inputs = HashMap::new();
+        inputs.insert("x".to_owned(), Value::Bool(true));
+        let (vs, vs2) = (p.eval(&inputs).unwrap(), p2.eval(&inputs).unwrap());
+        for i in 0..p.len() {
+            assert_eq!(vs[&i], vs2[&i]);
+        }
+    }
+
+    #[test]
+    fn renumber() {
+        let mut p = program();
+        assert_eq!(p.inputs(), vec![0]);
+        p.renumber(&[
+            Some(0),
+            None,
+            None,
+            Some(1),
+            Some(2),
+            Some(3),
+            None,
+            Some(4),
+        ]);
+        p.renumber(&[Some(0), Some(1), None, Some(2), None]);
+        let text = p.to_string();
+        assert!(text.ends_with("keep 0 3 5\n"));
+        assert_eq!(p.inputs(), vec![0]);
+        let p2: WitnessProgram = text.parse().unwrap();
+        assert_eq!(p2.to_string(), text);
+        let mut inputs = HashMap::new();
+        let x = FieldElem::new(Integer::from(5), Arc::new(Integer::from(101)));
+        inputs.insert("x".to_owned(), Value::Field(x));
+        let values = p2.eval(&inputs).unwrap();
+        assert_eq!(values.len(), 3);
+        for (i, e) in [5, 15, 1].iter().enumerate() {
+            assert_eq!(values[&i], *e, "signal {}", i);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("input x\n".parse::<WitnessProgram>().is_err());
+        assert!(matches!(
+            "modulus 7\ninput x\ninv 0 1*1\n".parse::<WitnessProgram>(),
+            Err(WitnessError::Parse(3, _))
+        ));
+        assert!(matches!(
+            "modulus 7\nfrobnicate 0\n".parse::<WitnessProgram>(),
+            Err(WitnessError::Parse(2, _))
+        ));
+        assert!(matches!(
+            "modulus 7\ninput x\nkeep 0 1\n".parse::<WitnessProgram>(),
+            Err(WitnessError::Parse(3, _))
+        ));
+        assert!(matches!(
+            "modulus 7\ninput x\nkeep 0\ninput y\n".parse::<WitnessProgram>(),
+            Err(WitnessError::Parse(4, _))
+        ));
+    }
+}